@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::ops::Deref;
 
 #[cfg(feature = "internal_debug")]
@@ -61,6 +62,8 @@ pub enum Stmt<'a> {
     SetBlock(Spanned<SetBlock<'a>>),
     AutoEscape(Spanned<AutoEscape<'a>>),
     FilterBlock(Spanned<FilterBlock<'a>>),
+    Do(Spanned<Do<'a>>),
+    CustomTag(Spanned<CustomTag<'a>>),
     #[cfg(feature = "multi-template")]
     Block(Spanned<Block<'a>>),
     #[cfg(feature = "multi-template")]
@@ -73,6 +76,8 @@ pub enum Stmt<'a> {
     Include(Spanned<Include<'a>>),
     #[cfg(feature = "macros")]
     Macro(Spanned<Macro<'a>>),
+    #[cfg(feature = "macros")]
+    CallBlock(Spanned<CallBlock<'a>>),
 }
 
 #[cfg(feature = "internal_debug")]
@@ -89,6 +94,8 @@ impl<'a> fmt::Debug for Stmt<'a> {
             Stmt::SetBlock(s) => fmt::Debug::fmt(s, f),
             Stmt::AutoEscape(s) => fmt::Debug::fmt(s, f),
             Stmt::FilterBlock(s) => fmt::Debug::fmt(s, f),
+            Stmt::Do(s) => fmt::Debug::fmt(s, f),
+            Stmt::CustomTag(s) => fmt::Debug::fmt(s, f),
             #[cfg(feature = "multi-template")]
             Stmt::Block(s) => fmt::Debug::fmt(s, f),
             #[cfg(feature = "multi-template")]
@@ -101,6 +108,43 @@ impl<'a> fmt::Debug for Stmt<'a> {
             Stmt::FromImport(s) => fmt::Debug::fmt(s, f),
             #[cfg(feature = "macros")]
             Stmt::Macro(s) => fmt::Debug::fmt(s, f),
+            #[cfg(feature = "macros")]
+            Stmt::CallBlock(s) => fmt::Debug::fmt(s, f),
+        }
+    }
+}
+
+#[cfg(feature = "unstable_machinery")]
+impl<'a> Stmt<'a> {
+    /// Returns the span of the statement.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Template(s) => s.span(),
+            Stmt::EmitExpr(s) => s.span(),
+            Stmt::EmitRaw(s) => s.span(),
+            Stmt::ForLoop(s) => s.span(),
+            Stmt::IfCond(s) => s.span(),
+            Stmt::WithBlock(s) => s.span(),
+            Stmt::Set(s) => s.span(),
+            Stmt::SetBlock(s) => s.span(),
+            Stmt::AutoEscape(s) => s.span(),
+            Stmt::FilterBlock(s) => s.span(),
+            Stmt::Do(s) => s.span(),
+            Stmt::CustomTag(s) => s.span(),
+            #[cfg(feature = "multi-template")]
+            Stmt::Block(s) => s.span(),
+            #[cfg(feature = "multi-template")]
+            Stmt::Extends(s) => s.span(),
+            #[cfg(feature = "multi-template")]
+            Stmt::Include(s) => s.span(),
+            #[cfg(feature = "multi-template")]
+            Stmt::Import(s) => s.span(),
+            #[cfg(feature = "multi-template")]
+            Stmt::FromImport(s) => s.span(),
+            #[cfg(feature = "macros")]
+            Stmt::Macro(s) => s.span(),
+            #[cfg(feature = "macros")]
+            Stmt::CallBlock(s) => s.span(),
         }
     }
 }
@@ -146,6 +190,29 @@ impl<'a> fmt::Debug for Expr<'a> {
     }
 }
 
+#[cfg(feature = "unstable_machinery")]
+impl<'a> Expr<'a> {
+    /// Returns the span of the expression.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Var(s) => s.span(),
+            Expr::Const(s) => s.span(),
+            Expr::Slice(s) => s.span(),
+            Expr::UnaryOp(s) => s.span(),
+            Expr::BinOp(s) => s.span(),
+            Expr::IfExpr(s) => s.span(),
+            Expr::Filter(s) => s.span(),
+            Expr::Test(s) => s.span(),
+            Expr::GetAttr(s) => s.span(),
+            Expr::GetItem(s) => s.span(),
+            Expr::Call(s) => s.span(),
+            Expr::List(s) => s.span(),
+            Expr::Map(s) => s.span(),
+            Expr::Kwargs(s) => s.span(),
+        }
+    }
+}
+
 /// Root template node.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct Template<'a> {
@@ -198,6 +265,10 @@ pub struct SetBlock<'a> {
 #[cfg(feature = "multi-template")]
 pub struct Block<'a> {
     pub name: &'a str,
+    // blocks are always scoped in MiniJinja, this is only retained for tools
+    #[cfg_attr(not(feature = "unstable_machinery"), allow(dead_code))]
+    pub scoped: bool,
+    pub required: bool,
     pub body: Vec<Stmt<'a>>,
 }
 
@@ -230,6 +301,20 @@ pub struct FilterBlock<'a> {
     pub body: Vec<Stmt<'a>>,
 }
 
+/// Evaluates an expression and discards the result.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+pub struct Do<'a> {
+    pub expr: Expr<'a>,
+}
+
+/// Invokes a custom tag registered on the environment.
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+pub struct CustomTag<'a> {
+    pub name: &'a str,
+    pub args: Vec<Expr<'a>>,
+    pub body: Vec<Stmt<'a>>,
+}
+
 /// Declares a macro.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 #[cfg(feature = "macros")]
@@ -240,12 +325,21 @@ pub struct Macro<'a> {
     pub body: Vec<Stmt<'a>>,
 }
 
+/// A call block
+#[cfg_attr(feature = "internal_debug", derive(Debug))]
+#[cfg(feature = "macros")]
+pub struct CallBlock<'a> {
+    pub call: Spanned<Call<'a>>,
+    pub macro_decl: Spanned<Macro<'a>>,
+}
+
 /// A "from" import
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 #[cfg(feature = "multi-template")]
 pub struct FromImport<'a> {
     pub expr: Expr<'a>,
     pub names: Vec<(Expr<'a>, Option<Expr<'a>>)>,
+    pub with_context: bool,
 }
 
 /// A full module import
@@ -254,6 +348,7 @@ pub struct FromImport<'a> {
 pub struct Import<'a> {
     pub expr: Expr<'a>,
     pub name: Expr<'a>,
+    pub with_context: bool,
 }
 
 /// Outputs the expression.
@@ -265,7 +360,7 @@ pub struct EmitExpr<'a> {
 /// Outputs raw template code.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct EmitRaw<'a> {
-    pub raw: &'a str,
+    pub raw: Cow<'a, str>,
 }
 
 /// Looks up a variable.
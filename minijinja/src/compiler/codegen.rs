@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 use crate::compiler::ast;
 use crate::compiler::instructions::{
@@ -24,6 +25,31 @@ fn get_local_id<'source>(ids: &mut BTreeMap<&'source str, LocalId>, name: &'sour
     }
 }
 
+/// Returns the built-in filter whose special form applies to a filter call.
+#[cfg(feature = "builtins")]
+fn special_filter_form(f: &ast::Filter<'_>) -> Option<&'static str> {
+    f.expr.as_ref()?;
+    match f.name {
+        "coalesce"
+            if !f.args.is_empty()
+                && !f.args.iter().any(|arg| matches!(arg, ast::Expr::Kwargs(_))) =>
+        {
+            Some("coalesce")
+        }
+        "default" | "d" => match f.args.last() {
+            Some(ast::Expr::Kwargs(kwargs))
+                if kwargs.pairs.iter().any(|(key, value)| {
+                    *key == "on_error" && matches!(value, ast::Expr::Const(_))
+                }) =>
+            {
+                Some("default")
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// Represents an open block of code that does not yet have updated
 /// jump targets.
 enum PendingBlock {
@@ -41,6 +67,7 @@ pub struct CodeGenerator<'source> {
     span_stack: Vec<Span>,
     filter_local_ids: BTreeMap<&'source str, LocalId>,
     test_local_ids: BTreeMap<&'source str, LocalId>,
+    feature_flags: Option<Arc<BTreeMap<String, bool>>>,
     #[cfg(feature = "multi-template")]
     has_extends: bool,
 }
@@ -56,11 +83,20 @@ impl<'source> CodeGenerator<'source> {
             span_stack: Vec::new(),
             filter_local_ids: BTreeMap::new(),
             test_local_ids: BTreeMap::new(),
+            feature_flags: None,
             #[cfg(feature = "multi-template")]
             has_extends: false,
         }
     }
 
+    /// Sets the feature flags known at compile time.
+    ///
+    /// Calls to `feature("name")` with a known flag are folded into
+    /// constants and `if` blocks testing them only compile the taken branch.
+    pub fn set_feature_flags(&mut self, flags: Arc<BTreeMap<String, bool>>) {
+        self.feature_flags = Some(flags);
+    }
+
     /// Sets the current location's line.
     pub fn set_line(&mut self, lineno: usize) {
         self.current_line = lineno;
@@ -108,6 +144,7 @@ impl<'source> CodeGenerator<'source> {
         let mut sub = CodeGenerator::new(self.instructions.name(), self.instructions.source());
         sub.current_line = self.current_line;
         sub.span_stack = self.span_stack.last().copied().into_iter().collect();
+        sub.feature_flags = self.feature_flags.clone();
         sub
     }
 
@@ -241,7 +278,7 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::Stmt::EmitRaw(raw) => {
                 self.set_line_from_span(raw.span());
-                self.add(Instruction::EmitRaw(raw.raw));
+                self.add(Instruction::EmitRaw(raw.raw.clone()));
             }
             ast::Stmt::ForLoop(for_loop) => {
                 self.compile_for_loop(for_loop);
@@ -297,6 +334,26 @@ impl<'source> CodeGenerator<'source> {
                 self.compile_expr(&filter_block.filter);
                 self.add(Instruction::Emit);
             }
+            ast::Stmt::CustomTag(tag) => {
+                self.set_line_from_span(tag.span());
+                self.add(Instruction::BeginCapture(CaptureMode::Capture));
+                self.add(Instruction::PushWith);
+                for node in &tag.body {
+                    self.compile_stmt(node);
+                }
+                self.add(Instruction::PopFrame);
+                self.add(Instruction::EndCapture);
+                for arg in &tag.args {
+                    self.compile_expr(arg);
+                }
+                self.add(Instruction::CallTag(tag.name, tag.args.len() + 1));
+                self.add(Instruction::Emit);
+            }
+            ast::Stmt::Do(do_stmt) => {
+                self.set_line_from_span(do_stmt.span());
+                self.compile_expr(&do_stmt.expr);
+                self.add(Instruction::DiscardTop);
+            }
             #[cfg(feature = "multi-template")]
             ast::Stmt::Block(block) => {
                 self.compile_block(block);
@@ -304,7 +361,11 @@ impl<'source> CodeGenerator<'source> {
             #[cfg(feature = "multi-template")]
             ast::Stmt::Import(import) => {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
-                self.add(Instruction::PushWith);
+                self.add(if import.with_context {
+                    Instruction::PushWith
+                } else {
+                    Instruction::PushIsolatedWith
+                });
                 self.compile_expr(&import.expr);
                 self.add_with_span(Instruction::Include(false), import.span());
                 self.add(Instruction::ExportLocals);
@@ -315,12 +376,23 @@ impl<'source> CodeGenerator<'source> {
             #[cfg(feature = "multi-template")]
             ast::Stmt::FromImport(from_import) => {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
-                self.add(Instruction::PushWith);
+                self.add(if from_import.with_context {
+                    Instruction::PushWith
+                } else {
+                    Instruction::PushIsolatedWith
+                });
                 self.compile_expr(&from_import.expr);
+                self.add(Instruction::DupTop);
                 self.add_with_span(Instruction::Include(false), from_import.span());
                 for (name, _) in &from_import.names {
-                    self.compile_expr(name);
+                    match name {
+                        ast::Expr::Var(var) => {
+                            self.add_with_span(Instruction::ImportName(var.id), var.span());
+                        }
+                        _ => unreachable!(),
+                    }
                 }
+                self.add(Instruction::DiscardTop);
                 self.add(Instruction::PopFrame);
                 for (name, alias) in from_import.names.iter().rev() {
                     self.compile_assignment(alias.as_ref().unwrap_or(name));
@@ -344,6 +416,11 @@ impl<'source> CodeGenerator<'source> {
             ast::Stmt::Macro(macro_decl) => {
                 self.compile_macro(macro_decl);
             }
+            #[cfg(feature = "macros")]
+            ast::Stmt::CallBlock(call_block) => {
+                self.compile_call(&call_block.call, Some(&call_block.macro_decl));
+                self.add(Instruction::Emit);
+            }
         }
     }
 
@@ -351,8 +428,14 @@ impl<'source> CodeGenerator<'source> {
     fn compile_block(&mut self, block: &ast::Spanned<ast::Block<'source>>) {
         self.set_line_from_span(block.span());
         let mut sub = self.new_subgenerator();
-        for node in &block.body {
-            sub.compile_stmt(node);
+        // a required block only renders if it was overridden.  Blocks are
+        // always scoped as they see the variables of the enclosing scope.
+        if block.required {
+            sub.add_with_span(Instruction::RequiredBlock(block.name), block.span());
+        } else {
+            for node in &block.body {
+                sub.compile_stmt(node);
+            }
         }
         let instructions = self.finish_subgenerator(sub);
         self.blocks.insert(block.name, instructions);
@@ -360,12 +443,33 @@ impl<'source> CodeGenerator<'source> {
     }
 
     #[cfg(feature = "macros")]
-    fn compile_macro(&mut self, macro_decl: &ast::Spanned<ast::Macro<'source>>) {
+    fn compile_macro_expression(&mut self, macro_decl: &ast::Spanned<ast::Macro<'source>>) {
+        use crate::compiler::instructions::{
+            MACRO_FLAG_CALLER, MACRO_FLAG_KWARGS, MACRO_FLAG_SELF_REFERENCE, MACRO_FLAG_VARARGS,
+        };
         use crate::value::ValueRepr;
 
         self.set_line_from_span(macro_decl.span());
         let instr = self.add(Instruction::Jump(!0));
 
+        // a macro that refers to `caller` without declaring it receives it
+        // as an implicit last argument from call blocks.
+        let mut undeclared = crate::compiler::meta::find_macro_closure(macro_decl);
+        let caller_reference = undeclared.remove("caller");
+        // likewise `varargs` and `kwargs` receive the surplus arguments.  They
+        // are passed after all other arguments.
+        let varargs_reference = undeclared.remove("varargs");
+        let kwargs_reference = undeclared.remove("kwargs");
+        if kwargs_reference {
+            self.add(Instruction::StoreLocal("kwargs"));
+        }
+        if varargs_reference {
+            self.add(Instruction::StoreLocal("varargs"));
+        }
+        if caller_reference {
+            self.add(Instruction::StoreLocal("caller"));
+        }
+
         let mut defaults_iter = macro_decl.defaults.iter().rev();
         for arg in macro_decl.args.iter().rev() {
             if let Some(default) = defaults_iter.next() {
@@ -384,8 +488,19 @@ impl<'source> CodeGenerator<'source> {
         }
         self.add(Instruction::Return);
 
-        let undeclared = crate::compiler::meta::find_macro_closure(macro_decl);
-        let self_reference = undeclared.contains(macro_decl.name);
+        let mut flags = 0;
+        if undeclared.contains(macro_decl.name) {
+            flags |= MACRO_FLAG_SELF_REFERENCE;
+        }
+        if caller_reference {
+            flags |= MACRO_FLAG_CALLER;
+        }
+        if varargs_reference {
+            flags |= MACRO_FLAG_VARARGS;
+        }
+        if kwargs_reference {
+            flags |= MACRO_FLAG_KWARGS;
+        }
         let macro_instr = self.next_instruction();
         for name in &undeclared {
             self.add(Instruction::LoadConst(Value::from(*name)));
@@ -401,16 +516,31 @@ impl<'source> CodeGenerator<'source> {
                     ast::Expr::Var(var) => Value::from(var.id),
                     _ => unreachable!(),
                 })
+                .chain(caller_reference.then(|| Value::from("caller")))
                 .collect::<Vec<_>>()
                 .into(),
         ))));
 
-        self.add(Instruction::BuildMacro(
-            macro_decl.name,
-            instr + 1,
-            self_reference,
-        ));
-        self.add(Instruction::StoreLocal(macro_decl.name));
+        // the defaults are only known ahead of time if they are constant,
+        // other defaults are reported as none.
+        self.add(Instruction::LoadConst(Value::from(ValueRepr::Seq(
+            macro_decl
+                .defaults
+                .iter()
+                .map(|x| {
+                    match x {
+                        ast::Expr::Const(c) => Some(c.value.clone()),
+                        ast::Expr::List(l) => l.as_const(),
+                        ast::Expr::Map(m) => m.as_const(),
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| Value::from(()))
+                })
+                .collect::<Vec<_>>()
+                .into(),
+        ))));
+
+        self.add(Instruction::BuildMacro(macro_decl.name, instr + 1, flags));
 
         if let Some(Instruction::Jump(ref mut target)) = self.instructions.get_mut(instr) {
             *target = macro_instr;
@@ -419,8 +549,25 @@ impl<'source> CodeGenerator<'source> {
         }
     }
 
+    #[cfg(feature = "macros")]
+    fn compile_macro(&mut self, macro_decl: &ast::Spanned<ast::Macro<'source>>) {
+        self.compile_macro_expression(macro_decl);
+        self.add(Instruction::StoreLocal(macro_decl.name));
+    }
+
     fn compile_if_stmt(&mut self, if_cond: &ast::Spanned<ast::IfCond<'source>>) {
         self.set_line_from_span(if_cond.span());
+        if let Some(enabled) = self.fold_feature_check(&if_cond.expr) {
+            let body = if enabled {
+                &if_cond.true_body
+            } else {
+                &if_cond.false_body
+            };
+            for node in body {
+                self.compile_stmt(node);
+            }
+            return;
+        }
         self.compile_expr(&if_cond.expr);
         self.start_if();
         for node in &if_cond.true_body {
@@ -573,14 +720,7 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::Expr::Filter(f) => {
                 self.push_span(f.span());
-                if let Some(ref expr) = f.expr {
-                    self.compile_expr(expr);
-                }
-                for arg in &f.args {
-                    self.compile_expr(arg);
-                }
-                let local_id = get_local_id(&mut self.filter_local_ids, f.name);
-                self.add(Instruction::ApplyFilter(f.name, f.args.len() + 1, local_id));
+                self.compile_filter(f);
                 self.pop_span();
             }
             ast::Expr::Test(f) => {
@@ -607,7 +747,11 @@ impl<'source> CodeGenerator<'source> {
                 self.pop_span();
             }
             ast::Expr::Call(c) => {
-                self.compile_call(c);
+                self.compile_call(
+                    c,
+                    #[cfg(feature = "macros")]
+                    None,
+                );
             }
             ast::Expr::List(l) => {
                 if let Some(val) = l.as_const() {
@@ -648,14 +792,47 @@ impl<'source> CodeGenerator<'source> {
         }
     }
 
-    fn compile_call(&mut self, c: &ast::Spanned<ast::Call<'source>>) {
+    /// Evaluates `feature("name")` (optionally negated) against the
+    /// feature flags known at compile time.
+    fn fold_feature_check(&self, expr: &ast::Expr<'source>) -> Option<bool> {
+        match expr {
+            ast::Expr::UnaryOp(op) if matches!(op.op, ast::UnaryOpKind::Not) => {
+                self.fold_feature_check(&op.expr).map(|x| !x)
+            }
+            ast::Expr::Call(c) => self.fold_feature_call(c),
+            _ => None,
+        }
+    }
+
+    fn fold_feature_call(&self, c: &ast::Call<'source>) -> Option<bool> {
+        match (c.identify_call(), &c.args[..]) {
+            (ast::CallType::Function("feature"), [ast::Expr::Const(name)]) => self
+                .feature_flags
+                .as_ref()
+                .and_then(|flags| flags.get(name.value.as_str()?))
+                .copied(),
+            _ => None,
+        }
+    }
+
+    fn compile_call(
+        &mut self,
+        c: &ast::Spanned<ast::Call<'source>>,
+        #[cfg(feature = "macros")] caller: Option<&ast::Spanned<ast::Macro<'source>>>,
+    ) {
+        if let Some(enabled) = self.fold_feature_call(c) {
+            self.add(Instruction::LoadConst(Value::from(enabled)));
+            return;
+        }
         self.push_span(c.span());
         match c.identify_call() {
             ast::CallType::Function(name) => {
-                for arg in &c.args {
-                    self.compile_expr(arg);
-                }
-                self.add(Instruction::CallFunction(name, c.args.len()));
+                let arg_count = self.compile_call_args(
+                    &c.args,
+                    #[cfg(feature = "macros")]
+                    caller,
+                );
+                self.add(Instruction::CallFunction(name, arg_count));
             }
             #[cfg(feature = "multi-template")]
             ast::CallType::Block(name) => {
@@ -665,22 +842,176 @@ impl<'source> CodeGenerator<'source> {
             }
             ast::CallType::Method(expr, name) => {
                 self.compile_expr(expr);
-                for arg in &c.args {
-                    self.compile_expr(arg);
-                }
-                self.add(Instruction::CallMethod(name, c.args.len() + 1));
+                let arg_count = self.compile_call_args(
+                    &c.args,
+                    #[cfg(feature = "macros")]
+                    caller,
+                );
+                self.add(Instruction::CallMethod(name, arg_count + 1));
             }
             ast::CallType::Object(expr) => {
                 self.compile_expr(expr);
-                for arg in &c.args {
-                    self.compile_expr(arg);
-                }
-                self.add(Instruction::CallObject(c.args.len() + 1));
+                let arg_count = self.compile_call_args(
+                    &c.args,
+                    #[cfg(feature = "macros")]
+                    caller,
+                );
+                self.add(Instruction::CallObject(arg_count + 1));
             }
         };
         self.pop_span();
     }
 
+    /// Compiles the arguments of a call and returns how many there are.
+    ///
+    /// For call blocks the caller macro is passed as `caller` keyword
+    /// argument, merged with the other keyword arguments.
+    fn compile_call_args(
+        &mut self,
+        args: &[ast::Expr<'source>],
+        #[cfg(feature = "macros")] caller: Option<&ast::Spanned<ast::Macro<'source>>>,
+    ) -> usize {
+        #[cfg(feature = "macros")]
+        {
+            if let Some(caller) = caller {
+                let mut kwargs_pairs = &[][..];
+                let mut arg_count = 0;
+                for arg in args {
+                    if let ast::Expr::Kwargs(kwargs) = arg {
+                        kwargs_pairs = &kwargs.pairs;
+                    } else {
+                        self.compile_expr(arg);
+                        arg_count += 1;
+                    }
+                }
+                for (key, value) in kwargs_pairs {
+                    self.add(Instruction::LoadConst(Value::from(*key)));
+                    self.compile_expr(value);
+                }
+                self.add(Instruction::LoadConst(Value::from("caller")));
+                self.compile_macro_expression(caller);
+                self.add(Instruction::BuildKwargs(kwargs_pairs.len() + 1));
+                return arg_count + 1;
+            }
+        }
+        for arg in args {
+            self.compile_expr(arg);
+        }
+        args.len()
+    }
+
+    fn compile_filter(&mut self, f: &ast::Filter<'source>) {
+        // `coalesce` and `default` have special forms which only apply if the
+        // filter is still the built-in one when the template is rendered.
+        #[cfg(feature = "builtins")]
+        if let Some(builtin) = special_filter_form(f) {
+            let special_instr = self.add(Instruction::JumpIfBuiltinFilter(f.name, !0));
+            self.compile_filter_call(f);
+            let end_instr = self.add(Instruction::Jump(!0));
+            let special = self.next_instruction();
+            if builtin == "coalesce" {
+                self.compile_lazy_coalesce(f);
+            } else {
+                self.compile_default_on_error(f);
+            }
+            let end = self.next_instruction();
+            if let Some(Instruction::JumpIfBuiltinFilter(_, ref mut target)) =
+                self.instructions.get_mut(special_instr)
+            {
+                *target = special;
+            }
+            if let Some(Instruction::Jump(ref mut target)) = self.instructions.get_mut(end_instr) {
+                *target = end;
+            }
+            return;
+        }
+        self.compile_filter_call(f);
+    }
+
+    fn compile_filter_call(&mut self, f: &ast::Filter<'source>) {
+        if let Some(ref expr) = f.expr {
+            self.compile_expr(expr);
+        }
+        for arg in &f.args {
+            self.compile_expr(arg);
+        }
+        let local_id = get_local_id(&mut self.filter_local_ids, f.name);
+        self.add(Instruction::ApplyFilter(f.name, f.args.len() + 1, local_id));
+    }
+
+    /// `coalesce` with arguments is compiled into a chain of jumps so that
+    /// the arguments are only evaluated until a value was picked.
+    #[cfg(feature = "builtins")]
+    fn compile_lazy_coalesce(&mut self, f: &ast::Filter<'source>) {
+        let mut jumps = Vec::with_capacity(f.args.len() + 1);
+        for candidate in f.expr.iter().chain(f.args.iter()) {
+            self.compile_expr(candidate);
+            jumps.push(self.add(Instruction::JumpIfDefinedOrPop(!0)));
+        }
+        self.add(Instruction::LoadConst(Value::UNDEFINED));
+        let end = self.next_instruction();
+        for instr in jumps {
+            if let Some(Instruction::JumpIfDefinedOrPop(ref mut target)) =
+                self.instructions.get_mut(instr)
+            {
+                *target = end;
+            }
+        }
+    }
+
+    /// `default(..., on_error=true)` also catches errors from evaluating its
+    /// input.  The keyword argument is consumed here and not passed on to the
+    /// filter.
+    #[cfg(feature = "builtins")]
+    fn compile_default_on_error(&mut self, f: &ast::Filter<'source>) {
+        let (kwargs, args) = match f.args.split_last() {
+            Some((ast::Expr::Kwargs(kwargs), args)) => (kwargs, args),
+            _ => unreachable!(),
+        };
+        let on_error = kwargs
+            .pairs
+            .iter()
+            .position(|(key, value)| *key == "on_error" && matches!(value, ast::Expr::Const(_)))
+            .unwrap();
+        let catch_errors = match kwargs.pairs[on_error].1 {
+            ast::Expr::Const(ref c) => c.value.is_true(),
+            _ => unreachable!(),
+        };
+        if let Some(ref expr) = f.expr {
+            if catch_errors {
+                let catch_instr = self.add(Instruction::CatchErrors(!0));
+                self.compile_expr(expr);
+                self.add(Instruction::Return);
+                let end = self.next_instruction();
+                if let Some(Instruction::CatchErrors(ref mut target)) =
+                    self.instructions.get_mut(catch_instr)
+                {
+                    *target = end;
+                }
+            } else {
+                self.compile_expr(expr);
+            }
+        }
+        for arg in args {
+            self.compile_expr(arg);
+        }
+        let mut arg_count = f.args.len();
+        if kwargs.pairs.len() > 1 {
+            self.set_line_from_span(kwargs.span());
+            for (idx, (key, value)) in kwargs.pairs.iter().enumerate() {
+                if idx != on_error {
+                    self.add(Instruction::LoadConst(Value::from(*key)));
+                    self.compile_expr(value);
+                }
+            }
+            self.add(Instruction::BuildKwargs(kwargs.pairs.len() - 1));
+        } else {
+            arg_count -= 1;
+        }
+        let local_id = get_local_id(&mut self.filter_local_ids, f.name);
+        self.add(Instruction::ApplyFilter(f.name, arg_count + 1, local_id));
+    }
+
     fn compile_bin_op(&mut self, c: &ast::Spanned<ast::BinOp<'source>>) {
         self.push_span(c.span());
         let instr = match c.op {
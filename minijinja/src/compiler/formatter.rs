@@ -0,0 +1,208 @@
+use std::sync::Arc;
+
+use crate::compiler::lexer::{tokenize_raw, Syntax, WhitespaceConfig};
+use crate::compiler::tokens::{Span, Token};
+use crate::error::{Error, ErrorKind};
+
+/// Identifiers after which an expression starts.
+const KEYWORDS: &[&str] = &["and", "or", "not", "in", "is", "if", "elif", "else"];
+
+/// Maps spans back to byte offsets into the source.
+struct SourceMap<'s> {
+    source: &'s str,
+    line_starts: Vec<usize>,
+}
+
+impl<'s> SourceMap<'s> {
+    fn new(source: &'s str) -> SourceMap<'s> {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(idx, _)| idx + 1));
+        SourceMap {
+            source,
+            line_starts,
+        }
+    }
+
+    fn offset(&self, line: usize, col: usize) -> usize {
+        let start = self.line_starts[line - 1];
+        self.source[start..]
+            .char_indices()
+            .nth(col)
+            .map_or(self.source.len(), |(idx, _)| start + idx)
+    }
+
+    fn slice(&self, span: Span) -> &'s str {
+        &self.source
+            [self.offset(span.start_line, span.start_col)..self.offset(span.end_line, span.end_col)]
+    }
+}
+
+/// Formats the contents of a single `{{ }}` or `{% %}` tag.
+struct TagFormatter<'s> {
+    rv: String,
+    is_block: bool,
+    prev: Option<Token<'s>>,
+    prev_unary: bool,
+    count: usize,
+    brackets: Vec<char>,
+}
+
+impl<'s> TagFormatter<'s> {
+    fn new(is_block: bool) -> TagFormatter<'s> {
+        TagFormatter {
+            rv: String::new(),
+            is_block,
+            prev: None,
+            prev_unary: false,
+            count: 0,
+            brackets: Vec::new(),
+        }
+    }
+
+    /// Is the previous token a keyword after which an expression starts?
+    fn prev_is_keyword(&self) -> bool {
+        match self.prev {
+            Some(Token::Ident(ident)) => {
+                (self.is_block && self.count == 1 && ident != "call") || KEYWORDS.contains(&ident)
+            }
+            _ => false,
+        }
+    }
+
+    /// Does an operand start at the current position?
+    fn expects_operand(&self) -> bool {
+        match self.prev {
+            None => true,
+            Some(Token::Ident(_)) => self.prev_is_keyword(),
+            Some(
+                Token::Str(_)
+                | Token::String(_)
+                | Token::Int(_)
+                | Token::Float(_)
+                | Token::ParenClose
+                | Token::BracketClose
+                | Token::BraceClose,
+            ) => false,
+            Some(_) => true,
+        }
+    }
+
+    fn needs_space(&self, token: &Token<'_>) -> bool {
+        let prev = match self.prev {
+            Some(ref prev) => prev,
+            None => return false,
+        };
+        if self.prev_unary {
+            return false;
+        }
+        match (prev, token) {
+            (Token::ParenOpen | Token::BracketOpen | Token::BraceOpen, _) => false,
+            (_, Token::ParenClose | Token::BracketClose | Token::BraceClose) => false,
+            (_, Token::Comma | Token::Colon) => false,
+            (Token::Dot | Token::Pipe, _) | (_, Token::Dot | Token::Pipe) => false,
+            (Token::Colon, _) => self.brackets.last() != Some(&'['),
+            (Token::Assign, _) | (_, Token::Assign) => self.brackets.last() != Some(&'('),
+            (Token::Ident(_), Token::ParenOpen | Token::BracketOpen) => self.prev_is_keyword(),
+            (
+                Token::Str(_)
+                | Token::String(_)
+                | Token::ParenClose
+                | Token::BracketClose
+                | Token::BraceClose,
+                Token::ParenOpen | Token::BracketOpen,
+            ) => false,
+            _ => true,
+        }
+    }
+
+    fn push(&mut self, token: Token<'s>, text: &str) {
+        let is_unary = matches!(token, Token::Minus | Token::Plus) && self.expects_operand();
+        if self.needs_space(&token) {
+            self.rv.push(' ');
+        }
+        self.rv.push_str(text);
+        match token {
+            Token::ParenOpen => self.brackets.push('('),
+            Token::BracketOpen => self.brackets.push('['),
+            Token::BraceOpen => self.brackets.push('{'),
+            Token::ParenClose | Token::BracketClose | Token::BraceClose => {
+                self.brackets.pop();
+            }
+            _ => {}
+        }
+        self.prev = Some(token);
+        self.prev_unary = is_unary;
+        self.count += 1;
+    }
+}
+
+/// Formats a template with the default syntax into its canonical form.
+///
+/// The whitespace inside of `{{ }}` and `{% %}` tags is normalized while
+/// template data, comments and raw blocks are retained verbatim, as are the
+/// whitespace control markers.  Formatting is idempotent: formatting the
+/// output again yields the same string.  The template is only tokenized, not
+/// parsed, so only lexical errors are reported.
+pub fn format(source: &str) -> Result<String, Error> {
+    let map = SourceMap::new(source);
+    let mut rv = String::with_capacity(source.len());
+    let mut tag: Option<TagFormatter> = None;
+    let mut last_span = Span::default();
+
+    let tokens = tokenize_raw(
+        source,
+        false,
+        WhitespaceConfig::default(),
+        Arc::new(Syntax::default()),
+    );
+    for result in tokens {
+        let (token, span) = match result {
+            Ok(rv) => rv,
+            Err(mut err) => {
+                err.set_filename_and_span("<string>", last_span);
+                return Err(err);
+            }
+        };
+        last_span = span;
+        let text = map.slice(span);
+        match token {
+            Token::TemplateData(_) => rv.push_str(text),
+            Token::VariableStart(_) | Token::BlockStart(_) => {
+                rv.push_str(text);
+                tag = Some(TagFormatter::new(matches!(token, Token::BlockStart(_))));
+            }
+            Token::VariableEnd(_) | Token::BlockEnd(_) => {
+                if let Some(tag) = tag.take() {
+                    rv.push(' ');
+                    if !tag.rv.is_empty() {
+                        rv.push_str(&tag.rv);
+                        rv.push(' ');
+                    }
+                }
+                rv.push_str(text);
+            }
+            token => match tag {
+                Some(ref mut tag) => tag.push(token, text),
+                None => rv.push_str(text),
+            },
+        }
+    }
+
+    if let Some(tag) = tag {
+        let mut err = Error::new(
+            ErrorKind::SyntaxError,
+            format!(
+                "unexpected end of input, expected end of {}",
+                if tag.is_block {
+                    "block"
+                } else {
+                    "variable block"
+                }
+            ),
+        );
+        err.set_filename_and_span("<string>", last_span);
+        return Err(err);
+    }
+
+    Ok(rv)
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 #[cfg(feature = "internal_debug")]
 use std::fmt;
 
@@ -14,6 +15,22 @@ pub const LOOP_FLAG_WITH_LOOP_VAR: u8 = 1;
 /// This loop is recursive.
 pub const LOOP_FLAG_RECURSIVE: u8 = 2;
 
+/// This macro references itself.
+#[cfg(feature = "macros")]
+pub const MACRO_FLAG_SELF_REFERENCE: u8 = 1;
+
+/// This macro accepts a `caller` from a call block.
+#[cfg(feature = "macros")]
+pub const MACRO_FLAG_CALLER: u8 = 2;
+
+/// This macro collects surplus positional arguments in `varargs`.
+#[cfg(feature = "macros")]
+pub const MACRO_FLAG_VARARGS: u8 = 4;
+
+/// This macro collects unknown keyword arguments in `kwargs`.
+#[cfg(feature = "macros")]
+pub const MACRO_FLAG_KWARGS: u8 = 8;
+
 /// Rust type to represent locals.
 pub type LocalId = u8;
 
@@ -25,7 +42,7 @@ pub const MAX_LOCALS: usize = 50;
 #[derive(Clone)]
 pub enum Instruction<'source> {
     /// Emits raw source
-    EmitRaw(&'source str),
+    EmitRaw(Cow<'source, str>),
 
     /// Stores a variable (only possible in for loops)
     StoreLocal(&'source str),
@@ -121,6 +138,9 @@ pub enum Instruction<'source> {
     /// Perform a filter.
     PerformTest(&'source str, usize, LocalId),
 
+    /// Invokes a custom tag with the captured body and arguments.
+    CallTag(&'source str, usize),
+
     /// Emit the stack top as output
     Emit,
 
@@ -132,6 +152,12 @@ pub enum Instruction<'source> {
     /// Starts a with block.
     PushWith,
 
+    /// Starts a with block that hides all outer frames.
+    ///
+    /// This is used for imports without context.
+    #[cfg(feature = "multi-template")]
+    PushIsolatedWith,
+
     /// Does a single loop iteration
     ///
     /// The argument is the jump target for when the loop
@@ -156,6 +182,14 @@ pub enum Instruction<'source> {
     /// Jump if the stack top evaluates to true or pops the value
     JumpIfTrueOrPop(usize),
 
+    /// Jump if the stack top is neither undefined nor none or pops the value
+    #[cfg(feature = "builtins")]
+    JumpIfDefinedOrPop(usize),
+
+    /// Jumps if the filter with the given name is still the built-in one.
+    #[cfg(feature = "builtins")]
+    JumpIfBuiltinFilter(&'source str, usize),
+
     /// Sets the auto escape flag to the current value.
     PushAutoEscape,
 
@@ -193,6 +227,10 @@ pub enum Instruction<'source> {
     #[cfg(feature = "multi-template")]
     CallBlock(&'source str),
 
+    /// Fails because a required block was not overridden.
+    #[cfg(feature = "multi-template")]
+    RequiredBlock(&'source str),
+
     /// Loads block from a template with name on stack ("extends")
     #[cfg(feature = "multi-template")]
     LoadBlocks,
@@ -209,12 +247,29 @@ pub enum Instruction<'source> {
     #[cfg(feature = "multi-template")]
     ExportLocals,
 
+    /// Loads a name exported by an imported template.
+    ///
+    /// The name of the imported template is expected on the stack and is
+    /// kept there (above the loaded value) for the next import.
+    #[cfg(feature = "multi-template")]
+    ImportName(&'source str),
+
     /// Builds a macro on the stack.
+    ///
+    /// The arguments are the name, the offset of the macro body and
+    /// the macro flags.
     #[cfg(feature = "macros")]
-    BuildMacro(&'source str, usize, bool),
+    BuildMacro(&'source str, usize, u8),
+
+    /// Evaluates the instructions up to the next `Return` and pushes the
+    /// result.  If the evaluation fails undefined is pushed instead.
+    ///
+    /// The argument is the jump target to continue with afterwards.
+    #[cfg(feature = "builtins")]
+    CatchErrors(usize),
 
     /// Breaks from the interpreter loop (exists a function)
-    #[cfg(feature = "macros")]
+    #[cfg(any(feature = "macros", feature = "builtins"))]
     Return,
 
     /// True if the value is undefined
@@ -384,6 +439,8 @@ impl<'source> Instructions<'source> {
                 | Instruction::CallFunction(name, _) => *name,
                 Instruction::PushLoop(flags) if flags & LOOP_FLAG_WITH_LOOP_VAR != 0 => "loop",
                 Instruction::PushLoop(_) | Instruction::PushWith => break,
+                #[cfg(feature = "multi-template")]
+                Instruction::PushIsolatedWith => break,
                 _ => continue,
             };
             if !rv.contains(&name) {
@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
 use crate::compiler::tokens::{Span, Token};
 use crate::error::{Error, ErrorKind};
 use crate::utils::{memchr, memstr, unescape};
@@ -5,6 +8,147 @@ use crate::utils::{memchr, memstr, unescape};
 #[cfg(test)]
 use similar_asserts::assert_eq;
 
+/// Controls the whitespace handling of the lexer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WhitespaceConfig {
+    /// Strips spaces and tabs from the start of a line to a block tag.
+    pub lstrip_blocks: bool,
+    /// Removes the first newline after a block tag.
+    pub trim_blocks: bool,
+    /// Keeps the final newline of a template.
+    pub keep_trailing_newline: bool,
+    /// Rewrites the newlines in template data.
+    pub newline_mode: NewlineMode,
+}
+
+/// Controls how newlines in template data are emitted.
+///
+/// The newline mode only applies to the literal text of a template.  The
+/// output of expressions is never rewritten.  It can be configured with
+/// [`Environment::set_newline_mode`](crate::Environment::set_newline_mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewlineMode {
+    /// Newlines are emitted as they appear in the template source.
+    Preserve,
+    /// `\r\n` and `\r` are normalized to `\n`.
+    Lf,
+    /// `\n` and `\r` are normalized to `\r\n`.
+    CrLf,
+    /// `\r\n` and `\n` are normalized to `\r`.
+    Cr,
+}
+
+impl Default for NewlineMode {
+    fn default() -> NewlineMode {
+        NewlineMode::Preserve
+    }
+}
+
+impl NewlineMode {
+    fn sequence(self) -> Option<&'static str> {
+        match self {
+            NewlineMode::Preserve => None,
+            NewlineMode::Lf => Some("\n"),
+            NewlineMode::CrLf => Some("\r\n"),
+            NewlineMode::Cr => Some("\r"),
+        }
+    }
+}
+
+/// The delimiters of the template syntax.
+///
+/// The default is the syntax known from Jinja2 (`{% %}` for blocks,
+/// `{{ }}` for variables and `{# #}` for comments).  A different syntax
+/// can be configured with [`Environment::set_syntax`](crate::Environment::set_syntax)
+/// which is useful when generating files in languages which themselves
+/// use curly braces such as LaTeX:
+///
+/// ```
+/// # use minijinja::{Environment, Syntax, context};
+/// let mut env = Environment::new();
+/// env.set_syntax(Syntax {
+///     block_start: "((*".into(),
+///     block_end: "*))".into(),
+///     variable_start: "(((".into(),
+///     variable_end: ")))".into(),
+///     comment_start: "((=".into(),
+///     comment_end: "=))".into(),
+/// }).unwrap();
+/// let rv = env.render_str(
+///     r"\section{(((title)))}((* if draft *)) \draft((* endif *))",
+///     context!(title => "Intro", draft => true),
+/// ).unwrap();
+/// assert_eq!(rv, r"\section{Intro} \draft");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Syntax {
+    /// The start of a block, defaults to `{%`.
+    pub block_start: Cow<'static, str>,
+    /// The end of a block, defaults to `%}`.
+    pub block_end: Cow<'static, str>,
+    /// The start of a variable, defaults to `{{`.
+    pub variable_start: Cow<'static, str>,
+    /// The end of a variable, defaults to `}}`.
+    pub variable_end: Cow<'static, str>,
+    /// The start of a comment, defaults to `{#`.
+    pub comment_start: Cow<'static, str>,
+    /// The end of a comment, defaults to `#}`.
+    pub comment_end: Cow<'static, str>,
+}
+
+impl Default for Syntax {
+    fn default() -> Syntax {
+        Syntax {
+            block_start: "{%".into(),
+            block_end: "%}".into(),
+            variable_start: "{{".into(),
+            variable_end: "}}".into(),
+            comment_start: "{#".into(),
+            comment_end: "#}".into(),
+        }
+    }
+}
+
+impl Syntax {
+    /// Checks that the delimiters can be told apart by the lexer.
+    ///
+    /// All delimiters have to be non empty and none of the start
+    /// delimiters may be a prefix of another start delimiter.
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let delimiters = [
+            ("block_start", &self.block_start),
+            ("block_end", &self.block_end),
+            ("variable_start", &self.variable_start),
+            ("variable_end", &self.variable_end),
+            ("comment_start", &self.comment_start),
+            ("comment_end", &self.comment_end),
+        ];
+        for (name, delimiter) in delimiters {
+            if delimiter.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("{} delimiter must not be empty", name),
+                ));
+            }
+        }
+        let starts = [delimiters[0], delimiters[2], delimiters[4]];
+        for (idx, (name, delimiter)) in starts.iter().enumerate() {
+            for (other_name, other) in &starts[idx + 1..] {
+                if delimiter.starts_with(&***other) || other.starts_with(&***delimiter) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!(
+                            "{} delimiter `{}` conflicts with {} delimiter `{}`",
+                            name, delimiter, other_name, other
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 enum LexerState {
     Template,
     InVariable,
@@ -17,18 +161,33 @@ struct TokenizerState<'s> {
     failed: bool,
     current_line: usize,
     current_col: usize,
+    ws_config: WhitespaceConfig,
 }
 
 #[inline(always)]
-fn find_marker(a: &str) -> Option<usize> {
+fn find_marker(a: &str, syntax: &Syntax) -> Option<usize> {
     let bytes = a.as_bytes();
+    let starts = [
+        syntax.block_start.as_bytes(),
+        syntax.variable_start.as_bytes(),
+        syntax.comment_start.as_bytes(),
+    ];
+    let first = starts[0][0];
+    let same_first = starts.iter().all(|x| x[0] == first);
     let mut offset = 0;
     loop {
-        let idx = match memchr(&bytes[offset..], b'{') {
+        let idx = if same_first {
+            memchr(&bytes[offset..], first)
+        } else {
+            bytes[offset..]
+                .iter()
+                .position(|c| starts.iter().any(|x| x[0] == *c))
+        };
+        let idx = match idx {
             Some(idx) => idx,
             None => return None,
         };
-        if let Some(b'{' | b'%' | b'#') = bytes.get(offset + idx + 1).copied() {
+        if starts.iter().any(|x| bytes[offset + idx..].starts_with(x)) {
             return Some(offset + idx);
         }
         offset += idx + 1;
@@ -73,10 +232,10 @@ fn lex_identifier(s: &str) -> usize {
         .count()
 }
 
-fn skip_basic_tag(block_str: &str, name: &str) -> Option<usize> {
+fn skip_basic_tag(block_str: &str, name: &str, block_end: &str) -> Option<usize> {
     let mut ptr = block_str;
 
-    if let Some(rest) = ptr.strip_prefix('-') {
+    if let Some(rest) = ptr.strip_prefix(|x| x == '-' || x == '+') {
         ptr = rest;
     }
     while let Some(rest) = ptr.strip_prefix(|x: char| x.is_ascii_whitespace()) {
@@ -91,10 +250,10 @@ fn skip_basic_tag(block_str: &str, name: &str) -> Option<usize> {
     while let Some(rest) = ptr.strip_prefix(|x: char| x.is_ascii_whitespace()) {
         ptr = rest;
     }
-    if let Some(rest) = ptr.strip_prefix('-') {
+    if let Some(rest) = ptr.strip_prefix(|x| x == '-' || x == '+') {
         ptr = rest;
     }
-    ptr = match ptr.strip_prefix("%}") {
+    ptr = match ptr.strip_prefix(block_end) {
         Some(ptr) => ptr,
         None => return None,
     };
@@ -118,6 +277,15 @@ impl<'s> TokenizerState<'s> {
         skipped
     }
 
+    /// Skips the newline after a block tag if `trim_blocks` is enabled.
+    fn trim_newline(&mut self) {
+        if self.ws_config.trim_blocks {
+            if let Some(newline) = ["\r\n", "\n"].iter().find(|x| self.rest.starts_with(*x)) {
+                self.advance(newline.len());
+            }
+        }
+    }
+
     #[inline(always)]
     fn loc(&self) -> (usize, usize) {
         (self.current_line, self.current_col)
@@ -133,7 +301,7 @@ impl<'s> TokenizerState<'s> {
         }
     }
 
-    fn syntax_error(&mut self, msg: &'static str) -> Error {
+    fn syntax_error<D: Into<Cow<'static, str>>>(&mut self, msg: D) -> Error {
         self.failed = true;
         Error::new(ErrorKind::SyntaxError, msg)
     }
@@ -227,9 +395,11 @@ impl<'s> TokenizerState<'s> {
 }
 
 /// Tokenizes without whitespace handling.
-fn tokenize_raw(
+pub(crate) fn tokenize_raw(
     input: &str,
     in_expr: bool,
+    ws_config: WhitespaceConfig,
+    syntax: Arc<Syntax>,
 ) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
     let mut state = TokenizerState {
         rest: input,
@@ -241,6 +411,7 @@ fn tokenize_raw(
         failed: false,
         current_line: 1,
         current_col: 0,
+        ws_config,
     };
 
     std::iter::from_fn(move || loop {
@@ -251,64 +422,81 @@ fn tokenize_raw(
         let old_loc = state.loc();
         match state.stack.last() {
             Some(LexerState::Template) => {
-                match state.rest.get(..2) {
-                    Some("{{") => {
-                        let ws = if state.rest.as_bytes().get(2) == Some(&b'-') {
-                            state.advance(3);
-                            true
-                        } else {
-                            state.advance(2);
-                            false
-                        };
-                        state.stack.push(LexerState::InVariable);
-                        return Some(Ok((Token::VariableStart(ws), state.span(old_loc))));
-                    }
-                    Some("{%") => {
-                        // raw blocks require some special handling.  If we are at the beginning of a raw
-                        // block we want to skip everything until {% endraw %} completely ignoring iterior
-                        // syntax and emit the entire raw block as TemplateData.
-                        if let Some(mut ptr) = skip_basic_tag(&state.rest[2..], "raw") {
-                            ptr += 2;
-                            while let Some(block) = memstr(&state.rest.as_bytes()[ptr..], b"{%") {
-                                ptr += block + 2;
-                                if let Some(endraw) = skip_basic_tag(&state.rest[ptr..], "endraw") {
-                                    let result = &state.rest[..ptr + endraw];
-                                    state.advance(ptr + endraw);
-                                    return Some(Ok((
-                                        Token::TemplateData(result),
-                                        state.span(old_loc),
-                                    )));
+                if let Some(after) = state.rest.strip_prefix(&*syntax.variable_start) {
+                    let ws = after.starts_with('-');
+                    state.advance(syntax.variable_start.len() + ws as usize);
+                    state.stack.push(LexerState::InVariable);
+                    return Some(Ok((Token::VariableStart(ws), state.span(old_loc))));
+                }
+
+                if let Some(after) = state.rest.strip_prefix(&*syntax.block_start) {
+                    // raw blocks require some special handling.  If we are at the beginning of a raw
+                    // block we want to skip everything until {% endraw %} completely ignoring iterior
+                    // syntax and emit the entire raw block as TemplateData.
+                    if let Some(mut ptr) = skip_basic_tag(after, "raw", &syntax.block_end) {
+                        ptr += syntax.block_start.len();
+                        while let Some(block) =
+                            memstr(&state.rest.as_bytes()[ptr..], syntax.block_start.as_bytes())
+                        {
+                            ptr += block + syntax.block_start.len();
+                            if let Some(endraw) =
+                                skip_basic_tag(&state.rest[ptr..], "endraw", &syntax.block_end)
+                            {
+                                let result = state.advance(ptr + endraw);
+                                let keep_newline =
+                                    result[..result.len() - syntax.block_end.len()].ends_with('+');
+                                if !keep_newline {
+                                    state.trim_newline();
                                 }
+                                return Some(Ok((
+                                    Token::TemplateData(Cow::Borrowed(result)),
+                                    state.span(old_loc),
+                                )));
                             }
-                            return Some(Err(state.syntax_error("unexpected end of raw block")));
                         }
+                        return Some(Err(state.syntax_error(format!(
+                            "unexpected end of raw block, expected `{} endraw {}`",
+                            syntax.block_start, syntax.block_end
+                        ))));
+                    }
 
-                        let ws = if state.rest.as_bytes().get(2) == Some(&b'-') {
-                            state.advance(3);
-                            true
-                        } else {
-                            state.advance(2);
-                            false
-                        };
+                    // `{%-` strips whitespace before the tag, `{%+` only
+                    // disables `lstrip_blocks` which is handled below.
+                    let ws = after.starts_with('-');
+                    let control = ws || after.starts_with('+');
+                    state.advance(syntax.block_start.len() + control as usize);
+                    state.stack.push(LexerState::InBlock);
+                    return Some(Ok((Token::BlockStart(ws), state.span(old_loc))));
+                }
 
-                        state.stack.push(LexerState::InBlock);
-                        return Some(Ok((Token::BlockStart(ws), state.span(old_loc))));
+                if let Some(after) = state.rest.strip_prefix(&*syntax.comment_start) {
+                    if let Some(comment_end) =
+                        memstr(after.as_bytes(), syntax.comment_end.as_bytes())
+                    {
+                        state.advance(
+                            syntax.comment_start.len() + comment_end + syntax.comment_end.len(),
+                        );
+                        state.trim_newline();
+                    } else {
+                        return Some(Err(state.syntax_error(format!(
+                            "unexpected end of comment, expected `{}`",
+                            syntax.comment_end
+                        ))));
                     }
-                    Some("{#") => {
-                        if let Some(comment_end) = memstr(state.rest.as_bytes(), b"#}") {
-                            state.advance(comment_end + 2);
-                        } else {
-                            return Some(Err(state.syntax_error("unexpected end of comment")));
-                        }
-                    }
-                    _ => {}
                 }
 
-                let lead = match find_marker(state.rest) {
+                let at_line_start = state.current_col == 0;
+                let mut lead = match find_marker(state.rest, &syntax) {
                     Some(start) => state.advance(start),
                     None => state.advance(state.rest.len()),
                 };
-                return Some(Ok((Token::TemplateData(lead), state.span(old_loc))));
+                if state.ws_config.lstrip_blocks {
+                    lead = lstrip_block_lead(lead, at_line_start, state.rest, &syntax);
+                }
+                return Some(Ok((
+                    Token::TemplateData(Cow::Borrowed(lead)),
+                    state.span(old_loc),
+                )));
             }
             Some(LexerState::InBlock | LexerState::InVariable) => {
                 // in blocks whitespace is generally ignored, skip it.
@@ -330,26 +518,41 @@ fn tokenize_raw(
                 }
 
                 // look out for the end of blocks
+                let ends_with_control = |control: char, end: &str| {
+                    state
+                        .rest
+                        .strip_prefix(control)
+                        .map_or(false, |x| x.starts_with(end))
+                };
                 if let Some(&LexerState::InBlock) = state.stack.last() {
-                    if let Some("-%}") = state.rest.get(..3) {
+                    let end = &*syntax.block_end;
+                    if ends_with_control('-', end) {
                         state.stack.pop();
-                        state.advance(3);
+                        state.advance(end.len() + 1);
                         return Some(Ok((Token::BlockEnd(true), state.span(old_loc))));
                     }
-                    if let Some("%}") = state.rest.get(..2) {
+                    if ends_with_control('+', end) {
                         state.stack.pop();
-                        state.advance(2);
+                        state.advance(end.len() + 1);
                         return Some(Ok((Token::BlockEnd(false), state.span(old_loc))));
                     }
+                    if state.rest.starts_with(end) {
+                        state.stack.pop();
+                        state.advance(end.len());
+                        let span = state.span(old_loc);
+                        state.trim_newline();
+                        return Some(Ok((Token::BlockEnd(false), span)));
+                    }
                 } else {
-                    if let Some("-}}") = state.rest.get(..3) {
+                    let end = &*syntax.variable_end;
+                    if ends_with_control('-', end) {
                         state.stack.pop();
-                        state.advance(3);
+                        state.advance(end.len() + 1);
                         return Some(Ok((Token::VariableEnd(true), state.span(old_loc))));
                     }
-                    if let Some("}}") = state.rest.get(..2) {
+                    if state.rest.starts_with(end) {
                         state.stack.pop();
-                        state.advance(2);
+                        state.advance(end.len());
                         return Some(Ok((Token::VariableEnd(false), state.span(old_loc))));
                     }
                 }
@@ -412,16 +615,76 @@ fn tokenize_raw(
     })
 }
 
-/// Automatically removes whitespace around blocks.
+/// Strips the indentation in front of a block tag or comment for `lstrip_blocks`.
+///
+/// The indentation is only removed if the tag is the first thing on its line,
+/// inline tags such as `text {% if x %}` keep their whitespace.
+fn lstrip_block_lead<'s>(
+    lead: &'s str,
+    at_line_start: bool,
+    rest: &str,
+    syntax: &Syntax,
+) -> &'s str {
+    let after_tag = match rest
+        .strip_prefix(&*syntax.block_start)
+        .or_else(|| rest.strip_prefix(&*syntax.comment_start))
+    {
+        Some(after_tag) => after_tag,
+        None => return lead,
+    };
+    if after_tag.starts_with('+') || after_tag.starts_with('-') {
+        return lead;
+    }
+    let line_start = match lead.rfind('\n') {
+        Some(idx) => idx + 1,
+        None if at_line_start => 0,
+        None => return lead,
+    };
+    if lead[line_start..].bytes().all(|c| c == b' ' || c == b'\t') {
+        &lead[..line_start]
+    } else {
+        lead
+    }
+}
+
+/// Rewrites all newlines in `data` to the newline sequence of `mode`.
+fn normalize_newlines(data: &str, mode: NewlineMode) -> Cow<'_, str> {
+    let is_newline = |c| c == '\r' || c == '\n';
+    let sequence = match mode.sequence() {
+        Some(sequence) if data.contains(is_newline) => sequence,
+        _ => return Cow::Borrowed(data),
+    };
+    let mut rv = String::with_capacity(data.len());
+    let mut rest = data;
+    while let Some(idx) = rest.find(is_newline) {
+        rv.push_str(&rest[..idx]);
+        rv.push_str(sequence);
+        let newline_len = if rest[idx..].starts_with("\r\n") {
+            2
+        } else {
+            1
+        };
+        rest = &rest[idx + newline_len..];
+    }
+    rv.push_str(rest);
+    if rv == data {
+        Cow::Borrowed(data)
+    } else {
+        Cow::Owned(rv)
+    }
+}
+
+/// Automatically removes whitespace around blocks and normalizes newlines.
 fn whitespace_filter<'a, I: Iterator<Item = Result<(Token<'a>, Span), Error>>>(
     iter: I,
+    newline_mode: NewlineMode,
 ) -> impl Iterator<Item = Result<(Token<'a>, Span), Error>> {
     let mut iter = iter.peekable();
     let mut remove_leading_ws = false;
     // TODO: this does not update spans
     std::iter::from_fn(move || loop {
         return match iter.next() {
-            Some(Ok((Token::TemplateData(mut data), span))) => {
+            Some(Ok((Token::TemplateData(Cow::Borrowed(mut data)), span))) => {
                 if remove_leading_ws {
                     remove_leading_ws = false;
                     data = data.trim_start();
@@ -440,6 +703,7 @@ fn whitespace_filter<'a, I: Iterator<Item = Result<(Token<'a>, Span), Error>>>(
                 if data.is_empty() {
                     continue;
                 }
+                let data = normalize_newlines(data, newline_mode);
                 Some(Ok((Token::TemplateData(data), span)))
             }
             rv @ Some(Ok((Token::VariableEnd(true) | Token::BlockEnd(true), _))) => {
@@ -458,37 +722,54 @@ fn whitespace_filter<'a, I: Iterator<Item = Result<(Token<'a>, Span), Error>>>(
 pub fn tokenize(
     input: &str,
     in_expr: bool,
+    ws_config: WhitespaceConfig,
+    syntax: Arc<Syntax>,
 ) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
-    whitespace_filter(tokenize_raw(input, in_expr))
+    whitespace_filter(
+        tokenize_raw(input, in_expr, ws_config, syntax),
+        ws_config.newline_mode,
+    )
 }
 
 #[test]
 fn test_find_marker() {
-    assert!(find_marker("{").is_none());
-    assert!(find_marker("foo").is_none());
-    assert!(find_marker("foo {").is_none());
-    assert_eq!(find_marker("foo {{"), Some(4));
+    let syntax = Syntax::default();
+    assert!(find_marker("{", &syntax).is_none());
+    assert!(find_marker("foo", &syntax).is_none());
+    assert!(find_marker("foo {", &syntax).is_none());
+    assert_eq!(find_marker("foo {{", &syntax), Some(4));
+
+    let syntax = Syntax {
+        block_start: "<%".into(),
+        variable_start: "((".into(),
+        ..Syntax::default()
+    };
+    assert!(find_marker("foo {{ <", &syntax).is_none());
+    assert_eq!(find_marker("foo ( ((", &syntax), Some(6));
+    assert_eq!(find_marker("foo < {# <%", &syntax), Some(6));
 }
 
 #[test]
 fn test_is_basic_tag() {
-    assert_eq!(skip_basic_tag(" raw %}", "raw"), Some(7));
-    assert_eq!(skip_basic_tag(" raw %}", "endraw"), None);
-    assert_eq!(skip_basic_tag("  raw  %}", "raw"), Some(9));
-    assert_eq!(skip_basic_tag("-  raw  -%}", "raw"), Some(11));
+    assert_eq!(skip_basic_tag(" raw %}", "raw", "%}"), Some(7));
+    assert_eq!(skip_basic_tag(" raw %}", "endraw", "%}"), None);
+    assert_eq!(skip_basic_tag("  raw  %}", "raw", "%}"), Some(9));
+    assert_eq!(skip_basic_tag("-  raw  -%}", "raw", "%}"), Some(11));
+    assert_eq!(skip_basic_tag(" raw %>", "raw", "%>"), Some(7));
 }
 
 #[test]
 fn test_basic_identifiers() {
     fn assert_ident(s: &str) {
-        match tokenize_raw(s, true).next() {
+        match tokenize_raw(s, true, WhitespaceConfig::default(), Arc::default()).next() {
             Some(Ok((Token::Ident(ident), _))) if ident == s => {}
             _ => panic!("did not get a matching token result: {:?}", s),
         }
     }
 
     fn assert_not_ident(s: &str) {
-        let res = tokenize_raw(s, true).collect::<Result<Vec<_>, _>>();
+        let res = tokenize_raw(s, true, WhitespaceConfig::default(), Arc::default())
+            .collect::<Result<Vec<_>, _>>();
         if let Ok(tokens) = res {
             if let &[(Token::Ident(_), _)] = &tokens[..] {
                 panic!("got a single ident for {:?}", s)
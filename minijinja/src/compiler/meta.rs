@@ -1,19 +1,36 @@
 use std::collections::HashSet;
+use std::fmt::Write;
 
 use crate::compiler::ast;
 
 struct AssignmentTracker<'a> {
     out: HashSet<&'a str>,
+    nested_out: Option<HashSet<String>>,
     assigned: Vec<HashSet<&'a str>>,
+    declared: HashSet<&'a str>,
 }
 
 impl<'a> AssignmentTracker<'a> {
+    fn new(track_nested: bool) -> AssignmentTracker<'a> {
+        AssignmentTracker {
+            out: HashSet::new(),
+            nested_out: if track_nested {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+            assigned: vec![Default::default()],
+            declared: HashSet::new(),
+        }
+    }
+
     fn is_assigned(&self, name: &str) -> bool {
         self.assigned.iter().any(|x| x.contains(name))
     }
 
     fn assign(&mut self, name: &'a str) {
         self.assigned.last_mut().unwrap().insert(name);
+        self.declared.insert(name);
     }
 
     fn push(&mut self) {
@@ -23,163 +40,339 @@ impl<'a> AssignmentTracker<'a> {
     fn pop(&mut self) {
         self.assigned.pop();
     }
-}
 
-/// Finds all variables that need to be captured as closure for a macro.
-pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
-    fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
-        if let Some(expr) = expr {
-            visit_expr(expr, state);
+    fn reference(&mut self, name: &'a str) {
+        if !self.is_assigned(name) {
+            self.out.insert(name);
+            if let Some(ref mut nested_out) = self.nested_out {
+                nested_out.insert(name.to_string());
+            }
         }
     }
+}
 
-    fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
-        match expr {
+/// Returns the attribute path of an expression if it is statically known.
+///
+/// This is the case for chains of attribute lookups and subscripts with
+/// string constants that start at a variable (`foo.bar["baz"]`).
+fn attribute_path<'a, 'b>(expr: &'b ast::Expr<'a>) -> Option<(&'a str, Vec<&'b str>)> {
+    let mut attrs = Vec::new();
+    let mut ptr = expr;
+    loop {
+        match ptr {
             ast::Expr::Var(var) => {
-                if !state.is_assigned(var.id) {
-                    state.out.insert(var.id);
-                    state.assign(var.id);
-                }
-            }
-            ast::Expr::Const(_) => {}
-            ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::BinOp(expr) => {
-                visit_expr(&expr.left, state);
-                visit_expr(&expr.right, state);
-            }
-            ast::Expr::IfExpr(expr) => {
-                visit_expr(&expr.test_expr, state);
-                visit_expr(&expr.true_expr, state);
-                visit_expr_opt(&expr.false_expr, state);
+                attrs.reverse();
+                return Some((var.id, attrs));
             }
-            ast::Expr::Filter(expr) => {
-                visit_expr_opt(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
+            ast::Expr::GetAttr(expr) => {
+                attrs.push(expr.name);
+                ptr = &expr.expr;
             }
-            ast::Expr::Test(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::GetAttr(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::GetItem(expr) => {
-                visit_expr(&expr.expr, state);
-                visit_expr(&expr.subscript_expr, state);
-            }
-            ast::Expr::Slice(slice) => {
-                visit_expr_opt(&slice.start, state);
-                visit_expr_opt(&slice.stop, state);
-                visit_expr_opt(&slice.step, state);
-            }
-            ast::Expr::Call(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
-            ast::Expr::Map(expr) => expr.keys.iter().zip(expr.values.iter()).for_each(|(k, v)| {
-                visit_expr(k, state);
-                visit_expr(v, state);
-            }),
-            ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
+            ast::Expr::GetItem(expr) => match expr.subscript_expr {
+                ast::Expr::Const(ref c) => {
+                    attrs.push(some!(c.value.as_str()));
+                    ptr = &expr.expr;
+                }
+                _ => return None,
+            },
+            _ => return None,
         }
     }
+}
 
-    fn assign_nested<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
-        match expr {
-            ast::Expr::Var(var) => state.assign(var.id),
-            ast::Expr::List(list) => list.items.iter().for_each(|x| assign_nested(x, state)),
-            _ => {}
+fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
+    if let Some(expr) = expr {
+        visit_expr(expr, state);
+    }
+}
+
+fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
+    match expr {
+        ast::Expr::Var(var) => state.reference(var.id),
+        ast::Expr::Const(_) => {}
+        ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
+        ast::Expr::BinOp(expr) => {
+            visit_expr(&expr.left, state);
+            visit_expr(&expr.right, state);
+        }
+        ast::Expr::IfExpr(expr) => {
+            visit_expr(&expr.test_expr, state);
+            visit_expr(&expr.true_expr, state);
+            visit_expr_opt(&expr.false_expr, state);
         }
+        ast::Expr::Filter(expr) => {
+            visit_expr_opt(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::Test(expr) => {
+            visit_expr(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::GetAttr(_) | ast::Expr::GetItem(_) => visit_lookup(expr, state),
+        ast::Expr::Slice(slice) => {
+            visit_expr(&slice.expr, state);
+            visit_expr_opt(&slice.start, state);
+            visit_expr_opt(&slice.stop, state);
+            visit_expr_opt(&slice.step, state);
+        }
+        ast::Expr::Call(expr) => visit_call(expr, state),
+        ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
+        ast::Expr::Map(expr) => expr.keys.iter().zip(expr.values.iter()).for_each(|(k, v)| {
+            visit_expr(k, state);
+            visit_expr(v, state);
+        }),
+        ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
     }
+}
 
-    fn walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
-        match node {
-            ast::Stmt::Template(stmt) => {
-                state.assign("self");
-                stmt.children.iter().for_each(|x| walk(x, state));
-            }
-            ast::Stmt::EmitExpr(expr) => visit_expr(&expr.expr, state),
-            ast::Stmt::EmitRaw(_) => {}
-            ast::Stmt::ForLoop(stmt) => {
-                state.push();
-                state.assign("loop");
-                visit_expr(&stmt.iter, state);
-                assign_nested(&stmt.target, state);
-                visit_expr_opt(&stmt.filter_expr, state);
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-                state.push();
-                stmt.else_body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::IfCond(stmt) => {
-                visit_expr(&stmt.expr, state);
-                state.push();
-                stmt.true_body.iter().for_each(|x| walk(x, state));
-                state.pop();
-                state.push();
-                stmt.false_body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::WithBlock(stmt) => {
-                state.push();
-                for (target, expr) in &stmt.assignments {
-                    assign_nested(target, state);
-                    visit_expr(expr, state);
-                }
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::Set(stmt) => {
-                assign_nested(&stmt.target, state);
-                visit_expr(&stmt.expr, state);
-            }
-            ast::Stmt::AutoEscape(stmt) => {
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::FilterBlock(stmt) => {
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::SetBlock(stmt) => {
-                assign_nested(&stmt.target, state);
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            #[cfg(feature = "multi-template")]
-            ast::Stmt::Block(stmt) => {
-                state.push();
-                state.assign("super");
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
+fn visit_call<'a>(call: &ast::Call<'a>, state: &mut AssignmentTracker<'a>) {
+    // for method calls only the object the method is invoked on is
+    // referenced, the method name is not part of the path.
+    match call.expr {
+        ast::Expr::GetAttr(ref attr) => visit_expr(&attr.expr, state),
+        ref other => visit_expr(other, state),
+    }
+    call.args.iter().for_each(|x| visit_expr(x, state));
+}
+
+fn visit_lookup<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
+    if let Some((name, attrs)) = attribute_path(expr) {
+        if state.is_assigned(name) {
+            return;
+        }
+        state.out.insert(name);
+        if let Some(ref mut nested_out) = state.nested_out {
+            let mut path = name.to_string();
+            for attr in attrs {
+                write!(path, ".{}", attr).unwrap();
             }
-            #[cfg(feature = "multi-template")]
-            ast::Stmt::Extends(_) | ast::Stmt::Include(_) => {}
-            #[cfg(feature = "multi-template")]
-            ast::Stmt::Import(stmt) => {
-                assign_nested(&stmt.name, state);
+            nested_out.insert(path);
+        }
+        return;
+    }
+    match expr {
+        ast::Expr::GetAttr(expr) => visit_expr(&expr.expr, state),
+        ast::Expr::GetItem(expr) => {
+            visit_expr(&expr.expr, state);
+            visit_expr(&expr.subscript_expr, state);
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn assign_nested<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
+    match expr {
+        ast::Expr::Var(var) => state.assign(var.id),
+        ast::Expr::List(list) => list.items.iter().for_each(|x| assign_nested(x, state)),
+        _ => {}
+    }
+}
+
+#[cfg(feature = "macros")]
+fn walk_macro<'a>(m: &ast::Macro<'a>, is_call_block: bool, state: &mut AssignmentTracker<'a>) {
+    state.push();
+    // the body of a call block refers to the caller of the surrounding
+    // macro, all other macros get their own caller.
+    if !is_call_block {
+        state.assign("caller");
+    }
+    state.assign("varargs");
+    state.assign("kwargs");
+    walk_macro_body(m, state);
+    state.pop();
+}
+
+/// Walks the arguments and body of a macro in the current scope.
+///
+/// Defaults are evaluated when the macro is called, so they can refer to
+/// the arguments and need to be captured like the body.
+#[cfg(feature = "macros")]
+fn walk_macro_body<'a>(m: &ast::Macro<'a>, state: &mut AssignmentTracker<'a>) {
+    m.args.iter().for_each(|arg| assign_nested(arg, state));
+    m.defaults.iter().for_each(|x| visit_expr(x, state));
+    m.body.iter().for_each(|node| walk(node, state));
+}
+
+fn walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
+    match node {
+        ast::Stmt::Template(stmt) => {
+            state.assign("self");
+            stmt.children.iter().for_each(|x| walk(x, state));
+        }
+        ast::Stmt::EmitExpr(expr) => visit_expr(&expr.expr, state),
+        ast::Stmt::EmitRaw(_) => {}
+        ast::Stmt::ForLoop(stmt) => {
+            visit_expr(&stmt.iter, state);
+            state.push();
+            state.assign("loop");
+            assign_nested(&stmt.target, state);
+            visit_expr_opt(&stmt.filter_expr, state);
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+            state.push();
+            stmt.else_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::IfCond(stmt) => {
+            visit_expr(&stmt.expr, state);
+            state.push();
+            stmt.true_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+            state.push();
+            stmt.false_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::WithBlock(stmt) => {
+            state.push();
+            for (target, expr) in &stmt.assignments {
+                visit_expr(expr, state);
+                assign_nested(target, state);
             }
-            #[cfg(feature = "multi-template")]
-            ast::Stmt::FromImport(stmt) => stmt.names.iter().for_each(|(arg, alias)| {
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::Set(stmt) => {
+            visit_expr(&stmt.expr, state);
+            assign_nested(&stmt.target, state);
+        }
+        ast::Stmt::AutoEscape(stmt) => {
+            visit_expr(&stmt.enabled, state);
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::Do(stmt) => visit_expr(&stmt.expr, state),
+        ast::Stmt::CustomTag(stmt) => {
+            stmt.args.iter().for_each(|x| visit_expr(x, state));
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::FilterBlock(stmt) => {
+            visit_expr(&stmt.filter, state);
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::SetBlock(stmt) => {
+            visit_expr_opt(&stmt.filter, state);
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+            assign_nested(&stmt.target, state);
+        }
+        #[cfg(feature = "multi-template")]
+        ast::Stmt::Block(stmt) => {
+            state.push();
+            state.assign("super");
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        #[cfg(feature = "multi-template")]
+        ast::Stmt::Extends(stmt) => visit_expr(&stmt.name, state),
+        #[cfg(feature = "multi-template")]
+        ast::Stmt::Include(stmt) => visit_expr(&stmt.name, state),
+        #[cfg(feature = "multi-template")]
+        ast::Stmt::Import(stmt) => {
+            visit_expr(&stmt.expr, state);
+            assign_nested(&stmt.name, state);
+        }
+        #[cfg(feature = "multi-template")]
+        ast::Stmt::FromImport(stmt) => {
+            visit_expr(&stmt.expr, state);
+            stmt.names.iter().for_each(|(arg, alias)| {
                 assign_nested(alias.as_ref().unwrap_or(arg), state);
-            }),
-            #[cfg(feature = "macros")]
-            ast::Stmt::Macro(stmt) => {
-                state.assign(stmt.name);
-            }
+            });
+        }
+        #[cfg(feature = "macros")]
+        ast::Stmt::Macro(stmt) => {
+            state.assign(stmt.name);
+            walk_macro(stmt, false, state);
+        }
+        #[cfg(feature = "macros")]
+        ast::Stmt::CallBlock(stmt) => {
+            visit_call(&stmt.call, state);
+            walk_macro(&stmt.macro_decl, true, state);
         }
     }
+}
 
-    let mut state = AssignmentTracker {
-        out: HashSet::new(),
-        assigned: vec![Default::default()],
-    };
+/// Finds all variables that need to be captured as closure for a macro.
+#[cfg(feature = "macros")]
+pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
+    let mut state = AssignmentTracker::new(false);
+    walk_macro_body(m, &mut state);
+    state.out
+}
 
-    m.args.iter().for_each(|arg| assign_nested(arg, &mut state));
-    m.body.iter().for_each(|node| walk(node, &mut state));
+/// Finds all variables that are referenced but not declared by a template.
+///
+/// If `track_nested` is enabled, statically known attribute paths are
+/// returned in dotted form (`foo.bar`) instead of just the variable name.
+pub fn find_undeclared(t: &ast::Stmt<'_>, track_nested: bool) -> HashSet<String> {
+    let mut state = AssignmentTracker::new(track_nested);
+    walk(t, &mut state);
+    match state.nested_out {
+        Some(nested_out) => nested_out,
+        None => state.out.into_iter().map(|x| x.to_string()).collect(),
+    }
+}
 
-    state.out
+/// Checks if a template declares a variable of the given name in any scope.
+pub fn declares_variable(t: &ast::Stmt<'_>, name: &str) -> bool {
+    let mut state = AssignmentTracker::new(false);
+    walk(t, &mut state);
+    state.declared.contains(name)
+}
+
+/// Finds all variables that are referenced by an expression.
+///
+/// See [`find_undeclared`] for the meaning of `track_nested`.
+pub fn find_undeclared_in_expr(expr: &ast::Expr<'_>, track_nested: bool) -> HashSet<String> {
+    let mut state = AssignmentTracker::new(track_nested);
+    visit_expr(expr, &mut state);
+    match state.nested_out {
+        Some(nested_out) => nested_out,
+        None => state.out.into_iter().map(|x| x.to_string()).collect(),
+    }
+}
+
+/// Checks if an expression is free of side effects.
+///
+/// This is the case if it does not call functions or methods and only uses
+/// filters and tests for which the given callbacks return `true`.  Variable
+/// lookups are not considered.
+pub fn is_pure_expr(
+    expr: &ast::Expr<'_>,
+    is_pure_filter: &dyn Fn(&str) -> bool,
+    is_pure_test: &dyn Fn(&str) -> bool,
+) -> bool {
+    let is_pure = |expr: &ast::Expr<'_>| is_pure_expr(expr, is_pure_filter, is_pure_test);
+    let is_pure_opt = |expr: &Option<ast::Expr<'_>>| expr.as_ref().map_or(true, is_pure);
+    match expr {
+        ast::Expr::Var(_) | ast::Expr::Const(_) => true,
+        ast::Expr::UnaryOp(expr) => is_pure(&expr.expr),
+        ast::Expr::BinOp(expr) => is_pure(&expr.left) && is_pure(&expr.right),
+        ast::Expr::IfExpr(expr) => {
+            is_pure(&expr.test_expr) && is_pure(&expr.true_expr) && is_pure_opt(&expr.false_expr)
+        }
+        ast::Expr::Filter(expr) => {
+            is_pure_filter(expr.name) && is_pure_opt(&expr.expr) && expr.args.iter().all(is_pure)
+        }
+        ast::Expr::Test(expr) => {
+            is_pure_test(expr.name) && is_pure(&expr.expr) && expr.args.iter().all(is_pure)
+        }
+        ast::Expr::GetAttr(expr) => is_pure(&expr.expr),
+        ast::Expr::GetItem(expr) => is_pure(&expr.expr) && is_pure(&expr.subscript_expr),
+        ast::Expr::Slice(slice) => {
+            is_pure(&slice.expr)
+                && is_pure_opt(&slice.start)
+                && is_pure_opt(&slice.stop)
+                && is_pure_opt(&slice.step)
+        }
+        ast::Expr::Call(_) => false,
+        ast::Expr::List(expr) => expr.items.iter().all(is_pure),
+        ast::Expr::Map(expr) => expr.keys.iter().chain(expr.values.iter()).all(is_pure),
+        ast::Expr::Kwargs(expr) => expr.pairs.iter().all(|(_, v)| is_pure(v)),
+    }
 }
@@ -2,9 +2,10 @@
 /// This module contains the internals of the compiler.
 pub mod ast;
 pub mod codegen;
+#[cfg(feature = "unstable_machinery")]
+pub mod formatter;
 pub mod instructions;
 pub mod lexer;
-#[cfg(feature = "macros")]
 pub mod meta;
 pub mod parser;
 pub mod tokens;
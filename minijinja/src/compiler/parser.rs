@@ -1,8 +1,9 @@
 use std::collections::BTreeSet;
 use std::fmt;
+use std::sync::Arc;
 
 use crate::compiler::ast::{self, Spanned};
-use crate::compiler::lexer::tokenize;
+use crate::compiler::lexer::{tokenize, Syntax, WhitespaceConfig};
 use crate::compiler::tokens::{Span, Token};
 use crate::error::{Error, ErrorKind};
 use crate::value::Value;
@@ -12,6 +13,38 @@ const RESERVED_NAMES: [&str; 8] = [
     "true", "True", "false", "False", "none", "None", "loop", "self",
 ];
 
+/// Block keywords understood by the parser itself.  Custom tags cannot use
+/// these names as the built-in statement would always win.
+pub(crate) const BUILTIN_STATEMENTS: [&str; 27] = [
+    "for",
+    "endfor",
+    "if",
+    "elif",
+    "else",
+    "endif",
+    "with",
+    "endwith",
+    "set",
+    "endset",
+    "autoescape",
+    "endautoescape",
+    "filter",
+    "endfilter",
+    "do",
+    "block",
+    "endblock",
+    "extends",
+    "include",
+    "import",
+    "from",
+    "macro",
+    "endmacro",
+    "call",
+    "endcall",
+    "raw",
+    "endraw",
+];
+
 macro_rules! syntax_error {
     ($msg:expr) => {{
         return Err(Error::new(ErrorKind::SyntaxError, $msg));
@@ -95,8 +128,14 @@ struct TokenStream<'a> {
 
 impl<'a> TokenStream<'a> {
     /// Tokenize a template
-    pub fn new(source: &'a str, in_expr: bool) -> TokenStream<'a> {
-        let mut iter = Box::new(tokenize(source, in_expr)) as Box<dyn Iterator<Item = _>>;
+    pub fn new(
+        source: &'a str,
+        in_expr: bool,
+        ws_config: WhitespaceConfig,
+        syntax: Arc<Syntax>,
+    ) -> TokenStream<'a> {
+        let mut iter =
+            Box::new(tokenize(source, in_expr, ws_config, syntax)) as Box<dyn Iterator<Item = _>>;
         let current = iter.next();
         TokenStream {
             iter,
@@ -154,6 +193,7 @@ struct Parser<'a> {
     #[allow(unused)]
     in_macro: bool,
     blocks: BTreeSet<&'a str>,
+    custom_tags: Arc<BTreeSet<String>>,
     depth: usize,
 }
 
@@ -215,11 +255,18 @@ macro_rules! with_recursion_guard {
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a str, in_expr: bool) -> Parser<'a> {
+    pub fn new(
+        source: &'a str,
+        in_expr: bool,
+        ws_config: WhitespaceConfig,
+        syntax: Arc<Syntax>,
+        custom_tags: Arc<BTreeSet<String>>,
+    ) -> Parser<'a> {
         Parser {
-            stream: TokenStream::new(source, in_expr),
+            stream: TokenStream::new(source, in_expr, ws_config, syntax),
             in_macro: false,
             blocks: BTreeSet::new(),
+            custom_tags,
             depth: 0,
         }
     }
@@ -649,6 +696,7 @@ impl<'a> Parser<'a> {
             Token::Ident("filter") => {
                 ast::Stmt::FilterBlock(respan!(ok!(self.parse_filter_block())))
             }
+            Token::Ident("do") => ast::Stmt::Do(respan!(ok!(self.parse_do()))),
             #[cfg(feature = "multi-template")]
             Token::Ident("block") => ast::Stmt::Block(respan!(ok!(self.parse_block()))),
             #[cfg(feature = "multi-template")]
@@ -661,6 +709,11 @@ impl<'a> Parser<'a> {
             Token::Ident("from") => ast::Stmt::FromImport(respan!(ok!(self.parse_from_import()))),
             #[cfg(feature = "macros")]
             Token::Ident("macro") => ast::Stmt::Macro(respan!(ok!(self.parse_macro()))),
+            #[cfg(feature = "macros")]
+            Token::Ident("call") => ast::Stmt::CallBlock(respan!(ok!(self.parse_call_block()))),
+            Token::Ident(name) if self.custom_tags.contains(name) => {
+                ast::Stmt::CustomTag(respan!(ok!(self.parse_custom_tag(name))))
+            }
             Token::Ident(name) => syntax_error!("unknown statement {}", name),
             token => syntax_error!("unknown {}, expected statement", token),
         })
@@ -836,10 +889,33 @@ impl<'a> Parser<'a> {
             syntax_error!("block '{}' defined twice", name);
         }
 
+        let mut scoped = false;
+        let mut required = false;
+        loop {
+            if !scoped && skip_token!(self, Token::Ident("scoped")) {
+                scoped = true;
+            } else if !required && skip_token!(self, Token::Ident("required")) {
+                required = true;
+            } else {
+                break;
+            }
+        }
+
         expect_token!(self, Token::BlockEnd(..), "end of block");
         let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident("endblock"))));
         ok!(self.stream.next());
 
+        if required
+            && !body
+                .iter()
+                .all(|node| matches!(node, ast::Stmt::EmitRaw(raw) if raw.raw.trim().is_empty()))
+        {
+            syntax_error!(
+                "required block '{}' can only contain comments and whitespace",
+                name
+            );
+        }
+
         if let Some((Token::Ident(trailing_name), _)) = ok!(self.stream.current()) {
             if *trailing_name != name {
                 syntax_error!(
@@ -851,7 +927,12 @@ impl<'a> Parser<'a> {
             ok!(self.stream.next());
         }
 
-        Ok(ast::Block { name, body })
+        Ok(ast::Block {
+            name,
+            scoped,
+            required,
+            body,
+        })
     }
     fn parse_auto_escape(&mut self) -> Result<ast::AutoEscape<'a>, Error> {
         let enabled = ok!(self.parse_expr());
@@ -895,6 +976,26 @@ impl<'a> Parser<'a> {
         Ok(ast::FilterBlock { filter, body })
     }
 
+    fn parse_do(&mut self) -> Result<ast::Do<'a>, Error> {
+        let expr = ok!(self.parse_expr());
+        Ok(ast::Do { expr })
+    }
+
+    fn parse_custom_tag(&mut self, name: &'a str) -> Result<ast::CustomTag<'a>, Error> {
+        let mut args = Vec::new();
+        while !matches_token!(self, Token::BlockEnd(_)) {
+            if !args.is_empty() {
+                expect_token!(self, Token::Comma, "comma");
+            }
+            args.push(ok!(self.parse_expr()));
+        }
+        expect_token!(self, Token::BlockEnd(..), "end of block");
+        let end_keyword = format!("end{}", name);
+        let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident(x) if *x == end_keyword)));
+        ok!(self.stream.next());
+        Ok(ast::CustomTag { name, args, body })
+    }
+
     #[cfg(feature = "multi-template")]
     fn parse_extends(&mut self) -> Result<ast::Extends<'a>, Error> {
         let name = ok!(self.parse_expr());
@@ -921,19 +1022,29 @@ impl<'a> Parser<'a> {
         let expr = ok!(self.parse_expr());
         expect_token!(self, Token::Ident("as"), "as");
         let name = ok!(self.parse_expr());
-        Ok(ast::Import { expr, name })
+        let with_context = ok!(self.parse_context_modifier()).unwrap_or(true);
+        Ok(ast::Import {
+            expr,
+            name,
+            with_context,
+        })
     }
 
     #[cfg(feature = "multi-template")]
     fn parse_from_import(&mut self) -> Result<ast::FromImport<'a>, Error> {
         let expr = ok!(self.parse_expr());
         let mut names = Vec::new();
+        let mut with_context = true;
         expect_token!(self, Token::Ident("import"), "import");
         loop {
             if matches_token!(self, Token::BlockEnd(_)) {
                 break;
             }
             if !names.is_empty() {
+                if let Some(value) = ok!(self.parse_context_modifier()) {
+                    with_context = value;
+                    break;
+                }
                 expect_token!(self, Token::Comma, "`,`");
             }
             if matches_token!(self, Token::BlockEnd(_)) {
@@ -947,15 +1058,32 @@ impl<'a> Parser<'a> {
             };
             names.push((name, alias));
         }
-        Ok(ast::FromImport { expr, names })
+        Ok(ast::FromImport {
+            expr,
+            names,
+            with_context,
+        })
+    }
+
+    #[cfg(feature = "multi-template")]
+    fn parse_context_modifier(&mut self) -> Result<Option<bool>, Error> {
+        let with_context = if skip_token!(self, Token::Ident("with")) {
+            true
+        } else if skip_token!(self, Token::Ident("without")) {
+            false
+        } else {
+            return Ok(None);
+        };
+        expect_token!(self, Token::Ident("context"), "context");
+        Ok(Some(with_context))
     }
 
     #[cfg(feature = "macros")]
-    fn parse_macro(&mut self) -> Result<ast::Macro<'a>, Error> {
-        let (name, _) = expect_token!(self, Token::Ident(name) => name, "identifier");
-        expect_token!(self, Token::ParenOpen, "`(`");
-        let mut args = Vec::new();
-        let mut defaults = Vec::new();
+    fn parse_macro_args_and_defaults(
+        &mut self,
+        args: &mut Vec<ast::Expr<'a>>,
+        defaults: &mut Vec<ast::Expr<'a>>,
+    ) -> Result<(), Error> {
         loop {
             if skip_token!(self, Token::ParenClose) {
                 break;
@@ -973,9 +1101,30 @@ impl<'a> Parser<'a> {
                 expect_token!(self, Token::Assign, "`=`");
             }
         }
+        Ok(())
+    }
+
+    #[cfg(feature = "macros")]
+    fn parse_macro(&mut self) -> Result<ast::Macro<'a>, Error> {
+        let (name, _) = expect_token!(self, Token::Ident(name) => name, "identifier");
+        expect_token!(self, Token::ParenOpen, "`(`");
+        let mut args = Vec::new();
+        let mut defaults = Vec::new();
+        ok!(self.parse_macro_args_and_defaults(&mut args, &mut defaults));
+        self.parse_macro_body(name, args, defaults, "endmacro")
+    }
+
+    #[cfg(feature = "macros")]
+    fn parse_macro_body(
+        &mut self,
+        name: &'a str,
+        args: Vec<ast::Expr<'a>>,
+        defaults: Vec<ast::Expr<'a>>,
+        end_keyword: &'static str,
+    ) -> Result<ast::Macro<'a>, Error> {
         expect_token!(self, Token::BlockEnd(..), "end of block");
         let old_in_macro = std::mem::replace(&mut self.in_macro, true);
-        let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident("endmacro"))));
+        let body = ok!(self.subparse(&|tok| matches!(tok, Token::Ident(x) if *x == end_keyword)));
         self.in_macro = old_in_macro;
         ok!(self.stream.next());
         Ok(ast::Macro {
@@ -986,6 +1135,25 @@ impl<'a> Parser<'a> {
         })
     }
 
+    #[cfg(feature = "macros")]
+    fn parse_call_block(&mut self) -> Result<ast::CallBlock<'a>, Error> {
+        let span = self.stream.last_span();
+        let mut args = Vec::new();
+        let mut defaults = Vec::new();
+        if skip_token!(self, Token::ParenOpen) {
+            ok!(self.parse_macro_args_and_defaults(&mut args, &mut defaults));
+        }
+        let call = match ok!(self.parse_expr()) {
+            ast::Expr::Call(call) => call,
+            _ => syntax_error!("expected call expression in call block"),
+        };
+        let macro_decl = ok!(self.parse_macro_body("caller", args, defaults, "endcall"));
+        Ok(ast::CallBlock {
+            call,
+            macro_decl: Spanned::new(macro_decl, self.stream.expand_span(span)),
+        })
+    }
+
     fn subparse(
         &mut self,
         end_check: &dyn Fn(&Token) -> bool,
@@ -1036,20 +1204,26 @@ impl<'a> Parser<'a> {
 pub fn parse<'source, 'name>(
     source: &'source str,
     filename: &'name str,
+    ws_config: WhitespaceConfig,
+    syntax: Arc<Syntax>,
+    custom_tags: Arc<BTreeSet<String>>,
 ) -> Result<ast::Stmt<'source>, Error> {
     // we want to chop off a single newline at the end.  This means that a template
     // by default does not end in a newline which is a useful property to allow
     // inline templates to work.  If someone wants a trailing newline the expectation
-    // is that the user adds it themselves for achieve consistency.
+    // is that the user adds it themselves for achieve consistency.  This can be
+    // disabled with the `keep_trailing_newline` setting.
     let mut source = source;
-    if source.ends_with('\n') {
-        source = &source[..source.len() - 1];
-    }
-    if source.ends_with('\r') {
-        source = &source[..source.len() - 1];
+    if !ws_config.keep_trailing_newline {
+        if source.ends_with('\n') {
+            source = &source[..source.len() - 1];
+        }
+        if source.ends_with('\r') {
+            source = &source[..source.len() - 1];
+        }
     }
 
-    let mut parser = Parser::new(source, false);
+    let mut parser = Parser::new(source, false, ws_config, syntax, custom_tags);
     parser.parse().map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span(filename, parser.stream.last_span())
@@ -1060,7 +1234,13 @@ pub fn parse<'source, 'name>(
 
 /// Parses an expression
 pub fn parse_expr(source: &str) -> Result<ast::Expr<'_>, Error> {
-    let mut parser = Parser::new(source, true);
+    let mut parser = Parser::new(
+        source,
+        true,
+        WhitespaceConfig::default(),
+        Arc::default(),
+        Arc::default(),
+    );
     parser.parse_expr().map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span("<expression>", parser.stream.last_span())
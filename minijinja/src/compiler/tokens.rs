@@ -1,10 +1,11 @@
+use std::borrow::Cow;
 use std::fmt;
 
 /// Represents a token in the stream.
 #[derive(Debug)]
 pub enum Token<'a> {
     /// Raw template data.
-    TemplateData(&'a str),
+    TemplateData(Cow<'a, str>),
     /// Variable block start (with or without whitespace removal).
     VariableStart(bool),
     /// Variable block start (with or without whitespace removal).
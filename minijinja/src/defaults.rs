@@ -38,6 +38,7 @@ pub fn default_auto_escape_callback(name: &str) -> AutoEscape {
 /// was requested.
 ///
 /// * [`Html`](AutoEscape::Html): performs HTML escaping
+/// * [`Xml`](AutoEscape::Xml): performs XML escaping
 #[cfg_attr(
     feature = "json",
     doc = r" * [`Json`](AutoEscape::Json): serializes values to JSON"
@@ -59,16 +60,37 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("upper".into(), BoxedFilter::new(filters::upper));
         rv.insert("title".into(), BoxedFilter::new(filters::title));
         rv.insert("replace".into(), BoxedFilter::new(filters::replace));
+        rv.insert("escape_xml".into(), BoxedFilter::new(filters::escape_xml));
         rv.insert("length".into(), BoxedFilter::new(filters::length));
         rv.insert("count".into(), BoxedFilter::new(filters::length));
         rv.insert("dictsort".into(), BoxedFilter::new(filters::dictsort));
+        rv.insert("sort".into(), BoxedFilter::new(filters::sort));
+        rv.insert("unique".into(), BoxedFilter::new(filters::unique));
+        rv.insert(
+            "unique_justseen".into(),
+            BoxedFilter::new(filters::unique_justseen),
+        );
+        rv.insert("select".into(), BoxedFilter::new(filters::select));
+        rv.insert("reject".into(), BoxedFilter::new(filters::reject));
+        rv.insert("selectattr".into(), BoxedFilter::new(filters::selectattr));
+        rv.insert("rejectattr".into(), BoxedFilter::new(filters::rejectattr));
+        rv.insert("pick".into(), BoxedFilter::new(filters::pick));
+        rv.insert("omit".into(), BoxedFilter::new(filters::omit));
         rv.insert("items".into(), BoxedFilter::new(filters::items));
+        rv.insert("keys".into(), BoxedFilter::new(filters::keys));
+        rv.insert("values".into(), BoxedFilter::new(filters::values));
         rv.insert("reverse".into(), BoxedFilter::new(filters::reverse));
         rv.insert("trim".into(), BoxedFilter::new(filters::trim));
+        rv.insert("truncate".into(), BoxedFilter::new(filters::truncate));
+        rv.insert("wordwrap".into(), BoxedFilter::new(filters::wordwrap));
+        rv.insert("indent".into(), BoxedFilter::new(filters::indent));
         rv.insert("join".into(), BoxedFilter::new(filters::join));
         rv.insert("default".into(), BoxedFilter::new(filters::default));
+        rv.insert("coalesce".into(), BoxedFilter::new(filters::coalesce));
         rv.insert("round".into(), BoxedFilter::new(filters::round));
         rv.insert("abs".into(), BoxedFilter::new(filters::abs));
+        rv.insert("int".into(), BoxedFilter::new(filters::int));
+        rv.insert("float".into(), BoxedFilter::new(filters::float));
         rv.insert("first".into(), BoxedFilter::new(filters::first));
         rv.insert("last".into(), BoxedFilter::new(filters::last));
         rv.insert("d".into(), BoxedFilter::new(filters::default));
@@ -76,17 +98,37 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("bool".into(), BoxedFilter::new(filters::bool));
         rv.insert("batch".into(), BoxedFilter::new(filters::batch));
         rv.insert("slice".into(), BoxedFilter::new(filters::slice));
+        rv.insert("windows".into(), BoxedFilter::new(filters::windows));
+        rv.insert("pairwise".into(), BoxedFilter::new(filters::pairwise));
+        rv.insert("enumerate".into(), BoxedFilter::new(filters::enumerate));
+        rv.insert("urlize".into(), BoxedFilter::new(filters::urlize));
         #[cfg(feature = "json")]
         {
             rv.insert("tojson".into(), BoxedFilter::new(filters::tojson));
+            #[cfg(feature = "deserialization")]
+            rv.insert("fromjson".into(), BoxedFilter::new(filters::fromjson));
         }
         #[cfg(feature = "urlencode")]
         {
             rv.insert("urlencode".into(), BoxedFilter::new(filters::urlencode));
         }
+        #[cfg(feature = "url")]
+        {
+            rv.insert("urlsplit".into(), BoxedFilter::new(filters::urlsplit));
+            rv.insert("parse_qs".into(), BoxedFilter::new(filters::parse_qs));
+        }
+        #[cfg(feature = "truncate_html")]
+        {
+            rv.insert(
+                "truncate_html".into(),
+                BoxedFilter::new(filters::truncate_html),
+            );
+        }
     }
 
-    rv
+    rv.into_iter()
+        .map(|(name, filter)| (name, filter.builtin()))
+        .collect()
 }
 
 pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
@@ -98,16 +140,33 @@ pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
         rv.insert("odd".into(), BoxedTest::new(tests::is_odd));
         rv.insert("even".into(), BoxedTest::new(tests::is_even));
         rv.insert("number".into(), BoxedTest::new(tests::is_number));
+        rv.insert("integer".into(), BoxedTest::new(tests::is_integer));
+        rv.insert("float".into(), BoxedTest::new(tests::is_float));
+        rv.insert("boolean".into(), BoxedTest::new(tests::is_boolean));
+        rv.insert("none".into(), BoxedTest::new(tests::is_none));
         rv.insert("string".into(), BoxedTest::new(tests::is_string));
         rv.insert("sequence".into(), BoxedTest::new(tests::is_sequence));
         rv.insert("mapping".into(), BoxedTest::new(tests::is_mapping));
+        rv.insert("iterable".into(), BoxedTest::new(tests::is_iterable));
+        rv.insert("callable".into(), BoxedTest::new(tests::is_callable));
         rv.insert(
             "startingwith".into(),
             BoxedTest::new(tests::is_startingwith),
         );
         rv.insert("endingwith".into(), BoxedTest::new(tests::is_endingwith));
+        rv.insert("eq".into(), BoxedTest::new(tests::is_eq));
+        rv.insert("equalto".into(), BoxedTest::new(tests::is_eq));
+        rv.insert("ne".into(), BoxedTest::new(tests::is_ne));
+        rv.insert("lt".into(), BoxedTest::new(tests::is_lt));
+        rv.insert("lessthan".into(), BoxedTest::new(tests::is_lt));
+        rv.insert("le".into(), BoxedTest::new(tests::is_le));
+        rv.insert("gt".into(), BoxedTest::new(tests::is_gt));
+        rv.insert("greaterthan".into(), BoxedTest::new(tests::is_gt));
+        rv.insert("ge".into(), BoxedTest::new(tests::is_ge));
     }
-    rv
+    rv.into_iter()
+        .map(|(name, test)| (name, test.builtin()))
+        .collect()
 }
 
 pub(crate) fn get_globals() -> BTreeMap<Cow<'static, str>, Value> {
@@ -128,6 +187,14 @@ pub(crate) fn get_globals() -> BTreeMap<Cow<'static, str>, Value> {
             "debug".into(),
             BoxedFunction::new(functions::debug).to_value(),
         );
+        rv.insert(
+            "feature".into(),
+            BoxedFunction::new(functions::feature).to_value(),
+        );
+        rv.insert(
+            "lipsum".into(),
+            BoxedFunction::new(functions::lipsum).to_value(),
+        );
     }
 
     rv
@@ -6,23 +6,25 @@ use std::sync::Arc;
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
+use crate::compiler::instructions::Instructions;
+use crate::compiler::lexer::{NewlineMode, Syntax};
 use crate::compiler::parser::parse_expr;
 use crate::error::{attach_basic_debug_info, Error};
 use crate::expression::Expression;
 use crate::output::Output;
-use crate::template::{CompiledTemplate, Template};
-use crate::utils::{AutoEscape, BTreeMapKeysDebug};
-use crate::value::{FunctionArgs, FunctionResult, Value};
-use crate::vm::{State, Vm};
+use crate::template::{CompiledTemplate, CompiledTemplateRef, Template, TemplateConfig};
+use crate::utils::{AutoEscape, BTreeMapKeysDebug, UndefinedBehavior, UndefinedInfo};
+use crate::value::{FunctionArgs, FunctionResult, Value, MAX_VALUE_DEPTH};
+use crate::vm::{Extensions, State, Vm, MAX_INCLUDE_DEPTH, MAX_MACRO_DEPTH, MAX_RECURSION};
 use crate::{defaults, filters, functions, tests};
 
 type TemplateMap<'source> = BTreeMap<&'source str, Arc<CompiledTemplate<'source>>>;
 
 #[derive(Clone)]
 enum Source<'source> {
-    Borrowed(TemplateMap<'source>),
+    Borrowed(Arc<TemplateMap<'source>>),
     #[cfg(feature = "source")]
-    Owned(crate::source::Source),
+    Owned(Arc<crate::source::Source>),
 }
 
 impl<'source> fmt::Debug for Source<'source> {
@@ -35,8 +37,8 @@ impl<'source> fmt::Debug for Source<'source> {
     }
 }
 
-type AutoEscapeFunc = dyn Fn(&str) -> AutoEscape + Sync + Send;
 type FormatterFunc = dyn Fn(&mut Output, &State, &Value) -> Result<(), Error> + Sync + Send;
+type UndefinedFactoryFunc = dyn Fn(&UndefinedInfo) -> Value + Sync + Send;
 
 /// An abstraction that holds the engine configuration.
 ///
@@ -60,14 +62,46 @@ environment it's recommended to turn on the `source` feature and to use the
 ///   defaults.  It will contain all built-in filters, tests and globals as well
 ///   as a callback for auto escaping based on file extension.
 /// * [`Environment::empty`] creates a completely blank environment.
+///
+/// Cloning an environment is cheap.  Templates, filters, tests and globals
+/// are shared between the clones and only copied on the first modification,
+/// so a clone can be used as a per-request overlay of a base environment
+/// (for instance to add a few request specific globals) without affecting
+/// the original.
+///
+/// ```
+/// # use minijinja::{Environment, context, value::Value};
+/// let mut base = Environment::new();
+/// base.add_template("hello.txt", "Hello {{ user }} from {{ site }}!").unwrap();
+/// base.add_global("site", Value::from("example.com"));
+///
+/// let mut env = base.clone();
+/// env.add_global("user", Value::from("Peter"));
+/// let tmpl = env.get_template("hello.txt").unwrap();
+/// assert_eq!(tmpl.render(context!()).unwrap(), "Hello Peter from example.com!");
+/// assert_eq!(base.global("user"), None);
+/// ```
 #[derive(Clone)]
 pub struct Environment<'source> {
     templates: Source<'source>,
-    filters: BTreeMap<Cow<'source, str>, filters::BoxedFilter>,
-    tests: BTreeMap<Cow<'source, str>, tests::BoxedTest>,
-    pub(crate) globals: BTreeMap<Cow<'source, str>, Value>,
-    default_auto_escape: Arc<AutoEscapeFunc>,
+    filters: Arc<BTreeMap<Cow<'source, str>, filters::BoxedFilter>>,
+    tests: Arc<BTreeMap<Cow<'source, str>, tests::BoxedTest>>,
+    tags: Arc<BTreeMap<Cow<'source, str>, filters::BoxedFilter>>,
+    pub(crate) globals: Arc<BTreeMap<Cow<'source, str>, Value>>,
     formatter: Arc<FormatterFunc>,
+    strict_kwargs: bool,
+    recursion_limit: usize,
+    max_include_depth: usize,
+    max_macro_depth: usize,
+    max_value_depth: usize,
+    sort_map_keys: bool,
+    fuel: Option<u64>,
+    random_seed: Option<u64>,
+    template_config: TemplateConfig,
+    undefined_behavior: UndefinedBehavior,
+    undefined_factory: Option<Arc<UndefinedFactoryFunc>>,
+    #[cfg(feature = "loader")]
+    auto_reload: bool,
     #[cfg(feature = "debug")]
     debug: bool,
 }
@@ -85,6 +119,7 @@ impl<'source> fmt::Debug for Environment<'source> {
             .field("tests", &BTreeMapKeysDebug(&self.tests))
             .field("filters", &BTreeMapKeysDebug(&self.filters))
             .field("templates", &self.templates)
+            .field("syntax", &self.template_config.syntax)
             .finish()
     }
 }
@@ -99,11 +134,28 @@ impl<'source> Environment<'source> {
     pub fn new() -> Environment<'source> {
         Environment {
             templates: Source::Borrowed(Default::default()),
-            filters: defaults::get_builtin_filters(),
-            tests: defaults::get_builtin_tests(),
-            globals: defaults::get_globals(),
-            default_auto_escape: Arc::new(defaults::default_auto_escape_callback),
+            filters: Arc::new(defaults::get_builtin_filters()),
+            tests: Arc::new(defaults::get_builtin_tests()),
+            tags: Default::default(),
+            globals: Arc::new(defaults::get_globals()),
             formatter: Arc::new(defaults::escape_formatter),
+            strict_kwargs: false,
+            recursion_limit: MAX_RECURSION,
+            max_include_depth: MAX_INCLUDE_DEPTH,
+            max_macro_depth: MAX_MACRO_DEPTH,
+            max_value_depth: MAX_VALUE_DEPTH,
+            sort_map_keys: false,
+            fuel: None,
+            random_seed: None,
+            template_config: TemplateConfig {
+                default_auto_escape: Arc::new(defaults::default_auto_escape_callback),
+                fold_feature_calls: cfg!(feature = "builtins"),
+                ..TemplateConfig::default()
+            },
+            undefined_behavior: UndefinedBehavior::default(),
+            undefined_factory: None,
+            #[cfg(feature = "loader")]
+            auto_reload: false,
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
         }
@@ -118,9 +170,22 @@ impl<'source> Environment<'source> {
             templates: Source::Borrowed(Default::default()),
             filters: Default::default(),
             tests: Default::default(),
+            tags: Default::default(),
             globals: Default::default(),
-            default_auto_escape: Arc::new(defaults::no_auto_escape),
             formatter: Arc::new(defaults::escape_formatter),
+            strict_kwargs: false,
+            recursion_limit: MAX_RECURSION,
+            max_include_depth: MAX_INCLUDE_DEPTH,
+            max_macro_depth: MAX_MACRO_DEPTH,
+            max_value_depth: MAX_VALUE_DEPTH,
+            sort_map_keys: false,
+            fuel: None,
+            random_seed: None,
+            template_config: TemplateConfig::default(),
+            undefined_behavior: UndefinedBehavior::default(),
+            undefined_factory: None,
+            #[cfg(feature = "loader")]
+            auto_reload: false,
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
         }
@@ -142,12 +207,16 @@ impl<'source> Environment<'source> {
     pub fn add_template(&mut self, name: &'source str, source: &'source str) -> Result<(), Error> {
         match self.templates {
             Source::Borrowed(ref mut map) => {
-                let compiled_template = ok!(CompiledTemplate::from_name_and_source(name, source));
-                map.insert(name, Arc::new(compiled_template));
+                let compiled_template = ok!(CompiledTemplate::from_name_and_source(
+                    name,
+                    source,
+                    &self.template_config,
+                ));
+                Arc::make_mut(map).insert(name, Arc::new(compiled_template));
                 Ok(())
             }
             #[cfg(feature = "source")]
-            Source::Owned(ref mut src) => src.add_template(name, source),
+            Source::Owned(ref mut src) => Arc::make_mut(src).add_template(name, source),
         }
     }
 
@@ -155,15 +224,28 @@ impl<'source> Environment<'source> {
     pub fn remove_template(&mut self, name: &str) {
         match self.templates {
             Source::Borrowed(ref mut map) => {
-                map.remove(name);
+                Arc::make_mut(map).remove(name);
             }
             #[cfg(feature = "source")]
             Source::Owned(ref mut source) => {
-                source.remove_template(name);
+                Arc::make_mut(source).remove_template(name);
             }
         }
     }
 
+    /// Removes all templates from the environment.
+    ///
+    /// If a loader is set (see [`set_loader`](Self::set_loader)) templates
+    /// are loaded again on next use which makes this useful to pick up
+    /// changes to the underlying template sources.
+    pub fn clear_templates(&mut self) {
+        match self.templates {
+            Source::Borrowed(ref mut map) => Arc::make_mut(map).clear(),
+            #[cfg(feature = "source")]
+            Source::Owned(ref mut source) => Arc::make_mut(source).clear_templates(),
+        }
+    }
+
     /// Fetches a template by name.
     ///
     /// This requires that the template has been loaded with
@@ -179,17 +261,71 @@ impl<'source> Environment<'source> {
     /// ```
     pub fn get_template(&self, name: &str) -> Result<Template<'_>, Error> {
         let compiled = match &self.templates {
-            Source::Borrowed(ref map) => {
-                ok!(map.get(name).ok_or_else(|| Error::new_not_found(name)))
-            }
+            Source::Borrowed(ref map) => CompiledTemplateRef::borrowed(ok!(map
+                .get(name)
+                .ok_or_else(|| Error::new_not_found(name)))),
             #[cfg(feature = "source")]
-            Source::Owned(source) => ok!(source.get_compiled_template(name)),
+            Source::Owned(source) => ok!(source.get_compiled_template(name, self.auto_reload())),
         };
-        Ok(Template::new(
-            self,
-            compiled,
-            self.get_initial_auto_escape(name),
-        ))
+        Ok(Template::new(self, compiled))
+    }
+
+    /// Returns an iterator over all templates in the environment.
+    ///
+    /// The templates are returned together with their names in sorted order.
+    /// If a loader is set (see [`set_loader`](Self::set_loader)) only the
+    /// templates which were already added or loaded are returned, the loader
+    /// itself is not consulted.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_template("b.txt", "B").unwrap();
+    /// env.add_template("a.txt", "A").unwrap();
+    /// let names: Vec<_> = env.templates().map(|(name, _)| name).collect();
+    /// assert_eq!(names, ["a.txt", "b.txt"]);
+    /// ```
+    ///
+    /// The source of each template is available via
+    /// [`Template::source`]:
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// # env.add_template("a.txt", "A").unwrap();
+    /// for (name, tmpl) in env.templates() {
+    ///     println!("{}: {}", name, tmpl.source());
+    /// }
+    /// ```
+    pub fn templates(&self) -> impl Iterator<Item = (&str, Template<'_>)> + '_ {
+        let compiled: Vec<_> = match &self.templates {
+            Source::Borrowed(ref map) => map
+                .iter()
+                .map(|(name, tmpl)| (*name, CompiledTemplateRef::borrowed(tmpl)))
+                .collect(),
+            #[cfg(feature = "source")]
+            Source::Owned(source) => source.compiled_templates(),
+        };
+        compiled
+            .into_iter()
+            .map(move |(name, compiled)| (name, Template::new(self, compiled)))
+    }
+
+    /// Returns the names of all templates in the environment.
+    ///
+    /// This is a shortcut for mapping the names from
+    /// [`templates`](Self::templates) and returns them in the same order.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_template("b.txt", "B").unwrap();
+    /// env.add_template("a.txt", "A").unwrap();
+    /// let names: Vec<_> = env.template_names().collect();
+    /// assert_eq!(names, ["a.txt", "b.txt"]);
+    /// ```
+    pub fn template_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.templates().map(|(name, _)| name)
     }
 
     /// Parses and renders a template from a string in one go.
@@ -212,7 +348,12 @@ impl<'source> Environment<'source> {
     /// Parses and renders a template from a string in one go with name.
     ///
     /// Like [`render_str`](Self::render_str), but provide a name for the
-    /// template to be used instead of the default `<string>`.
+    /// template to be used instead of the default `<string>`.  The name is
+    /// used in error messages and debug information and it's passed to the
+    /// auto escape callback (see
+    /// [`set_auto_escape_callback`](Self::set_auto_escape_callback)) so a
+    /// name ending in `.html` enables HTML escaping by default.  The template
+    /// is not added to the environment.
     ///
     /// ```
     /// # use minijinja::{Environment, context};
@@ -236,7 +377,11 @@ impl<'source> Environment<'source> {
     }
 
     fn _render_str(&self, name: &str, source: &str, root: Value) -> Result<String, Error> {
-        let compiled = ok!(CompiledTemplate::from_name_and_source(name, source));
+        let compiled = ok!(CompiledTemplate::from_name_and_source(
+            name,
+            source,
+            &self.template_config,
+        ));
         let mut rv = String::new();
         Vm::new(self)
             .eval(
@@ -244,19 +389,36 @@ impl<'source> Environment<'source> {
                 root,
                 &compiled.blocks,
                 &mut Output::with_string(&mut rv),
-                self.get_initial_auto_escape(name),
+                compiled.initial_auto_escape,
+                &Extensions::new(),
             )
             .map(|_| rv)
     }
 
     /// Sets a new function to select the default auto escaping.
     ///
-    /// This function is invoked when templates are loaded from the environment
+    /// This function is invoked when templates are added to or loaded by the
+    /// environment as well as for [`render_named_str`](Self::render_named_str)
     /// to determine the default auto escaping behavior.  The function is
     /// invoked with the name of the template and can make an initial auto
-    /// escaping decision based on that.  The default implementation
+    /// escaping decision based on that.  The result is stored with the
+    /// compiled template, so the callback should be set before templates are
+    /// added.  The default implementation
     /// ([`default_auto_escape_callback`](defaults::default_auto_escape_callback))
-    /// turn on escaping depending on the file extension.
+    /// turns on escaping depending on the file extension.
+    ///
+    /// Auto escaping only ever applies to values printed with `{{ ... }}`,
+    /// literal template text is emitted unchanged.  The active setting is
+    /// determined like this:
+    ///
+    /// * rendering starts with the setting of the rendered template.
+    /// * `{% include %}` switches to the setting of the included template
+    ///   while the included template renders and restores the previous
+    ///   setting afterwards.
+    /// * `{% extends %}` does not switch: the parent templates and all blocks
+    ///   render with the setting of the template that was rendered.
+    /// * macros render with the setting that is active where they are called.
+    /// * `{% autoescape %}` blocks override the setting for their body.
     ///
     /// ```
     /// # use minijinja::{Environment, AutoEscape};
@@ -273,7 +435,8 @@ impl<'source> Environment<'source> {
     where
         F: Fn(&str) -> AutoEscape + 'static + Sync + Send,
     {
-        self.default_auto_escape = Arc::new(f);
+        self.template_config.default_auto_escape = Arc::new(f);
+        self.sync_template_config();
     }
 
     /// Sets a different formatter function.
@@ -282,6 +445,13 @@ impl<'source> Environment<'source> {
     /// [`Output`].  The default implementation is
     /// [`escape_formatter`](defaults::escape_formatter).
     ///
+    /// The formatter is called once for every `{{ ... }}` expression, after
+    /// all filters of the expression were applied.  Literal template text does
+    /// not pass through the formatter.  Escaping is the responsibility of the
+    /// formatter: the value is handed over unescaped and the default formatter
+    /// escapes it according to [`State::auto_escape`] unless it is marked as
+    /// safe.  An error returned from the formatter aborts rendering.
+    ///
     /// When implementing a custom formatter it depends on if auto escaping
     /// should be supported or not.  If auto escaping should be supported then
     /// it's easiest to just wrap the default formatter.  The
@@ -340,6 +510,483 @@ impl<'source> Environment<'source> {
         self.debug
     }
 
+    /// Enables or disables strict keyword arguments.
+    ///
+    /// By default keyword arguments that a filter, test or function does not
+    /// know about are silently ignored.  When strict keyword arguments are
+    /// enabled, passing unknown keyword arguments (for instance because of a
+    /// typo like `sort(revrse=true)`) fails with an error instead.  This also
+    /// applies to keyword arguments passed to callables that do not accept
+    /// any.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_strict_kwargs(true);
+    /// assert!(env.render_str("{{ [2, 1]|sort(revrse=true) }}", ()).is_err());
+    /// ```
+    pub fn set_strict_kwargs(&mut self, enabled: bool) {
+        self.strict_kwargs = enabled;
+    }
+
+    /// Returns `true` if strict keyword arguments are enabled.
+    pub fn strict_kwargs(&self) -> bool {
+        self.strict_kwargs
+    }
+
+    /// Sets the recursion limit.
+    ///
+    /// The recursion limit protects against stack overflows caused by deeply
+    /// nested or self-recursive templates such as recursive macros, recursive
+    /// loops or templates including themselves.  The following counts towards
+    /// the limit:
+    ///
+    /// * every scope the engine enters costs `1`.  This covers `for` loops and
+    ///   every level of a recursive loop, `with` blocks, `{% block %}`
+    ///   evaluations and `super()` calls.
+    /// * every `{% include %}` costs `10` in addition to the scopes of the
+    ///   included template.
+    /// * every macro call costs `5` in addition to the scopes of the macro
+    ///   body.
+    ///
+    /// `{% extends %}` does not count towards this limit as inheritance is
+    /// bounded by the [include depth](Self::set_max_include_depth) instead.
+    /// Once the limit is exceeded rendering fails with a
+    /// [`RecursionLimitExceeded`](crate::ErrorKind::RecursionLimitExceeded)
+    /// error.  The default is `500`.  Raising the limit considerably can cause
+    /// the rendering thread to overflow its native stack.
+    ///
+    /// In addition to this there are separate limits for
+    /// [includes](Self::set_max_include_depth), [macro
+    /// calls](Self::set_max_macro_depth) and [nested
+    /// values](Self::set_max_value_depth).
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_recursion_limit(20);
+    /// let err = env
+    ///     .render_str("{% macro x() %}{{ x() }}{% endmacro %}{{ x() }}", ())
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "recursion limit exceeded (in <string>:1)");
+    /// ```
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// Returns the current recursion limit.
+    pub fn recursion_limit(&self) -> usize {
+        self.recursion_limit
+    }
+
+    /// Sets how deeply includes and template inheritance can be nested.
+    ///
+    /// Both an `{% include %}` and an `{% extends %}` add a level.  A very long
+    /// chain of `{% extends %}` or a template including itself fails with a
+    /// [`RecursionLimitExceeded`](crate::ErrorKind::RecursionLimitExceeded)
+    /// error once this limit is reached.  The default is `32`.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, ErrorKind};
+    /// let mut env = Environment::new();
+    /// env.set_max_include_depth(2);
+    /// env.add_template("self.txt", "{% include 'self.txt' %}").unwrap();
+    /// let err = env.get_template("self.txt").unwrap().render(()).unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    /// ```
+    pub fn set_max_include_depth(&mut self, depth: usize) {
+        self.max_include_depth = depth;
+    }
+
+    /// Returns the maximum include depth.
+    pub fn max_include_depth(&self) -> usize {
+        self.max_include_depth
+    }
+
+    /// Sets how deeply macro calls can be nested.
+    ///
+    /// Once a macro (directly or indirectly) calls itself this many times the
+    /// render fails with a [`RecursionLimitExceeded`](crate::ErrorKind::RecursionLimitExceeded)
+    /// error.  The default is `64`.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_max_macro_depth(10);
+    /// let err = env
+    ///     .render_str("{% macro x() %}{{ x() }}{% endmacro %}{{ x() }}", ())
+    ///     .unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "recursion limit exceeded: too many nested macro calls (limit is 10) (in <string>:1)"
+    /// );
+    /// ```
+    pub fn set_max_macro_depth(&mut self, depth: usize) {
+        self.max_macro_depth = depth;
+    }
+
+    /// Returns the maximum macro call depth.
+    pub fn max_macro_depth(&self) -> usize {
+        self.max_macro_depth
+    }
+
+    /// Sets how deeply values can be nested when printed or serialized.
+    ///
+    /// When a value is printed in a template, containers nested deeper than
+    /// this are shown as `...`.  Serializing such a value (for instance with
+    /// the `tojson` filter) fails instead.  This protects against stack
+    /// overflows caused by pathologically nested data.  The default is `100`.
+    ///
+    /// The limit only applies while a template or expression is evaluated,
+    /// printing or serializing values outside of a render is not limited.
+    pub fn set_max_value_depth(&mut self, depth: usize) {
+        self.max_value_depth = depth;
+    }
+
+    /// Returns the maximum value nesting depth.
+    pub fn max_value_depth(&self) -> usize {
+        self.max_value_depth
+    }
+
+    /// Enables or disables sorting of map keys.
+    ///
+    /// Maps are normally iterated in the order of their keys.  With the
+    /// `preserve_order` feature they are iterated in insertion order instead
+    /// which for maps created from a `HashMap` is not deterministic, and
+    /// [struct objects](crate::value::StructObject) are always iterated in
+    /// the order of their fields.  When this is enabled all maps are iterated
+    /// in sorted key order while rendering.  This affects loops over maps,
+    /// filters like `items` and `dictsort`, printing of maps as well as the
+    /// output of `tojson`.  The default is disabled.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.set_sort_map_keys(true);
+    /// let rv = env.render_str(
+    ///     "{% for key in map %}{{ key }}{% endfor %}",
+    ///     context!(map => context!(b => 2, a => 1)),
+    /// ).unwrap();
+    /// assert_eq!(rv, "ab");
+    /// ```
+    pub fn set_sort_map_keys(&mut self, yes: bool) {
+        self.sort_map_keys = yes;
+    }
+
+    /// Returns `true` if map keys are sorted while rendering.
+    pub fn sort_map_keys(&self) -> bool {
+        self.sort_map_keys
+    }
+
+    /// Sets the fuel of the engine.
+    ///
+    /// When fuel is set, every instruction executed by the engine consumes
+    /// one unit of fuel.  Once the fuel is used up the render fails with a
+    /// [`FuelExhausted`](crate::ErrorKind::FuelExhausted) error.  A dedicated
+    /// error kind is used rather than
+    /// [`InvalidOperation`](crate::ErrorKind::InvalidOperation) so that running
+    /// out of fuel can be told apart from template errors and is never
+    /// recovered from within the template.  This
+    /// can be used to bound the execution time of untrusted templates.  Every
+    /// render starts out with the full amount of fuel; included templates and
+    /// macros invoked during a render draw from the same budget.  `None` (the
+    /// default) disables fuel tracking.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_fuel(Some(100));
+    /// let err = env
+    ///     .render_str("{% for x in range(1000) %}{{ x }}{% endfor %}", ())
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "fuel exhausted (in <string>:1)");
+    /// ```
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Returns the configured fuel.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Sets the seed for the random number generator.
+    ///
+    /// Every render gets its own random number generator which is used by
+    /// functions like [`lipsum`](crate::functions::lipsum).  By default it's
+    /// seeded randomly.  When a seed is set, renders of the same template
+    /// produce the same output which is useful for tests.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_random_seed(Some(42));
+    /// let a = env.render_str("{{ lipsum(1) }}", ()).unwrap();
+    /// let b = env.render_str("{{ lipsum(1) }}", ()).unwrap();
+    /// assert_eq!(a, b);
+    /// ```
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.random_seed = seed;
+    }
+
+    /// Returns the configured random seed.
+    pub fn random_seed(&self) -> Option<u64> {
+        self.random_seed
+    }
+
+    /// Changes the undefined behavior.
+    ///
+    /// This changes the runtime behavior of [`undefined`](crate::value::Value::UNDEFINED)
+    /// values in the template engine.  For more information see
+    /// [`UndefinedBehavior`].  The default is [`UndefinedBehavior::Lenient`].
+    /// In strict mode the error names the variable or attribute that was
+    /// undefined:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, UndefinedBehavior};
+    /// let mut env = Environment::new();
+    /// env.set_undefined_behavior(UndefinedBehavior::Strict);
+    /// let err = env.render_str("Hello {{ name }}!", ()).unwrap_err();
+    /// assert_eq!(
+    ///     err.to_string(),
+    ///     "undefined value: `name` is undefined (in <string>:1)"
+    /// );
+    /// ```
+    pub fn set_undefined_behavior(&mut self, behavior: UndefinedBehavior) {
+        self.undefined_behavior = behavior;
+    }
+
+    /// Returns the current undefined behavior.
+    pub fn undefined_behavior(&self) -> UndefinedBehavior {
+        self.undefined_behavior
+    }
+
+    /// Sets a factory that is invoked for failed lookups.
+    ///
+    /// Whenever a variable, attribute or item lookup fails the engine invokes
+    /// the factory with an [`UndefinedInfo`] describing the lookup.  If the
+    /// factory returns a defined value it is used in place of the undefined
+    /// value, if it returns [`Value::UNDEFINED`] the configured
+    /// [`UndefinedBehavior`] applies as usual.  This can be used to log missing
+    /// variables or to render placeholders for them:
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// use minijinja::value::Value;
+    ///
+    /// let mut env = Environment::new();
+    /// env.set_undefined_factory(|info| {
+    ///     Value::from(format!("[missing:{}]", info.name()))
+    /// });
+    /// let rv = env.render_str("Hello {{ name }}!", ()).unwrap();
+    /// assert_eq!(rv, "Hello [missing:name]!");
+    /// ```
+    pub fn set_undefined_factory<F>(&mut self, f: F)
+    where
+        F: Fn(&UndefinedInfo) -> Value + 'static + Sync + Send,
+    {
+        self.undefined_factory = Some(Arc::new(f));
+    }
+
+    /// Returns the undefined value for a failed lookup.
+    pub(crate) fn make_undefined(&self, info: &UndefinedInfo) -> Value {
+        match self.undefined_factory {
+            Some(ref factory) => factory(info),
+            None => Value::UNDEFINED,
+        }
+    }
+
+    /// Returns `true` if an undefined factory is installed.
+    pub(crate) fn has_undefined_factory(&self) -> bool {
+        self.undefined_factory.is_some()
+    }
+
+    /// Sets a feature flag.
+    ///
+    /// Feature flags are queried from templates with the
+    /// [`feature`](crate::functions::feature) function.  Flags that are known
+    /// when a template is compiled are resolved at compile time, so that
+    /// `{% if feature("name") %}` only compiles the branch that is taken.  For
+    /// this reason flags should be set before templates are added to the
+    /// environment; changing a flag does not recompile templates that were
+    /// already loaded.  Flags that are not known at compile time are looked up
+    /// when the template is rendered instead, as are all calls in templates
+    /// that declare their own `feature` variable or if the `feature` global was
+    /// replaced or removed.
+    ///
+    /// Unlike other globals, `feature` cannot be shadowed by a variable of the
+    /// same name in the render context for flags that were resolved at compile
+    /// time as the context is not known at that point.  Use a different name
+    /// for such context variables.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_feature_flag("new_ui", true);
+    /// env.add_template("hello", "{% if feature('new_ui') %}new{% else %}old{% endif %}")
+    ///     .unwrap();
+    /// assert_eq!(env.get_template("hello").unwrap().render(()).unwrap(), "new");
+    /// ```
+    pub fn set_feature_flag<N: Into<String>>(&mut self, name: N, enabled: bool) {
+        Arc::make_mut(&mut self.template_config.feature_flags).insert(name.into(), enabled);
+        self.sync_template_config();
+    }
+
+    /// Returns the value of a feature flag if it was set.
+    pub fn feature_flag(&self, name: &str) -> Option<bool> {
+        self.template_config.feature_flags.get(name).copied()
+    }
+
+    /// Enables or disables the `lstrip_blocks` feature.
+    ///
+    /// When enabled, spaces and tabs are stripped from the start of a line to
+    /// a block tag or comment.  This only happens if the tag is the first
+    /// non-whitespace on its line, so inline tags like `text {% if x %}` keep
+    /// the whitespace in front of them.  Putting a plus sign at the start of a
+    /// tag (`{%+ if x %}`) disables the stripping for that tag.  The default
+    /// is disabled.
+    ///
+    /// Like feature flags this is applied when templates are compiled, so it
+    /// should be configured before templates are added.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_lstrip_blocks(true);
+    /// env.set_trim_blocks(true);
+    /// let tmpl = "<ul>\n  {% for x in [1, 2] %}\n  <li>{{ x }}\n  {% endfor %}\n</ul>";
+    /// let rv = env.render_str(tmpl, ()).unwrap();
+    /// assert_eq!(rv, "<ul>\n  <li>1\n  <li>2\n</ul>");
+    /// ```
+    pub fn set_lstrip_blocks(&mut self, yes: bool) {
+        self.template_config.ws_config.lstrip_blocks = yes;
+        self.sync_template_config();
+    }
+
+    /// Returns the value of the `lstrip_blocks` feature.
+    pub fn lstrip_blocks(&self) -> bool {
+        self.template_config.ws_config.lstrip_blocks
+    }
+
+    /// Enables or disables the `trim_blocks` feature.
+    ///
+    /// When enabled, the first newline after a block tag or comment is
+    /// removed.  Putting a plus sign at the end of a tag (`{% if x +%}`)
+    /// disables this for that tag.  The default is disabled.
+    ///
+    /// Like feature flags this is applied when templates are compiled, so it
+    /// should be configured before templates are added.
+    pub fn set_trim_blocks(&mut self, yes: bool) {
+        self.template_config.ws_config.trim_blocks = yes;
+        self.sync_template_config();
+    }
+
+    /// Returns the value of the `trim_blocks` feature.
+    pub fn trim_blocks(&self) -> bool {
+        self.template_config.ws_config.trim_blocks
+    }
+
+    /// Preserves the trailing newline when rendering templates.
+    ///
+    /// By default a single newline at the end of a template is removed so
+    /// that a template file ending in a newline renders without it.  When
+    /// enabled the final newline is kept which is useful when generating
+    /// files that need to end in a newline.  The default is disabled.
+    ///
+    /// Like feature flags this is applied when templates are compiled, so it
+    /// should be configured before templates are added.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// assert_eq!(env.render_str("Hello\n", ()).unwrap(), "Hello");
+    /// env.set_keep_trailing_newline(true);
+    /// assert_eq!(env.render_str("Hello\n", ()).unwrap(), "Hello\n");
+    /// ```
+    pub fn set_keep_trailing_newline(&mut self, yes: bool) {
+        self.template_config.ws_config.keep_trailing_newline = yes;
+        self.sync_template_config();
+    }
+
+    /// Returns the value of the `keep_trailing_newline` feature.
+    pub fn keep_trailing_newline(&self) -> bool {
+        self.template_config.ws_config.keep_trailing_newline
+    }
+
+    /// Changes how newlines in the template source are emitted.
+    ///
+    /// By default newlines are emitted as they appear in the template
+    /// ([`NewlineMode::Preserve`]).  Setting a different mode normalizes
+    /// `\r\n`, `\r` and `\n` in the literal text of templates to the
+    /// configured newline sequence.  Values printed by expressions are never
+    /// rewritten.
+    ///
+    /// Like feature flags this is applied when templates are compiled, so it
+    /// should be configured before templates are added.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, NewlineMode, context};
+    /// let mut env = Environment::new();
+    /// env.set_newline_mode(NewlineMode::Lf);
+    /// let rv = env.render_str("a\r\n{{ x }}\r\n", context!(x => "b\r\n"));
+    /// assert_eq!(rv.unwrap(), "a\nb\r\n");
+    /// ```
+    pub fn set_newline_mode(&mut self, mode: NewlineMode) {
+        self.template_config.ws_config.newline_mode = mode;
+        self.sync_template_config();
+    }
+
+    /// Returns the currently configured newline mode.
+    pub fn newline_mode(&self) -> NewlineMode {
+        self.template_config.ws_config.newline_mode
+    }
+
+    /// Changes the delimiters of the template syntax.
+    ///
+    /// By default the syntax known from Jinja2 is used.  This can be changed
+    /// if the default delimiters collide with the syntax of the generated
+    /// output, see [`Syntax`] for an example.  All delimiters have to be
+    /// non empty and the start delimiters of blocks, variables and comments
+    /// must be distinguishable, otherwise an error is returned and the
+    /// syntax is not changed.
+    ///
+    /// Like feature flags this is applied when templates are compiled, so it
+    /// should be configured before templates are added.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Syntax, context};
+    /// let mut env = Environment::new();
+    /// env.set_syntax(Syntax {
+    ///     block_start: "<%".into(),
+    ///     block_end: "%>".into(),
+    ///     variable_start: "<<".into(),
+    ///     variable_end: ">>".into(),
+    ///     ..Syntax::default()
+    /// }).unwrap();
+    /// let rv = env.render_str("<% for x in seq %><<x>>{{ x }}<% endfor %>", context!(seq => [1]));
+    /// assert_eq!(rv.unwrap(), "1{{ x }}");
+    /// ```
+    pub fn set_syntax(&mut self, syntax: Syntax) -> Result<(), Error> {
+        ok!(syntax.validate());
+        self.template_config.syntax = Arc::new(syntax);
+        self.sync_template_config();
+        Ok(())
+    }
+
+    /// Returns the currently configured syntax.
+    pub fn syntax(&self) -> &Syntax {
+        &self.template_config.syntax
+    }
+
+    fn sync_template_config(&mut self) {
+        #[cfg(feature = "source")]
+        {
+            if let Source::Owned(ref mut source) = self.templates {
+                Arc::make_mut(source).template_config = self.template_config.clone();
+            }
+        }
+    }
+
     /// Sets the template source for the environment.
     ///
     /// This helps when working with dynamically loaded templates.  The
@@ -354,8 +1001,112 @@ impl<'source> Environment<'source> {
     /// For more information see [`Source`](crate::source::Source).
     #[cfg(feature = "source")]
     #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
-    pub fn set_source(&mut self, source: crate::source::Source) {
-        self.templates = Source::Owned(source);
+    pub fn set_source(&mut self, mut source: crate::source::Source) {
+        source.template_config = self.template_config.clone();
+        self.templates = Source::Owned(Arc::new(source));
+    }
+
+    /// Sets a loader for templates that are not known to the environment.
+    ///
+    /// Whenever a template is requested that was not added to the environment
+    /// (be it via [`get_template`](Self::get_template) or from a template via
+    /// `{% include %}`, `{% extends %}`, `{% import %}` or `{% from %}`) the
+    /// loader is invoked with its name.  If the template exists it has to
+    /// return `Ok(Some(template_source))`, otherwise `Ok(None)` in which case
+    /// an error of kind [`TemplateNotFound`](crate::ErrorKind::TemplateNotFound)
+    /// is produced.  Errors returned by the loader are passed through unchanged
+    /// so they can be told apart from missing templates and they also are not
+    /// suppressed by `ignore missing` on includes.
+    ///
+    /// Loaded templates are compiled once and then cached in the environment
+    /// until they are removed with [`remove_template`](Self::remove_template)
+    /// or [`clear_templates`](Self::clear_templates).  The cache is guarded by
+    /// a lock, so the loader is invoked only once per template even if the
+    /// environment is shared between threads.  Templates that were
+    /// added to the environment before are retained.  For this they are
+    /// compiled again which fails if the configuration of the environment
+    /// changed in an incompatible way since (for instance if the syntax was
+    /// changed).  In that case the environment is left unchanged and the
+    /// error is returned.  This is a shortcut for
+    /// setting a [`Source`](crate::source::Source) with a loader (see
+    /// [`Source::set_loader`](crate::source::Source::set_loader)).
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.add_template("index.html", "{% include 'footer.html' %}").unwrap();
+    /// env.set_loader(|name| match name {
+    ///     "footer.html" => Ok(Some("(c) {{ year }}".into())),
+    ///     _ => Ok(None),
+    /// })
+    /// .unwrap();
+    /// let tmpl = env.get_template("index.html").unwrap();
+    /// assert_eq!(tmpl.render(context!(year => 2023)).unwrap(), "(c) 2023");
+    /// ```
+    #[cfg(feature = "source")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+    pub fn set_loader<F>(&mut self, f: F) -> Result<(), Error>
+    where
+        F: Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static,
+    {
+        match self.templates {
+            Source::Borrowed(ref map) => {
+                let mut source = crate::source::Source::new();
+                source.template_config = self.template_config.clone();
+                for tmpl in map.values() {
+                    ok!(source.add_template(tmpl.instructions.name(), tmpl.instructions.source()));
+                }
+                source.set_loader(f);
+                self.templates = Source::Owned(Arc::new(source));
+            }
+            Source::Owned(ref mut source) => Arc::make_mut(source).set_loader(f),
+        }
+        Ok(())
+    }
+
+    /// Enables or disables automatic reloading of loaded templates.
+    ///
+    /// When enabled, every time a template that was loaded by the loader (see
+    /// [`set_loader`](Self::set_loader)) is requested the loader is invoked
+    /// again and the template is recompiled if its source changed.  This also
+    /// applies to templates pulled in via `{% include %}`, `{% extends %}` and
+    /// imports.  If the loader no longer finds a template the previously
+    /// loaded version is used.
+    ///
+    /// Templates of a source created with
+    /// [`Source::from_path`](crate::Source::from_path) are only read again if
+    /// the modification time or the size of the file changed.  Loaders set
+    /// with [`set_loader`](Self::set_loader) (including
+    /// [`path_loader`](crate::path_loader)) do not know about files, so they
+    /// are invoked on every lookup and the template is only recompiled if
+    /// the returned source differs.
+    /// Outdated versions of templates are dropped once they are no longer in
+    /// use.  This is meant for local development.  For watching the file
+    /// system for changes have a look at the
+    /// [`minijinja-autoreload`](https://docs.rs/minijinja-autoreload) crate.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Source};
+    /// let mut env = Environment::new();
+    /// env.set_source(Source::from_path("templates"));
+    /// env.set_auto_reload(cfg!(debug_assertions));
+    /// ```
+    #[cfg(feature = "loader")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "loader")))]
+    pub fn set_auto_reload(&mut self, enabled: bool) {
+        self.auto_reload = enabled;
+    }
+
+    /// Returns `true` if automatic reloading of templates is enabled.
+    #[cfg(feature = "loader")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "loader")))]
+    pub fn auto_reload(&self) -> bool {
+        self.auto_reload
+    }
+
+    #[cfg(all(feature = "source", not(feature = "loader")))]
+    fn auto_reload(&self) -> bool {
+        false
     }
 
     /// Returns the currently set source.
@@ -379,11 +1130,37 @@ impl<'source> Environment<'source> {
     }
 
     fn _compile_expression(&self, expr: &'source str) -> Result<Expression<'_, 'source>, Error> {
-        let ast = ok!(parse_expr(expr));
-        let mut gen = CodeGenerator::new("<expression>", expr);
-        gen.compile_expr(&ast);
-        let (instructions, _) = gen.finish();
-        Ok(Expression::new(self, instructions))
+        Ok(Expression::new(
+            self,
+            ok!(compile_expression_instructions(expr)),
+        ))
+    }
+
+    /// Compiles an expression without borrowing the source.
+    ///
+    /// This works like [`compile_expression`](Self::compile_expression) but the
+    /// expression holds on to a copy of the source.  The resulting expression
+    /// only borrows the environment which means that it can be compiled once
+    /// and then be stored alongside the environment (for instance in a cache)
+    /// and evaluated many times, also from multiple threads.  For an
+    /// environment that lives for the rest of the program the expression is
+    /// an `Expression<'static, 'static>`.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let env = Environment::new();
+    /// let rules = vec!["age >= 18".to_string(), "name == 'root'".to_string()];
+    /// let compiled = rules
+    ///     .iter()
+    ///     .map(|rule| env.compile_expression_owned(rule))
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// drop(rules);
+    /// let rv = compiled[0].eval(context!(age => 23)).unwrap();
+    /// assert!(rv.is_true());
+    /// ```
+    pub fn compile_expression_owned(&self, expr: &str) -> Result<Expression<'_, 'static>, Error> {
+        attach_basic_debug_info(Expression::new_owned(self, expr.to_string()), expr)
     }
 
     /// Adds a new filter function.
@@ -400,13 +1177,86 @@ impl<'source> Environment<'source> {
         Rv: FunctionResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        self.filters
-            .insert(name.into(), filters::BoxedFilter::new(f));
+        Arc::make_mut(&mut self.filters).insert(name.into(), filters::BoxedFilter::new(f));
     }
 
     /// Removes a filter by name.
+    ///
+    /// Templates that use the filter fail to render with an
+    /// [`UnknownFilter`](crate::ErrorKind::UnknownFilter) error once the
+    /// filter was removed.
     pub fn remove_filter(&mut self, name: &str) {
-        self.filters.remove(name);
+        Arc::make_mut(&mut self.filters).remove(name);
+    }
+
+    /// Returns `true` if a filter with the given name is registered.
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.contains_key(name)
+    }
+
+    /// Returns an iterator over the names of all registered filters.
+    ///
+    /// The names are returned in sorted order.
+    pub fn filters(&self) -> impl Iterator<Item = &str> + '_ {
+        self.filters.keys().map(|x| x.as_ref())
+    }
+
+    /// Adds a new custom tag.
+    ///
+    /// A custom tag is a block statement like `{% name %}...{% endname %}`
+    /// that is implemented in Rust.  The body of the tag is rendered into a
+    /// string and passed as first argument to the handler, the return value
+    /// of the handler is emitted in place of the block.  Any comma separated
+    /// expressions after the tag name are passed as additional arguments.
+    /// The handler is invoked like a [`Filter`](crate::filters::Filter) so it
+    /// can also request the [`State`](crate::State) as first parameter.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.add_tag("repeat", |body: String, n: usize| body.repeat(n));
+    /// let rv = env.render_str("{% repeat 3 %}{{ x }}{% endrepeat %}", context!(x => "a"));
+    /// assert_eq!(rv.unwrap(), "aaa");
+    /// ```
+    ///
+    /// The parser hooks into custom tags with the following contract:
+    ///
+    /// * the tag name is only recognized in block position and it cannot
+    ///   replace built-in statements such as `for` or `if`.
+    /// * the arguments are parsed as expressions up to the end of the opening
+    ///   block; the body is parsed as regular template code until the matching
+    ///   `end<name>` block.
+    /// * the body is rendered in its own scope with the current auto escaping.
+    ///   With auto escaping enabled the body is handed over as a safe string,
+    ///   so a handler that wants to keep it that way should accept and return
+    ///   a [`Value`] (eg: by using [`Value::from_safe_string`]).
+    ///
+    /// Like the syntax the tags are consulted when templates are compiled, so
+    /// they have to be registered before templates using them are added.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the name is one of the built-in statements
+    /// (eg: `for`, `if`, `set`, `block` or `call`) or one of their closing
+    /// or intermediate keywords (eg: `endfor` or `else`).
+    pub fn add_tag<N, F, Rv, Args>(&mut self, name: N, f: F)
+    where
+        N: Into<Cow<'source, str>>,
+        // the crazy bounds here exist to enable borrowing in closures
+        F: filters::Filter<Rv, Args>
+            + for<'a> filters::Filter<Rv, <Args as FunctionArgs<'a>>::Output>,
+        Rv: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        let name = name.into();
+        assert!(
+            !crate::compiler::parser::BUILTIN_STATEMENTS.contains(&name.as_ref()),
+            "cannot register custom tag {:?}: it is a built-in statement",
+            name
+        );
+        Arc::make_mut(&mut self.template_config.custom_tags).insert(name.to_string());
+        Arc::make_mut(&mut self.tags).insert(name, filters::BoxedFilter::new(f));
+        self.sync_template_config();
     }
 
     /// Adds a new test function.
@@ -422,12 +1272,28 @@ impl<'source> Environment<'source> {
         Rv: tests::TestResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        self.tests.insert(name.into(), tests::BoxedTest::new(f));
+        Arc::make_mut(&mut self.tests).insert(name.into(), tests::BoxedTest::new(f));
     }
 
     /// Removes a test by name.
+    ///
+    /// Templates that use the test fail to render with an
+    /// [`UnknownTest`](crate::ErrorKind::UnknownTest) error once the test
+    /// was removed.
     pub fn remove_test(&mut self, name: &str) {
-        self.tests.remove(name);
+        Arc::make_mut(&mut self.tests).remove(name);
+    }
+
+    /// Returns `true` if a test with the given name is registered.
+    pub fn has_test(&self, name: &str) -> bool {
+        self.tests.contains_key(name)
+    }
+
+    /// Returns an iterator over the names of all registered tests.
+    ///
+    /// The names are returned in sorted order.
+    pub fn tests(&self) -> impl Iterator<Item = &str> + '_ {
+        self.tests.keys().map(|x| x.as_ref())
     }
 
     /// Adds a new global function.
@@ -448,21 +1314,74 @@ impl<'source> Environment<'source> {
         self.add_global(name.into(), Value::from_function(f))
     }
 
+    /// Removes a global function by name.
+    ///
+    /// Unlike [`remove_global`](Self::remove_global) this only removes the
+    /// global if it's a function registered with
+    /// [`add_function`](Self::add_function).
+    pub fn remove_function(&mut self, name: &str) {
+        if self.globals.get(name).map_or(false, is_function) {
+            self.remove_global(name);
+        }
+    }
+
+    /// Returns an iterator over the names of all registered functions.
+    ///
+    /// This only includes globals registered with
+    /// [`add_function`](Self::add_function).  The names are returned in
+    /// sorted order.
+    pub fn functions(&self) -> impl Iterator<Item = &str> + '_ {
+        self.globals
+            .iter()
+            .filter(|(_, value)| is_function(value))
+            .map(|(name, _)| name.as_ref())
+    }
+
     /// Adds a global variable.
+    ///
+    /// Globals are visible to all templates rendered from the environment
+    /// as well as to expressions compiled with
+    /// [`compile_expression`](Self::compile_expression).  Variables passed
+    /// to the render call (and variables set in the template) shadow globals
+    /// of the same name.  Globals are also visible inside macros and included
+    /// templates.  A `{% set %}` in a template never modifies the global, it
+    /// only creates a local variable that shadows it.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context, value::Value};
+    /// let mut env = Environment::new();
+    /// env.add_global("site_name", Value::from("My Site"));
+    /// let rv = env.render_str("{{ site_name }}", context!()).unwrap();
+    /// assert_eq!(rv, "My Site");
+    /// let rv = env.render_str("{{ site_name }}", context!(site_name => "Other")).unwrap();
+    /// assert_eq!(rv, "Other");
+    /// ```
     pub fn add_global<N>(&mut self, name: N, value: Value)
     where
         N: Into<Cow<'source, str>>,
     {
-        self.globals.insert(name.into(), value);
+        let name = name.into();
+        self.global_changed(&name);
+        Arc::make_mut(&mut self.globals).insert(name, value);
     }
 
     /// Removes a global function or variable by name.
     pub fn remove_global(&mut self, name: &str) {
-        self.globals.remove(name);
+        self.global_changed(name);
+        Arc::make_mut(&mut self.globals).remove(name);
     }
 
-    /// Looks up a function.
-    pub(crate) fn get_global(&self, name: &str) -> Option<Value> {
+    fn global_changed(&mut self, name: &str) {
+        // once the built-in `feature` function is replaced or removed, calls
+        // to it can no longer be resolved at compile time.
+        if name == "feature" && self.template_config.fold_feature_calls {
+            self.template_config.fold_feature_calls = false;
+            self.sync_template_config();
+        }
+    }
+
+    /// Looks up a global function or variable by name.
+    pub fn global(&self, name: &str) -> Option<Value> {
         self.globals.get(name).cloned()
     }
 
@@ -471,13 +1390,18 @@ impl<'source> Environment<'source> {
         self.filters.get(name)
     }
 
+    /// Looks up a custom tag.
+    pub(crate) fn get_tag(&self, name: &str) -> Option<&filters::BoxedFilter> {
+        self.tags.get(name)
+    }
+
     /// Looks up a test function.
     pub(crate) fn get_test(&self, name: &str) -> Option<&tests::BoxedTest> {
         self.tests.get(name)
     }
 
     pub(crate) fn get_initial_auto_escape(&self, name: &str) -> AutoEscape {
-        (self.default_auto_escape)(name)
+        (self.template_config.default_auto_escape)(name)
     }
 
     /// Formats a value into the final format.
@@ -494,3 +1418,17 @@ impl<'source> Environment<'source> {
         (self.formatter)(out, state, value)
     }
 }
+
+fn is_function(value: &Value) -> bool {
+    value
+        .downcast_object_ref::<functions::BoxedFunction>()
+        .is_some()
+}
+
+pub(crate) fn compile_expression_instructions(expr: &str) -> Result<Instructions<'_>, Error> {
+    let ast = ok!(parse_expr(expr));
+    let mut gen = CodeGenerator::new("<expression>", expr);
+    gen.compile_expr(&ast);
+    let (instructions, _) = gen.finish();
+    Ok(instructions)
+}
@@ -9,7 +9,9 @@ use crate::compiler::tokens::Span;
 /// information that can be displayed by formatting an error with the
 /// alternative formatting (``format!("{:#}", err)``).  That information
 /// is also shown for the [`Debug`] display where the extended information
-/// is hidden when the alternative formatting is used.
+/// is hidden when the alternative formatting is used.  To get the debug
+/// information of an error and all the template errors it was caused by
+/// [`display_debug_info`](Self::display_debug_info) can be used.
 ///
 /// Since MiniJinja takes advantage of chained errors it's recommended
 /// to render the entire chain to better understand the causes.
@@ -126,6 +128,8 @@ pub enum ErrorKind {
     UndefinedError,
     /// Not able to serialize this value.
     BadSerialization,
+    /// Not able to deserialize this value.
+    CannotDeserialize,
     /// An error happened in an include.
     BadInclude,
     /// An error happened in a super block.
@@ -134,6 +138,10 @@ pub enum ErrorKind {
     CannotUnpack,
     /// Failed writing output.
     WriteFailure,
+    /// The render used up all of its fuel.
+    FuelExhausted,
+    /// Templates, macros or values were nested too deeply.
+    RecursionLimitExceeded,
 }
 
 impl ErrorKind {
@@ -153,10 +161,13 @@ impl ErrorKind {
             ErrorKind::BadEscape => "bad string escape",
             ErrorKind::UndefinedError => "undefined value",
             ErrorKind::BadSerialization => "could not serialize to internal format",
+            ErrorKind::CannotDeserialize => "cannot deserialize",
             ErrorKind::BadInclude => "could not render include",
             ErrorKind::EvalBlock => "could not render block",
             ErrorKind::CannotUnpack => "cannot unpack",
             ErrorKind::WriteFailure => "failed to write output",
+            ErrorKind::FuelExhausted => "fuel exhausted",
+            ErrorKind::RecursionLimitExceeded => "recursion limit exceeded",
         }
     }
 }
@@ -231,6 +242,15 @@ impl Error {
         )
     }
 
+    /// Annotates the error with the name of the test that raised it.
+    pub(crate) fn with_test_name(mut self, name: &str) -> Error {
+        self.repr.detail = Some(match self.repr.detail {
+            Some(detail) => format!("test `{}` failed: {}", name, detail).into(),
+            None => format!("test `{}` failed", name).into(),
+        });
+        self
+    }
+
     /// Attaches another error as source to this error.
     #[allow(unused)]
     pub fn with_source<E: std::error::Error + Send + Sync + 'static>(mut self, source: E) -> Self {
@@ -274,6 +294,56 @@ impl Error {
         self.repr.debug_info.as_ref()
     }
 
+    /// Returns a displayable version of the debug information.
+    ///
+    /// This renders the source context and the referenced variables of this
+    /// error and of all template errors in its chain of sources.  That way an
+    /// error raised in an included template shows both the location of the
+    /// include and the failing line of the included template.  Nothing is
+    /// rendered if the error does not carry debug information.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let mut env = Environment::new();
+    /// env.set_debug(true);
+    /// env.add_template("item.txt", "{{ item.name.first }}").unwrap();
+    /// env.add_template("list.txt", "{% include 'item.txt' %}").unwrap();
+    /// let tmpl = env.get_template("list.txt").unwrap();
+    /// let err = tmpl.render(context!(item => context!())).unwrap_err();
+    /// let info = err.display_debug_info().to_string();
+    /// assert!(info.contains("1 > {% include 'item.txt' %}"));
+    /// assert!(info.contains("1 > {{ item.name.first }}"));
+    /// ```
+    #[cfg(feature = "debug")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "debug")))]
+    pub fn display_debug_info(&self) -> impl fmt::Display + '_ {
+        struct DebugInfoChain<'a>(&'a Error);
+
+        impl<'a> fmt::Display for DebugInfoChain<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut err = Some(self.0);
+                while let Some(current) = err {
+                    if let Some(info) = current.debug_info() {
+                        ok!(crate::debug::render_debug_info(
+                            f,
+                            current.name(),
+                            current.kind(),
+                            current.line(),
+                            current.span(),
+                            info,
+                        ));
+                        ok!(writeln!(f));
+                    }
+                    err = std::error::Error::source(current)
+                        .and_then(|source| source.downcast_ref::<Error>());
+                }
+                Ok(())
+            }
+        }
+
+        DebugInfoChain(self)
+    }
+
     #[cfg(feature = "debug")]
     #[cfg_attr(docsrs, doc(cfg(feature = "debug")))]
     pub(crate) fn attach_debug_info(&mut self, value: crate::debug::DebugInfo) {
@@ -319,6 +389,85 @@ impl serde::ser::Error for Error {
     }
 }
 
+#[cfg(feature = "deserialization")]
+impl serde::de::Error for Error {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Error::new(ErrorKind::CannotDeserialize, msg.to_string())
+    }
+}
+
+/// Represents a non-fatal issue encountered while rendering.
+///
+/// Warnings are only collected when rendering with
+/// [`Template::render_with_warnings`](crate::Template::render_with_warnings).
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub(crate) kind: WarningKind,
+    pub(crate) detail: String,
+    pub(crate) name: String,
+    pub(crate) line: Option<usize>,
+}
+
+/// An enum describing the warning kind.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A variable, attribute or item lookup produced an undefined value.
+    UndefinedAccess,
+}
+
+impl WarningKind {
+    fn description(self) -> &'static str {
+        match self {
+            WarningKind::UndefinedAccess => "undefined access",
+        }
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        ok!(write!(
+            f,
+            "{}: {} (in {}",
+            self.kind, self.detail, self.name
+        ));
+        if let Some(line) = self.line {
+            ok!(write!(f, ":{}", line));
+        }
+        write!(f, ")")
+    }
+}
+
+impl Warning {
+    /// Returns the warning kind.
+    pub fn kind(&self) -> WarningKind {
+        self.kind
+    }
+
+    /// Returns the description of the warning.
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// Returns the name of the template that caused the warning.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the line number where the warning was caused.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
 pub fn attach_basic_debug_info<T>(rv: Result<T, Error>, source: &str) -> Result<T, Error> {
     #[cfg(feature = "debug")]
     {
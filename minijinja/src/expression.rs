@@ -1,14 +1,18 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 
 use serde::Serialize;
 
 use crate::compiler::instructions::Instructions;
-use crate::environment::Environment;
-use crate::error::Error;
+use crate::compiler::meta::{find_undeclared_in_expr, is_pure_expr};
+use crate::compiler::parser::parse_expr;
+use crate::environment::{compile_expression_instructions, Environment};
+use crate::error::{attach_basic_debug_info, Error};
 use crate::output::Output;
 use crate::value::Value;
-use crate::vm::Vm;
+use crate::vm::{Extensions, Vm};
 
 /// A handle to a compiled expression.
 ///
@@ -31,8 +35,45 @@ use crate::vm::Vm;
 /// assert!(rv.is_true());
 /// ```
 pub struct Expression<'env, 'source> {
-    env: &'env Environment<'source>,
-    instructions: Instructions<'source>,
+    env: &'env Environment<'env>,
+    instructions: ExpressionBacking<'source>,
+    // caches the result of `is_constant`, see the `CONSTNESS_*` constants
+    constness: AtomicU8,
+}
+
+const CONSTNESS_UNKNOWN: u8 = 0;
+const CONSTNESS_CONSTANT: u8 = 1;
+const CONSTNESS_VARIABLE: u8 = 2;
+
+enum ExpressionBacking<'source> {
+    Borrowed(Instructions<'source>),
+    Owned(OwnedInstructions),
+}
+
+/// Instructions together with the source they borrow from.
+struct OwnedInstructions {
+    // borrows from `source` which is why it's declared (and dropped) first.
+    instructions: Instructions<'static>,
+    #[allow(unused)]
+    source: Arc<str>,
+}
+
+impl OwnedInstructions {
+    fn compile(source: String) -> Result<OwnedInstructions, Error> {
+        let source: Arc<str> = source.into();
+        // SAFETY: the source is on the heap and never changes.  It is kept
+        // alive by the struct for as long as the instructions exist and the
+        // instructions are only handed out with the lifetime of the struct.
+        let borrowed = unsafe { &*(&*source as *const str) };
+        Ok(OwnedInstructions {
+            instructions: ok!(compile_expression_instructions(borrowed)),
+            source,
+        })
+    }
+
+    fn get(&self) -> &Instructions<'_> {
+        &self.instructions
+    }
 }
 
 impl<'env, 'source> fmt::Debug for Expression<'env, 'source> {
@@ -45,10 +86,25 @@ impl<'env, 'source> fmt::Debug for Expression<'env, 'source> {
 
 impl<'env, 'source> Expression<'env, 'source> {
     pub(crate) fn new(
-        env: &'env Environment<'source>,
+        env: &'env Environment<'env>,
         instructions: Instructions<'source>,
     ) -> Expression<'env, 'source> {
-        Expression { env, instructions }
+        Expression {
+            env,
+            instructions: ExpressionBacking::Borrowed(instructions),
+            constness: AtomicU8::new(CONSTNESS_UNKNOWN),
+        }
+    }
+
+    pub(crate) fn new_owned(
+        env: &'env Environment<'env>,
+        source: String,
+    ) -> Result<Expression<'env, 'source>, Error> {
+        Ok(Expression {
+            env,
+            instructions: ExpressionBacking::Owned(ok!(OwnedInstructions::compile(source))),
+            constness: AtomicU8::new(CONSTNESS_UNKNOWN),
+        })
     }
 
     /// Evaluates the expression with some context.
@@ -60,13 +116,87 @@ impl<'env, 'source> Expression<'env, 'source> {
         self._eval(Value::from_serializable(&ctx))
     }
 
+    /// Returns a set of all variables the expression references.
+    ///
+    /// This works like [`Template::undeclared_variables`](crate::Template::undeclared_variables):
+    /// if `nested` is set to `true`, attribute lookups are returned as dotted
+    /// paths such as `user.name` where they can be determined statically.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// let expr = env.compile_expression("user.age >= min_age").unwrap();
+    /// let mut vars: Vec<_> = expr.undeclared_variables(true).unwrap().into_iter().collect();
+    /// vars.sort();
+    /// assert_eq!(vars, ["min_age", "user.age"]);
+    /// ```
+    pub fn undeclared_variables(&self, nested: bool) -> Result<HashSet<String>, Error> {
+        let source = self.instructions().source();
+        let ast = ok!(attach_basic_debug_info(parse_expr(source), source));
+        Ok(find_undeclared_in_expr(&ast, nested))
+    }
+
+    /// Returns `true` if the expression always evaluates to the same value.
+    ///
+    /// This is the case if the expression does not reference any variables.
+    /// The check is conservative: expressions calling functions or methods
+    /// are never considered constant and neither are expressions using
+    /// filters or tests other than the built-in ones, as those could have
+    /// side effects.  A built-in filter or test that was replaced by a custom
+    /// implementation of the same name is not considered built-in.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// assert!(env.compile_expression("[1, 2, 3]|length > 2").unwrap().is_constant());
+    /// assert!(!env.compile_expression("items|length > 2").unwrap().is_constant());
+    /// assert!(!env.compile_expression("range(3)|length > 2").unwrap().is_constant());
+    /// ```
+    pub fn is_constant(&self) -> bool {
+        match self.constness.load(Ordering::Relaxed) {
+            CONSTNESS_CONSTANT => return true,
+            CONSTNESS_VARIABLE => return false,
+            _ => {}
+        }
+        // the environment is borrowed for the lifetime of the expression so
+        // its filters and tests cannot change and the result can be cached.
+        let rv = match parse_expr(self.instructions().source()) {
+            Ok(ast) => {
+                find_undeclared_in_expr(&ast, false).is_empty()
+                    && is_pure_expr(
+                        &ast,
+                        &|name| self.env.get_filter(name).map_or(false, |f| f.is_builtin()),
+                        &|name| self.env.get_test(name).map_or(false, |t| t.is_builtin()),
+                    )
+            }
+            Err(_) => false,
+        };
+        self.constness.store(
+            if rv {
+                CONSTNESS_CONSTANT
+            } else {
+                CONSTNESS_VARIABLE
+            },
+            Ordering::Relaxed,
+        );
+        rv
+    }
+
+    fn instructions(&self) -> &Instructions<'_> {
+        match self.instructions {
+            ExpressionBacking::Borrowed(ref x) => x,
+            ExpressionBacking::Owned(ref x) => x.get(),
+        }
+    }
+
     fn _eval(&self, root: Value) -> Result<Value, Error> {
         Ok(ok!(Vm::new(self.env).eval(
-            &self.instructions,
+            self.instructions(),
             root,
             &BTreeMap::new(),
             &mut Output::null(),
             crate::AutoEscape::None,
+            &Extensions::new(),
         ))
         .expect("expression evaluation did not leave value on stack"))
     }
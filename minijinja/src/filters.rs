@@ -83,7 +83,10 @@ use crate::{AutoEscape, Output};
 type FilterFunc = dyn Fn(&State, &[Value]) -> Result<Value, Error> + Sync + Send + 'static;
 
 #[derive(Clone)]
-pub(crate) struct BoxedFilter(Arc<FilterFunc>);
+pub(crate) struct BoxedFilter {
+    func: Arc<FilterFunc>,
+    builtin: bool,
+}
 
 /// A utility trait that represents filters.
 ///
@@ -99,7 +102,7 @@ pub(crate) struct BoxedFilter(Arc<FilterFunc>);
 /// * `Result<Rv, Error>` where `Rv` implements `Into<Value>`
 ///
 /// Filters accept one mandatory parameter which is the value the filter is
-/// applied to and up to 4 extra parameters.  The extra parameters can be
+/// applied to and up to 5 extra parameters.  The extra parameters can be
 /// marked optional by using `Option<T>`.  The last argument can also use
 /// [`Rest<T>`](crate::value::Rest) to capture the remaining arguments.  All
 /// types are supported for which [`ArgType`](crate::value::ArgType) is implemented.
@@ -159,6 +162,66 @@ pub(crate) struct BoxedFilter(Arc<FilterFunc>);
 /// ```jinja
 /// {{ "|".join(1, 2, 3) }} -> 1|2|3
 /// ```
+///
+/// # Accessing State
+///
+/// If the first argument of a filter is [`&State`](crate::State) the engine
+/// passes the current state in addition to the value.  The state does not
+/// consume a template argument, so such a filter is invoked like any other
+/// filter.  This is useful to consult context variables or globals via
+/// [`State::lookup`](crate::State::lookup) or to look at the current
+/// [`auto_escape`](crate::State::auto_escape) mode:
+///
+/// ```
+/// # use minijinja::Environment;
+/// # let mut env = Environment::new();
+/// use minijinja::State;
+///
+/// env.add_filter("translate", |state: &State, msg: String| {
+///     match state.lookup("locale").as_ref().and_then(|x| x.as_str()) {
+///         Some("de") if msg == "Hello" => "Hallo".to_string(),
+///         _ => msg,
+///     }
+/// });
+/// ```
+///
+/// ```jinja
+/// {{ "Hello"|translate }} -> Hallo (if locale is "de")
+/// ```
+///
+/// When a filter is applied within a macro, the state is the one of the
+/// macro and not of the caller.  [`lookup`](crate::State::lookup) then only
+/// sees the macro's arguments, the variables set within the macro and the
+/// globals of the environment, but not the context passed to the render call.
+/// [`name`](crate::State::name) returns the template the macro was defined in
+/// whereas the auto escape mode is inherited from the caller.  To make
+/// context variables available to such filters pass them to the macro
+/// explicitly.
+///
+/// # Closures
+///
+/// Filters are not restricted to plain functions.  Any closure that is
+/// `Send + Sync + 'static` can be registered which makes it possible to
+/// capture configuration that is specific to an environment:
+///
+/// ```
+/// # use minijinja::Environment;
+/// # let mut env = Environment::new();
+/// use std::sync::Arc;
+///
+/// struct Config {
+///     static_url: String,
+/// }
+///
+/// let config = Arc::new(Config { static_url: "/static/".into() });
+/// env.add_filter("static", move |path: String| {
+///     format!("{}{}", config.static_url, path)
+/// });
+/// ```
+///
+/// ```jinja
+/// {{ "style.css"|static }} -> /static/style.css
+/// ```
 pub trait Filter<Rv, Args>: Send + Sync + 'static {
     /// Applies a filter to value with the given arguments.
     ///
@@ -190,6 +253,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedFilter {
     /// Creates a new boxed filter.
@@ -199,15 +263,32 @@ impl BoxedFilter {
         Rv: FunctionResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        BoxedFilter(Arc::new(move |state, args| -> Result<Value, Error> {
-            f.apply_to(ok!(Args::from_values(Some(state), args)), SealedMarker)
-                .into_result()
-        }))
+        BoxedFilter {
+            func: Arc::new(move |state, args| -> Result<Value, Error> {
+                f.apply_to(ok!(Args::from_values(Some(state), args)), SealedMarker)
+                    .into_result()
+            }),
+            builtin: false,
+        }
+    }
+
+    /// Marks the filter as one of the built-in filters.
+    ///
+    /// As filters can be replaced by custom ones at any time, the marker is
+    /// used to check if a filter name still refers to the built-in filter.
+    pub fn builtin(mut self) -> BoxedFilter {
+        self.builtin = true;
+        self
+    }
+
+    /// Checks if this is a built-in filter.
+    pub fn is_builtin(&self) -> bool {
+        self.builtin
     }
 
     /// Applies the filter to a value and argument.
     pub fn apply_to(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
-        (self.0)(state, args)
+        (self.func)(state, args)
     }
 }
 
@@ -250,8 +331,10 @@ mod builtins {
     use super::*;
 
     use crate::error::ErrorKind;
-    use crate::value::ValueRepr;
+    use crate::value::{Kwargs, Rest, SeqObject, ValueKind, ValueRepr};
     use std::borrow::Cow;
+    use std::cmp::Ordering;
+    use std::collections::BTreeSet;
     use std::fmt::Write;
     use std::mem;
 
@@ -301,22 +384,53 @@ mod builtins {
         rv
     }
 
+    /// Escapes a string for XML.
+    ///
+    /// Unlike [`escape`] this always uses XML escaping rules independently
+    /// of the current auto escape mode: `<`, `>`, `&`, `"` and `'` are
+    /// replaced by the entities predefined by XML.  If `true` is passed as
+    /// argument, control characters are additionally emitted as numeric
+    /// character references.
+    ///
+    /// ```jinja
+    /// <title>{{ feed.title|escape_xml }}</title>
+    /// <summary>{{ feed.summary|escape_xml(true) }}</summary>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn escape_xml(v: Value, escape_control_chars: Option<bool>) -> Value {
+        if v.is_safe() {
+            return v;
+        }
+        let escaper = |s: &str| {
+            crate::utils::XmlEscape::new(s)
+                .escape_control_chars(escape_control_chars.unwrap_or(false))
+                .to_string()
+        };
+        Value::from_safe_string(match v.as_str() {
+            Some(s) => escaper(s),
+            None => escaper(&v.to_string()),
+        })
+    }
+
     /// Does a string replace.
     ///
     /// It replaces all ocurrences of the first parameter with the second.
+    /// The result is only safe if the value and both parameters are safe.
     ///
     /// ```jinja
     /// {{ "Hello World"|replace("Hello", "Goodbye") }}
     ///   -> Goodbye World
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn replace(
-        _state: &State,
-        v: Cow<'_, str>,
-        from: Cow<'_, str>,
-        to: Cow<'_, str>,
-    ) -> String {
-        v.replace(&from as &str, &to as &str)
+    pub fn replace(_state: &State, v: Value, from: Value, to: Value) -> Value {
+        let rv = v
+            .to_cowstr()
+            .replace(&from.to_cowstr() as &str, &to.to_cowstr() as &str);
+        if v.is_safe() && from.is_safe() && to.is_safe() {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        }
     }
 
     /// Returns the "length" of the value
@@ -339,26 +453,443 @@ mod builtins {
     /// Dict sorting functionality.
     ///
     /// This filter works like `|items` but sorts the pairs by key first.
+    /// The following keyword arguments are accepted:
+    ///
+    /// * `by`: set to `"value"` to sort by value instead of by key.
+    /// * `case_sensitive`: set to `true` to sort strings case sensitively.
+    ///   Defaults to `false`.
+    /// * `reverse`: set to `true` to sort in descending order.
+    ///
+    /// ```jinja
+    /// {% for key, value in scores|dictsort(by="value", reverse=true) %}
+    ///   <li>{{ key }}: {{ value }}
+    /// {% endfor %}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn dictsort(v: Value) -> Result<Value, Error> {
-        let mut pairs = match v.0 {
-            ValueRepr::Map(ref v, _) => v.iter().collect::<Vec<_>>(),
-            _ => {
+    pub fn dictsort(v: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let by_value = match ok!(kwargs.get::<Option<&str>>("by")) {
+            None | Some("key") => false,
+            Some("value") => true,
+            Some(other) => {
                 return Err(Error::new(
                     ErrorKind::InvalidOperation,
-                    "cannot convert value into pair list",
+                    format!("invalid value for 'by': {:?}", other),
                 ))
             }
         };
-        pairs.sort_by(|a, b| a.0.cmp(b.0));
+        let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
+        let reverse = ok!(kwargs.get::<Option<bool>>("reverse")).unwrap_or(false);
+        ok!(kwargs.assert_all_used());
+
+        let mut pairs = ok!(v.items());
+        pairs.sort_by(|a, b| {
+            let (a, b) = if by_value { (&a.1, &b.1) } else { (&a.0, &b.0) };
+            let rv = sort_helper(a, b, case_sensitive);
+            if reverse {
+                rv.reverse()
+            } else {
+                rv
+            }
+        });
         Ok(Value::from(
             pairs
                 .into_iter()
-                .map(|(k, v)| vec![Value::from(k.clone()), v.clone()])
+                .map(|(k, v)| vec![k, v])
                 .collect::<Vec<_>>(),
         ))
     }
 
+    fn sort_helper(a: &Value, b: &Value, case_sensitive: bool) -> Ordering {
+        if !case_sensitive {
+            if let (Some(a), Some(b)) = (a.as_str(), b.as_str()) {
+                return a.to_lowercase().cmp(&b.to_lowercase());
+            }
+        }
+        a.cmp(b)
+    }
+
+    fn get_attribute_path(value: &Value, path: &str) -> Result<Value, Error> {
+        let mut rv = value.clone();
+        for part in path.split('.') {
+            rv = match part.parse::<i64>() {
+                Ok(idx) => ok!(rv.get_item(&Value::from(idx))),
+                Err(_) => ok!(rv.get_attr(part)),
+            };
+        }
+        Ok(rv)
+    }
+
+    /// Returns the sorted version of the given list.
+    ///
+    /// The sort is stable and accepts the following keyword arguments:
+    ///
+    /// * `reverse`: set to `true` to sort in descending order.
+    /// * `case_sensitive`: set to `true` to sort strings case sensitively.
+    ///   Defaults to `false`.
+    /// * `attribute`: sorts by an attribute of the items instead of the items
+    ///   themselves.  Dotted paths like `address.city` are supported and
+    ///   multiple attributes can be separated by commas to sort by several
+    ///   keys at once.
+    ///
+    /// Sorting values of different types (other than mixing numbers and
+    /// booleans) fails with an error.
+    ///
+    /// ```jinja
+    /// {% for user in users|sort(attribute="dept,name") %}
+    ///   <li>{{ user.dept }}: {{ user.name }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn sort(v: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let reverse = ok!(kwargs.get::<Option<bool>>("reverse")).unwrap_or(false);
+        let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
+        let attributes = ok!(kwargs.get::<Option<&str>>("attribute"))
+            .map(|x| x.split(',').map(str::trim).collect::<Vec<_>>())
+            .unwrap_or_default();
+        ok!(kwargs.assert_all_used());
+
+        let mut items = ok!(ok!(v.try_iter())
+            .map(|item| {
+                let keys = if attributes.is_empty() {
+                    vec![item.clone()]
+                } else {
+                    ok!(attributes
+                        .iter()
+                        .map(|attr| get_attribute_path(&item, attr))
+                        .collect::<Result<Vec<_>, _>>())
+                };
+                Ok((keys, item))
+            })
+            .collect::<Result<Vec<_>, Error>>());
+
+        // refuse to sort values that cannot be meaningfully compared
+        let sort_kind = |v: &Value| match v.kind() {
+            ValueKind::Bool => ValueKind::Number,
+            kind => kind,
+        };
+        if let Some((first, _)) = items.first() {
+            for (keys, _) in &items[1..] {
+                for (a, b) in first.iter().zip(keys.iter()) {
+                    if sort_kind(a) != sort_kind(b) {
+                        return Err(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("cannot sort values of type {} and {}", a.kind(), b.kind()),
+                        ));
+                    }
+                }
+            }
+        }
+
+        items.sort_by(|a, b| {
+            let rv =
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .map(|(a, b)| sort_helper(a, b, case_sensitive))
+                    .find(|x| *x != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal);
+            if reverse {
+                rv.reverse()
+            } else {
+                rv
+            }
+        });
+        Ok(Value::from(
+            items.into_iter().map(|x| x.1).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Returns a list of unique items from the given iterable.
+    ///
+    /// The first occurrence of every item is kept and the original order is
+    /// retained.  Items are compared by value.  The following keyword
+    /// arguments are accepted:
+    ///
+    /// * `case_sensitive`: set to `true` to compare strings case sensitively.
+    ///   Defaults to `false`.
+    /// * `attribute`: deduplicate by an attribute of the items instead of
+    ///   the items themselves.  The items are returned unchanged.  Dotted
+    ///   paths like `address.city` are supported.
+    ///
+    /// ```jinja
+    /// {{ ["foo", "bar", "Foo", "bar"]|unique }}
+    ///   -> ["foo", "bar"]
+    /// {% for user in users|unique(attribute="email") %}
+    ///   <li>{{ user.name }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn unique(v: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
+        let attribute = ok!(kwargs.get::<Option<&str>>("attribute"));
+        ok!(kwargs.assert_all_used());
+
+        let mut seen = BTreeSet::new();
+        let mut rv = Vec::new();
+        for item in ok!(v.try_iter()) {
+            let key = ok!(unique_key(&item, attribute, case_sensitive));
+            if seen.insert(key) {
+                rv.push(item);
+            }
+        }
+        Ok(Value::from(rv))
+    }
+
+    fn unique_key(
+        item: &Value,
+        attribute: Option<&str>,
+        case_sensitive: bool,
+    ) -> Result<Value, Error> {
+        let key = match attribute {
+            Some(attr) => ok!(get_attribute_path(item, attr)),
+            None => item.clone(),
+        };
+        Ok(match key.as_str() {
+            Some(s) if !case_sensitive => Value::from(s.to_lowercase()),
+            _ => key,
+        })
+    }
+
+    /// Removes consecutive duplicate items from the given iterable.
+    ///
+    /// Unlike `|unique` this only drops an item if it's equal to the item
+    /// directly before it, which makes it useful to collapse runs of
+    /// repeated values in sorted data or time series.  It accepts the same
+    /// `case_sensitive` and `attribute` keyword arguments as `|unique`.
+    ///
+    /// ```jinja
+    /// {{ [1, 1, 2, 2, 2, 1, 3, 3]|unique_justseen }}
+    ///   -> [1, 2, 1, 3]
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn unique_justseen(v: Value, kwargs: Kwargs) -> Result<Value, Error> {
+        let case_sensitive = ok!(kwargs.get::<Option<bool>>("case_sensitive")).unwrap_or(false);
+        let attribute = ok!(kwargs.get::<Option<&str>>("attribute"));
+        ok!(kwargs.assert_all_used());
+
+        let mut last_key = None;
+        let mut rv = Vec::new();
+        for item in ok!(v.try_iter()) {
+            let key = ok!(unique_key(&item, attribute, case_sensitive));
+            if last_key.as_ref() != Some(&key) {
+                rv.push(item);
+                last_key = Some(key);
+            }
+        }
+        Ok(Value::from(rv))
+    }
+
+    fn select_or_reject(
+        state: &State,
+        invert: bool,
+        value: Value,
+        attr: Option<&str>,
+        test_name: Option<&str>,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let test = match test_name {
+            Some(name) => Some(ok!(state.env.get_test(name).ok_or_else(|| {
+                Error::new(ErrorKind::UnknownTest, format!("test {} is unknown", name))
+            }))),
+            None => None,
+        };
+        let mut test_args = Vec::with_capacity(args.len() + 1);
+        test_args.push(Value::UNDEFINED);
+        test_args.extend(args);
+
+        let mut rv = Vec::new();
+        for item in ok!(value.try_iter()) {
+            let test_value = match attr {
+                Some(attr) => ok!(get_attribute_path(&item, attr)),
+                None => item.clone(),
+            };
+            let passed = match (test, test_name) {
+                (Some(test), Some(name)) => {
+                    test_args[0] = test_value;
+                    ok!(test
+                        .perform(state, &test_args)
+                        .map_err(|err| err.with_test_name(name)))
+                }
+                _ => test_value.is_true(),
+            };
+            if passed != invert {
+                rv.push(item);
+            }
+        }
+        Ok(Value::from(rv))
+    }
+
+    /// Filters a sequence of values by applying a test to each value.
+    ///
+    /// Only values for which the test succeeds are retained.  Additional
+    /// arguments are passed to the test.  If no test is given the
+    /// truthiness of the value is checked instead.  The test is looked up
+    /// by name in the environment which means custom tests (including
+    /// tests that accept the [`State`]) can be used as well.
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3, 4]|select("odd") }} -> [1, 3]
+    /// {{ [false, null, 42]|select }} -> [42]
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn select(
+        state: &State,
+        value: Value,
+        test_name: Option<&str>,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        select_or_reject(state, false, value, None, test_name, args.0)
+    }
+
+    /// Filters a sequence of values by applying a test to each value.
+    ///
+    /// This is the inverse of `|select`: values for which the test
+    /// succeeds are removed.
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3, 4]|reject("odd") }} -> [2, 4]
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn reject(
+        state: &State,
+        value: Value,
+        test_name: Option<&str>,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        select_or_reject(state, true, value, None, test_name, args.0)
+    }
+
+    /// Filters a sequence of objects by applying a test to an attribute.
+    ///
+    /// Works like `|select` but the test is applied to the given attribute
+    /// of each value.  Dotted paths like `address.city` are supported.
+    ///
+    /// ```jinja
+    /// {% for user in users|selectattr("is_active") %}
+    ///   <li>{{ user.name }}
+    /// {% endfor %}
+    /// {{ users|selectattr("id", "odd")|length }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn selectattr(
+        state: &State,
+        value: Value,
+        attr: &str,
+        test_name: Option<&str>,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        select_or_reject(state, false, value, Some(attr), test_name, args.0)
+    }
+
+    /// Filters a sequence of objects by applying a test to an attribute.
+    ///
+    /// This is the inverse of `|selectattr`.
+    ///
+    /// ```jinja
+    /// {% for user in users|rejectattr("is_active") %}
+    ///   <li>{{ user.name }} (inactive)
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn rejectattr(
+        state: &State,
+        value: Value,
+        attr: &str,
+        test_name: Option<&str>,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        select_or_reject(state, true, value, Some(attr), test_name, args.0)
+    }
+
+    fn pick_or_omit(
+        state: &State,
+        invert: bool,
+        value: Value,
+        keys_or_test: Value,
+        args: Vec<Value>,
+    ) -> Result<Value, Error> {
+        let items = ok!(value.items());
+        let mut rv = Vec::with_capacity(items.len());
+        if let Some(name) = keys_or_test.as_str() {
+            let test = ok!(state.env.get_test(name).ok_or_else(|| {
+                Error::new(ErrorKind::UnknownTest, format!("test {} is unknown", name))
+            }));
+            let mut test_args = Vec::with_capacity(args.len() + 1);
+            test_args.push(Value::UNDEFINED);
+            test_args.extend(args);
+            for (key, value) in items {
+                test_args[0] = key.clone();
+                let passed = ok!(test
+                    .perform(state, &test_args)
+                    .map_err(|err| err.with_test_name(name)));
+                if passed != invert {
+                    rv.push((ok!(key.try_into_key()), value));
+                }
+            }
+        } else if matches!(keys_or_test.kind(), ValueKind::Seq) {
+            if !args.is_empty() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            let keys = ok!(keys_or_test.try_iter()).collect::<Vec<_>>();
+            for (key, value) in items {
+                if keys.contains(&key) != invert {
+                    rv.push((ok!(key.try_into_key()), value));
+                }
+            }
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "expected a list of keys or a test name, got {}",
+                    keys_or_test.kind()
+                ),
+            ));
+        }
+        Ok(rv.into_iter().collect())
+    }
+
+    /// Returns a new mapping with only the selected keys.
+    ///
+    /// The keys are either given as a list or selected by a test which is
+    /// invoked for each key with the remaining arguments, similar to how
+    /// `|select` works.  This also works with struct objects.  The order of
+    /// the remaining keys is retained if the `preserve_order` feature is
+    /// enabled.
+    ///
+    /// ```jinja
+    /// {{ {"a": 1, "b": 2, "c": 3}|pick(["a", "c"]) }}
+    ///   -> {"a": 1, "c": 3}
+    /// {{ record|pick("startingwith", "meta_") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn pick(
+        state: &State,
+        value: Value,
+        keys_or_test: Value,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        pick_or_omit(state, false, value, keys_or_test, args.0)
+    }
+
+    /// Returns a new mapping without the selected keys.
+    ///
+    /// This is the inverse of `|pick`: the keys given as a list or matched
+    /// by the test are removed.
+    ///
+    /// ```jinja
+    /// {{ {"user": "john", "secret": "hunter2"}|omit(["secret"]) }}
+    ///   -> {"user": "john"}
+    /// {{ record|omit("startingwith", "_") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn omit(
+        state: &State,
+        value: Value,
+        keys_or_test: Value,
+        args: Rest<Value>,
+    ) -> Result<Value, Error> {
+        pick_or_omit(state, true, value, keys_or_test, args.0)
+    }
+
     /// Returns a list of pairs (items) from a mapping.
     ///
     /// This can be used to iterate over keys and values of a mapping
@@ -366,7 +897,8 @@ mod builtins {
     /// which is typically arbitrary unless the `preserve_order` feature
     /// is used in which case the original order of the map is retained.
     /// It's generally better to use `|dictsort` which sorts the map by
-    /// key before iterating.
+    /// key before iterating.  The same pairs are also returned by the
+    /// `items()` method of maps.
     ///
     /// ```jinja
     /// <dl>
@@ -379,17 +911,40 @@ mod builtins {
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn items(v: Value) -> Result<Value, Error> {
         Ok(Value::from(
-            match v.0 {
-                ValueRepr::Map(ref v, _) => v.iter(),
-                _ => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidOperation,
-                        "cannot convert value into pair list",
-                    ))
-                }
-            }
-            .map(|(k, v)| vec![Value::from(k.clone()), v.clone()])
-            .collect::<Vec<_>>(),
+            ok!(v.items())
+                .into_iter()
+                .map(|(k, v)| vec![k, v])
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Returns a list of keys of a mapping.
+    ///
+    /// Like `|items` this uses the original order of the map.
+    ///
+    /// ```jinja
+    /// {{ {"a": 1, "b": 2}|keys|join(", ") }}
+    ///   -> a, b
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn keys(v: Value) -> Result<Value, Error> {
+        Ok(Value::from(
+            ok!(v.items()).into_iter().map(|x| x.0).collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Returns a list of values of a mapping.
+    ///
+    /// Like `|items` this uses the original order of the map.
+    ///
+    /// ```jinja
+    /// {{ {"a": 1, "b": 2}|values|join(", ") }}
+    ///   -> 1, 2
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn values(v: Value) -> Result<Value, Error> {
+        Ok(Value::from(
+            ok!(v.items()).into_iter().map(|x| x.1).collect::<Vec<_>>(),
         ))
     }
 
@@ -415,54 +970,290 @@ mod builtins {
     }
 
     /// Trims a value
+    ///
+    /// As trimming only removes characters a safe value stays safe.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn trim(s: Cow<'_, str>, chars: Option<Cow<'_, str>>) -> String {
-        match chars {
+    pub fn trim(v: Value, chars: Option<Cow<'_, str>>) -> Value {
+        let s = v.to_cowstr();
+        let rv = match chars {
             Some(chars) => {
                 let chars = chars.chars().collect::<Vec<_>>();
                 s.trim_matches(&chars[..]).to_string()
             }
             None => s.trim().to_string(),
+        };
+        if v.is_safe() {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        }
+    }
+
+    /// Truncates a string to a maximum length.
+    ///
+    /// Strings longer than `length` (defaults to `255`) characters are cut off
+    /// and `end` (defaults to `"..."`) is appended so that the result is at
+    /// most `length` characters long.  The string is cut at the last word
+    /// boundary before that point unless `killwords` is set to `true`.
+    /// Strings that exceed the length by no more than `leeway` (defaults to
+    /// `5`) characters are returned unchanged.  All parameters can also be
+    /// passed as keyword arguments.
+    ///
+    /// ```jinja
+    /// {{ "foo bar baz qux"|truncate(12) }}
+    ///   -> "foo bar baz qux"
+    /// {{ "foo bar baz qux"|truncate(9) }}
+    ///   -> "foo..."
+    /// {{ "foo bar baz qux"|truncate(9, true) }}
+    ///   -> "foo ba..."
+    /// {{ "foo bar baz qux"|truncate(11, false, " >>", 0) }}
+    ///   -> "foo bar >>"
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn truncate(
+        value: Cow<'_, str>,
+        length: Option<usize>,
+        killwords: Option<bool>,
+        end: Option<Cow<'_, str>>,
+        leeway: Option<usize>,
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        let length = ok!(arg_or_kwarg(length, &kwargs, "length")).unwrap_or(255);
+        let killwords = ok!(arg_or_kwarg(killwords, &kwargs, "killwords")).unwrap_or(false);
+        let end = ok!(arg_or_kwarg(end, &kwargs, "end")).unwrap_or(Cow::Borrowed("..."));
+        let leeway = ok!(arg_or_kwarg(leeway, &kwargs, "leeway")).unwrap_or(5);
+        ok!(kwargs.assert_all_used());
+
+        let end_len = end.chars().count();
+        if length < end_len {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("expected length >= {}, got {}", end_len, length),
+            ));
+        }
+        if value.chars().count() <= length + leeway {
+            return Ok(value.into_owned());
+        }
+        let cut = value
+            .char_indices()
+            .nth(length - end_len)
+            .map_or(value.len(), |x| x.0);
+        let mut rv = &value[..cut];
+        if !killwords {
+            if let Some(pos) = rv.rfind(' ') {
+                rv = &rv[..pos];
+            }
+        }
+        Ok(format!("{}{}", rv, end))
+    }
+
+    /// Wraps a string to a given width.
+    ///
+    /// Every line of the input is wrapped separately so that no line is longer
+    /// than `width` (defaults to `79`) characters.  Words longer than the
+    /// width are split up unless `break_long_words` is set to `false`.  The
+    /// wrapped lines are joined with `wrapstring` which defaults to a
+    /// newline.  All parameters can also be passed as keyword arguments.
+    ///
+    /// ```jinja
+    /// {{ "Lorem ipsum dolor sit amet"|wordwrap(11) }}
+    ///   -> "Lorem ipsum\ndolor sit\namet"
+    /// {{ "Lorem ipsum dolor sit amet"|wordwrap(11, wrapstring="<br>") }}
+    ///   -> "Lorem ipsum<br>dolor sit<br>amet"
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn wordwrap(
+        value: Cow<'_, str>,
+        width: Option<usize>,
+        break_long_words: Option<bool>,
+        wrapstring: Option<Cow<'_, str>>,
+        kwargs: Kwargs,
+    ) -> Result<String, Error> {
+        let width = ok!(arg_or_kwarg(width, &kwargs, "width")).unwrap_or(79);
+        let break_long_words =
+            ok!(arg_or_kwarg(break_long_words, &kwargs, "break_long_words")).unwrap_or(true);
+        let wrapstring = ok!(arg_or_kwarg(wrapstring, &kwargs, "wrapstring"));
+        ok!(kwargs.assert_all_used());
+
+        let wrapstring = wrapstring.as_deref().unwrap_or("\n");
+        let mut rv = String::new();
+        for (idx, line) in value.lines().enumerate() {
+            if idx > 0 {
+                rv.push_str(wrapstring);
+            }
+            for (idx, wrapped) in wrap_line(line, width, break_long_words).iter().enumerate() {
+                if idx > 0 {
+                    rv.push_str(wrapstring);
+                }
+                rv.push_str(wrapped);
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Wraps a single line like Python's `textwrap.wrap` does.
+    fn wrap_line(line: &str, width: usize, break_long_words: bool) -> Vec<String> {
+        // split into alternating runs of whitespace and words, kept in
+        // reverse order so that the next chunk can be popped off the end.
+        let mut chunks = Vec::new();
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let is_ws = c.is_whitespace();
+            let end = rest
+                .find(|c: char| c.is_whitespace() != is_ws)
+                .unwrap_or(rest.len());
+            chunks.push(Cow::Borrowed(&rest[..end]));
+            rest = &rest[end..];
+        }
+        chunks.reverse();
+
+        let is_ws = |chunk: &str| chunk.chars().all(char::is_whitespace);
+        let mut lines = Vec::new();
+        while !chunks.is_empty() {
+            let mut current = String::new();
+            let mut current_len = 0;
+            if !lines.is_empty() && chunks.last().map_or(false, |x| is_ws(x)) {
+                chunks.pop();
+            }
+            while let Some(chunk) = chunks.last() {
+                let chunk_len = chunk.chars().count();
+                if current_len + chunk_len > width {
+                    break;
+                }
+                current.push_str(chunk);
+                current_len += chunk_len;
+                chunks.pop();
+            }
+            if let Some(chunk) = chunks.pop() {
+                if chunk.chars().count() > width && break_long_words {
+                    let space_left = width.saturating_sub(current_len).max(1);
+                    let split = chunk
+                        .char_indices()
+                        .nth(space_left)
+                        .map_or(chunk.len(), |x| x.0);
+                    current.push_str(&chunk[..split]);
+                    chunks.push(Cow::Owned(chunk[split..].to_string()));
+                } else if chunk.chars().count() > width && current.is_empty() {
+                    current.push_str(&chunk);
+                } else {
+                    chunks.push(chunk);
+                }
+            }
+            let current = current.trim_end();
+            if !current.is_empty() {
+                lines.push(current.to_string());
+            }
+        }
+        lines
+    }
+
+    /// Indents every line of a string.
+    ///
+    /// Each line is prefixed with `width` spaces (defaults to `4`).  Instead
+    /// of a number a string can be passed which is then used as prefix.  The
+    /// first line and blank lines are not indented by default, this can be
+    /// changed by setting `first` and `blank` to `true`.  All parameters can
+    /// also be passed as keyword arguments.  The result is only safe if the
+    /// value and a string prefix are safe.
+    ///
+    /// This is particularly useful together with the `filter` block:
+    ///
+    /// ```jinja
+    /// config:
+    ///   {% filter indent(2) %}
+    ///   {{ config_lines|join("\n") }}
+    ///   {% endfilter %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn indent(
+        value: Value,
+        width: Option<Value>,
+        first: Option<bool>,
+        blank: Option<bool>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let width = ok!(arg_or_kwarg(width, &kwargs, "width"));
+        let first = ok!(arg_or_kwarg(first, &kwargs, "first")).unwrap_or(false);
+        let blank = ok!(arg_or_kwarg(blank, &kwargs, "blank")).unwrap_or(false);
+        ok!(kwargs.assert_all_used());
+
+        let mut safe = value.is_safe();
+        let indention = match width {
+            None => Cow::Borrowed("    "),
+            Some(width) => match width.as_str() {
+                Some(s) => {
+                    safe = safe && width.is_safe();
+                    Cow::Owned(s.to_string())
+                }
+                None => Cow::Owned(" ".repeat(ok!(usize::try_from(width)))),
+            },
+        };
+
+        let s = value.to_cowstr();
+        let mut rv = String::new();
+        for (idx, line) in s.split('\n').enumerate() {
+            if idx > 0 {
+                rv.push('\n');
+            }
+            if (idx > 0 || first) && (blank || !line.trim().is_empty()) {
+                rv.push_str(&indention);
+            }
+            rv.push_str(line);
         }
+
+        Ok(if safe {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        })
     }
 
     /// Joins a sequence by a character
+    ///
+    /// The result is only safe if the joiner (if given) and all items are
+    /// safe.  To join safe items with a literal use `join(", "|safe)`.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn join(val: Value, joiner: Option<Cow<'_, str>>) -> Result<String, Error> {
+    pub fn join(val: Value, joiner: Option<Value>) -> Result<Value, Error> {
         if val.is_undefined() || val.is_none() {
-            return Ok(String::new());
+            return Ok(Value::from(""));
         }
 
-        let joiner = joiner.as_ref().unwrap_or(&Cow::Borrowed(""));
+        let mut safe = joiner.as_ref().map_or(true, |x| x.is_safe());
+        let joiner = joiner.as_ref().map_or(Cow::Borrowed(""), |x| x.to_cowstr());
 
+        let mut rv = String::new();
         if let Some(s) = val.as_str() {
-            let mut rv = String::new();
+            safe = safe && val.is_safe();
             for c in s.chars() {
                 if !rv.is_empty() {
-                    rv.push_str(joiner);
+                    rv.push_str(&joiner);
                 }
                 rv.push(c);
             }
-            Ok(rv)
         } else if let Some(seq) = val.as_seq() {
-            let mut rv = String::new();
             for item in seq.iter() {
                 if !rv.is_empty() {
-                    rv.push_str(joiner);
+                    rv.push_str(&joiner);
                 }
+                safe = safe && item.is_safe();
                 if let Some(s) = item.as_str() {
                     rv.push_str(s);
                 } else {
                     write!(rv, "{}", item).ok();
                 }
             }
-            Ok(rv)
         } else {
-            Err(Error::new(
+            return Err(Error::new(
                 ErrorKind::InvalidOperation,
                 format!("cannot join value of type {}", val.kind()),
-            ))
+            ));
         }
+
+        Ok(if safe {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        })
     }
 
     /// If the value is undefined it will return the passed default value,
@@ -471,12 +1262,77 @@ mod builtins {
     /// ```jinja
     /// <p>{{ my_variable|default("my_variable was not defined") }}</p>
     /// ```
+    ///
+    /// If the second parameter (`boolean`) is set to `true` the default value
+    /// is also used for values that are defined but falsy such as empty
+    /// strings, `0` or `none`:
+    ///
+    /// ```jinja
+    /// <p>{{ user.nickname|default("anonymous", true) }}</p>
+    /// ```
+    ///
+    /// If `on_error=true` is passed the default value is also used if
+    /// evaluating the value raised an error.  Only errors from the filtered
+    /// expression itself are caught, not errors from the default value.  The
+    /// flag has to be given as a literal:
+    ///
+    /// ```jinja
+    /// {{ items|batch(size)|default("invalid batch size", on_error=true) }}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn default(value: Value, other: Option<Value>) -> Value {
-        if value.is_undefined() {
+    pub fn default(
+        value: Value,
+        other: Option<Value>,
+        boolean: Option<bool>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let other = ok!(arg_or_kwarg(other, &kwargs, "default_value"));
+        let boolean = ok!(arg_or_kwarg(boolean, &kwargs, "boolean")).unwrap_or(false);
+        ok!(kwargs.assert_all_used());
+        Ok(if value.is_undefined() || (boolean && !value.is_true()) {
             other.unwrap_or_else(|| Value::from(""))
         } else {
             value
+        })
+    }
+
+    /// Returns the first value that is neither undefined nor none.
+    ///
+    /// Without arguments the filter is applied to a sequence and picks the
+    /// first matching item of it:
+    ///
+    /// ```jinja
+    /// <p>{{ [user.nickname, user.name, "anonymous"]|coalesce }}</p>
+    /// ```
+    ///
+    /// Alternatively the candidates can be passed as arguments in which case
+    /// the filtered value is the first candidate.  The arguments are evaluated
+    /// from left to right and only as far as needed, so later arguments are
+    /// not evaluated once a value was picked:
+    ///
+    /// ```jinja
+    /// <p>{{ user.nickname|coalesce(user.name, "anonymous") }}</p>
+    /// ```
+    ///
+    /// If no value matches the result is undefined.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn coalesce(value: Value, rest: Rest<Value>) -> Result<Value, Error> {
+        let is_set = |value: &Value| !value.is_undefined() && !value.is_none();
+        if !rest.is_empty() {
+            return Ok(Some(value)
+                .into_iter()
+                .chain(rest.0)
+                .find(is_set)
+                .unwrap_or(Value::UNDEFINED));
+        }
+        if value.kind() == ValueKind::Seq {
+            Ok(ok!(value.try_iter())
+                .find(is_set)
+                .unwrap_or(Value::UNDEFINED))
+        } else if is_set(&value) {
+            Ok(value)
+        } else {
+            Ok(Value::UNDEFINED)
         }
     }
 
@@ -501,21 +1357,67 @@ mod builtins {
 
     /// Round the number to a given precision.
     ///
-    /// Round the number to a given precision. The first parameter specifies the
-    /// precision (default is 0).
+    /// The first parameter specifies the precision (default is 0), a negative
+    /// precision rounds to tens, hundreds and so on.  The second parameter
+    /// specifies the rounding method:
+    ///
+    /// * `"common"` rounds either up or down (the default)
+    /// * `"ceil"` always rounds up
+    /// * `"floor"` always rounds down
+    ///
+    /// Both parameters can also be passed as keyword arguments.  Integers
+    /// stay integers.
     ///
     /// ```jinja
     /// {{ 42.55|round }}
     ///   -> 43.0
+    /// {{ 42.55|round(1, "floor") }}
+    ///   -> 42.5
+    /// {{ 1234|round(-2) }}
+    ///   -> 1200
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn round(value: Value, precision: Option<i32>) -> Result<Value, Error> {
+    pub fn round(
+        value: Value,
+        precision: Option<i32>,
+        method: Option<&str>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let precision = ok!(arg_or_kwarg(precision, &kwargs, "precision")).unwrap_or(0);
+        let method = ok!(arg_or_kwarg(method, &kwargs, "method")).unwrap_or("common");
+        ok!(kwargs.assert_all_used());
+        let round: fn(f64) -> f64 = match method {
+            "common" => f64::round,
+            "ceil" => f64::ceil,
+            "floor" => f64::floor,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "method must be common, ceil or floor",
+                ))
+            }
+        };
+        // negative precisions divide so that the factor is exact
+        let round_to = |val: f64| {
+            if precision >= 0 {
+                let x = 10f64.powi(precision);
+                round(val * x) / x
+            } else {
+                let x = 10f64.powi(-precision);
+                round(val / x) * x
+            }
+        };
         match value.0 {
-            ValueRepr::I64(_) | ValueRepr::I128(_) => Ok(value),
-            ValueRepr::F64(val) => {
-                let x = 10f64.powi(precision.unwrap_or(0));
-                Ok(Value::from((x * val).round() / x))
+            ValueRepr::I64(_) | ValueRepr::I128(_) | ValueRepr::U64(_) | ValueRepr::U128(_) => {
+                if precision >= 0 {
+                    Ok(value)
+                } else {
+                    Ok(int_value(
+                        round_to(ok!(i128::try_from(value)) as f64) as i128
+                    ))
+                }
             }
+            ValueRepr::F64(val) => Ok(Value::from(round_to(val))),
             _ => Err(Error::new(
                 ErrorKind::InvalidOperation,
                 "cannot round value",
@@ -523,6 +1425,132 @@ mod builtins {
         }
     }
 
+    /// Converts a value into an integer.
+    ///
+    /// Floats are truncated, strings are parsed.  The first parameter is the
+    /// value returned if the conversion fails (default is `0`), the second is
+    /// the base used for parsing strings (default is `10`).  For the bases 2,
+    /// 8 and 16 the prefixes `0b`, `0o` and `0x` are accepted, a base of `0`
+    /// detects the base from the prefix.  Both parameters can also be passed
+    /// as keyword arguments.
+    ///
+    /// ```jinja
+    /// {{ "42"|int + 1 }}
+    ///   -> 43
+    /// {{ "0xff"|int(base=16) }}
+    ///   -> 255
+    /// {{ "unknown"|int(default=-1) }}
+    ///   -> -1
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn int(
+        value: Value,
+        default: Option<Value>,
+        base: Option<u32>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let default = ok!(arg_or_kwarg(default, &kwargs, "default"));
+        let base = ok!(arg_or_kwarg(base, &kwargs, "base")).unwrap_or(10);
+        ok!(kwargs.assert_all_used());
+        if base != 0 && !(2..=36).contains(&base) {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "base must be 0 or between 2 and 36",
+            ));
+        }
+        let rv = match value.0 {
+            ValueRepr::Bool(val) => Some(val as i128),
+            ValueRepr::F64(val) if val.is_finite() => Some(val.trunc() as i128),
+            ValueRepr::String(ref s, _) => parse_int(s, base).or_else(|| {
+                // like in Jinja2 strings holding floats are truncated too
+                if base == 10 {
+                    s.trim()
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|val| val.is_finite())
+                        .map(|val| val.trunc() as i128)
+                } else {
+                    None
+                }
+            }),
+            _ => i128::try_from(value).ok(),
+        };
+        Ok(match rv {
+            Some(rv) => int_value(rv),
+            None => default.unwrap_or_else(|| Value::from(0)),
+        })
+    }
+
+    /// Converts a value into a float.
+    ///
+    /// Integers are converted, strings are parsed.  The first parameter is
+    /// the value returned if the conversion fails (default is `0.0`).  It can
+    /// also be passed as `default` keyword argument.
+    ///
+    /// ```jinja
+    /// {{ "42.5"|float * 2 }}
+    ///   -> 85.0
+    /// {{ "unknown"|float(default=1.0) }}
+    ///   -> 1.0
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn float(value: Value, default: Option<Value>, kwargs: Kwargs) -> Result<Value, Error> {
+        let default = ok!(arg_or_kwarg(default, &kwargs, "default"));
+        ok!(kwargs.assert_all_used());
+        let rv = match value.0 {
+            ValueRepr::F64(val) => Some(val),
+            ValueRepr::Bool(val) => Some(val as i64 as f64),
+            ValueRepr::String(ref s, _) => s.trim().parse::<f64>().ok(),
+            _ => i128::try_from(value).ok().map(|val| val as f64),
+        };
+        Ok(match rv {
+            Some(rv) => Value::from(rv),
+            None => default.unwrap_or_else(|| Value::from(0.0)),
+        })
+    }
+
+    /// Parses an integer with an optional base prefix.
+    fn parse_int(s: &str, base: u32) -> Option<i128> {
+        let s = s.trim();
+        let (negative, s) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let prefix = s.get(..2).map(|x| x.to_ascii_lowercase());
+        let (base, digits) = match (base, prefix.as_deref()) {
+            (0 | 16, Some("0x")) => (16, &s[2..]),
+            (0 | 8, Some("0o")) => (8, &s[2..]),
+            (0 | 2, Some("0b")) => (2, &s[2..]),
+            (0, _) => (10, s),
+            (base, _) => (base, s),
+        };
+        if digits.starts_with(['+', '-']) {
+            return None;
+        }
+        let rv = some!(i128::from_str_radix(digits, base).ok());
+        Some(if negative { -rv } else { rv })
+    }
+
+    /// Converts an integer into the smallest matching value.
+    fn int_value(val: i128) -> Value {
+        i64::try_from(val).map_or_else(|_| Value::from(val), Value::from)
+    }
+
+    /// Resolves an argument that can be passed positionally or as keyword.
+    fn arg_or_kwarg<'a, T: ArgType<'a, Output = T>>(
+        positional: Option<T>,
+        kwargs: &'a Kwargs,
+        name: &str,
+    ) -> Result<Option<T>, Error> {
+        match (positional, ok!(kwargs.get::<Option<T>>(name))) {
+            (Some(_), Some(_)) => Err(Error::new(
+                ErrorKind::TooManyArguments,
+                format!("{} passed both positionally and as keyword argument", name),
+            )),
+            (positional, keyword) => Ok(positional.or(keyword)),
+        }
+    }
+
     /// Returns the first item from a list.
     ///
     /// If the list is empty `undefined` is returned.
@@ -567,7 +1595,11 @@ mod builtins {
         if let Some(s) = value.as_str() {
             Ok(s.chars().rev().next().map_or(Value::UNDEFINED, Value::from))
         } else if let Some(seq) = value.as_seq() {
-            Ok(seq.iter().last().unwrap_or(Value::UNDEFINED))
+            Ok(seq
+                .item_count()
+                .checked_sub(1)
+                .and_then(|idx| seq.get_item(idx))
+                .unwrap_or(Value::UNDEFINED))
         } else {
             Err(Error::new(
                 ErrorKind::InvalidOperation,
@@ -622,8 +1654,16 @@ mod builtins {
     /// </div>
     /// ```
     ///
-    /// If you pass it a second argument it’s used to fill missing values on the
-    /// last iteration.
+    /// If the number of items is not divisible by the number of slices, the
+    /// remaining items are distributed across the leading slices so that no
+    /// slice is more than one item longer than another.  If you pass it a
+    /// second argument it’s appended to every slice that did not receive
+    /// one of the remaining items (as in Jinja2):
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3, 4, 5]|slice(3) }} -> [[1, 2], [3, 4], [5]]
+    /// {{ [1, 2, 3, 4, 5]|slice(3, 0) }} -> [[1, 2], [3, 4], [5, 0]]
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn slice(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
         if count == 0 {
@@ -676,8 +1716,14 @@ mod builtins {
     ///   {% endfor %}
     /// </table>
     /// ```
+    ///
+    /// Only the last batch can be shorter than the given count and only that
+    /// batch is padded with the fill value.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn batch(value: Value, count: usize, fill_with: Option<Value>) -> Result<Value, Error> {
+        if count == 0 {
+            return Err(Error::new(ErrorKind::InvalidOperation, "count cannot be 0"));
+        }
         let mut rv = Vec::new();
         let mut tmp = Vec::with_capacity(count);
 
@@ -703,6 +1749,86 @@ mod builtins {
         Ok(Value::from(rv))
     }
 
+    struct Windows {
+        items: Vec<Value>,
+        size: usize,
+    }
+
+    impl SeqObject for Windows {
+        fn get_item(&self, idx: usize) -> Option<Value> {
+            self.items
+                .get(idx..some!(idx.checked_add(self.size)))
+                .map(|window| Value::from(window.to_vec()))
+        }
+
+        fn item_count(&self) -> usize {
+            (self.items.len() + 1).saturating_sub(self.size)
+        }
+    }
+
+    /// Returns overlapping windows of the given size over an iterable.
+    ///
+    /// Every window is a list of `size` consecutive items and each window
+    /// starts one item after the previous one.  The items of the iterable
+    /// are collected up front but the windows are only created when they are
+    /// accessed.  If the iterable has fewer items than the window size no
+    /// windows are produced.
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3, 4]|windows(3) }}
+    ///   -> [[1, 2, 3], [2, 3, 4]]
+    /// {{ [1, 2]|windows(3) }}
+    ///   -> []
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn windows(value: Value, size: usize) -> Result<Value, Error> {
+        if size == 0 {
+            return Err(Error::new(ErrorKind::InvalidOperation, "size cannot be 0"));
+        }
+        Ok(Value::from_seq_object(Windows {
+            items: ok!(value.try_iter_owned()).collect(),
+            size,
+        }))
+    }
+
+    /// Returns adjacent pairs of items of an iterable.
+    ///
+    /// This is the same as `|windows(2)`.  If the iterable has fewer than two
+    /// items no pairs are produced.
+    ///
+    /// ```jinja
+    /// {% for prev, next in series|pairwise %}
+    ///   {{ next - prev }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn pairwise(value: Value) -> Result<Value, Error> {
+        windows(value, 2)
+    }
+
+    /// Returns a list of `(index, item)` pairs for an iterable.
+    ///
+    /// This works like Python's `enumerate`.  The index starts at `0` unless
+    /// a different start is passed either positionally or as the `start`
+    /// keyword argument.
+    ///
+    /// ```jinja
+    /// {% for idx, item in items|enumerate(start=1) %}
+    ///   <li>{{ idx }}. {{ item }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn enumerate(value: Value, start: Option<i64>, kwargs: Kwargs) -> Result<Value, Error> {
+        let start = ok!(arg_or_kwarg(start, &kwargs, "start")).unwrap_or(0);
+        ok!(kwargs.assert_all_used());
+        Ok(Value::from(
+            ok!(value.try_iter_owned())
+                .zip(start..)
+                .map(|(item, idx)| Value::from(vec![Value::from(idx), item]))
+                .collect::<Vec<_>>(),
+        ))
+    }
+
     /// Dumps a value to JSON.
     ///
     /// This filter is only available if the `json` feature is enabled.  The resulting
@@ -718,9 +1844,27 @@ mod builtins {
     /// </script>
     /// <a href="#" data-info='{{ json_object|tojson }}'>...</a>
     /// ```
+    ///
+    /// The `max_depth` keyword argument limits how deeply nested structures
+    /// are dumped.  Maps and sequences nested deeper than that are replaced
+    /// with the string `"..."`.  The same placeholder is used when an object
+    /// contains itself, so dumping self referential objects terminates:
+    ///
+    /// ```jinja
+    /// <pre>{{ debug_info|tojson(true, max_depth=3) }}</pre>
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
     #[cfg(feature = "json")]
-    pub fn tojson(value: Value, pretty: Option<bool>) -> Result<Value, Error> {
+    pub fn tojson(value: Value, pretty: Option<bool>, kwargs: Kwargs) -> Result<Value, Error> {
+        let max_depth = ok!(kwargs.get::<Option<usize>>("max_depth")).unwrap_or(usize::MAX);
+        ok!(kwargs.assert_all_used());
+        let parents = std::cell::RefCell::new(Vec::new());
+        let value = json::DepthLimited {
+            value: &value,
+            depth: 0,
+            max_depth,
+            parents: &parents,
+        };
         if pretty.unwrap_or(false) {
             serde_json::to_string_pretty(&value)
         } else {
@@ -745,6 +1889,122 @@ mod builtins {
         })
     }
 
+    #[cfg(feature = "json")]
+    mod json {
+        use std::cell::RefCell;
+        use std::sync::Arc;
+
+        use serde::ser::{SerializeMap, SerializeSeq};
+        use serde::{Serialize, Serializer};
+
+        use crate::value::{map_entries, struct_items, DepthGuard, ObjectKind, Value, ValueRepr};
+
+        /// Serializes a value up to a maximum depth.
+        ///
+        /// Containers below the maximum depth and objects that are already
+        /// being serialized further up are replaced with a placeholder.
+        pub struct DepthLimited<'a> {
+            pub value: &'a Value,
+            pub depth: usize,
+            pub max_depth: usize,
+            pub parents: &'a RefCell<Vec<usize>>,
+        }
+
+        impl<'a> DepthLimited<'a> {
+            fn child<'b>(&'b self, value: &'b Value) -> DepthLimited<'b> {
+                DepthLimited {
+                    value,
+                    depth: self.depth + 1,
+                    max_depth: self.max_depth,
+                    parents: self.parents,
+                }
+            }
+        }
+
+        impl<'a> Serialize for DepthLimited<'a> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let id = match self.value.0 {
+                    ValueRepr::Seq(_) | ValueRepr::Map(..) => None,
+                    ValueRepr::Dynamic(ref obj) => match obj.kind() {
+                        ObjectKind::Plain => return self.value.serialize(serializer),
+                        _ => Some(Arc::as_ptr(obj) as *const () as usize),
+                    },
+                    _ => return self.value.serialize(serializer),
+                };
+                if self.depth >= self.max_depth
+                    || id.map_or(false, |id| self.parents.borrow().contains(&id))
+                {
+                    return serializer.serialize_str("...");
+                }
+
+                let _guard = ok!(DepthGuard::enter_serialize(self.value));
+                if let Some(id) = id {
+                    self.parents.borrow_mut().push(id);
+                }
+                let rv = match self.value.0 {
+                    ValueRepr::Seq(ref items) => {
+                        let mut seq = ok!(serializer.serialize_seq(Some(items.len())));
+                        for item in items.iter() {
+                            ok!(seq.serialize_element(&self.child(item)));
+                        }
+                        seq.end()
+                    }
+                    ValueRepr::Map(ref entries, _) => {
+                        let mut map = ok!(serializer.serialize_map(Some(entries.len())));
+                        for (k, v) in map_entries(entries) {
+                            ok!(map.serialize_entry(k, &self.child(v)));
+                        }
+                        map.end()
+                    }
+                    ValueRepr::Dynamic(ref obj) => match obj.kind() {
+                        ObjectKind::Seq(s) => {
+                            let mut seq = ok!(serializer.serialize_seq(Some(s.item_count())));
+                            for item in s.iter() {
+                                ok!(seq.serialize_element(&self.child(&item)));
+                            }
+                            seq.end()
+                        }
+                        ObjectKind::Struct(s) => {
+                            let mut map = ok!(serializer.serialize_map(None));
+                            for (k, v) in struct_items(s) {
+                                ok!(map.serialize_entry(k, &self.child(&v)));
+                            }
+                            map.end()
+                        }
+                        ObjectKind::Plain => unreachable!(),
+                    },
+                    _ => unreachable!(),
+                };
+                if id.is_some() {
+                    self.parents.borrow_mut().pop();
+                }
+                rv
+            }
+        }
+    }
+
+    /// Parses a JSON string into a value.
+    ///
+    /// This filter is only available if the `json` and `deserialization`
+    /// features are enabled.  The result is a regular value, so parsed
+    /// objects can be used with all map filters and subscripting.
+    ///
+    /// ```jinja
+    /// {% for key, value in '{"a": 1, "b": 2}'|fromjson|dictsort %}
+    ///   <li>{{ key }}: {{ value }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "builtins", feature = "json", feature = "deserialization")))
+    )]
+    #[cfg(all(feature = "json", feature = "deserialization"))]
+    pub fn fromjson(value: &str) -> Result<Value, Error> {
+        serde_json::from_str(value).map_err(|err| {
+            Error::new(ErrorKind::InvalidOperation, "cannot parse JSON").with_source(err)
+        })
+    }
+
     /// URL encodes a value.
     ///
     /// If given a map it encodes the parameters into a query set, otherwise it
@@ -789,6 +2049,412 @@ mod builtins {
         }
     }
 
+    /// Converts URLs in plain text into clickable links.
+    ///
+    /// Words starting with `http://`, `https://` or `www.` are turned into
+    /// anchor tags (`www.` links point to `https://`).  Punctuation around a
+    /// URL such as a trailing period or enclosing parentheses is kept outside
+    /// of the link.  If auto escaping is enabled the surrounding text is
+    /// escaped unless the value is already marked as safe, the URLs
+    /// themselves are always escaped.  The return value is safe.
+    ///
+    /// The optional `trim_url_limit` shortens the displayed URLs to this many
+    /// characters.  Additionally the keyword arguments `nofollow` (adds
+    /// `rel="nofollow"`) and `target` (sets the link target) are supported.
+    ///
+    /// ```jinja
+    /// <p>{{ comment|urlize(40, target="_blank") }}</p>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn urlize(
+        state: &State,
+        value: Value,
+        trim_url_limit: Option<usize>,
+        kwargs: Kwargs,
+    ) -> Result<Value, Error> {
+        let trim_url_limit = ok!(arg_or_kwarg(trim_url_limit, &kwargs, "trim_url_limit"));
+        let nofollow = ok!(kwargs.get::<Option<bool>>("nofollow")).unwrap_or(false);
+        let target = ok!(kwargs.get::<Option<&str>>("target"));
+        ok!(kwargs.assert_all_used());
+
+        let escape_text = !value.is_safe() && !matches!(state.auto_escape(), AutoEscape::None);
+        let text = value.to_string();
+        let mut rv = String::new();
+        let push_text = |rv: &mut String, text: &str| {
+            if escape_text {
+                write!(rv, "{}", crate::HtmlEscape(text)).unwrap();
+            } else {
+                rv.push_str(text);
+            }
+        };
+
+        let mut rest = text.as_str();
+        while !rest.is_empty() {
+            let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let word = &rest[..word_end];
+            let (lead, url, trail) = split_url_punctuation(word);
+            let href = if url.starts_with("http://") || url.starts_with("https://") {
+                Some(Cow::Borrowed(url))
+            } else if url.starts_with("www.") && url.len() > 4 {
+                Some(Cow::Owned(format!("https://{}", url)))
+            } else {
+                None
+            };
+            match href {
+                Some(href) => {
+                    push_text(&mut rv, lead);
+                    write!(rv, "<a href=\"{}\"", crate::HtmlEscape(&href)).unwrap();
+                    if nofollow {
+                        rv.push_str(" rel=\"nofollow\"");
+                    }
+                    if let Some(target) = target {
+                        write!(rv, " target=\"{}\"", crate::HtmlEscape(target)).unwrap();
+                    }
+                    rv.push('>');
+                    match trim_url_limit {
+                        Some(limit) if url.chars().count() > limit => {
+                            let end = url.char_indices().nth(limit).map_or(url.len(), |x| x.0);
+                            write!(rv, "{}...", crate::HtmlEscape(&url[..end])).unwrap();
+                        }
+                        _ => write!(rv, "{}", crate::HtmlEscape(url)).unwrap(),
+                    }
+                    rv.push_str("</a>");
+                    push_text(&mut rv, trail);
+                }
+                None => push_text(&mut rv, word),
+            }
+            let ws_end = rest[word_end..]
+                .find(|c: char| !c.is_whitespace())
+                .map_or(rest.len(), |x| word_end + x);
+            push_text(&mut rv, &rest[word_end..ws_end]);
+            rest = &rest[ws_end..];
+        }
+
+        Ok(Value::from_safe_string(rv))
+    }
+
+    /// Splits a word into leading punctuation, the URL and trailing punctuation.
+    fn split_url_punctuation(word: &str) -> (&str, &str, &str) {
+        let url = word.trim_start_matches(&['(', '[', '<', '"', '\''][..]);
+        let lead = &word[..word.len() - url.len()];
+        let mut end = url.len();
+        while let Some(c) = url[..end].chars().next_back() {
+            let keep = match c {
+                '.' | ',' | ':' | ';' | '!' | '?' | ']' | '>' | '"' | '\'' => false,
+                // closing parentheses are part of URLs that open them
+                ')' => url[..end].matches('(').count() >= url[..end].matches(')').count(),
+                _ => true,
+            };
+            if keep {
+                break;
+            }
+            end -= c.len_utf8();
+        }
+        (lead, &url[..end], &url[end..])
+    }
+
+    /// Splits a URL into its components.
+    ///
+    /// The return value is a map with the keys `scheme`, `netloc`, `path`,
+    /// `query` and `fragment` which are always strings (empty if the part is
+    /// missing) as well as `username`, `password`, `host` and `port` which are
+    /// none if the URL does not contain them.  The scheme and host are
+    /// lowercased, all other parts are returned as they appear in the URL
+    /// without decoding.  Malformed URLs (for instance ones with an invalid
+    /// port or containing whitespace) fail with an error.
+    ///
+    /// ```jinja
+    /// {% set url = link|urlsplit %}
+    /// <a href="{{ link }}">{{ url.host }}{{ url.path }}</a>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "url"))))]
+    #[cfg(feature = "url")]
+    pub fn urlsplit(value: &str) -> Result<Value, Error> {
+        let malformed = |msg: &str| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("malformed URL {:?}: {}", value, msg),
+            )
+        };
+
+        if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(malformed("URL contains whitespace or control characters"));
+        }
+
+        let mut rest = value;
+        let mut scheme = String::new();
+        if let Some((prefix, after)) = rest.split_once(':') {
+            let mut chars = prefix.chars();
+            if chars.next().map_or(false, |c| c.is_ascii_alphabetic())
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+            {
+                scheme = prefix.to_ascii_lowercase();
+                rest = after;
+            }
+        }
+
+        let mut netloc = None;
+        if let Some(after) = rest.strip_prefix("//") {
+            let end = after.find(&['/', '?', '#'][..]).unwrap_or(after.len());
+            netloc = Some(&after[..end]);
+            rest = &after[end..];
+        }
+        let (rest, fragment) = rest.split_once('#').unwrap_or((rest, ""));
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let mut username = Value::from(());
+        let mut password = Value::from(());
+        let mut host = Value::from(());
+        let mut port = Value::from(());
+        if let Some(netloc) = netloc {
+            let host_port = match netloc.rsplit_once('@') {
+                Some((userinfo, host_port)) => {
+                    let (user, pass) = match userinfo.split_once(':') {
+                        Some((user, pass)) => (user, Some(pass)),
+                        None => (userinfo, None),
+                    };
+                    username = Value::from(user);
+                    password = pass.map_or(Value::from(()), Value::from);
+                    host_port
+                }
+                None => netloc,
+            };
+            let (host_str, port_str) = if let Some(after) = host_port.strip_prefix('[') {
+                match after.split_once(']') {
+                    Some((addr, after)) => match after {
+                        "" => (addr, None),
+                        _ => match after.strip_prefix(':') {
+                            Some(port) => (addr, Some(port)),
+                            None => {
+                                return Err(malformed("unexpected characters after IPv6 address"))
+                            }
+                        },
+                    },
+                    None => return Err(malformed("unterminated IPv6 address")),
+                }
+            } else if host_port.contains(&['[', ']'][..]) {
+                return Err(malformed("invalid IPv6 address"));
+            } else {
+                match host_port.split_once(':') {
+                    Some((host, port)) => (host, Some(port)),
+                    None => (host_port, None),
+                }
+            };
+            if !host_str.is_empty() {
+                host = Value::from(host_str.to_lowercase());
+            }
+            if let Some(port_str) = port_str.filter(|x| !x.is_empty()) {
+                port = Value::from(
+                    port_str
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|_| port_str.bytes().all(|c| c.is_ascii_digit()))
+                        .ok_or_else(|| malformed("invalid port"))?,
+                );
+            }
+        }
+
+        Ok(Value::from_iter([
+            ("scheme", Value::from(scheme)),
+            ("netloc", Value::from(netloc.unwrap_or(""))),
+            ("username", username),
+            ("password", password),
+            ("host", host),
+            ("port", port),
+            ("path", Value::from(path)),
+            ("query", Value::from(query)),
+            ("fragment", Value::from(fragment)),
+        ]))
+    }
+
+    /// Parses a query string into a map.
+    ///
+    /// Every key maps to a list of all the values given for it in the order
+    /// they appear, as a key can show up more than once in a query string.  A
+    /// leading `?` is ignored, `+` is decoded to a space and percent escapes
+    /// are decoded.  Parameters without a `=` get an empty value.  If the
+    /// decoded query string is not valid UTF-8 an error is raised.
+    ///
+    /// ```jinja
+    /// {% set args = (link|urlsplit).query|parse_qs %}
+    /// Page {{ args.page[0] }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "url"))))]
+    #[cfg(feature = "url")]
+    pub fn parse_qs(value: &str) -> Result<Value, Error> {
+        let decode = |s: &str| {
+            let s = s.replace('+', " ");
+            percent_encoding::percent_decode_str(&s)
+                .decode_utf8()
+                .map(|x| x.into_owned())
+                .map_err(|err| {
+                    Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("malformed query string {:?}", value),
+                    )
+                    .with_source(err)
+                })
+        };
+
+        let mut rv = std::collections::BTreeMap::<String, Vec<Value>>::new();
+        for pair in value.strip_prefix('?').unwrap_or(value).split('&') {
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, val) = pair.split_once('=').unwrap_or((pair, ""));
+            rv.entry(ok!(decode(key)))
+                .or_default()
+                .push(Value::from(ok!(decode(val))));
+        }
+        Ok(rv
+            .into_iter()
+            .map(|(k, v)| (crate::key::Key::make_string_key(&k), v))
+            .collect())
+    }
+
+    /// Truncates HTML to a number of visible characters.
+    ///
+    /// Unlike a plain truncation this only counts the text outside of tags,
+    /// never cuts through a tag or an entity and closes all tags that are
+    /// still open at the cut point.  The `end` string (defaults to `...`) is
+    /// appended before the closing tags if the text was truncated.  The
+    /// default length is 255 characters.  Values that are not marked as
+    /// safe are escaped first so only trusted markup is kept intact.  The
+    /// return value is always safe.
+    ///
+    /// ```jinja
+    /// <div class="teaser">{{ article.body|safe|truncate_html(200) }}</div>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "truncate_html"))))]
+    #[cfg(feature = "truncate_html")]
+    pub fn truncate_html(v: Value, length: Option<usize>, end: Option<&str>) -> Value {
+        let html = if v.is_safe() {
+            v.to_string()
+        } else {
+            crate::HtmlEscape(&v.to_string()).to_string()
+        };
+        Value::from_safe_string(truncate_html_str(
+            &html,
+            length.unwrap_or(255),
+            end.unwrap_or("..."),
+        ))
+    }
+
+    #[cfg(feature = "truncate_html")]
+    fn truncate_html_str(html: &str, length: usize, end: &str) -> String {
+        const VOID_ELEMENTS: &[&str] = &[
+            "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param",
+            "source", "track", "wbr",
+        ];
+
+        let mut rv = String::new();
+        let mut open_tags = Vec::new();
+        let mut visible = 0;
+        let mut rest = html;
+
+        while let Some(c) = rest.chars().next() {
+            // tags, comments and doctypes are copied as a whole
+            if c == '<' {
+                let tag_end = rest.find('>').map_or(rest.len(), |idx| idx + 1);
+                let tag = &rest[..tag_end];
+                rest = &rest[tag_end..];
+                rv.push_str(tag);
+                let inner = tag.trim_start_matches('<').trim_end_matches('>');
+                if inner.starts_with('!') || inner.starts_with('?') || inner.ends_with('/') {
+                    continue;
+                }
+                let (closing, inner) = match inner.strip_prefix('/') {
+                    Some(inner) => (true, inner),
+                    None => (false, inner),
+                };
+                let name = inner
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                if name.is_empty() || VOID_ELEMENTS.contains(&name.as_str()) {
+                    continue;
+                }
+                if closing {
+                    if let Some(idx) = open_tags.iter().rposition(|x| *x == name) {
+                        open_tags.truncate(idx);
+                    }
+                } else {
+                    open_tags.push(name);
+                }
+                continue;
+            }
+
+            // text is truncated once the visible length was reached
+            if visible == length {
+                rv.push_str(end);
+                break;
+            }
+            visible += 1;
+
+            // entities count as a single character and are never split
+            let char_len = if c == '&' {
+                match rest[1..].find(';') {
+                    Some(idx)
+                        if idx > 0
+                            && rest[1..idx + 1]
+                                .chars()
+                                .all(|c| c.is_ascii_alphanumeric() || c == '#') =>
+                    {
+                        idx + 2
+                    }
+                    _ => 1,
+                }
+            } else {
+                c.len_utf8()
+            };
+            rv.push_str(&rest[..char_len]);
+            rest = &rest[char_len..];
+        }
+
+        for tag in open_tags.iter().rev() {
+            rv.push_str("</");
+            rv.push_str(tag);
+            rv.push('>');
+        }
+        rv
+    }
+
+    #[cfg(feature = "truncate_html")]
+    #[test]
+    fn test_truncate_html() {
+        assert_eq!(
+            truncate_html_str("<p>Hello <b>World</b>!</p>", 8, "..."),
+            "<p>Hello <b>Wo...</b></p>"
+        );
+        assert_eq!(
+            truncate_html_str("<p>Hello <b>World</b>!</p>", 20, "..."),
+            "<p>Hello <b>World</b>!</p>"
+        );
+        assert_eq!(
+            truncate_html_str("<p>Tom &amp; Jerry</p>", 5, ""),
+            "<p>Tom &amp;</p>"
+        );
+        assert_eq!(
+            truncate_html_str("<div>a<br>b<img src=x.png/>c<hr/>d</div>", 2, "!"),
+            "<div>a<br>b<img src=x.png/>!</div>"
+        );
+        assert_eq!(
+            truncate_html_str("<ul><li>one</li><li>two</li></ul>", 4, "..."),
+            "<ul><li>one</li><li>t...</li></ul>"
+        );
+        assert_eq!(truncate_html_str("a&b", 2, "."), "a&.");
+    }
+
+    #[test]
+    fn test_windows_out_of_range() {
+        let rv = windows(Value::from(vec![1, 2, 3]), 2).unwrap();
+        let seq = rv.as_seq().unwrap();
+        assert_eq!(seq.get_item(1), Some(Value::from(vec![2, 3])));
+        assert_eq!(seq.get_item(2), None);
+        assert_eq!(seq.get_item(usize::MAX), None);
+    }
+
     #[test]
     fn test_basics() {
         fn test(a: u32, b: u32) -> Result<u32, Error> {
@@ -40,6 +40,36 @@
 //! env.add_function("include_file", include_file);
 //! ```
 //!
+//! The arguments are converted into the types of the function's parameters
+//! via [`ArgType`](crate::value::ArgType).  Trailing parameters of type
+//! [`Option`] are optional and [`Kwargs`](crate::value::Kwargs) can be used to
+//! accept keyword arguments.  Functions that accept their arguments as
+//! [`Rest<Value>`](crate::value::Rest) can convert them later with
+//! [`from_args`](crate::value::from_args):
+//!
+//! ```rust
+//! # use minijinja::Environment;
+//! # let mut env = Environment::new();
+//! fn pad(value: String, width: usize, fill: Option<&str>) -> String {
+//!     let fill = fill.unwrap_or(" ");
+//!     let mut rv = value;
+//!     if fill.is_empty() {
+//!         return rv;
+//!     }
+//!     while rv.chars().count() < width {
+//!         rv.push_str(fill);
+//!     }
+//!     rv
+//! }
+//!
+//! env.add_function("pad", pad);
+//! ```
+//!
+//! ```jinja
+//! {{ pad("a", 3) }} -> "a  "
+//! {{ pad("a", 3, "-") }} -> "a--"
+//! ```
+//!
 //! # Note on Keyword Arguments
 //!
 //! MiniJinja inherits a lot of the runtime model from Jinja2.  That includes support for
@@ -83,7 +113,7 @@ pub(crate) struct BoxedFunction(Arc<FuncFunc>, #[cfg(feature = "debug")] &'stati
 /// method to abstract over different types of functions.
 ///
 /// Functions which at the very least accept the [`State`] by reference as first
-/// parameter and additionally up to 4 further parameters.  They share much of
+/// parameter and additionally up to 5 further parameters.  They share much of
 /// their interface with [`filters`](crate::filters).
 ///
 /// A function can return any of the following types:
@@ -166,6 +196,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedFunction {
     /// Creates a new boxed filter.
@@ -218,16 +249,18 @@ impl Object for BoxedFunction {
     fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         self.invoke(state, args)
     }
+
+    fn is_callable(&self) -> bool {
+        true
+    }
 }
 
 #[cfg(feature = "builtins")]
 mod builtins {
     use super::*;
 
-    use std::collections::BTreeMap;
-
     use crate::error::ErrorKind;
-    use crate::value::ValueKind;
+    use crate::value::{Kwargs, MapType, ValueKind, ValueMap, ValueRepr};
 
     /// Returns a range.
     ///
@@ -235,6 +268,10 @@ mod builtins {
     /// j)` returns `[i, i+1, i+2, ..., j-1]`. `lower` defaults to 0. When `step` is
     /// given, it specifies the increment (or decrement). For example, `range(4)`
     /// and `range(0, 4, 1)` return `[0, 1, 2, 3]`. The end point is omitted.
+    /// With a negative step the range counts down, so `range(3, 0, -1)` returns
+    /// `[3, 2, 1]`.  A step of zero is an error.  If fuel is set (see
+    /// [`Environment::set_fuel`](crate::Environment::set_fuel)) every item of the
+    /// range consumes one unit of fuel before the range is created.
     ///
     /// ```jinja
     /// <ul>
@@ -244,29 +281,54 @@ mod builtins {
     /// </ul>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn range(lower: u32, upper: Option<u32>, step: Option<u32>) -> Result<Vec<u32>, Error> {
-        let rng = match upper {
-            Some(upper) => lower..upper,
-            None => 0..lower,
+    pub fn range(
+        state: &State,
+        lower: i64,
+        upper: Option<i64>,
+        step: Option<i64>,
+    ) -> Result<Vec<i64>, Error> {
+        let (start, end) = match upper {
+            Some(upper) => (lower, upper),
+            None => (0, lower),
         };
-        if let Some(step) = step {
-            if step == 0 {
-                Err(Error::new(
-                    ErrorKind::InvalidOperation,
-                    "cannot create range with step of 0",
-                ))
+        let step = step.unwrap_or(1);
+        let step_size = usize::try_from(step.unsigned_abs()).unwrap_or(usize::MAX);
+        if step == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot create range with step of 0",
+            ));
+        }
+
+        // the range is allocated up front, so it is paid for before that
+        if let Some(ref tracker) = state.fuel_tracker {
+            let distance = if step > 0 {
+                end as i128 - start as i128
             } else {
-                Ok(rng.step_by(step as usize).collect())
-            }
+                start as i128 - end as i128
+            };
+            let len = if distance > 0 {
+                (distance - 1) / step.unsigned_abs() as i128 + 1
+            } else {
+                0
+            };
+            ok!(tracker.consume(u64::try_from(len).unwrap_or(u64::MAX)));
+        }
+
+        if step > 0 {
+            Ok((start..end).step_by(step_size).collect())
+        } else if start > end {
+            Ok((end + 1..=start).rev().step_by(step_size).collect())
         } else {
-            Ok(rng.collect())
+            Ok(Vec::new())
         }
     }
 
     /// Creates a dictionary.
     ///
     /// This is a convenient alternative for a dictionary literal.
-    /// `{"foo": "bar"}` is the same as `dict(foo="bar")`.
+    /// `{"foo": "bar"}` is the same as `dict(foo="bar")`.  If a map is passed
+    /// as positional argument, the keyword arguments are merged into it.
     ///
     /// ```jinja
     /// <script>const CONFIG = {{ dict(
@@ -275,14 +337,26 @@ mod builtins {
     /// )|tojson }};</script>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn dict(value: Value) -> Result<Value, Error> {
-        if value.is_undefined() {
-            Ok(Value::from(BTreeMap::<bool, Value>::new()))
-        } else if value.kind() != ValueKind::Map {
-            Err(Error::from(ErrorKind::InvalidOperation))
-        } else {
-            Ok(value)
+    pub fn dict(value: Option<Value>, update_with: Kwargs) -> Result<Value, Error> {
+        let value = match value {
+            None => return Ok(Value::from(update_with)),
+            Some(value) if value.kind() != ValueKind::Map => {
+                return Err(Error::from(ErrorKind::InvalidOperation))
+            }
+            Some(value) => value,
+        };
+        let update_with = Value::from(update_with);
+        if update_with.len() == Some(0) {
+            return Ok(value);
+        }
+        let mut rv = ValueMap::new();
+        for (k, v) in ok!(value.items())
+            .into_iter()
+            .chain(ok!(update_with.items()))
+        {
+            rv.insert(ok!(k.try_into_key()), v);
         }
+        Ok(Value(ValueRepr::Map(Arc::new(rv), MapType::Normal)))
     }
 
     /// Outputs the current context stringified.
@@ -299,6 +373,133 @@ mod builtins {
     pub fn debug(state: &State) -> String {
         format!("{:#?}", state)
     }
+
+    /// Checks if a feature flag is enabled.
+    ///
+    /// Feature flags are configured on the environment with
+    /// [`set_feature_flag`](crate::Environment::set_feature_flag).  Unknown
+    /// flags are disabled.  If the flag is known when the template is compiled
+    /// the call is resolved at compile time and an `if` block testing it only
+    /// contains the branch that is taken.
+    ///
+    /// ```jinja
+    /// {% if feature("new_ui") %}
+    ///   {% include "new_header.html" %}
+    /// {% else %}
+    ///   {% include "header.html" %}
+    /// {% endif %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn feature(state: &State, name: &str) -> bool {
+        state.env().feature_flag(name).unwrap_or(false)
+    }
+
+    const LOREM_IPSUM_WORDS: &str = "\
+        a ac accumsan ad adipiscing aenean aliquam aliquet amet ante aptent arcu at \
+        auctor augue bibendum blandit class commodo condimentum congue consectetuer \
+        consequat conubia convallis cras cubilia cum curabitur curae cursus dapibus diam \
+        dictum dictumst dignissim dis dolor donec dui duis egestas eget eleifend \
+        elementum elit enim erat eros est et etiam eu euismod facilisi facilisis fames \
+        faucibus felis fermentum feugiat fringilla fusce gravida habitant habitasse hac \
+        hendrerit hymenaeos iaculis id imperdiet in inceptos integer interdum ipsum \
+        justo lacinia lacus laoreet lectus leo libero ligula litora lobortis lorem \
+        luctus maecenas magna magnis malesuada massa mattis mauris metus mi molestie \
+        mollis montes morbi mus nam nascetur natoque nec neque netus nibh nisi nisl non \
+        nonummy nostra nulla nullam nunc odio orci ornare parturient pede pellentesque \
+        penatibus per pharetra phasellus placerat platea porta porttitor posuere potenti \
+        praesent pretium primis proin pulvinar purus quam quis quisque rhoncus ridiculus \
+        risus rutrum sagittis sapien scelerisque sed sem semper senectus sociis sociosqu \
+        sodales sollicitudin suscipit suspendisse taciti tellus tempor tempus tincidunt \
+        torquent tortor tristique turpis ullamcorper ultrices ultricies urna ut varius \
+        vehicula vel velit venenatis vestibulum vitae vivamus viverra volutpat vulputate";
+
+    /// Generates some lorem ipsum for the template.
+    ///
+    /// By default five paragraphs of HTML are generated with each paragraph
+    /// being between 20 and 100 words.  The first argument is the number of
+    /// paragraphs, the keyword arguments are:
+    ///
+    /// * `html`: set to `false` to return plain text paragraphs separated by
+    ///   blank lines instead of `<p>` elements.
+    /// * `min`: the minimum number of words per paragraph (defaults to `20`).
+    /// * `max`: the maximum number of words per paragraph (defaults to `100`).
+    ///
+    /// The text is random but can be made deterministic by setting a seed with
+    /// [`set_random_seed`](crate::Environment::set_random_seed).
+    ///
+    /// ```jinja
+    /// <div class="placeholder">{{ lipsum(2, min=10, max=30) }}</div>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn lipsum(state: &State, n: Option<usize>, kwargs: Kwargs) -> Result<Value, Error> {
+        let html = ok!(kwargs.get::<Option<bool>>("html")).unwrap_or(true);
+        let min = ok!(kwargs.get::<Option<usize>>("min")).unwrap_or(20);
+        let max = ok!(kwargs.get::<Option<usize>>("max")).unwrap_or(100);
+        ok!(kwargs.assert_all_used());
+        if min > max {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "min must not be larger than max",
+            ));
+        }
+
+        let rng = &state.rng;
+        let words = LOREM_IPSUM_WORDS.split_whitespace().collect::<Vec<_>>();
+        let mut paragraphs = Vec::new();
+        for _ in 0..n.unwrap_or(5) {
+            let mut paragraph = String::new();
+            let mut next_capitalized = true;
+            let mut last_comma = 0;
+            let mut last_fullstop = 0;
+            let mut last_word = None;
+
+            for idx in 0..rng.gen_range(min, max.saturating_add(1)) {
+                let word = loop {
+                    let word = words[rng.gen_range(0, words.len())];
+                    if last_word != Some(word) {
+                        break word;
+                    }
+                };
+                last_word = Some(word);
+                if idx > 0 {
+                    if idx > last_fullstop + rng.gen_range(10, 20) {
+                        last_fullstop = idx;
+                        last_comma = idx;
+                        next_capitalized = true;
+                        paragraph.push('.');
+                    } else if idx > last_comma + rng.gen_range(3, 8) {
+                        last_comma = idx;
+                        paragraph.push(',');
+                    }
+                    paragraph.push(' ');
+                }
+                if next_capitalized {
+                    let mut chars = word.chars();
+                    paragraph.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                    paragraph.push_str(chars.as_str());
+                    next_capitalized = false;
+                } else {
+                    paragraph.push_str(word);
+                }
+            }
+            if !paragraph.is_empty() {
+                paragraph.push('.');
+            }
+            paragraphs.push(paragraph);
+        }
+
+        Ok(if html {
+            Value::from_safe_string(
+                paragraphs
+                    .iter()
+                    .map(|p| format!("<p>{}</p>", p))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        } else {
+            Value::from(paragraphs.join("\n\n"))
+        })
+    }
 }
 
 #[cfg(feature = "builtins")]
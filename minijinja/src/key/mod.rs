@@ -20,7 +20,7 @@ pub enum Key<'a> {
     Bool(bool),
     I64(i64),
     Char(char),
-    String(Arc<String>),
+    String(Arc<str>),
     Str(&'a str),
 }
 
@@ -54,13 +54,13 @@ impl<'a> Key<'a> {
         }
         #[cfg(not(feature = "key_interning"))]
         {
-            Key::String(Arc::new(String::from(s)))
+            Key::String(Arc::from(s))
         }
     }
 
     pub fn as_str(&self) -> Option<&str> {
         match *self {
-            Key::String(ref x) => Some(x.as_str()),
+            Key::String(ref x) => Some(x),
             Key::Str(x) => Some(x),
             _ => None,
         }
@@ -71,7 +71,7 @@ impl<'a> Key<'a> {
             Key::Bool(x) => InternalKeyRef::Bool(x),
             Key::I64(x) => InternalKeyRef::I64(x),
             Key::Char(x) => InternalKeyRef::Char(x),
-            Key::String(ref x) => InternalKeyRef::Str(x.as_str()),
+            Key::String(ref x) => InternalKeyRef::Str(x),
             Key::Str(x) => InternalKeyRef::Str(x),
         }
     }
@@ -198,14 +198,14 @@ pub mod key_interning {
 
     enum CachedKey<'a> {
         Ref(&'a str),
-        Stored(Arc<String>),
+        Stored(Arc<str>),
     }
 
     impl<'a> CachedKey<'a> {
         fn as_str(&self) -> &str {
             match self {
                 CachedKey::Ref(x) => x,
-                CachedKey::Stored(x) => x.as_str(),
+                CachedKey::Stored(x) => x,
             }
         }
     }
@@ -238,14 +238,14 @@ pub mod key_interning {
         })
     }
 
-    pub(crate) fn try_intern(s: &str) -> Arc<String> {
+    pub(crate) fn try_intern(s: &str) -> Arc<str> {
         let depth = STRING_KEY_CACHE_DEPTH.with(|depth| depth.load(Ordering::Relaxed));
 
         // strings longer than 16 bytes are never interned or if we're at
         // depth 0.  (serialization code outside of internal serialization)
         // not checking for depth can cause a memory leak.
         if depth == 0 || s.len() > 16 {
-            return Arc::new(String::from(s));
+            return Arc::from(s);
         }
 
         STRING_KEY_CACHE.with(|cache| {
@@ -253,7 +253,7 @@ pub mod key_interning {
             match set.get(&CachedKey::Ref(s)) {
                 Some(CachedKey::Stored(s)) => s.clone(),
                 None => {
-                    let rv = Arc::new(String::from(s));
+                    let rv: Arc<str> = Arc::from(s);
                     set.insert(CachedKey::Stored(rv.clone()));
                     rv
                 }
@@ -289,7 +289,7 @@ pub mod key_interning {
 #[test]
 fn test_string_key_lookup() {
     let mut m = std::collections::BTreeMap::new();
-    m.insert(Key::String(Arc::new("foo".into())), Value::from(42));
+    m.insert(Key::String(Arc::from("foo")), Value::from(42));
     let m = Value::from(m);
     assert_eq!(m.get_item(&Value::from("foo")).unwrap(), Value::from(42));
 }
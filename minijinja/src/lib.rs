@@ -149,16 +149,26 @@
 //!     the value type is disabled.  The default behavior can cut down on the memory
 //!     consumption of the value type by interning all string keys used in values.
 //!   - `deserialization`: when removed this disables deserialization support for
-//!     the [`Value`](crate::value::Value) type.
+//!     the [`Value`](crate::value::Value) type as well as the
+//!     `ViaDeserialize` argument type.
 //!
 //! There are some additional features that can be enabled:
 //!
-//! - `source`: enables the `Source` type which helps with dynamic loading of templates.
+//! - `source`: enables the `Source` type which helps with dynamic loading of
+//!   templates.
+//! - `loader`: enables the `path_loader` helpers which load templates from a
+//!   directory and automatic reloading of changed templates.  This also turns on
+//!   `source` automatically.
 //! - `speedups`: enables all speedups, in particular it turns on the `v_htmlescape` dependency
 //!   for faster HTML escapling.  This also turns on `key_interning` automatically.
 //! - `json`: When enabled the `tojson` filter is added as builtin filter as well as
-//!   the ability to auto escape via `AutoEscape::Json`.
+//!   the ability to auto escape via `AutoEscape::Json`.  Together with the
+//!   `deserialization` feature the `fromjson` filter is added as well.
 //! - `urlencode`: When enabled the `urlencode` filter is added as builtin filter.
+//! - `url`: When enabled the `urlsplit` and `parse_qs` filters are added as
+//!   builtin filters.
+//! - `truncate_html`: When enabled the `truncate_html` filter is added as builtin
+//!   filter.
 //! - `preserve_order`: When enable the internal value implementation uses an indexmap
 //!   which preserves the original order of maps and structs.
 //!
@@ -195,19 +205,22 @@ mod source;
 #[cfg(feature = "debug")]
 mod debug;
 
+pub use self::compiler::lexer::{NewlineMode, Syntax};
 pub use self::defaults::{default_auto_escape_callback, escape_formatter};
 pub use self::environment::Environment;
-pub use self::error::{Error, ErrorKind};
+pub use self::error::{Error, ErrorKind, Warning, WarningKind};
 pub use self::expression::Expression;
 pub use self::output::Output;
 pub use self::template::Template;
-pub use self::utils::{AutoEscape, HtmlEscape};
+pub use self::utils::{AutoEscape, HtmlEscape, UndefinedBehavior, UndefinedInfo, XmlEscape};
 
 #[cfg(feature = "source")]
 pub use self::source::Source;
+#[cfg(feature = "loader")]
+pub use self::source::{path_loader, path_loader_with_extensions};
 
 pub use self::macros::__context;
-pub use self::vm::State;
+pub use self::vm::{Extensions, State};
 
 /// This module gives access to the low level machinery.
 ///
@@ -220,16 +233,32 @@ pub mod machinery {
     #![allow(missing_docs)]
     pub use crate::compiler::ast;
     pub use crate::compiler::codegen::CodeGenerator;
+    pub use crate::compiler::formatter::format;
     pub use crate::compiler::instructions::{Instruction, Instructions};
-    pub use crate::compiler::lexer::tokenize;
-    pub use crate::compiler::parser::parse;
+    pub use crate::compiler::lexer::{tokenize, WhitespaceConfig};
+    pub use crate::compiler::parser::parse as parse_with_config;
     pub use crate::compiler::tokens::{Span, Token};
-    pub use crate::template::CompiledTemplate;
+    pub use crate::template::{CompiledTemplate, TemplateConfig};
     pub use crate::vm::Vm;
 
-    use crate::Output;
+    use crate::{Error, Output};
 
     pub fn make_string_output(s: &mut String) -> Output<'_> {
         Output::with_string(s)
     }
+
+    /// Parses a template with the default syntax into its syntax tree.
+    ///
+    /// The returned root node is always a [`ast::Stmt::Template`].  Every node
+    /// carries the [`Span`] it was parsed from.  To parse with a custom
+    /// syntax or whitespace configuration use [`parse_with_config`].
+    pub fn parse<'source>(source: &'source str, name: &str) -> Result<ast::Stmt<'source>, Error> {
+        parse_with_config(
+            source,
+            name,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+    }
 }
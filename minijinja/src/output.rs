@@ -1,3 +1,5 @@
+#[cfg(feature = "multi-template")]
+use std::collections::{BTreeMap, BTreeSet};
 use std::{fmt, io};
 
 use crate::utils::AutoEscape;
@@ -19,6 +21,10 @@ pub enum CaptureMode {
 pub struct Output<'a> {
     w: &'a mut (dyn fmt::Write + 'a),
     capture_stack: Vec<Option<String>>,
+    #[cfg(feature = "multi-template")]
+    block_captures: Option<BTreeMap<String, String>>,
+    #[cfg(feature = "multi-template")]
+    requested_blocks: Option<BTreeSet<String>>,
 }
 
 impl<'a> Output<'a> {
@@ -27,6 +33,10 @@ impl<'a> Output<'a> {
         Self {
             w: buf,
             capture_stack: Vec::new(),
+            #[cfg(feature = "multi-template")]
+            block_captures: None,
+            #[cfg(feature = "multi-template")]
+            requested_blocks: None,
         }
     }
 
@@ -34,6 +44,10 @@ impl<'a> Output<'a> {
         Self {
             w,
             capture_stack: Vec::new(),
+            #[cfg(feature = "multi-template")]
+            block_captures: None,
+            #[cfg(feature = "multi-template")]
+            requested_blocks: None,
         }
     }
 
@@ -42,6 +56,10 @@ impl<'a> Output<'a> {
         Self {
             w: NullWriter::get_mut(),
             capture_stack: Vec::new(),
+            #[cfg(feature = "multi-template")]
+            block_captures: None,
+            #[cfg(feature = "multi-template")]
+            requested_blocks: None,
         }
     }
 
@@ -53,6 +71,18 @@ impl<'a> Output<'a> {
         });
     }
 
+    /// Returns the number of active captures.
+    #[cfg(feature = "builtins")]
+    pub(crate) fn capture_depth(&self) -> usize {
+        self.capture_stack.len()
+    }
+
+    /// Discards all captures above the given depth.
+    #[cfg(feature = "builtins")]
+    pub(crate) fn discard_captures(&mut self, depth: usize) {
+        self.capture_stack.truncate(depth);
+    }
+
     /// Ends capturing and returns the captured string as value.
     pub(crate) fn end_capture(&mut self, auto_escape: AutoEscape) -> Value {
         if let Some(captured) = self.capture_stack.pop().unwrap() {
@@ -66,6 +96,63 @@ impl<'a> Output<'a> {
         }
     }
 
+    /// Enables recording of the output of top-level blocks.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn enable_block_captures(&mut self) {
+        self.block_captures = Some(BTreeMap::new());
+    }
+
+    /// Returns `true` if the output of top-level blocks is recorded.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn is_capturing_blocks(&self) -> bool {
+        self.block_captures.is_some()
+    }
+
+    /// Restricts the rendering of blocks to the given names.
+    ///
+    /// This also enables block captures.  Requested blocks are recorded
+    /// even if they are nested in other blocks, other blocks are only
+    /// rendered if they contain a requested block.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn request_blocks(&mut self, names: BTreeSet<String>) {
+        self.enable_block_captures();
+        self.requested_blocks = Some(names);
+    }
+
+    /// Returns `true` if only some blocks are rendered.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn is_filtering_blocks(&self) -> bool {
+        self.requested_blocks.is_some()
+    }
+
+    /// Returns `true` if the given block was requested.
+    ///
+    /// If rendering is not restricted to some blocks, all blocks count as
+    /// requested.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn is_block_requested(&self, name: &str) -> bool {
+        self.requested_blocks
+            .as_ref()
+            .map_or(true, |names| names.contains(name))
+    }
+
+    /// Records the output of a block.  Only the first rendering of a
+    /// block is retained.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn record_block(&mut self, name: &str, contents: &str) {
+        if let Some(ref mut captures) = self.block_captures {
+            captures
+                .entry(name.to_string())
+                .or_insert_with(|| contents.to_string());
+        }
+    }
+
+    /// Returns the recorded block outputs.
+    #[cfg(feature = "multi-template")]
+    pub(crate) fn take_block_captures(&mut self) -> BTreeMap<String, String> {
+        self.block_captures.take().unwrap_or_default()
+    }
+
     #[inline(always)]
     fn target(&mut self) -> &mut dyn fmt::Write {
         match self.capture_stack.last_mut() {
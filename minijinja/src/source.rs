@@ -4,18 +4,20 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use memo_map::MemoMap;
 use self_cell::self_cell;
 
 use crate::error::{Error, ErrorKind};
-use crate::template::CompiledTemplate;
+use crate::template::{CompiledTemplate, CompiledTemplateRef, TemplateConfig};
 
 #[cfg(test)]
 use similar_asserts::assert_eq;
 
-type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<String, Error> + Send + Sync;
+/// Loads the source of a template together with the file it was read from.
+type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<(String, Option<FileStamp>), Error> + Send + Sync;
 
 /// Utility for dynamic template loading.
 ///
@@ -35,12 +37,14 @@ type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<String, Error> + Send + Sync;
 #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
 pub struct Source {
     backing: SourceBacking,
+    pub(crate) template_config: TemplateConfig,
 }
 
 #[derive(Clone)]
 enum SourceBacking {
     Dynamic {
         templates: MemoMap<String, Arc<LoadedTemplate>>,
+        latest: LatestVersions,
         loader: Arc<LoadFunc>,
     },
     Static {
@@ -69,8 +73,50 @@ impl fmt::Debug for Source {
     }
 }
 
+/// What is known about the latest version of every loaded template.
+#[derive(Default)]
+struct LatestVersions(Mutex<HashMap<String, LatestVersion>>);
+
+impl Clone for LatestVersions {
+    fn clone(&self) -> LatestVersions {
+        LatestVersions(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+#[derive(Clone, Default)]
+struct LatestVersion {
+    // the template if it was reloaded with a different source.  Superseded
+    // versions are dropped once they are no longer in use.
+    tmpl: Option<Arc<LoadedTemplate>>,
+    // the file the template was loaded from
+    file: Option<FileStamp>,
+}
+
+/// Identifies the state of a template file at the time it was read.
+#[derive(Clone, PartialEq)]
+struct FileStamp {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(FileStamp {
+            path: path.to_path_buf(),
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+
+    fn is_current(&self) -> bool {
+        FileStamp::of(&self.path).as_ref() == Some(self)
+    }
+}
+
 self_cell! {
-    struct LoadedTemplate {
+    pub(crate) struct LoadedTemplate {
         owner: (String, String),
         #[covariant]
         dependent: CompiledTemplate,
@@ -101,6 +147,7 @@ impl Source {
             backing: SourceBacking::Static {
                 templates: HashMap::new(),
             },
+            template_config: TemplateConfig::default(),
         }
     }
 
@@ -131,22 +178,67 @@ impl Source {
     where
         F: Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static,
     {
-        Source {
-            backing: SourceBacking::Dynamic {
-                templates: MemoMap::new(),
-                loader: Arc::new(move |name| match ok!(f(name)) {
-                    Some(rv) => Ok(rv),
-                    None => Err(Error::new_not_found(name)),
-                }),
+        let mut rv = Source::new();
+        rv.set_loader(f);
+        rv
+    }
+
+    /// Sets or replaces the dynamic loader of the source.
+    ///
+    /// This works like [`with_loader`](Self::with_loader) but for an existing
+    /// source.  Templates that were already added to or loaded by the source
+    /// are retained, the loader is only invoked for templates that are not
+    /// known yet.
+    ///
+    /// Errors returned by the loader are passed through unchanged which means
+    /// that they can be told apart from a template that does not exist (in
+    /// which case the loader returns `Ok(None)` and an error of kind
+    /// [`TemplateNotFound`](ErrorKind::TemplateNotFound) is produced).  A
+    /// failed load is not cached, so the loader is invoked again the next time
+    /// the template is requested.
+    pub fn set_loader<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static,
+    {
+        // files are not known for custom loaders, they are always invoked
+        // again when reloading.
+        self.set_stamped_loader(move |name| match ok!(f(name)) {
+            Some(rv) => Ok((rv, None)),
+            None => Err(Error::new_not_found(name)),
+        });
+    }
+
+    fn set_stamped_loader<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> Result<(String, Option<FileStamp>), Error> + Send + Sync + 'static,
+    {
+        let templates = match std::mem::replace(
+            &mut self.backing,
+            SourceBacking::Static {
+                templates: HashMap::new(),
             },
-        }
+        ) {
+            SourceBacking::Dynamic { templates, .. } => templates,
+            SourceBacking::Static { templates } => {
+                let rv = MemoMap::new();
+                for (name, tmpl) in templates {
+                    rv.insert(name, tmpl);
+                }
+                rv
+            }
+        };
+        self.backing = SourceBacking::Dynamic {
+            templates,
+            latest: LatestVersions::default(),
+            loader: Arc::new(f),
+        };
     }
 
     /// Creates a source that loads on demand from a given directory.
     ///
     /// This creates a source with a dynamic loader which looks up templates in the
-    /// given directory.  Templates that start with a dot (`.`) or are contained in
-    /// a folder starting with a dot cannot be loaded.
+    /// given directory.  For the rules which template names can be loaded see
+    /// [`path_loader`].
     ///
     /// # Example
     ///
@@ -160,20 +252,12 @@ impl Source {
     /// ```
     pub fn from_path<P: AsRef<Path>>(dir: P) -> Source {
         let dir = dir.as_ref().to_path_buf();
-        Source::with_loader(move |name| {
-            let path = match safe_join(&dir, name) {
-                Some(path) => path,
-                None => return Ok(None),
-            };
-            match fs::read_to_string(path) {
-                Ok(result) => Ok(Some(result)),
-                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
-                Err(err) => Err(
-                    Error::new(ErrorKind::InvalidOperation, "could not read template")
-                        .with_source(err),
-                ),
-            }
-        })
+        let mut rv = Source::new();
+        rv.set_stamped_loader(move |name| match ok!(load_from_path(&dir, name, None)) {
+            Some(rv) => Ok(rv),
+            None => Err(Error::new_not_found(name)),
+        });
+        rv
     }
 
     /// Adds a new template into the source.
@@ -186,24 +270,20 @@ impl Source {
         name: N,
         source: S,
     ) -> Result<(), Error> {
-        let source = source.into();
         let name = name.into();
-        let owner = (name.clone(), source);
-        let tmpl = ok!(LoadedTemplate::try_new(
-            owner,
-            |(name, source)| -> Result<_, Error> {
-                CompiledTemplate::from_name_and_source(name.as_str(), source)
-            }
-        ));
+        let tmpl = ok!(self.compile_template(name.clone(), source.into()));
 
         match self.backing {
             SourceBacking::Dynamic {
-                ref mut templates, ..
+                ref mut templates,
+                ref mut latest,
+                ..
             } => {
-                templates.replace(name, Arc::new(tmpl));
+                latest.0.get_mut().unwrap().remove(&name);
+                templates.replace(name, tmpl);
             }
             SourceBacking::Static { ref mut templates } => {
-                templates.insert(name, Arc::new(tmpl));
+                templates.insert(name, tmpl);
             }
         }
         Ok(())
@@ -212,41 +292,261 @@ impl Source {
     /// Removes an already loaded template from the source.
     pub fn remove_template(&mut self, name: &str) {
         match &mut self.backing {
-            SourceBacking::Dynamic { templates, .. } => templates.remove(name),
-            SourceBacking::Static { templates } => templates.remove(name),
+            SourceBacking::Dynamic {
+                templates, latest, ..
+            } => {
+                templates.remove(name);
+                latest.0.get_mut().unwrap().remove(name);
+            }
+            SourceBacking::Static { templates } => {
+                templates.remove(name);
+            }
+        }
+    }
+
+    /// Removes all templates from the source.
+    ///
+    /// If the source has a loader, templates are loaded again on next use.
+    pub fn clear_templates(&mut self) {
+        match &mut self.backing {
+            SourceBacking::Dynamic {
+                templates, latest, ..
+            } => {
+                templates.clear();
+                latest.0.get_mut().unwrap().clear();
+            }
+            SourceBacking::Static { templates } => templates.clear(),
+        }
+    }
+
+    fn compile_template(&self, name: String, source: String) -> Result<Arc<LoadedTemplate>, Error> {
+        LoadedTemplate::try_new((name, source), |(name, source)| -> Result<_, Error> {
+            CompiledTemplate::from_name_and_source(name.as_str(), source, &self.template_config)
+        })
+        .map(Arc::new)
+    }
+
+    /// Returns all compiled templates sorted by name.
+    ///
+    /// For sources with a loader this only includes the templates that were
+    /// already loaded.
+    pub(crate) fn compiled_templates(&self) -> Vec<(&str, CompiledTemplateRef<'_>)> {
+        let mut rv: Vec<_> = match &self.backing {
+            SourceBacking::Dynamic {
+                templates, latest, ..
+            } => {
+                let latest = latest.0.lock().unwrap();
+                templates
+                    .iter()
+                    .map(|(name, tmpl)| {
+                        let tmpl = match latest.get(name).and_then(|x| x.tmpl.clone()) {
+                            Some(reloaded) => CompiledTemplateRef::owned(reloaded),
+                            None => CompiledTemplateRef::borrowed(tmpl.borrow_dependent()),
+                        };
+                        (name.as_str(), tmpl)
+                    })
+                    .collect()
+            }
+            SourceBacking::Static { templates } => templates
+                .iter()
+                .map(|(name, tmpl)| {
+                    let tmpl = CompiledTemplateRef::borrowed(tmpl.borrow_dependent());
+                    (name.as_str(), tmpl)
+                })
+                .collect(),
         };
+        rv.sort_by_key(|x| x.0);
+        rv
     }
 
     /// Gets a compiled template from the source.
-    pub(crate) fn get_compiled_template(&self, name: &str) -> Result<&CompiledTemplate<'_>, Error> {
+    ///
+    /// With `auto_reload` templates that were loaded before are checked for
+    /// changes and recompiled if their source changed.  Templates read from a
+    /// file are only loaded again if the file's modification time or size
+    /// changed, for all others the loader is invoked again.
+    pub(crate) fn get_compiled_template(
+        &self,
+        name: &str,
+        auto_reload: bool,
+    ) -> Result<CompiledTemplateRef<'_>, Error> {
         match &self.backing {
-            SourceBacking::Dynamic { templates, loader } => Ok(ok!(templates.get_or_try_insert(
-                name,
-                || -> Result<_, Error> {
-                    let source = ok!(loader(name));
-                    let owner = (name.to_owned(), source);
-                    let tmpl = ok!(LoadedTemplate::try_new(
-                        owner,
-                        |(name, source)| -> Result<_, Error> {
-                            CompiledTemplate::from_name_and_source(name.as_str(), source)
-                        }
-                    ));
-                    Ok(Arc::new(tmpl))
+            SourceBacking::Dynamic {
+                templates,
+                latest,
+                loader,
+            } => {
+                let mut fresh = false;
+                let tmpl = ok!(templates.get_or_try_insert(name, || {
+                    fresh = true;
+                    let (source, file) = ok!(loader(name));
+                    let rv = ok!(self.compile_template(name.to_owned(), source));
+                    if file.is_some() {
+                        let mut latest = latest.0.lock().unwrap();
+                        latest.insert(name.to_owned(), LatestVersion { tmpl: None, file });
+                    }
+                    Ok(rv)
+                }));
+                let to_ref = |reloaded: Option<Arc<LoadedTemplate>>| match reloaded {
+                    Some(reloaded) => CompiledTemplateRef::owned(reloaded),
+                    None => CompiledTemplateRef::borrowed(tmpl.borrow_dependent()),
+                };
+                if !auto_reload || fresh {
+                    return Ok(to_ref(None));
+                }
+
+                // the lock is only held briefly, never while loading
+                let version = latest.0.lock().unwrap().get(name).cloned();
+                let version = version.unwrap_or_default();
+                if let Some(ref file) = version.file {
+                    if file.is_current() {
+                        return Ok(to_ref(version.tmpl));
+                    }
+                }
+
+                // templates the loader no longer knows (for instance the ones
+                // added with `add_template`) are kept as they are.
+                let (source, file) = match loader(name) {
+                    Ok(rv) => rv,
+                    Err(err) if err.kind() == ErrorKind::TemplateNotFound => {
+                        return Ok(to_ref(version.tmpl))
+                    }
+                    Err(err) => return Err(err),
+                };
+                let reloaded = if source == tmpl.borrow_owner().1 {
+                    None
+                } else {
+                    match version.tmpl {
+                        Some(current) if source == current.borrow_owner().1 => Some(current),
+                        _ => Some(ok!(self.compile_template(name.to_owned(), source))),
+                    }
+                };
+                let mut latest = latest.0.lock().unwrap();
+                if reloaded.is_none() && file.is_none() {
+                    latest.remove(name);
+                } else {
+                    let tmpl = reloaded.clone();
+                    latest.insert(name.to_owned(), LatestVersion { tmpl, file });
                 }
-            ))
-            .borrow_dependent()),
+                Ok(to_ref(reloaded))
+            }
             SourceBacking::Static { templates } => templates
                 .get(name)
-                .map(|value| value.borrow_dependent())
+                .map(|value| CompiledTemplateRef::borrowed(value.borrow_dependent()))
                 .ok_or_else(|| Error::new_not_found(name)),
         }
     }
 }
 
+/// Creates a loader that loads templates from a directory.
+///
+/// The returned loader can be passed to
+/// [`Environment::set_loader`](crate::Environment::set_loader) or
+/// [`Source::with_loader`].  Template names are resolved relative to the given
+/// directory and both `/` and `\` are accepted as separators, so names such as
+/// `emails/welcome.html` work the same on all platforms.  Names that could
+/// escape the directory are never loaded: absolute paths, `..` segments,
+/// segments starting with a dot (hidden files) and files that resolve to a
+/// location outside of the directory through symlinks are all treated as if
+/// the template did not exist.
+///
+/// Missing templates result in an error of kind
+/// [`TemplateNotFound`](ErrorKind::TemplateNotFound) while failures to read
+/// an existing file are reported as
+/// [`InvalidOperation`](ErrorKind::InvalidOperation) errors with the I/O
+/// error as source.
+///
+/// ```rust
+/// # use minijinja::{path_loader, Environment};
+/// let mut env = Environment::new();
+/// env.set_loader(path_loader("path/to/templates")).unwrap();
+/// ```
+#[cfg(feature = "loader")]
+#[cfg_attr(docsrs, doc(cfg(feature = "loader")))]
+pub fn path_loader<P: AsRef<Path>>(
+    dir: P,
+) -> impl Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static {
+    let dir = dir.as_ref().to_path_buf();
+    move |name| Ok(ok!(load_from_path(&dir, name, None)).map(|x| x.0))
+}
+
+/// Creates a loader that loads templates with certain extensions from a directory.
+///
+/// This works like [`path_loader`] but only templates with one of the given
+/// file extensions (given without the leading dot, compared case-sensitively)
+/// can be loaded.  All other templates are treated as if they did not exist.
+///
+/// ```rust
+/// # use minijinja::{path_loader_with_extensions, Environment};
+/// let mut env = Environment::new();
+/// env.set_loader(path_loader_with_extensions("path/to/templates", ["html", "txt"])).unwrap();
+/// ```
+#[cfg(feature = "loader")]
+#[cfg_attr(docsrs, doc(cfg(feature = "loader")))]
+pub fn path_loader_with_extensions<P, I, S>(
+    dir: P,
+    extensions: I,
+) -> impl Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let dir = dir.as_ref().to_path_buf();
+    let extensions = extensions.into_iter().map(Into::into).collect::<Vec<_>>();
+    move |name| Ok(ok!(load_from_path(&dir, name, Some(&extensions))).map(|x| x.0))
+}
+
+/// Loads a template from a directory together with the file it was read from.
+fn load_from_path(
+    dir: &Path,
+    name: &str,
+    extensions: Option<&[String]>,
+) -> Result<Option<(String, Option<FileStamp>)>, Error> {
+    let path = match safe_join(dir, name) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    if let Some(extensions) = extensions {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some(ext) if extensions.iter().any(|x| x == ext) => {}
+            _ => return Ok(None),
+        }
+    }
+
+    let io_err = |err: io::Error| {
+        Error::new(ErrorKind::InvalidOperation, "could not read template").with_source(err)
+    };
+    // resolve symlinks and make sure the file did not end up outside of
+    // the template folder.
+    let (dir, path) = match (fs::canonicalize(dir), fs::canonicalize(&path)) {
+        (Ok(dir), Ok(path)) => (dir, path),
+        (Err(err), _) | (_, Err(err)) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        (Err(err), _) | (_, Err(err)) => return Err(io_err(err)),
+    };
+    if !path.starts_with(&dir) || !path.is_file() {
+        return Ok(None);
+    }
+    // the file is checked before reading so that a change while reading is
+    // picked up by the next auto reload.
+    let file = FileStamp::of(&path);
+    match fs::read_to_string(path) {
+        Ok(result) => Ok(Some((result, file))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(io_err(err)),
+    }
+}
+
 fn safe_join(base: &Path, template: &str) -> Option<PathBuf> {
+    if template.starts_with(&['/', '\\'][..]) {
+        return None;
+    }
     let mut rv = base.to_path_buf();
-    for segment in template.split('/') {
-        if segment.starts_with('.') || segment.contains('\\') {
+    for segment in template.split(&['/', '\\'][..]) {
+        if segment.is_empty() {
+            continue;
+        }
+        if segment.starts_with('.') || segment.contains(':') {
             return None;
         }
         rv.push(segment);
@@ -276,6 +576,89 @@ fn test_source_replace_dynamic() {
     assert_eq!(rv, "2");
 }
 
+#[test]
+fn test_source_drops_outdated_versions() {
+    let current = Arc::new(Mutex::new("1".to_string()));
+    let source = Source::with_loader({
+        let current = current.clone();
+        move |_| Ok(Some(current.lock().unwrap().clone()))
+    });
+    let reloaded = |source: &Source| match source.backing {
+        SourceBacking::Dynamic { ref latest, .. } => latest
+            .0
+            .lock()
+            .unwrap()
+            .get("a")
+            .and_then(|x| x.tmpl.clone()),
+        SourceBacking::Static { .. } => unreachable!(),
+    };
+    let get_source = |source: &Source| {
+        let tmpl = source.get_compiled_template("a", true).unwrap();
+        tmpl.instructions.source().to_string()
+    };
+
+    assert_eq!(get_source(&source), "1");
+    *current.lock().unwrap() = "2".into();
+    let second = source.get_compiled_template("a", true).unwrap();
+    assert_eq!(second.instructions.source(), "2");
+    let weak = Arc::downgrade(&reloaded(&source).unwrap());
+
+    // superseded versions stay alive for as long as they are in use
+    *current.lock().unwrap() = "3".into();
+    assert_eq!(get_source(&source), "3");
+    assert!(weak.upgrade().is_some());
+    drop(second);
+    assert!(weak.upgrade().is_none());
+    assert_eq!(source.compiled_templates()[0].1.instructions.source(), "3");
+
+    // going back to the original source does not retain a reloaded version
+    *current.lock().unwrap() = "1".into();
+    assert_eq!(get_source(&source), "1");
+    assert!(reloaded(&source).is_none());
+}
+
+#[test]
+fn test_source_checks_files() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let root = std::env::temp_dir().join(format!(
+        "minijinja-source-checks-files-{}",
+        std::process::id()
+    ));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), "first").unwrap();
+
+    let loads = Arc::new(AtomicUsize::new(0));
+    let mut source = Source::new();
+    source.set_stamped_loader({
+        let loads = loads.clone();
+        let root = root.clone();
+        move |name| {
+            loads.fetch_add(1, Ordering::Relaxed);
+            Ok(load_from_path(&root, name, None).unwrap().unwrap())
+        }
+    });
+    let get_source = |source: &Source| {
+        let tmpl = source.get_compiled_template("index.html", true).unwrap();
+        tmpl.instructions.source().to_string()
+    };
+
+    // unchanged files are not read again
+    assert_eq!(get_source(&source), "first");
+    assert_eq!(get_source(&source), "first");
+    assert_eq!(get_source(&source), "first");
+    assert_eq!(loads.load(Ordering::Relaxed), 1);
+
+    // a changed file is
+    fs::write(root.join("index.html"), "second!").unwrap();
+    assert_eq!(get_source(&source), "second!");
+    assert_eq!(get_source(&source), "second!");
+    assert_eq!(loads.load(Ordering::Relaxed), 2);
+
+    fs::remove_dir_all(&root).ok();
+}
+
 #[test]
 fn test_safe_join() {
     assert_eq!(
@@ -285,4 +668,11 @@ fn test_safe_join() {
     assert_eq!(safe_join(Path::new("foo"), ".bar/baz"), None);
     assert_eq!(safe_join(Path::new("foo"), "bar/.baz"), None);
     assert_eq!(safe_join(Path::new("foo"), "bar/../baz"), None);
+    assert_eq!(
+        safe_join(Path::new("foo"), "bar\\baz"),
+        Some(PathBuf::from("foo").join("bar").join("baz"))
+    );
+    assert_eq!(safe_join(Path::new("foo"), "/etc/passwd"), None);
+    assert_eq!(safe_join(Path::new("foo"), "\\\\server\\share"), None);
+    assert_eq!(safe_join(Path::new("foo"), "C:\\secret"), None);
 }
@@ -10,6 +10,7 @@
 //!   - [Comparisons](#comparisons)
 //!   - [Logic](#logic)
 //!   - [Other Operators](#other-operators)
+//!   - [Methods](#methods)
 //!   - [If Expressions](#if-expressions)
 //! - [Tags](#tags)
 //!   - [`{% for %}`](#-for-)
@@ -21,9 +22,12 @@
 //!   - [`{% with %}`](#-with-)
 //!   - [`{% set %}`](#-set-)
 //!   - [`{% filter %}`](#-filter-)
+//!   - [`{% do %}`](#-do-)
 //!   - [`{% macro %}`](#-macro-)
+//!   - [`{% call %}`](#-call-)
 //!   - [`{% autoescape %}`](#-autoescape-)
 //!   - [`{% raw %}`](#-raw-)
+//!   - [Custom Tags](#custom-tags)
 //!
 //! </details>
 //!
@@ -56,12 +60,21 @@
 //! {# a comment #}
 //! ```
 //!
+//! The delimiters used in this documentation are the defaults.  If they collide with
+//! the format that is generated (for instance in LaTeX or Terraform files) different
+//! delimiters can be configured with [`Environment::set_syntax`](crate::Environment::set_syntax).
+//!
 //! # Trailing Newlines
 //!
 //! MiniJinja, like Jinja2, will remove one trailing newline from the end of the file automatically
 //! on parsing.  This lets templates produce a consistent output no matter if the editor adds a
 //! trailing newline or not.  If one wants a trailing newline an extra newline can be added or the
-//! code rendering it adds it manually.
+//! code rendering it adds it manually.  Alternatively the trailing newline can be kept by enabling
+//! [`Environment::set_keep_trailing_newline`](crate::Environment::set_keep_trailing_newline).
+//!
+//! Newlines in the template source are emitted as they are.  Templates authored with `\r\n`
+//! line endings can be normalized with [`Environment::set_newline_mode`](crate::Environment::set_newline_mode)
+//! which only affects the literal text of the template.
 //!
 //! # Expressions
 //!
@@ -113,6 +126,12 @@
 //! - ``<``:``true`` if the left hand side is less than the right hand side.
 //! - ``<=``: ``true`` if the left hand side is less or equal to the right hand side.
 //!
+//! Numbers compare by their value regardless of their type so ``{{ 3 == 3.0 }}``
+//! and ``{{ 2 < 10.5 }}`` are both ``true``.  Strings compare lexically, sequences
+//! item by item.  Values of different types are never equal and trying to order
+//! them (for instance ``{{ 2 < "10" }}``) fails with an error.  For the complete
+//! rules see [the value module](crate::value#comparisons-and-ordering).
+//!
 //! ## Logic
 //!
 //! For ``if`` statements it can be useful to combine multiple expressions:
@@ -143,6 +162,24 @@
 //!   just `"Hello"`.  Likewise ``"Hello"[1:-1]`` will return `"ell"`.  The step component can
 //!   be used to change the step size.  `"12345"[::2]` will return `"135"`.
 //!
+//! ### Methods
+//!
+//! Like in Jinja2 some of the methods that Python provides are available on
+//! strings, maps and lists.  They behave like their Python counterparts:
+//!
+//! - strings: `upper()`, `lower()`, `title()`, `strip(chars)`, `lstrip(chars)`,
+//!   `rstrip(chars)`, `split(sep, maxsplit)`, `startswith(prefix)`,
+//!   `endswith(suffix)` and `replace(old, new, count)`.
+//! - maps: `get(key, default)`, `keys()`, `values()` and `items()`.
+//! - lists: `count(value)` and `index(value)`.
+//!
+//! ```jinja
+//! {% for tag in tags.split(",") if tag.strip() %}{{ tag.strip().title() }}{% endfor %}
+//! {{ config.get("title", "Untitled") }}
+//! ```
+//!
+//! With the exception of `items()` these require the `builtins` feature.
+//!
 //! ### If Expressions
 //!
 //! It is also possible to use inline _if_ expressions. These are useful in some situations.
@@ -226,6 +263,11 @@
 //! {% endfor %}
 //! ```
 //!
+//! Every loop has its own loop object, so the state of `loop.cycle` and
+//! `loop.changed` in a nested loop is independent of the outer loop.  The
+//! outer loop object can be kept around under a different name with
+//! `{% set outer_loop = loop %}`.
+//!
 //! Unlike in Rust or Python, it’s not possible to break or continue in a loop. You can,
 //! however, filter the sequence during iteration, which allows you to skip items.  The
 //! following example skips all the users which are hidden:
@@ -266,6 +308,11 @@
 //! </ul>
 //! ```
 //!
+//! Every level of recursion gets a fresh loop object, so `loop.index` and the
+//! other index variables start over while `loop.depth` and `loop.depth0` tell
+//! how deep the recursion currently is.  Recursive loops count towards the
+//! [recursion limit](crate::Environment::set_recursion_limit).
+//!
 //! ## `{% if %}`
 //!
 //! The `if` statement is comparable with the Python if statement. In the simplest form,
@@ -302,6 +349,15 @@
 //! `extends` tags in a file, but only one of them may be executed at a time.  For more
 //! information see [block](#-block-).
 //!
+//! The name of the template to extend can be any expression, it's evaluated when
+//! the template is rendered.  Like with `include` a list of names can be given in
+//! which case the first template that exists is extended:
+//!
+//! ```jinja
+//! {% extends layout_template %}
+//! {% extends ["layouts/" ~ tenant ~ ".html", "layouts/default.html"] %}
+//! ```
+//!
 //! ## `{% block %}`
 //!
 //! Blocks are used for inheritance and act as both placeholders and replacements at the
@@ -350,7 +406,12 @@
 //! first tag in the template.
 //!
 //! As you can see it's also possible to render the contents of the parent block by calling
-//! ``super()``. You can’t define multiple ``{% block %}`` tags with the same name in
+//! ``super()``.  With multiple levels of inheritance the parent block can itself call
+//! ``super()`` to render the block of its own parent, and templates in the middle of the
+//! chain that do not override a block are skipped.  Calling ``super()`` in a block
+//! that has no parent block is an error.
+//!
+//! You can’t define multiple ``{% block %}`` tags with the same name in
 //! the same template. This limitation exists because a block tag works in “both”
 //! directions. That is, a block tag doesn’t just provide a placeholder to fill -
 //! it also defines the content that fills the placeholder in the parent. If
@@ -379,6 +440,26 @@
 //!
 //! However, the name after the `endblock` word must match the block name.
 //!
+//! Blocks can be marked as `required`.  A required block may only contain
+//! whitespace and comments and must be overridden by a child template.  Rendering
+//! a template where a required block was not overridden fails with an error:
+//!
+//! ```jinja
+//! <title>{% block title required %}{# provided by child #}{% endblock %}</title>
+//! ```
+//!
+//! The `scoped` modifier is accepted for compatibility with Jinja2.  In MiniJinja
+//! blocks always have access to the variables of the enclosing scope, so a block
+//! placed inside a loop can always refer to the loop variable:
+//!
+//! ```jinja
+//! {% for item in seq %}
+//!   <li>{% block loop_item scoped %}{{ item }}{% endblock %}</li>
+//! {% endfor %}
+//! ```
+//!
+//! Both modifiers can be combined in any order (`{% block body scoped required %}`).
+//!
 //! ## `{% include %}`
 //!
 //! **Feature:** `multi-template` (included by default)
@@ -408,6 +489,14 @@
 //! {% include ['page_detailed.html', 'page.html'] %}
 //! {% include ['special_sidebar.html', 'sidebar.html'] ignore missing %}
 //! ```
+//!
+//! Only missing templates are skipped or ignored.  If a candidate exists but
+//! fails to compile or render, the error is reported even with `ignore missing`.
+//! When a loader is set (see [`Environment::set_loader`](crate::Environment::set_loader))
+//! each candidate that is not loaded yet triggers a call to the loader until one
+//! is found.  Candidates the loader did not find are not cached, so they are
+//! asked for again the next time the include runs.  Errors returned by the loader
+//! itself are not swallowed by `ignore missing` either.
 //!  
 //! Included templates have access to the variables of the active context.
 //!
@@ -429,6 +518,8 @@
 //! {{ other_name() }}
 //! ```
 //!
+//! Importing a name that the template does not define is an error.
+//!
 //! Full modules can be imported with `{% import ... as ... %}`:
 //!
 //! ```jinja
@@ -438,7 +529,17 @@
 //!
 //! Note that unlike Jinja2, exported modules do not contain any template code.  Only
 //! variables and macros that are defined can be imported.  Also imports unlike in Jinja2
-//! are not cached and they get access to the full template context.
+//! are not cached and by default they get access to the full template context.
+//! Macros defined in an imported template capture the variables of that context
+//! they refer to.  To import a template without giving it access to the context
+//! (only globals remain visible) add `without context`:
+//!
+//! ```jinja
+//! {% import "my_template.html" as helpers without context %}
+//! {% from "my_template.html" import my_macro without context %}
+//! ```
+//!
+//! `with context` is accepted as well and spells out the default.
 //!
 //! ## `{% with %}`
 //!
@@ -452,6 +553,10 @@
 //! foo is not visible here any longer
 //! ```
 //!
+//! Variables of the same name from the outer scope are shadowed within the
+//! block and visible again after `endwith`.  This also applies to variables
+//! assigned with `{% set %}` inside of the block.
+//!
 //! Multiple variables can be set at once and unpacking is supported:
 //!
 //! ```jinja
@@ -504,6 +609,28 @@
 //! {% endfilter %}
 //! ```
 //!
+//! Filters can take arguments and be chained just like in expressions:
+//!
+//! ```jinja
+//! {% filter trim|indent(2, first=true) %}
+//!   {{ config_lines|join("\n") }}
+//! {% endfilter %}
+//! ```
+//!
+//! The body is rendered first, which includes auto escaping of the
+//! values emitted inside of it, and the result is then passed as a
+//! string to the filter.
+//!
+//! ## `{% do %}`
+//!
+//! The do tag evaluates an expression and discards the result without
+//! emitting anything.  This is useful for calling methods for their side
+//! effects, for instance on custom objects:
+//!
+//! ```jinja
+//! {% do collector.append(item) %}
+//! ```
+//!
 //! ## `{% macro %}`
 //!
 //! **Feature:** `macros` (included by default)
@@ -530,8 +657,71 @@
 //! The behavior of macros with regards to undefined variables is that they capture
 //! them at macro declaration time (eg: they use a closure).
 //!
+//! Like in Jinja2 a macro that refers to the special `varargs` variable accepts
+//! more positional arguments than it declares, the surplus ones are available as
+//! a list in `varargs`.  Likewise unknown keyword arguments end up in the `kwargs`
+//! map if the macro refers to `kwargs`.  Macros that refer to neither fail when
+//! called with extra arguments:
+//!
+//! ```jinja
+//! {% macro button(label) -%}
+//! <button class="{{ kwargs.cls }}">{{ label }}{{ varargs|join }}</button>
+//! {%- endmacro %}
+//! {{ button("Save", "!", cls="primary") }}
+//! ```
+//!
+//! Macros also carry some attributes for introspection: `name`, `arguments`
+//! (the names of the declared arguments), `defaults` (the default values of
+//! the trailing arguments, `none` for defaults that are not constant),
+//! `catch_varargs`, `catch_kwargs` and `caller` (if the macro refers to
+//! `varargs`, `kwargs` or `caller`).
+//!
 //! Macros can be imported via `{% import %}` or `{% from ... import %}`.
 //!
+//! ## `{% call %}`
+//!
+//! **Feature:** `macros` (included by default)
+//!
+//! Sometimes it's useful to pass a macro to another macro.  For this purpose
+//! the `{% call %}` block can be used.  The contents of the block are made
+//! available to the called macro as a macro named `caller`:
+//!
+//! ```jinja
+//! {% macro dialog(title) %}
+//!   <div class="dialog">
+//!     <h3>{{ title }}</h3>
+//!     <div class="contents">{{ caller() }}</div>
+//!   </div>
+//! {% endmacro %}
+//!
+//! {% call dialog(title="Hello World") %}
+//!   This is the dialog body.
+//! {% endcall %}
+//! ```
+//!
+//! The macro can also pass arguments back to the call block:
+//!
+//! ```jinja
+//! {% macro render_user_list(users) %}
+//!   <ul>
+//!   {% for user in users %}
+//!     <li><p>{{ user.username }}</p>{{ caller(user) }}</li>
+//!   {% endfor %}
+//!   </ul>
+//! {% endmacro %}
+//!
+//! {% call(user) render_user_list(list_of_user) %}
+//!   <dl>
+//!     <dt>Name</dt>
+//!     <dd>{{ user.name }}</dd>
+//!   </dl>
+//! {% endcall %}
+//! ```
+//!
+//! The body of the call block can refer to variables of the call site (such
+//! as `loop` of an enclosing for loop) and call blocks can be nested.  Calling
+//! `caller()` in a macro that was not invoked by a call block is an error.
+//!
 //! ## `{% autoescape %}`
 //!
 //! If you want you can activate and deactivate the autoescaping from within
@@ -549,6 +739,16 @@
 //! {% endautoescape %}
 //! ```
 //!
+//! Instead of a boolean the name of an escaping format can be given as string
+//! to switch to a specific format: `"html"`, `"xml"`, `"json"` (if the `json`
+//! feature is enabled) or `"none"`:
+//!
+//! ```jinja
+//! {% autoescape "xml" %}
+//!   <title>{{ title }}</title>
+//! {% endautoescape %}
+//! ```
+//!
 //! After an `endautoescape` the behavior is reverted to what it was before.
 //!
 //! The exact auto escaping behavior is determined by the value of
@@ -571,5 +771,17 @@
 //! </ul>
 //! {% endraw %}
 //! ```
+//!
+//! ## Custom Tags
+//!
+//! Applications can register additional block tags with
+//! [`Environment::add_tag`](crate::Environment::add_tag).  A custom tag
+//! encloses a body which is rendered and handed to the tag together with the
+//! optional comma separated arguments.  The result replaces the block:
+//!
+//! ```jinja
+//! {% upper %}Hello {{ user.name }}!{% endupper %}
+//! {% repeat 3 %}-{% endrepeat %}
+//! ```
 
 // this is just for docs
@@ -1,30 +1,36 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 use std::{fmt, io};
 
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
 use crate::compiler::instructions::Instructions;
+use crate::compiler::lexer::{Syntax, WhitespaceConfig};
+use crate::compiler::meta::{declares_variable, find_undeclared};
 use crate::compiler::parser::parse;
+use crate::defaults;
 use crate::environment::Environment;
-use crate::error::{attach_basic_debug_info, Error, ErrorKind};
+use crate::error::{attach_basic_debug_info, Error, ErrorKind, Warning};
 use crate::output::{Output, WriteWrapper};
+#[cfg(feature = "source")]
+use crate::source::LoadedTemplate;
 use crate::utils::AutoEscape;
 use crate::value::{self, Value};
-use crate::vm::Vm;
+use crate::vm::{Extensions, State, Vm};
 
 /// Represents a handle to a template.
 ///
 /// Templates are stored in the [`Environment`] as bytecode instructions.  With the
 /// [`Environment::get_template`] method that is looked up and returned in form of
-/// this handle.  Such a template can be cheaply copied as it only holds references.
+/// this handle.  Such a template can be cheaply cloned as it only holds references.
 ///
 /// To render the [`render`](Template::render) method can be used.
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct Template<'env> {
     env: &'env Environment<'env>,
-    compiled: &'env CompiledTemplate<'env>,
-    initial_auto_escape: AutoEscape,
+    compiled: CompiledTemplateRef<'env>,
 }
 
 impl<'env> fmt::Debug for Template<'env> {
@@ -36,7 +42,7 @@ impl<'env> fmt::Debug for Template<'env> {
             ds.field("instructions", &self.compiled.instructions);
             ds.field("blocks", &self.compiled.blocks);
         }
-        ds.field("initial_auto_escape", &self.initial_auto_escape);
+        ds.field("initial_auto_escape", &self.compiled.initial_auto_escape);
         ds.finish()
     }
 }
@@ -44,14 +50,9 @@ impl<'env> fmt::Debug for Template<'env> {
 impl<'env> Template<'env> {
     pub(crate) fn new(
         env: &'env Environment<'env>,
-        compiled: &'env CompiledTemplate<'env>,
-        initial_auto_escape: AutoEscape,
+        compiled: CompiledTemplateRef<'env>,
     ) -> Template<'env> {
-        Template {
-            env,
-            compiled,
-            initial_auto_escape,
-        }
+        Template { env, compiled }
     }
 
     /// Returns the name of the template.
@@ -64,6 +65,55 @@ impl<'env> Template<'env> {
         self.compiled.instructions.source()
     }
 
+    /// Returns a set of all variables the template references but does not declare.
+    ///
+    /// This can be used to find out which variables need to be provided in the
+    /// context before rendering.  Variables assigned with `{% set %}`, loop
+    /// targets, macro parameters and imports are not included.  If `nested` is
+    /// set to `true`, attribute lookups are returned as dotted paths such as
+    /// `user.name` where they can be determined statically, otherwise only the
+    /// top-level names are returned.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_template("x", "{% set x = foo %}{{ x }}{{ bar.baz }}").unwrap();
+    /// let tmpl = env.get_template("x").unwrap();
+    /// let mut vars: Vec<_> = tmpl.undeclared_variables(false).unwrap().into_iter().collect();
+    /// vars.sort();
+    /// assert_eq!(vars, ["bar", "foo"]);
+    /// let mut vars: Vec<_> = tmpl.undeclared_variables(true).unwrap().into_iter().collect();
+    /// vars.sort();
+    /// assert_eq!(vars, ["bar.baz", "foo"]);
+    /// ```
+    ///
+    /// This is a purely static analysis of this template with some limitations:
+    ///
+    /// * subscripts with non-constant keys such as `foo[bar]` only report the
+    ///   variables involved (`foo` and `bar`), not a path.
+    /// * included, imported and extended templates are not analyzed; their
+    ///   variables need to be looked up on those templates separately.
+    /// * globals and functions such as `range` are reported like any other
+    ///   variable as they are resolved at render time.
+    ///
+    /// The template source is parsed again for the analysis with the same
+    /// configuration (such as the syntax) the template was compiled with.
+    pub fn undeclared_variables(&self, nested: bool) -> Result<HashSet<String>, Error> {
+        let config = &self.compiled.config;
+        let source = self.compiled.instructions.source();
+        let ast = ok!(attach_basic_debug_info(
+            parse(
+                source,
+                self.name(),
+                config.ws_config,
+                config.syntax.clone(),
+                config.custom_tags.clone(),
+            ),
+            source,
+        ));
+        Ok(find_undeclared(&ast, nested))
+    }
+
     /// Renders the template into a string.
     ///
     /// The provided value is used as the initial context for the template.  It
@@ -81,19 +131,174 @@ impl<'env> Template<'env> {
     pub fn render<S: Serialize>(&self, ctx: S) -> Result<String, Error> {
         // reduce total amount of code faling under mono morphization into
         // this function, and share the rest in _eval.
-        self._render(Value::from_serializable(&ctx))
+        self._render(Value::from_serializable(&ctx), &Extensions::new())
+    }
+
+    /// Renders the template into a string with extensions.
+    ///
+    /// This works like [`render`](Self::render) but makes the given
+    /// [`Extensions`] available to filters, functions, tests and object
+    /// methods via [`State::extension`](crate::State::extension).
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Extensions, context};
+    /// # let mut env = Environment::new();
+    /// # env.add_template("hello", "Hello {{ name }}!").unwrap();
+    /// struct Services;
+    ///
+    /// let mut extensions = Extensions::new();
+    /// extensions.insert(Services);
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// println!("{}", tmpl.render_with_extensions(context!(name => "John"), &extensions).unwrap());
+    /// ```
+    pub fn render_with_extensions<S: Serialize>(
+        &self,
+        ctx: S,
+        extensions: &Extensions,
+    ) -> Result<String, Error> {
+        self._render(Value::from_serializable(&ctx), extensions)
     }
 
-    fn _render(&self, root: Value) -> Result<String, Error> {
+    fn _render(&self, root: Value, extensions: &Extensions) -> Result<String, Error> {
         let mut rv = String::new();
-        self._eval(root, &mut Output::with_string(&mut rv))
+        self._eval(root, &mut Output::with_string(&mut rv), extensions)
             .map(|_| rv)
     }
 
+    /// Renders the template and collects warnings about non-fatal issues.
+    ///
+    /// This works like [`render`](Self::render) but in addition to the
+    /// rendered template it returns the [`Warning`]s that were encountered
+    /// along the way, in the order they happened.  Currently every lookup of
+    /// a variable, attribute or item that produces an undefined value is
+    /// reported together with the template and line where it happened.
+    /// Lookups that are directly checked with the `defined` or `undefined`
+    /// tests are not reported, lookups passed to the `default` filter are.
+    /// This is useful to find the places that would fail once
+    /// [`UndefinedBehavior::Strict`](crate::UndefinedBehavior::Strict) is
+    /// turned on.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template("hello", "Hello {{ user.name }}!").unwrap();
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// let (rv, warnings) = tmpl.render_with_warnings(context!(user => context!())).unwrap();
+    /// assert_eq!(rv, "Hello !");
+    /// assert_eq!(warnings[0].to_string(),
+    ///            "undefined access: attribute `name` is undefined (in hello:1)");
+    /// ```
+    pub fn render_with_warnings<S: Serialize>(
+        &self,
+        ctx: S,
+    ) -> Result<(String, Vec<Warning>), Error> {
+        let mut rv = String::new();
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        ok!(self._eval_with_vm(
+            Vm::new(self.env).with_warnings(warnings.clone()),
+            Value::from_serializable(&ctx),
+            &mut Output::with_string(&mut rv),
+            &Extensions::new(),
+        ));
+        let warnings = std::mem::take(&mut *warnings.lock().unwrap());
+        Ok((rv, warnings))
+    }
+
+    /// Renders the template and records the output of each top-level block.
+    ///
+    /// This works like [`render`](Self::render) but in addition to the
+    /// rendered template it returns a map of block name to the output that
+    /// block produced.  Only blocks that are not nested in other blocks are
+    /// recorded (nested blocks are part of the output of their parent block)
+    /// and the recorded output is the output of the final (most derived)
+    /// version of the block, before any enclosing filter is applied.  If a
+    /// block is rendered more than once only the first output is retained.
+    /// This is primarily useful for debugging layouts.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template("layout", "<title>{% block title %}{% endblock %}</title>").unwrap();
+    /// env.add_template("page", "{% extends 'layout' %}{% block title %}Hi{% endblock %}").unwrap();
+    /// let tmpl = env.get_template("page").unwrap();
+    /// let (rv, blocks) = tmpl.render_blocks(context!()).unwrap();
+    /// assert_eq!(rv, "<title>Hi</title>");
+    /// assert_eq!(blocks["title"], "Hi");
+    /// ```
+    #[cfg(feature = "multi-template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi-template")))]
+    pub fn render_blocks<S: Serialize>(
+        &self,
+        ctx: S,
+    ) -> Result<(String, BTreeMap<String, String>), Error> {
+        let mut rv = String::new();
+        let blocks = {
+            let mut out = Output::with_string(&mut rv);
+            out.enable_block_captures();
+            ok!(self._eval(Value::from_serializable(&ctx), &mut out, &Extensions::new()));
+            out.take_block_captures()
+        };
+        Ok((rv, blocks))
+    }
+
+    /// Renders only the given blocks and returns their outputs by name.
+    ///
+    /// Unlike [`render_blocks`](Self::render_blocks) the rest of the template
+    /// output is discarded and blocks which were not requested are not
+    /// rendered at all unless they contain one of the requested blocks.
+    /// Requested blocks can be nested in other blocks.  The remaining template
+    /// code outside of blocks is still evaluated so that variables set there
+    /// are available in the blocks.  This is useful for responses that are
+    /// made up of fragments of a page.
+    ///
+    /// Blocks which do not exist or are never rendered (for instance because
+    /// they are in a branch of an `if` that was not taken) are missing in the
+    /// returned map.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template(
+    ///     "page",
+    ///     "<title>{% block title %}{{ title }}{% endblock %}</title>\
+    ///      <main>{% block body %}Hello {{ name }}!{% endblock %}</main>\
+    ///      <footer>{% block footer %}{% endblock %}</footer>",
+    /// ).unwrap();
+    /// let tmpl = env.get_template("page").unwrap();
+    /// let blocks = tmpl.render_named_blocks(["title", "body"], context!(
+    ///     title => "Index",
+    ///     name => "John",
+    /// )).unwrap();
+    /// assert_eq!(blocks["title"], "Index");
+    /// assert_eq!(blocks["body"], "Hello John!");
+    /// assert!(!blocks.contains_key("footer"));
+    /// ```
+    #[cfg(feature = "multi-template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi-template")))]
+    pub fn render_named_blocks<I, N, S>(
+        &self,
+        names: I,
+        ctx: S,
+    ) -> Result<BTreeMap<String, String>, Error>
+    where
+        I: IntoIterator<Item = N>,
+        N: AsRef<str>,
+        S: Serialize,
+    {
+        let mut out = Output::null();
+        out.request_blocks(names.into_iter().map(|x| x.as_ref().to_string()).collect());
+        ok!(self._eval(Value::from_serializable(&ctx), &mut out, &Extensions::new()));
+        Ok(out.take_block_captures())
+    }
+
     /// Renders the template into a [`io::Write`].
     ///
     /// This works exactly like [`render`](Self::render) but instead writes the template
-    /// as it's evaluating into a [`io::Write`].
+    /// as it's evaluating into a [`io::Write`].  The output is not buffered up in
+    /// between so it's recommended to pass a buffered writer if the target is a file
+    /// or socket.  If writing fails, rendering is aborted and an error of kind
+    /// [`WriteFailure`](ErrorKind::WriteFailure) is returned with the I/O error as
+    /// source.
     ///
     /// ```
     /// # use minijinja::{Environment, context};
@@ -109,6 +314,7 @@ impl<'env> Template<'env> {
         self._eval(
             Value::from_serializable(&ctx),
             &mut Output::with_write(&mut wrapper),
+            &Extensions::new(),
         )
         .map_err(|err| {
             wrapper
@@ -122,34 +328,219 @@ impl<'env> Template<'env> {
         })
     }
 
-    fn _eval(&self, root: Value, out: &mut Output) -> Result<(), Error> {
-        Vm::new(self.env)
-            .eval(
-                &self.compiled.instructions,
-                root,
-                &self.compiled.blocks,
-                out,
-                self.initial_auto_escape,
-            )
-            .map(|_| ())
+    /// Renders the template into a [`fmt::Write`].
+    ///
+    /// This works like [`render_to_write`](Self::render_to_write) but writes into a
+    /// [`fmt::Write`] instead.  If the writer fails, rendering is aborted and an error
+    /// of kind [`WriteFailure`](ErrorKind::WriteFailure) is returned.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// # env.add_template("hello", "Hello {{ name }}!").unwrap();
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// let mut rv = String::from("> ");
+    /// tmpl.render_to_fmt_write(context!(name => "John"), &mut rv).unwrap();
+    /// assert_eq!(rv, "> Hello John!");
+    /// ```
+    pub fn render_to_fmt_write<S: Serialize, W: fmt::Write>(
+        &self,
+        ctx: S,
+        mut w: W,
+    ) -> Result<(), Error> {
+        self._eval(
+            Value::from_serializable(&ctx),
+            &mut Output::with_write(&mut w),
+            &Extensions::new(),
+        )
+    }
+
+    /// Evaluates the template into a [`State`].
+    ///
+    /// This evaluates the template, discards the output and returns the final
+    /// state of the engine.  The state can then be used to look up top-level
+    /// variables and macros the template defined (see [`State::lookup`]).
+    /// Macros retrieved this way can be invoked with [`Value::call`] as long as
+    /// the state is passed to them:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # use minijinja::value::Value;
+    /// # let mut env = Environment::new();
+    /// env.add_template("macros", "{% macro greet(name) %}Hello {{ name }}!{% endmacro %}")
+    ///     .unwrap();
+    /// let tmpl = env.get_template("macros").unwrap();
+    /// let state = tmpl.eval_to_state(context!()).unwrap();
+    /// let greet = state.lookup("greet").unwrap();
+    /// let rv = greet.call(&state, &[Value::from("John")]).unwrap();
+    /// assert_eq!(rv.as_str(), Some("Hello John!"));
+    /// ```
+    pub fn eval_to_state<S: Serialize>(&self, ctx: S) -> Result<State<'env, 'env>, Error> {
+        let mut state = ok!(Vm::new(self.env).eval_to_state(
+            self.instructions(),
+            Value::from_serializable(&ctx),
+            self.blocks(),
+            &mut Output::null(),
+            self.compiled.initial_auto_escape,
+        ));
+        state.retain_template(self);
+        Ok(state)
+    }
+
+    /// Renders the template and returns the output together with the final [`State`].
+    ///
+    /// This works like [`render`](Self::render) but also returns the state of
+    /// the engine after rendering, like [`eval_to_state`](Self::eval_to_state)
+    /// does.  This gives access to the top-level variables and macros the
+    /// template defined:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template("page", "{% set title = 'Index' %}<h1>{{ title }}</h1>").unwrap();
+    /// let tmpl = env.get_template("page").unwrap();
+    /// let (rv, state) = tmpl.render_and_return_state(context!()).unwrap();
+    /// assert_eq!(rv, "<h1>Index</h1>");
+    /// assert_eq!(state.lookup("title").unwrap().as_str(), Some("Index"));
+    /// ```
+    pub fn render_and_return_state<S: Serialize>(
+        &self,
+        ctx: S,
+    ) -> Result<(String, State<'env, 'env>), Error> {
+        let mut rv = String::new();
+        let mut state = ok!(Vm::new(self.env).eval_to_state(
+            self.instructions(),
+            Value::from_serializable(&ctx),
+            self.blocks(),
+            &mut Output::with_string(&mut rv),
+            self.compiled.initial_auto_escape,
+        ));
+        state.retain_template(self);
+        Ok((rv, state))
+    }
+
+    fn _eval(&self, root: Value, out: &mut Output, extensions: &Extensions) -> Result<(), Error> {
+        self._eval_with_vm(Vm::new(self.env), root, out, extensions)
+    }
+
+    fn _eval_with_vm(
+        &self,
+        vm: Vm<'env>,
+        root: Value,
+        out: &mut Output,
+        extensions: &Extensions,
+    ) -> Result<(), Error> {
+        vm.eval(
+            &self.compiled.instructions,
+            root,
+            &self.compiled.blocks,
+            out,
+            self.compiled.initial_auto_escape,
+            extensions,
+        )
+        .map(|_| ())
     }
 
     /// Returns the root instructions.
-    #[cfg(feature = "multi-template")]
+    ///
+    /// The instructions are only valid for as long as the template is
+    /// retained (see [`State::retain_template`]).
     pub(crate) fn instructions(&self) -> &'env Instructions<'env> {
-        &self.compiled.instructions
+        &self.compiled.compiled.instructions
     }
 
     /// Returns the blocks.
-    #[cfg(feature = "multi-template")]
     pub(crate) fn blocks(&self) -> &'env BTreeMap<&'env str, Instructions<'env>> {
-        &self.compiled.blocks
+        &self.compiled.compiled.blocks
+    }
+
+    /// Returns the loaded template that needs to be kept alive.
+    #[cfg(feature = "source")]
+    pub(crate) fn owner(&self) -> Option<&Arc<LoadedTemplate>> {
+        self.compiled.owner.as_ref()
     }
 
     /// Returns the initial auto escape setting.
     #[cfg(feature = "multi-template")]
     pub(crate) fn initial_auto_escape(&self) -> AutoEscape {
-        self.initial_auto_escape
+        self.compiled.initial_auto_escape
+    }
+}
+
+/// A reference to a compiled template.
+///
+/// Templates loaded by a [`Source`](crate::Source) might be replaced while
+/// they are still in use when they are reloaded.  Such templates are owned by
+/// the reference which keeps them alive.
+#[derive(Clone)]
+pub(crate) struct CompiledTemplateRef<'env> {
+    compiled: &'env CompiledTemplate<'env>,
+    #[cfg(feature = "source")]
+    owner: Option<Arc<LoadedTemplate>>,
+}
+
+impl<'env> CompiledTemplateRef<'env> {
+    /// Refers to a template that lives as long as the environment.
+    pub(crate) fn borrowed(compiled: &'env CompiledTemplate<'env>) -> CompiledTemplateRef<'env> {
+        CompiledTemplateRef {
+            compiled,
+            #[cfg(feature = "source")]
+            owner: None,
+        }
+    }
+
+    /// Refers to a loaded template that is kept alive by the reference.
+    #[cfg(feature = "source")]
+    pub(crate) fn owned(owner: Arc<LoadedTemplate>) -> CompiledTemplateRef<'env> {
+        let compiled = owner.borrow_dependent() as *const CompiledTemplate<'_>;
+        CompiledTemplateRef {
+            // SAFETY: the compiled template is behind an `Arc` and does not move.
+            // It lives as long as the owner which is held by this reference and
+            // retained by every state that uses the template.
+            compiled: unsafe { &*compiled.cast::<CompiledTemplate<'env>>() },
+            owner: Some(owner),
+        }
+    }
+}
+
+impl<'env> Deref for CompiledTemplateRef<'env> {
+    type Target = CompiledTemplate<'env>;
+
+    fn deref(&self) -> &CompiledTemplate<'env> {
+        self.compiled
+    }
+}
+
+pub(crate) type AutoEscapeFunc = dyn Fn(&str) -> AutoEscape + Sync + Send;
+
+/// Settings that control how templates are compiled.
+#[derive(Clone)]
+pub struct TemplateConfig {
+    /// Feature flags that are resolved at compile time.
+    pub feature_flags: Arc<BTreeMap<String, bool>>,
+    /// Enables resolving calls to the built-in `feature()` function at
+    /// compile time.  This must be off if `feature` is not the built-in.
+    pub fold_feature_calls: bool,
+    /// The whitespace handling of the lexer.
+    pub ws_config: WhitespaceConfig,
+    /// The delimiters of the template syntax.
+    pub syntax: Arc<Syntax>,
+    /// The names of custom tags the parser accepts.
+    pub custom_tags: Arc<BTreeSet<String>>,
+    /// Selects the initial auto escaping based on the template name.
+    pub default_auto_escape: Arc<AutoEscapeFunc>,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> TemplateConfig {
+        TemplateConfig {
+            feature_flags: Arc::default(),
+            fold_feature_calls: false,
+            ws_config: WhitespaceConfig::default(),
+            syntax: Arc::default(),
+            custom_tags: Arc::default(),
+            default_auto_escape: Arc::new(defaults::no_auto_escape),
+        }
     }
 }
 
@@ -159,6 +550,10 @@ pub struct CompiledTemplate<'source> {
     pub instructions: Instructions<'source>,
     /// Block local instructions.
     pub blocks: BTreeMap<&'source str, Instructions<'source>>,
+    /// The initial setting of auto escaping.
+    pub initial_auto_escape: AutoEscape,
+    /// The configuration the template was compiled with.
+    pub(crate) config: TemplateConfig,
 }
 
 impl<'env> fmt::Debug for CompiledTemplate<'env> {
@@ -175,27 +570,50 @@ impl<'env> fmt::Debug for CompiledTemplate<'env> {
 
 impl<'source> CompiledTemplate<'source> {
     /// Creates a compiled template from name and source.
+    ///
+    /// Calls to `feature()` for flags in the config's `feature_flags` are
+    /// resolved at compile time if `fold_feature_calls` is enabled and the
+    /// template does not declare a variable named `feature` itself.
     pub fn from_name_and_source(
         name: &'source str,
         source: &'source str,
+        config: &TemplateConfig,
     ) -> Result<CompiledTemplate<'source>, Error> {
-        attach_basic_debug_info(Self::_from_name_and_source_impl(name, source), source)
+        attach_basic_debug_info(
+            Self::_from_name_and_source_impl(name, source, config),
+            source,
+        )
     }
 
     fn _from_name_and_source_impl(
         name: &'source str,
         source: &'source str,
+        config: &TemplateConfig,
     ) -> Result<CompiledTemplate<'source>, Error> {
         // the parser/compiler combination can create constants in which case
         // we can probably benefit from the value optimization a bit.
         value::with_value_optimization(|| {
-            let ast = ok!(parse(source, name));
+            let ast = ok!(parse(
+                source,
+                name,
+                config.ws_config,
+                config.syntax.clone(),
+                config.custom_tags.clone(),
+            ));
             let mut gen = CodeGenerator::new(name, source);
+            if config.fold_feature_calls
+                && !config.feature_flags.is_empty()
+                && !declares_variable(&ast, "feature")
+            {
+                gen.set_feature_flags(config.feature_flags.clone());
+            }
             gen.compile_stmt(&ast);
             let (instructions, blocks) = gen.finish();
             Ok(CompiledTemplate {
                 instructions,
                 blocks,
+                initial_auto_escape: (config.default_auto_escape)(name),
+                config: config.clone(),
             })
         })
     }
@@ -46,6 +46,29 @@
 //! MiniJinja will perform the necessary conversions automatically.  For more
 //! information see the [`Test`] trait.
 //!
+//! # Accessing State
+//!
+//! Like filters, tests can declare a [`&State`](crate::State) as their first
+//! parameter to get access to the execution state.  This makes it possible to
+//! consult context variables or globals when performing a test:
+//!
+//! ```
+//! # use minijinja::Environment;
+//! # let mut env = Environment::new();
+//! use minijinja::value::Value;
+//! use minijinja::State;
+//!
+//! fn is_current_user(state: &State, value: &Value) -> bool {
+//!     state.lookup("current_user").as_ref() == Some(value)
+//! }
+//!
+//! env.add_test("current_user", is_current_user);
+//! ```
+//!
+//! Such tests can also be invoked dynamically by name through the `select`,
+//! `reject`, `selectattr` and `rejectattr` filters.  Errors raised by a test
+//! are annotated with the name of the test.
+//!
 //! # Built-in Tests
 //!
 //! When the `builtins` feature is enabled a range of built-in tests are
@@ -63,7 +86,10 @@ use crate::vm::State;
 type TestFunc = dyn Fn(&State, &[Value]) -> Result<bool, Error> + Sync + Send + 'static;
 
 #[derive(Clone)]
-pub(crate) struct BoxedTest(Arc<TestFunc>);
+pub(crate) struct BoxedTest {
+    func: Arc<TestFunc>,
+    builtin: bool,
+}
 
 /// A utility trait that represents the return value of filters.
 ///
@@ -97,7 +123,7 @@ impl TestResult for bool {
 /// [`filters`](crate::filters) but they always return boolean values and use a
 /// slightly different syntax to filters.  Like filters they accept the [`State`] by
 /// reference as first parameter and the value that that the test is applied to as second.
-/// Additionally up to 4 further parameters are supported.
+/// Additionally up to 5 further parameters are supported.
 ///
 /// A test function can return any of the following types:
 ///
@@ -105,7 +131,7 @@ impl TestResult for bool {
 /// * `Result<bool, Error>`
 ///
 /// Tests accept one mandatory parameter which is the value the filter is
-/// applied to and up to 4 extra parameters.  The extra parameters can be
+/// applied to and up to 5 extra parameters.  The extra parameters can be
 /// marked optional by using `Option<T>`.  The last argument can also use
 /// [`Rest<T>`](crate::value::Rest) to capture the remaining arguments.  All
 /// types are supported for which [`ArgType`] is implemented.
@@ -176,6 +202,7 @@ tuple_impls! { A B }
 tuple_impls! { A B C }
 tuple_impls! { A B C D }
 tuple_impls! { A B C D E }
+tuple_impls! { A B C D E F }
 
 impl BoxedTest {
     /// Creates a new boxed filter.
@@ -185,15 +212,29 @@ impl BoxedTest {
         Rv: TestResult,
         Args: for<'a> FunctionArgs<'a>,
     {
-        BoxedTest(Arc::new(move |state, args| -> Result<bool, Error> {
-            f.perform(ok!(Args::from_values(Some(state), args)), SealedMarker)
-                .into_result()
-        }))
+        BoxedTest {
+            func: Arc::new(move |state, args| -> Result<bool, Error> {
+                f.perform(ok!(Args::from_values(Some(state), args)), SealedMarker)
+                    .into_result()
+            }),
+            builtin: false,
+        }
+    }
+
+    /// Marks the test as one of the built-in tests.
+    pub fn builtin(mut self) -> BoxedTest {
+        self.builtin = true;
+        self
+    }
+
+    /// Checks if this is a built-in test.
+    pub fn is_builtin(&self) -> bool {
+        self.builtin
     }
 
     /// Applies the filter to a value and argument.
     pub fn perform(&self, state: &State, args: &[Value]) -> Result<bool, Error> {
-        (self.0)(state, args)
+        (self.func)(state, args)
     }
 }
 
@@ -212,9 +253,10 @@ mod builtins {
     use super::*;
 
     use std::borrow::Cow;
+    use std::cmp::Ordering;
     use std::convert::TryFrom;
 
-    use crate::value::ValueKind;
+    use crate::value::{ops, ObjectKind, ValueKind, ValueRepr};
 
     /// Checks if a value is odd.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
@@ -234,6 +276,35 @@ mod builtins {
         matches!(v.kind(), ValueKind::Number)
     }
 
+    /// Checks if this value is an integer.
+    ///
+    /// Booleans are not considered to be integers.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_integer(v: Value) -> bool {
+        matches!(
+            v.0,
+            ValueRepr::U64(_) | ValueRepr::I64(_) | ValueRepr::U128(_) | ValueRepr::I128(_)
+        )
+    }
+
+    /// Checks if this value is a float.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_float(v: Value) -> bool {
+        matches!(v.0, ValueRepr::F64(_))
+    }
+
+    /// Checks if this value is a boolean.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_boolean(v: Value) -> bool {
+        matches!(v.kind(), ValueKind::Bool)
+    }
+
+    /// Checks if this value is none.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_none(v: Value) -> bool {
+        v.is_none()
+    }
+
     /// Checks if this value is a string.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn is_string(v: Value) -> bool {
@@ -247,9 +318,34 @@ mod builtins {
     }
 
     /// Checks if this value is a mapping
+    ///
+    /// This is true for maps and struct objects.  Callables such as
+    /// functions or macros and plain objects are not mappings.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn is_mapping(v: Value) -> bool {
-        matches!(v.kind(), ValueKind::Map)
+        match v.as_object() {
+            Some(obj) => matches!(obj.kind(), ObjectKind::Struct(_)) && !obj.is_callable(),
+            None => matches!(v.kind(), ValueKind::Map),
+        }
+    }
+
+    /// Checks if this value can be iterated over.
+    ///
+    /// This is true for sequences and mappings which are the values a
+    /// `{% for %}` loop can iterate over.  Unlike in Jinja2 strings are
+    /// not iterable.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_iterable(v: Value) -> bool {
+        is_sequence(v.clone()) || is_mapping(v)
+    }
+
+    /// Checks if this value can be called.
+    ///
+    /// This is true for functions, macros and objects that declare
+    /// themselves callable via [`Object::is_callable`](crate::value::Object::is_callable).
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_callable(v: Value) -> bool {
+        v.as_object().map_or(false, |obj| obj.is_callable())
     }
 
     /// Checks if the value is starting with a string.
@@ -264,6 +360,74 @@ mod builtins {
         v.ends_with(&other as &str)
     }
 
+    /// Checks if a value is equal to another value.
+    ///
+    /// This is the same as the `==` operator.  It's mostly useful in
+    /// combination with filters like `|select` or `|selectattr`.
+    ///
+    /// ```jinja
+    /// {{ users|selectattr("role", "eq", "admin") }}
+    /// {{ 1 is eq 1 }} -> true
+    /// ```
+    ///
+    /// This test is also available under the alias `equalto`.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_eq(value: &Value, other: &Value) -> bool {
+        *value == *other
+    }
+
+    /// Checks if a value is not equal to another value.
+    ///
+    /// This is the same as the `!=` operator.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_ne(value: &Value, other: &Value) -> bool {
+        *value != *other
+    }
+
+    /// Checks if a value is less than another value.
+    ///
+    /// This is the same as the `<` operator and fails for values that
+    /// cannot be ordered.  The test is also available under the alias
+    /// `lessthan`.
+    ///
+    /// ```jinja
+    /// {{ users|selectattr("age", "lt", 18) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_lt(value: &Value, other: &Value) -> Result<bool, Error> {
+        ops::cmp(value, other, "<").map(|x| x == Ordering::Less)
+    }
+
+    /// Checks if a value is less than or equal to another value.
+    ///
+    /// This is the same as the `<=` operator.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_le(value: &Value, other: &Value) -> Result<bool, Error> {
+        ops::cmp(value, other, "<=").map(|x| x != Ordering::Greater)
+    }
+
+    /// Checks if a value is greater than another value.
+    ///
+    /// This is the same as the `>` operator and fails for values that
+    /// cannot be ordered.  The test is also available under the alias
+    /// `greaterthan`.
+    ///
+    /// ```jinja
+    /// {{ users|selectattr("age", "gt", 17) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_gt(value: &Value, other: &Value) -> Result<bool, Error> {
+        ops::cmp(value, other, ">").map(|x| x == Ordering::Greater)
+    }
+
+    /// Checks if a value is greater than or equal to another value.
+    ///
+    /// This is the same as the `>=` operator.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_ge(value: &Value, other: &Value) -> Result<bool, Error> {
+        ops::cmp(value, other, ">=").map(|x| x != Ordering::Less)
+    }
+
     #[test]
     fn test_basics() {
         fn test(_: &State, a: u32, b: u32) -> bool {
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::char::decode_utf16;
 use std::collections::BTreeMap;
 use std::fmt;
@@ -37,6 +38,19 @@ fn write_with_html_escaping(out: &mut Output, value: &Value) -> fmt::Result {
     }
 }
 
+fn write_with_xml_escaping(out: &mut Output, value: &Value) -> fmt::Result {
+    if matches!(
+        value.kind(),
+        ValueKind::Undefined | ValueKind::None | ValueKind::Bool | ValueKind::Number
+    ) {
+        write!(out, "{}", value)
+    } else if let Some(s) = value.as_str() {
+        write!(out, "{}", XmlEscape::new(s))
+    } else {
+        write!(out, "{}", XmlEscape::new(&value.to_string()))
+    }
+}
+
 fn invalid_autoescape(name: &str) -> Result<(), Error> {
     Err(Error::new(
         ErrorKind::InvalidOperation,
@@ -63,6 +77,7 @@ pub fn write_escaped(
     match auto_escape {
         AutoEscape::None => write!(out, "{}", value).map_err(Error::from),
         AutoEscape::Html => write_with_html_escaping(out, value).map_err(Error::from),
+        AutoEscape::Xml => write_with_xml_escaping(out, value).map_err(Error::from),
         #[cfg(feature = "json")]
         AutoEscape::Json => {
             let value = ok!(serde_json::to_string(&value).map_err(|err| {
@@ -89,6 +104,12 @@ pub enum AutoEscape {
     /// will be escaped in ways compatible to XML and HTML: `<`, `>`, `&`, `"`,
     /// `'`, and `/`.
     Html,
+    /// Use XML auto escaping rules.
+    ///
+    /// Any value will be converted into a string and the following characters
+    /// will be escaped with the entities predefined by XML: `<`, `>`, `&`, `"`
+    /// and `'`.
+    Xml,
     /// Use escaping rules suitable for JSON/JavaScript or YAML.
     ///
     /// Any value effectively ends up being serialized to JSON upon printing.  The
@@ -104,6 +125,86 @@ pub enum AutoEscape {
     Custom(&'static str),
 }
 
+/// Defines the behavior of undefined values in the engine.
+///
+/// For more information see
+/// [`set_undefined_behavior`](crate::Environment::set_undefined_behavior).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UndefinedBehavior {
+    /// The default, somewhat lenient undefined behavior.
+    ///
+    /// * **printing:** allowed (returns empty string)
+    /// * **iteration:** allowed (returns empty array)
+    /// * **attribute access of undefined values:** fails
+    /// * **comparisons:** allowed
+    Lenient,
+    /// Like `Lenient`, but also allows chaining of undefined lookups.
+    ///
+    /// * **printing:** allowed (returns empty string)
+    /// * **iteration:** allowed (returns empty array)
+    /// * **attribute access of undefined values:** allowed (returns undefined)
+    /// * **comparisons:** allowed
+    Chainable,
+    /// Complains very quickly about undefined values.
+    ///
+    /// * **printing:** fails
+    /// * **iteration:** fails
+    /// * **attribute access of undefined values:** fails
+    /// * **comparisons:** fail
+    /// * **truthiness checks:** fail
+    /// * **passing to filters expecting strings:** fails
+    ///
+    /// Undefined values can still be checked for with the `defined` and
+    /// `undefined` tests and replaced with the `default` filter.
+    Strict,
+}
+
+impl Default for UndefinedBehavior {
+    fn default() -> UndefinedBehavior {
+        UndefinedBehavior::Lenient
+    }
+}
+
+/// Information about a failed lookup passed to an undefined factory.
+///
+/// For more information see
+/// [`set_undefined_factory`](crate::Environment::set_undefined_factory).
+#[derive(Debug)]
+pub struct UndefinedInfo<'a> {
+    pub(crate) name: Cow<'a, str>,
+    pub(crate) parent: Option<&'a Value>,
+    pub(crate) template_name: &'a str,
+    pub(crate) line: Option<usize>,
+}
+
+impl<'a> UndefinedInfo<'a> {
+    /// Returns the name of the variable, attribute or item that was looked up.
+    ///
+    /// For item lookups with non string keys this is the rendered key.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value the attribute or item was looked up on.
+    ///
+    /// This is `None` for failed variable lookups.  The debug representation
+    /// of the value (`{:?}`) is its repr.
+    pub fn parent(&self) -> Option<&Value> {
+        self.parent
+    }
+
+    /// Returns the name of the template that performed the lookup.
+    pub fn template_name(&self) -> &str {
+        self.template_name
+    }
+
+    /// Returns the line in the template where the lookup happened.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
 /// Helper to HTML escape a string.
 pub struct HtmlEscape<'a>(pub &'a str);
 
@@ -153,6 +254,60 @@ impl<'a> fmt::Display for HtmlEscape<'a> {
     }
 }
 
+/// Helper to XML escape a string.
+///
+/// Unlike [`HtmlEscape`] this uses the entities predefined by XML (`&apos;`
+/// for the apostrophe) and leaves slashes alone.  Optionally control
+/// characters (other than tab, newline and carriage return) can be emitted
+/// as numeric character references.
+pub struct XmlEscape<'a> {
+    s: &'a str,
+    escape_control: bool,
+}
+
+impl<'a> XmlEscape<'a> {
+    /// Creates a new XML escaper for a string.
+    pub fn new(s: &'a str) -> XmlEscape<'a> {
+        XmlEscape {
+            s,
+            escape_control: false,
+        }
+    }
+
+    /// Enables escaping of control characters as numeric entities.
+    pub fn escape_control_chars(mut self, yes: bool) -> XmlEscape<'a> {
+        self.escape_control = yes;
+        self
+    }
+}
+
+impl<'a> fmt::Display for XmlEscape<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in self.s.char_indices() {
+            let entity = match c {
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '&' => "&amp;",
+                '"' => "&quot;",
+                '\'' => "&apos;",
+                '\t' | '\n' | '\r' => continue,
+                c if self.escape_control && c.is_control() => {
+                    ok!(f.write_str(&self.s[start..i]));
+                    ok!(write!(f, "&#{};", c as u32));
+                    start = i + c.len_utf8();
+                    continue;
+                }
+                _ => continue,
+            };
+            ok!(f.write_str(&self.s[start..i]));
+            ok!(f.write_str(entity));
+            start = i + 1;
+        }
+        f.write_str(&self.s[start..])
+    }
+}
+
 struct Unescaper {
     out: String,
     pending_surrogate: u16,
@@ -264,6 +419,15 @@ fn test_html_escape() {
     assert_eq!(output, "&lt;&gt;&amp;&quot;&#x27;&#x2f;");
 }
 
+#[test]
+fn test_xml_escape() {
+    let input = "<>&\"'/\x01\n";
+    let output = XmlEscape::new(input).to_string();
+    assert_eq!(output, "&lt;&gt;&amp;&quot;&apos;/\x01\n");
+    let output = XmlEscape::new(input).escape_control_chars(true).to_string();
+    assert_eq!(output, "&lt;&gt;&amp;&quot;&apos;/&#1;\n");
+}
+
 #[test]
 fn test_unescape() {
     assert_eq!(unescape(r"foo\u2603bar").unwrap(), "foo\u{2603}bar");
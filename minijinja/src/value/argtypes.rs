@@ -1,12 +1,15 @@
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::hash::BuildHasher;
 use std::ops::{Deref, DerefMut};
 
 use crate::error::{Error, ErrorKind};
 use crate::key::{Key, StaticKey};
+use crate::utils::UndefinedBehavior;
 use crate::value::{
-    Arc, MapType, Object, Packed, SeqObject, StringType, Value, ValueKind, ValueRepr,
+    Arc, MapType, Object, Packed, SeqObject, StringType, Value, ValueKind, ValueMap, ValueRepr,
 };
 use crate::vm::State;
 
@@ -155,6 +158,12 @@ pub trait ArgType<'a> {
     ) -> Result<(Self::Output, usize), Error> {
         Self::from_state_and_value(state, values.get(offset))
     }
+
+    #[doc(hidden)]
+    #[inline(always)]
+    fn is_kwargs() -> bool {
+        false
+    }
 }
 
 macro_rules! tuple_impls {
@@ -166,12 +175,35 @@ macro_rules! tuple_impls {
 
             fn from_values(state: Option<&'a State>, values: &'a [Value]) -> Result<Self::Output, Error> {
                 #![allow(non_snake_case, unused)]
+                // keyword arguments are passed as a map in the last position
+                // and are bound to the `Kwargs` parameter if there is one.
+                let has_kwargs = false $(|| $name::is_kwargs())* || $rest_name::is_kwargs();
+                let (values, kwargs) = match values.split_last() {
+                    Some((last, rest)) if last.is_kwargs() => {
+                        if has_kwargs {
+                            (rest, Some(last))
+                        } else if state.map_or(false, |x| x.env().strict_kwargs()) {
+                            return Err(unexpected_kwargs(last));
+                        } else {
+                            (values, None)
+                        }
+                    }
+                    _ => (values, None),
+                };
                 let mut idx = 0;
                 $(
-                    let ($name, offset) = ok!($name::from_state_and_value(state, values.get(idx)));
+                    let ($name, offset) = if $name::is_kwargs() {
+                        (ok!($name::from_state_and_value(state, kwargs)).0, 0)
+                    } else {
+                        ok!($name::from_state_and_value(state, values.get(idx)))
+                    };
                     idx += offset;
                 )*
-                let ($rest_name, offset) = ok!($rest_name::from_state_and_values(state, values, idx));
+                let ($rest_name, offset) = if $rest_name::is_kwargs() {
+                    (ok!($rest_name::from_state_and_value(state, kwargs)).0, 0)
+                } else {
+                    ok!($rest_name::from_state_and_values(state, values, idx))
+                };
                 idx += offset;
                 if values.get(idx).is_some() {
                     Err(Error::from(ErrorKind::TooManyArguments))
@@ -183,14 +215,29 @@ macro_rules! tuple_impls {
     };
 }
 
+fn unexpected_kwargs(kwargs: &Value) -> Error {
+    let names = kwargs
+        .try_iter()
+        .map(|iter| iter.map(|x| x.to_string()).collect::<Vec<_>>().join(", "))
+        .unwrap_or_default();
+    Error::new(
+        ErrorKind::TooManyArguments,
+        format!("unexpected keyword arguments: {}", names),
+    )
+}
+
 impl<'a> FunctionArgs<'a> for () {
     type Output = ();
 
-    fn from_values(_state: Option<&'a State>, values: &'a [Value]) -> Result<Self::Output, Error> {
-        if values.is_empty() {
-            Ok(())
-        } else {
-            Err(Error::from(ErrorKind::TooManyArguments))
+    fn from_values(state: Option<&'a State>, values: &'a [Value]) -> Result<Self::Output, Error> {
+        match values.first() {
+            None => Ok(()),
+            Some(value)
+                if value.is_kwargs() && state.map_or(false, |x| x.env().strict_kwargs()) =>
+            {
+                Err(unexpected_kwargs(value))
+            }
+            Some(_) => Err(Error::from(ErrorKind::TooManyArguments)),
         }
     }
 }
@@ -200,6 +247,7 @@ tuple_impls! { A *B }
 tuple_impls! { A B *C }
 tuple_impls! { A B C *D }
 tuple_impls! { A B C D *E }
+tuple_impls! { A B C D E *F }
 
 impl From<ValueRepr> for Value {
     #[inline(always)]
@@ -218,14 +266,21 @@ impl<'a> From<&'a [u8]> for Value {
 impl<'a> From<&'a str> for Value {
     #[inline(always)]
     fn from(val: &'a str) -> Self {
-        ValueRepr::String(Arc::new(val.into()), StringType::Normal).into()
+        ValueRepr::String(Arc::from(val), StringType::Normal).into()
     }
 }
 
 impl From<String> for Value {
     #[inline(always)]
     fn from(val: String) -> Self {
-        ValueRepr::String(Arc::new(val), StringType::Normal).into()
+        ValueRepr::String(Arc::from(val), StringType::Normal).into()
+    }
+}
+
+impl From<Arc<str>> for Value {
+    #[inline(always)]
+    fn from(val: Arc<str>) -> Self {
+        ValueRepr::String(val, StringType::Normal).into()
     }
 }
 
@@ -289,6 +344,61 @@ impl<T: Into<Value>> From<Vec<T>> for Value {
     }
 }
 
+impl<K: Into<StaticKey>, V: Into<Value>, S: BuildHasher> From<HashMap<K, V, S>> for Value {
+    fn from(val: HashMap<K, V, S>) -> Self {
+        val.into_iter().map(|(k, v)| (k.into(), v.into())).collect()
+    }
+}
+
+/// Converts a list of key/value pairs into a map.
+///
+/// If the `preserve_order` feature is enabled the map retains the order of
+/// the pairs, otherwise the keys are sorted.
+impl<K: Into<StaticKey>, V: Into<Value>> From<Vec<(K, V)>> for Value {
+    fn from(val: Vec<(K, V)>) -> Self {
+        val.into_iter().map(|(k, v)| (k.into(), v.into())).collect()
+    }
+}
+
+impl<T: Into<Value>> From<VecDeque<T>> for Value {
+    fn from(val: VecDeque<T>) -> Self {
+        val.into_iter().map(|v| v.into()).collect()
+    }
+}
+
+impl<T: Into<Value>> From<BTreeSet<T>> for Value {
+    fn from(val: BTreeSet<T>) -> Self {
+        val.into_iter().map(|v| v.into()).collect()
+    }
+}
+
+impl<T: Into<Value>, S: BuildHasher> From<HashSet<T, S>> for Value {
+    fn from(val: HashSet<T, S>) -> Self {
+        val.into_iter().map(|v| v.into()).collect()
+    }
+}
+
+// Tuples convert into sequences.  Pairs are not supported as they stand for
+// key/value pairs when collecting into a map.
+macro_rules! tuple_from {
+    ($($name:ident),*) => {
+        impl<$($name: Into<Value>),*> From<($($name,)*)> for Value {
+            #[allow(non_snake_case)]
+            fn from(($($name,)*): ($($name,)*)) -> Self {
+                Value::from(vec![$($name.into()),*] as Vec<Value>)
+            }
+        }
+    };
+}
+
+tuple_from!(A);
+tuple_from!(A, B, C);
+tuple_from!(A, B, C, D);
+tuple_from!(A, B, C, D, E);
+tuple_from!(A, B, C, D, E, F);
+tuple_from!(A, B, C, D, E, F, G);
+tuple_from!(A, B, C, D, E, F, G, H);
+
 impl<T: Object> From<Arc<T>> for Value {
     fn from(object: Arc<T>) -> Self {
         Value::from(object as Arc<dyn Object>)
@@ -336,6 +446,19 @@ value_from!(Arc<Vec<u8>>, Bytes);
 value_from!(Arc<Vec<Value>>, Seq);
 value_from!(Arc<dyn Object>, Dynamic);
 
+/// Fails if an undefined value is converted into a string in strict mode.
+fn check_strict_undefined(state: Option<&State>, value: Option<&Value>) -> Result<(), Error> {
+    match (state, value) {
+        (Some(state), Some(value))
+            if value.is_undefined()
+                && state.env().undefined_behavior() == UndefinedBehavior::Strict =>
+        {
+            Err(Error::from(ErrorKind::UndefinedError))
+        }
+        _ => Ok(()),
+    }
+}
+
 fn unsupported_conversion(kind: ValueKind, target: &str) -> Error {
     Error::new(
         ErrorKind::InvalidOperation,
@@ -490,6 +613,14 @@ impl<'a> ArgType<'a> for Cow<'_, str> {
             None => Err(Error::from(ErrorKind::MissingArgument)),
         }
     }
+
+    fn from_state_and_value(
+        state: Option<&'a State>,
+        value: Option<&'a Value>,
+    ) -> Result<(Self::Output, usize), Error> {
+        ok!(check_strict_undefined(state, value));
+        Ok((ok!(Self::from_value(value)), 1))
+    }
 }
 
 impl<'a> ArgType<'a> for &Value {
@@ -567,6 +698,199 @@ impl<'a, T: ArgType<'a, Output = T>> ArgType<'a> for Rest<T> {
     }
 }
 
+/// Utility type to deserialize an argument.
+///
+/// If a [`Filter`](crate::filters::Filter), [`Test`](crate::tests::Test) or
+/// [`Function`](crate::functions::Function) wants to accept a structured value
+/// it can declare the argument as `ViaDeserialize<T>` where `T` implements
+/// [`Deserialize`](serde::Deserialize).  The passed value is deserialized into
+/// `T` and the type itself deref's into it.  This requires the
+/// `deserialization` feature.
+///
+/// ```
+/// # use minijinja::{Environment, context};
+/// # let mut env = Environment::new();
+/// use serde::Deserialize;
+/// use minijinja::value::ViaDeserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Person {
+///     first_name: String,
+///     last_name: String,
+/// }
+///
+/// fn full_name(person: ViaDeserialize<Person>) -> String {
+///     format!("{} {}", person.first_name, person.last_name)
+/// }
+/// env.add_filter("full_name", full_name);
+/// let ctx = context!(user => context!(first_name => "John", last_name => "Doe"));
+/// assert_eq!(env.render_str("{{ user|full_name }}", ctx).unwrap(), "John Doe");
+/// ```
+#[cfg(feature = "deserialization")]
+#[cfg_attr(docsrs, doc(cfg(feature = "deserialization")))]
+#[derive(Debug)]
+pub struct ViaDeserialize<T>(pub T);
+
+#[cfg(feature = "deserialization")]
+impl<T> Deref for ViaDeserialize<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "deserialization")]
+impl<T> DerefMut for ViaDeserialize<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(feature = "deserialization")]
+impl<'a, T: serde::de::DeserializeOwned> ArgType<'a> for ViaDeserialize<T> {
+    type Output = Self;
+
+    fn from_value(value: Option<&'a Value>) -> Result<Self, Error> {
+        match value {
+            Some(value) => T::deserialize(value).map(ViaDeserialize),
+            None => Err(Error::from(ErrorKind::MissingArgument)),
+        }
+    }
+
+    fn from_value_owned(value: Value) -> Result<Self, Error> {
+        T::deserialize(value).map(ViaDeserialize)
+    }
+}
+
+/// Utility type to capture keyword arguments.
+///
+/// Keyword arguments are passed to [`Filter`](crate::filters::Filter)s,
+/// [`Test`](crate::tests::Test)s and [`Function`](crate::functions::Function)s
+/// as a map in the last position.  If the last argument is declared as
+/// `Kwargs` the keyword arguments can be retrieved by name with
+/// [`get`](Self::get).  If no keyword arguments were passed the value is empty.
+///
+/// ```
+/// # use minijinja::Environment;
+/// # let mut env = Environment::new();
+/// use minijinja::Error;
+/// use minijinja::value::Kwargs;
+///
+/// fn repeat(value: String, kwargs: Kwargs) -> Result<String, Error> {
+///     let times = kwargs.get::<Option<usize>>("times")?.unwrap_or(2);
+///     kwargs.assert_all_used()?;
+///     Ok(value.repeat(times))
+/// }
+/// env.add_filter("repeat", repeat);
+/// # assert_eq!(env.render_str("{{ 'a'|repeat(times=3) }}", ()).unwrap(), "aaa");
+/// ```
+#[derive(Debug, Default)]
+pub struct Kwargs {
+    values: Arc<ValueMap>,
+    used: RefCell<BTreeSet<String>>,
+    strict: bool,
+}
+
+impl Kwargs {
+    /// Returns the keyword argument with the given name.
+    ///
+    /// The value is converted with [`ArgType`], so to make an argument
+    /// optional request it as `Option<T>`.  The argument is marked as used.
+    pub fn get<'a, T: ArgType<'a, Output = T>>(&'a self, key: &str) -> Result<T, Error> {
+        let value = self
+            .values
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v);
+        let rv = T::from_value(value).map_err(|err| {
+            if err.kind() == ErrorKind::MissingArgument {
+                Error::new(
+                    ErrorKind::MissingArgument,
+                    format!("missing keyword argument '{}'", key),
+                )
+            } else {
+                err
+            }
+        });
+        self.used.borrow_mut().insert(key.to_string());
+        rv
+    }
+
+    /// Returns `true` if a keyword argument with the given name was passed.
+    pub fn has(&self, key: &str) -> bool {
+        self.values.contains_key(&Key::Str(key))
+    }
+
+    /// Iterates over the names of all passed keyword arguments.
+    pub fn args(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().filter_map(|k| k.as_str())
+    }
+
+    /// Fails with an error if a keyword argument was passed that was never
+    /// retrieved with [`get`](Self::get).
+    ///
+    /// Unknown keyword arguments are only reported if strict keyword
+    /// arguments are enabled on the environment (see
+    /// [`Environment::set_strict_kwargs`](crate::Environment::set_strict_kwargs)),
+    /// otherwise they are ignored.
+    pub fn assert_all_used(&self) -> Result<(), Error> {
+        if !self.strict {
+            return Ok(());
+        }
+        let used = self.used.borrow();
+        for key in self.values.keys() {
+            let name = key.as_str().unwrap_or_default();
+            if !used.contains(name) {
+                return Err(Error::new(
+                    ErrorKind::TooManyArguments,
+                    format!("unknown keyword argument '{}'", key),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ArgType<'a> for Kwargs {
+    type Output = Self;
+
+    fn from_value(value: Option<&'a Value>) -> Result<Self, Error> {
+        match value {
+            Some(Value(ValueRepr::Map(map, MapType::Kwargs))) => Ok(Kwargs {
+                values: map.clone(),
+                used: RefCell::default(),
+                strict: false,
+            }),
+            Some(value) if value.is_undefined() => Ok(Kwargs::default()),
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "expected keyword arguments",
+            )),
+            None => Ok(Kwargs::default()),
+        }
+    }
+
+    fn from_state_and_value(
+        state: Option<&'a State>,
+        value: Option<&'a Value>,
+    ) -> Result<(Self, usize), Error> {
+        let mut rv = ok!(Self::from_value(value));
+        rv.strict = state.map_or(false, |x| x.env().strict_kwargs());
+        Ok((rv, 1))
+    }
+
+    fn is_kwargs() -> bool {
+        true
+    }
+}
+
+impl From<Kwargs> for Value {
+    fn from(value: Kwargs) -> Value {
+        Value(ValueRepr::Map(value.values, MapType::Normal))
+    }
+}
+
 impl<'a> ArgType<'a> for Value {
     type Output = Self;
 
@@ -595,6 +919,35 @@ impl<'a> ArgType<'a> for String {
     fn from_value_owned(value: Value) -> Result<Self, Error> {
         Ok(value.to_string())
     }
+
+    fn from_state_and_value(
+        state: Option<&'a State>,
+        value: Option<&'a Value>,
+    ) -> Result<(Self::Output, usize), Error> {
+        ok!(check_strict_undefined(state, value));
+        Ok((ok!(Self::from_value(value)), 1))
+    }
+}
+
+impl<'a> ArgType<'a> for Arc<str> {
+    type Output = Self;
+
+    fn from_value(value: Option<&'a Value>) -> Result<Self, Error> {
+        match value {
+            Some(value) => Ok(value
+                .as_shared_str()
+                .unwrap_or_else(|| Arc::from(value.to_string()))),
+            None => Err(Error::from(ErrorKind::MissingArgument)),
+        }
+    }
+
+    fn from_state_and_value(
+        state: Option<&'a State>,
+        value: Option<&'a Value>,
+    ) -> Result<(Self::Output, usize), Error> {
+        ok!(check_strict_undefined(state, value));
+        Ok((ok!(Self::from_value(value)), 1))
+    }
 }
 
 impl<'a, T: ArgType<'a, Output = T>> ArgType<'a> for Vec<T> {
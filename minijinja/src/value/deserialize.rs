@@ -1,7 +1,12 @@
-use serde::de::{self, MapAccess, SeqAccess, Visitor};
-use serde::Deserialize;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize};
 
-use crate::value::{MapType, Value, ValueMap, ValueRepr};
+use crate::error::{Error, ErrorKind};
+use crate::value::{MapType, ObjectKind, Value, ValueKind, ValueMap, ValueRepr};
 
 impl<'de> Deserialize<'de> for Value {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -101,3 +106,240 @@ impl<'de> Visitor<'de> for ValueVisitor {
         Ok(Value(ValueRepr::Map(rv.into(), MapType::Normal)))
     }
 }
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::None => visitor.visit_unit(),
+            ValueRepr::Bool(v) => visitor.visit_bool(v),
+            ValueRepr::U64(v) => visitor.visit_u64(v),
+            ValueRepr::I64(v) => visitor.visit_i64(v),
+            ValueRepr::F64(v) => visitor.visit_f64(v),
+            ValueRepr::Char(v) => visitor.visit_char(v),
+            ValueRepr::U128(v) => visitor.visit_u128(v.0),
+            ValueRepr::I128(v) => visitor.visit_i128(v.0),
+            ValueRepr::String(ref v, _) => visitor.visit_str(v),
+            ValueRepr::Bytes(ref v) => visitor.visit_bytes(v),
+            ValueRepr::Dynamic(ref obj) if matches!(obj.kind(), ObjectKind::Plain) => {
+                Err(Error::new(
+                    ErrorKind::CannotDeserialize,
+                    "cannot deserialize plain objects",
+                ))
+            }
+            _ => match self.kind() {
+                ValueKind::Seq => {
+                    let mut de = SeqDeserializer::new(ok!(self.try_iter_owned()));
+                    let rv = ok!(visitor.visit_seq(&mut de));
+                    ok!(de.end());
+                    Ok(rv)
+                }
+                _ => {
+                    let items = ok!(self.try_iter_owned()).map(|key| {
+                        let value = self.get_item_opt(&key).unwrap_or_default();
+                        (key, value)
+                    });
+                    let mut de = MapDeserializer::new(items);
+                    let rv = ok!(visitor.visit_map(&mut de));
+                    ok!(de.end());
+                    Ok(rv)
+                }
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.0 {
+            ValueRepr::Undefined | ValueRepr::None => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let (variant, value) = match self.kind() {
+            ValueKind::String => (self, None),
+            ValueKind::Map if self.len() == Some(1) => {
+                let variant = ok!(self.try_iter_owned()).next().unwrap_or_default();
+                let value = self.get_item_opt(&variant);
+                (variant, value)
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::CannotDeserialize,
+                    "expected a string or a map with a single key for an enum",
+                ))
+            }
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value })
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// A deserializer over a [`Value`].
+///
+/// This can be used to deserialize a value into any type implementing
+/// [`Deserialize`](serde::Deserialize).  [`Value`] and `&Value` also implement
+/// [`Deserializer`](serde::Deserializer) directly, this wrapper exists for
+/// APIs that want an explicit deserializer type.  Errors are reported as
+/// [`Error`] with the [`CannotDeserialize`](ErrorKind::CannotDeserialize) kind.
+///
+/// ```
+/// # use minijinja::value::{Value, ValueDeserializer};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// struct Config {
+///     name: String,
+///     port: Option<u16>,
+/// }
+///
+/// let value = minijinja::context! { name => "app" };
+/// let config = Config::deserialize(ValueDeserializer::new(value)).unwrap();
+/// assert_eq!(config.name, "app");
+/// assert_eq!(config.port, None);
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "deserialization")))]
+#[derive(Debug, Clone)]
+pub struct ValueDeserializer {
+    value: Value,
+}
+
+impl ValueDeserializer {
+    /// Creates a deserializer for the given value.
+    pub fn new(value: Value) -> ValueDeserializer {
+        ValueDeserializer { value }
+    }
+}
+
+macro_rules! forward_to_value {
+    ($ty:ty, |$this:ident| $value:expr) => {
+        impl<'de> Deserializer<'de> for $ty {
+            type Error = Error;
+
+            fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let $this = self;
+                $value.deserialize_any(visitor)
+            }
+
+            fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                let $this = self;
+                $value.deserialize_option(visitor)
+            }
+
+            fn deserialize_enum<V: Visitor<'de>>(
+                self,
+                name: &'static str,
+                variants: &'static [&'static str],
+                visitor: V,
+            ) -> Result<V::Value, Error> {
+                let $this = self;
+                $value.deserialize_enum(name, variants, visitor)
+            }
+
+            fn deserialize_newtype_struct<V: Visitor<'de>>(
+                self,
+                name: &'static str,
+                visitor: V,
+            ) -> Result<V::Value, Error> {
+                let $this = self;
+                $value.deserialize_newtype_struct(name, visitor)
+            }
+
+            forward_to_deserialize_any! {
+                bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+                bytes byte_buf unit unit_struct seq tuple
+                tuple_struct map struct identifier ignored_any
+            }
+        }
+    };
+}
+
+forward_to_value!(&Value, |this| this.clone());
+forward_to_value!(ValueDeserializer, |this| this.value);
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+struct EnumDeserializer {
+    variant: Value,
+    value: Option<Value>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantDeserializer), Error> {
+        let variant = ok!(seed.deserialize(self.variant));
+        Ok((variant, VariantDeserializer { value: self.value }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<Value>,
+}
+
+impl VariantDeserializer {
+    fn into_value(self) -> Result<Value, Error> {
+        self.value.ok_or_else(|| {
+            Error::new(
+                ErrorKind::CannotDeserialize,
+                "expected a value for the enum variant",
+            )
+        })
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            Some(value) => Deserialize::deserialize(value),
+            None => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(ok!(self.into_value()))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        ok!(self.into_value()).deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        ok!(self.into_value()).deserialize_any(visitor)
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::value::{
+    with_default_max_value_depth, DepthGuard, ObjectKind, Value, ValueKind, ValueRepr,
+};
+
+/// Describes how a single path differs between two values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueChange {
+    /// The path only exists in the new value.
+    Added(Value),
+    /// The path only exists in the old value.
+    Removed(Value),
+    /// The path exists in both values but with different values.
+    Changed {
+        /// The value in the old value.
+        old: Value,
+        /// The value in the new value.
+        new: Value,
+    },
+}
+
+/// The differences between two values.
+///
+/// This is returned by [`Value::diff`].  Every difference is reported
+/// together with the path it was found at.  Paths use the syntax of
+/// [`Value::get_item_by_path`] (for instance `items[0].name`), the empty
+/// path refers to the value itself.
+///
+/// The display implementation renders one difference per line, prefixed
+/// with `+` for added, `-` for removed and `~` for changed paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueDiff {
+    changes: Vec<(String, ValueChange)>,
+}
+
+impl ValueDiff {
+    /// Returns the differences as pairs of path and change.
+    pub fn changes(&self) -> &[(String, ValueChange)] {
+        &self.changes
+    }
+
+    /// Returns the change at the given path.
+    pub fn get(&self, path: &str) -> Option<&ValueChange> {
+        self.changes
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, change)| change)
+    }
+}
+
+impl fmt::Display for ValueDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, (path, change)) in self.changes.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            match change {
+                ValueChange::Added(value) => write!(f, "+ {}: {:?}", path, value)?,
+                ValueChange::Removed(value) => write!(f, "- {}: {:?}", path, value)?,
+                ValueChange::Changed { old, new } => {
+                    write!(f, "~ {}: {:?} -> {:?}", path, old, new)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_plain_object(value: &Value) -> bool {
+    matches!(value.0, ValueRepr::Dynamic(ref obj) if matches!(obj.kind(), ObjectKind::Plain))
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn join_path(path: &str, key: &Value) -> String {
+    match key.as_str() {
+        Some(name) if is_identifier(name) && path.is_empty() => name.to_string(),
+        Some(name) if is_identifier(name) => format!("{}.{}", path, name),
+        _ => format!("{}[{:?}]", path, key),
+    }
+}
+
+/// Compares two values and records all differences in `out`.
+///
+/// If `first_only` is set the comparison stops at the first difference.
+fn diff_values(
+    old: &Value,
+    new: &Value,
+    path: &str,
+    first_only: bool,
+    out: &mut Vec<(String, ValueChange)>,
+) {
+    let changed = || ValueChange::Changed {
+        old: old.clone(),
+        new: new.clone(),
+    };
+
+    if let (ValueRepr::Dynamic(a), ValueRepr::Dynamic(b)) = (&old.0, &new.0) {
+        if Arc::ptr_eq(a, b) {
+            return;
+        }
+    }
+
+    // values nested too deeply are not compared but reported as changed
+    let _guard = match DepthGuard::enter(old) {
+        Ok(guard) => guard,
+        Err(_) => return out.push((path.to_string(), changed())),
+    };
+
+    match (old.kind(), new.kind()) {
+        (ValueKind::Seq, ValueKind::Seq) => {
+            let (old_items, new_items) = match (old.try_to_vec(), new.try_to_vec()) {
+                (Ok(a), Ok(b)) => (a, b),
+                _ => return out.push((path.to_string(), changed())),
+            };
+            for idx in 0..old_items.len().max(new_items.len()) {
+                if first_only && !out.is_empty() {
+                    return;
+                }
+                let item_path = join_path(path, &Value::from(idx));
+                match (old_items.get(idx), new_items.get(idx)) {
+                    (Some(a), Some(b)) => diff_values(a, b, &item_path, first_only, out),
+                    (Some(a), None) => out.push((item_path, ValueChange::Removed(a.clone()))),
+                    (None, Some(b)) => out.push((item_path, ValueChange::Added(b.clone()))),
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        (ValueKind::Map, ValueKind::Map) if !is_plain_object(old) && !is_plain_object(new) => {
+            let (old_keys, new_keys) = match (old.try_iter_owned(), new.try_iter_owned()) {
+                (Ok(a), Ok(b)) => (a, b),
+                _ => return out.push((path.to_string(), changed())),
+            };
+            for key in old_keys {
+                if first_only && !out.is_empty() {
+                    return;
+                }
+                let item_path = join_path(path, &key);
+                let a = old.get_item_opt(&key).unwrap_or(Value::UNDEFINED);
+                match new.get_item_opt(&key) {
+                    Some(b) => diff_values(&a, &b, &item_path, first_only, out),
+                    None => out.push((item_path, ValueChange::Removed(a))),
+                }
+            }
+            for key in new_keys {
+                if first_only && !out.is_empty() {
+                    return;
+                }
+                if old.get_item_opt(&key).is_none() {
+                    let b = new.get_item_opt(&key).unwrap_or(Value::UNDEFINED);
+                    out.push((join_path(path, &key), ValueChange::Added(b)));
+                }
+            }
+        }
+        (a, b) if a == b && old == new => {}
+        _ => out.push((path.to_string(), changed())),
+    }
+}
+
+impl Value {
+    /// Checks if two values are structurally equal.
+    ///
+    /// Sequences and maps (including those provided by [`SeqObject`] and
+    /// [`StructObject`] implementations) are compared item by item.  Unlike
+    /// `==` this never considers values of different [`kinds`](Self::kind)
+    /// equal, so `true` is not equal to `1` and a char is not equal to a
+    /// string.  Numbers of different types are still compared by value.
+    /// Values nested deeper than the [value depth
+    /// limit](crate::Environment::set_max_value_depth) are not compared and
+    /// considered different.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let a = Value::from(vec![Value::from(1), Value::from(true)]);
+    /// let b = Value::from(vec![Value::from(1.0), Value::from(true)]);
+    /// let c = Value::from(vec![Value::from(1), Value::from(1)]);
+    /// assert!(a.deep_eq(&b));
+    /// assert!(!a.deep_eq(&c));
+    /// ```
+    ///
+    /// [`SeqObject`]: crate::value::SeqObject
+    /// [`StructObject`]: crate::value::StructObject
+    pub fn deep_eq(&self, other: &Value) -> bool {
+        let mut out = Vec::new();
+        with_default_max_value_depth(|| diff_values(self, other, "", true, &mut out));
+        out.is_empty()
+    }
+
+    /// Reports the differences between this and another value.
+    ///
+    /// This compares the values like [`deep_eq`](Self::deep_eq) does and
+    /// returns `None` if they are equal.  Otherwise a [`ValueDiff`] with the
+    /// paths that were added, removed or changed is returned.
+    ///
+    /// ```
+    /// # use minijinja::{context, value::{Value, ValueChange}};
+    /// let old = context! { user => context! { name => "Peter", age => 42 } };
+    /// let new = context! { user => context! { name => "Paul", age => 42 } };
+    /// let diff = old.diff(&new).unwrap();
+    /// assert_eq!(diff.to_string(), r#"~ user.name: "Peter" -> "Paul""#);
+    /// assert_eq!(diff.get("user.name"), Some(&ValueChange::Changed {
+    ///     old: Value::from("Peter"),
+    ///     new: Value::from("Paul"),
+    /// }));
+    /// ```
+    pub fn diff(&self, other: &Value) -> Option<ValueDiff> {
+        let mut changes = Vec::new();
+        with_default_max_value_depth(|| diff_values(self, other, "", false, &mut changes));
+        if changes.is_empty() {
+            None
+        } else {
+            Some(ValueDiff { changes })
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::borrow::Cow;
+
+use crate::error::{Error, ErrorKind};
+use crate::value::{FunctionArgs, Kwargs, Value, ValueKind};
+use crate::vm::State;
+
+/// Converts the arguments of a method call like a function would.
+fn method_args<'a, A: FunctionArgs<'a, Output = A>>(
+    state: &'a State,
+    args: &'a [Value],
+) -> Result<A, Error> {
+    A::from_values(Some(state), args)
+}
+
+/// Calls one of the built-in methods of strings, maps and sequences.
+///
+/// These mirror the methods Python provides on these types so that templates
+/// written for Jinja2 can call `"a,b".split(",")` or `d.get("key")`.  If the
+/// value has no built-in method of that name `None` is returned.
+pub(crate) fn call_builtin_method(
+    state: &State,
+    value: &Value,
+    name: &str,
+    args: &[Value],
+) -> Option<Result<Value, Error>> {
+    match value.kind() {
+        ValueKind::String => call_string_method(state, value, name, args),
+        ValueKind::Map => call_map_method(state, value, name, args),
+        ValueKind::Seq => call_seq_method(state, value, name, args),
+        _ => None,
+    }
+}
+
+fn call_string_method(
+    state: &State,
+    value: &Value,
+    name: &str,
+    args: &[Value],
+) -> Option<Result<Value, Error>> {
+    let s = value.to_cowstr();
+    Some(match name {
+        "upper" => method_args(state, args).map(|()| Value::from(s.to_uppercase())),
+        "lower" => method_args(state, args).map(|()| Value::from(s.to_lowercase())),
+        "title" => method_args(state, args).map(|()| Value::from(crate::filters::title(s))),
+        "strip" | "lstrip" | "rstrip" => {
+            method_args(state, args).map(|(chars,): (Option<Cow<'_, str>>,)| {
+                let chars = chars.map(|x| x.chars().collect::<Vec<_>>());
+                let is_strip_char = |c: char| match chars {
+                    Some(ref chars) => chars.contains(&c),
+                    None => c.is_whitespace(),
+                };
+                Value::from(match name {
+                    "lstrip" => s.trim_start_matches(is_strip_char),
+                    "rstrip" => s.trim_end_matches(is_strip_char),
+                    _ => s.trim_matches(is_strip_char),
+                })
+            })
+        }
+        "split" => split(state, &s, args),
+        "startswith" | "endswith" => method_args(state, args).and_then(|(affix,): (Value,)| {
+            let matches = |affix: &str| match name {
+                "startswith" => s.starts_with(affix),
+                _ => s.ends_with(affix),
+            };
+            // like in Python a tuple (sequence) of alternatives is accepted
+            if affix.kind() == ValueKind::Seq {
+                for item in ok!(affix.try_iter()) {
+                    if matches(&item.to_cowstr()) {
+                        return Ok(Value::from(true));
+                    }
+                }
+                Ok(Value::from(false))
+            } else {
+                Ok(Value::from(matches(&affix.to_cowstr())))
+            }
+        }),
+        "replace" => method_args(state, args).map(
+            |(old, new, count): (Cow<'_, str>, Cow<'_, str>, Option<i64>)| match count {
+                Some(count) if count >= 0 => Value::from(s.replacen(&*old, &new, count as usize)),
+                _ => Value::from(s.replace(&*old, &new)),
+            },
+        ),
+        _ => return None,
+    })
+}
+
+fn split(state: &State, s: &str, args: &[Value]) -> Result<Value, Error> {
+    let (sep, maxsplit, kwargs): (Option<Cow<'_, str>>, Option<i64>, Kwargs) =
+        ok!(method_args(state, args));
+    let sep = match sep {
+        Some(sep) => Some(sep.into_owned()),
+        None => ok!(kwargs.get::<Option<String>>("sep")),
+    };
+    let maxsplit = match maxsplit {
+        Some(maxsplit) => maxsplit,
+        None => ok!(kwargs.get::<Option<i64>>("maxsplit")).unwrap_or(-1),
+    };
+    ok!(kwargs.assert_all_used());
+    let maxsplit = usize::try_from(maxsplit).ok();
+
+    let items: Vec<Value> = match sep {
+        Some(sep) if sep.is_empty() => {
+            return Err(Error::new(ErrorKind::InvalidOperation, "empty separator"))
+        }
+        Some(sep) => match maxsplit {
+            Some(maxsplit) => s
+                .splitn(maxsplit + 1, &sep as &str)
+                .map(Value::from)
+                .collect(),
+            None => s.split(&sep as &str).map(Value::from).collect(),
+        },
+        // without a separator runs of whitespace are split on and leading or
+        // trailing whitespace does not produce empty strings.
+        None => {
+            let mut rv = Vec::new();
+            let mut rest = s.trim_start();
+            while !rest.is_empty() {
+                if maxsplit.map_or(false, |maxsplit| rv.len() >= maxsplit) {
+                    rv.push(Value::from(rest));
+                    break;
+                }
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                rv.push(Value::from(&rest[..end]));
+                rest = rest[end..].trim_start();
+            }
+            rv
+        }
+    };
+    Ok(Value::from(items))
+}
+
+fn call_map_method(
+    state: &State,
+    value: &Value,
+    name: &str,
+    args: &[Value],
+) -> Option<Result<Value, Error>> {
+    Some(match name {
+        "get" => method_args(state, args).map(|(key, default): (Value, Option<Value>)| {
+            value
+                .get_item_opt(&key)
+                .or(default)
+                .unwrap_or_else(|| Value::from(()))
+        }),
+        "keys" => method_args(state, args)
+            .and_then(|()| value.try_iter())
+            .map(|keys| keys.collect()),
+        "values" => method_args(state, args).and_then(|()| {
+            Ok(ok!(value.try_iter())
+                .map(|key| value.get_item_opt(&key).unwrap_or_default())
+                .collect())
+        }),
+        _ => return None,
+    })
+}
+
+fn call_seq_method(
+    state: &State,
+    value: &Value,
+    name: &str,
+    args: &[Value],
+) -> Option<Result<Value, Error>> {
+    Some(match name {
+        "count" => method_args(state, args).and_then(|(needle,): (Value,)| {
+            Ok(Value::from(
+                ok!(value.try_iter()).filter(|item| *item == needle).count(),
+            ))
+        }),
+        "index" => method_args(state, args).and_then(|(needle,): (Value,)| {
+            match ok!(value.try_iter()).position(|item| item == needle) {
+                Some(idx) => Ok(Value::from(idx)),
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("{:?} is not in sequence", needle),
+                )),
+            }
+        }),
+        _ => return None,
+    })
+}
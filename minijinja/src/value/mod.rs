@@ -18,6 +18,18 @@
 //! let value = Value::from(42);
 //! ```
 //!
+//! This also works for the common collection types.  Vectors, sets, deques
+//! and tuples (other than pairs) become sequences, maps as well as vectors
+//! of key/value pairs become maps:
+//!
+//! ```
+//! # use minijinja::value::Value;
+//! # use std::collections::HashMap;
+//! let seq = Value::from((1, "two", 3.0));
+//! let map = Value::from(vec![("a", 1), ("b", 2)]);
+//! let map = Value::from(HashMap::from([("a", 1), ("b", 2)]));
+//! ```
+//!
 //! Or via the [`FromIterator`] trait:
 //!
 //! ```
@@ -59,13 +71,53 @@
 //! means they can be copied relatively cheaply.  Special care must be taken
 //! so that cycles are not created to avoid causing memory leaks.
 //!
+//! # Comparisons and Ordering
+//!
+//! Values implement [`Ord`] which gives them a deterministic total order.  This
+//! order is used by the comparison operators in templates as well as whenever
+//! values are sorted or the smallest or largest value is picked:
+//!
+//! * numbers (and booleans) are compared by their numeric value, even across
+//!   integer and float types.  Integers are compared to floats without first
+//!   converting them to floats so no precision is lost.
+//! * `-0.0` is equal to `0.0`.  `NaN` is equal to itself and sorts after all
+//!   other numbers, including infinity.
+//! * strings, bytes and chars compare by their contents.
+//! * sequences and maps are compared item by item.
+//! * values of different kinds are ordered by their [`ValueKind`]: undefined,
+//!   none, numbers, chars, strings, bytes, sequences and maps.
+//!
+//! The last rule only exists so that mixed values can be sorted.  The `<`,
+//! `<=`, `>` and `>=` operators in templates are stricter and fail with an
+//! error if the two values cannot be ordered meaningfully, for instance when
+//! a number is compared to a string or when maps are compared.  They do
+//! however order chars and strings by their contents and keep ordering
+//! undefined values before everything else unless the strict undefined
+//! behavior is used.  `==` and `!=` never fail, values of different kinds
+//! are simply not equal.
+//!
+//! ```
+//! # use minijinja::value::Value;
+//! let mut values = vec![Value::from(3), Value::from(f64::NAN), Value::from(1.5), Value::from(2)];
+//! values.sort();
+//! assert_eq!(values[..3], [Value::from(1.5), Value::from(2), Value::from(3)]);
+//! ```
+//!
 //! # HTML Escaping
 //!
 //! MiniJinja inherits the general desire to be clever about escaping.  For this
 //! prupose a value will (when auto escaping is enabled) always be escaped.  To
 //! prevent this behavior the [`safe`](crate::filters::safe) filter can be used
 //! in the template.  Outside of templates the [`Value::from_safe_string`] method
-//! can be used to achieve the same result.
+//! can be used to achieve the same result and [`Value::is_safe`] tells if a
+//! value is safe.
+//!
+//! Safety only carries over to derived values if no untrusted input can end up
+//! in the result.  Concatenation with `~` as well as the `join` and `replace`
+//! filters return a safe string only if all of their string inputs are safe.
+//! Filters that only remove characters (like `trim`) keep the safety of the
+//! value, all other filters (like `upper`) return strings that are escaped
+//! again.
 //!
 //! # Dynamic Objects
 //!
@@ -103,7 +155,7 @@
 
 use std::any::TypeId;
 use std::borrow::Cow;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
@@ -122,14 +174,25 @@ use crate::value::object::{SimpleSeqObject, SimpleStructObject};
 use crate::value::serialize::ValueSerializer;
 use crate::vm::State;
 
-pub use crate::value::argtypes::{from_args, ArgType, FunctionArgs, FunctionResult, Rest};
-pub use crate::value::object::{Object, ObjectKind, SeqObject, SeqObjectIter, StructObject};
+#[cfg(feature = "deserialization")]
+pub use crate::value::argtypes::ViaDeserialize;
+pub use crate::value::argtypes::{from_args, ArgType, FunctionArgs, FunctionResult, Kwargs, Rest};
+#[cfg(feature = "deserialization")]
+pub use crate::value::deserialize::ValueDeserializer;
+pub use crate::value::diff::{ValueChange, ValueDiff};
+pub use crate::value::object::{
+    MemoizedStruct, Object, ObjectKind, SeqObject, SeqObjectIter, StructObject,
+};
 
 mod argtypes;
 #[cfg(feature = "deserialization")]
 mod deserialize;
+mod diff;
+#[cfg(feature = "builtins")]
+mod methods;
 mod object;
 pub(crate) mod ops;
+mod path;
 mod serialize;
 
 #[cfg(test)]
@@ -149,8 +212,16 @@ thread_local! {
     static INTERNAL_SERIALIZATION: AtomicBool = AtomicBool::new(false);
     static LAST_VALUE_HANDLE: AtomicUsize = AtomicUsize::new(0);
     static VALUE_HANDLES: RefCell<BTreeMap<usize, Value>> = RefCell::new(BTreeMap::new());
+    static VALUE_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // only limited while rendering, see `with_max_value_depth`
+    static VALUE_DEPTH_LIMIT: Cell<usize> = const { Cell::new(usize::MAX) };
+    static SORT_MAP_KEYS: Cell<bool> = const { Cell::new(false) };
 }
 
+/// The default for how deeply values can be nested when printed or serialized
+/// during a render.
+pub(crate) const MAX_VALUE_DEPTH: usize = 100;
+
 /// Function that returns true when serialization for [`Value`] is taking place.
 ///
 /// MiniJinja internally creates [`Value`] objects from all values passed to the
@@ -201,6 +272,101 @@ fn with_internal_serialization<R, F: FnOnce() -> R>(f: F) -> R {
     })
 }
 
+/// Runs the given function with a different limit for value nesting.
+pub(crate) fn with_max_value_depth<R, F: FnOnce() -> R>(limit: usize, f: F) -> R {
+    VALUE_DEPTH_LIMIT.with(|max| {
+        let old = max.replace(limit);
+        let _on_drop = OnDrop::new(|| max.set(old));
+        f()
+    })
+}
+
+/// Runs the given function with the default limit for value nesting unless
+/// a limit is already in place (as it is during a render).
+pub(crate) fn with_default_max_value_depth<R, F: FnOnce() -> R>(f: F) -> R {
+    if VALUE_DEPTH_LIMIT.with(|max| max.get()) == usize::MAX {
+        with_max_value_depth(MAX_VALUE_DEPTH, f)
+    } else {
+        f()
+    }
+}
+
+/// Runs the given function with sorting of map keys enabled or disabled.
+pub(crate) fn with_sorted_map_keys<R, F: FnOnce() -> R>(yes: bool, f: F) -> R {
+    SORT_MAP_KEYS.with(|flag| {
+        let old = flag.replace(yes);
+        let _on_drop = OnDrop::new(|| flag.set(old));
+        f()
+    })
+}
+
+/// Returns `true` if maps should be iterated in sorted key order.
+///
+/// Maps backed by a `BTreeMap` are always sorted, so this only has an
+/// effect with the `preserve_order` feature and for struct objects.
+fn sort_map_keys() -> bool {
+    SORT_MAP_KEYS.with(|flag| flag.get())
+}
+
+/// Iterates over the entries of a map, sorted if requested.
+pub(crate) fn map_entries(map: &ValueMap) -> Box<dyn Iterator<Item = (&StaticKey, &Value)> + '_> {
+    if cfg!(feature = "preserve_order") && sort_map_keys() {
+        let mut entries = map.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        Box::new(entries.into_iter())
+    } else {
+        Box::new(map.iter())
+    }
+}
+
+/// Iterates over the fields of a struct object, sorted if requested.
+pub(crate) fn struct_items(obj: &dyn StructObject) -> Box<dyn Iterator<Item = (&str, Value)> + '_> {
+    if sort_map_keys() {
+        let mut items = obj.items().collect::<Vec<_>>();
+        items.sort_by(|a, b| a.0.cmp(b.0));
+        Box::new(items.into_iter())
+    } else {
+        obj.items()
+    }
+}
+
+/// Tracks how deeply nested a value is that is being printed or serialized.
+pub(crate) struct DepthGuard;
+
+impl DepthGuard {
+    /// Enters a nested value, fails with the limit if it's nested too deeply.
+    pub fn enter(value: &Value) -> Result<Option<DepthGuard>, usize> {
+        if !matches!(
+            value.0,
+            ValueRepr::Seq(_) | ValueRepr::Map(..) | ValueRepr::Dynamic(_)
+        ) {
+            return Ok(None);
+        }
+        let limit = VALUE_DEPTH_LIMIT.with(|max| max.get());
+        VALUE_DEPTH.with(|depth| {
+            if depth.get() >= limit {
+                Err(limit)
+            } else {
+                depth.set(depth.get() + 1);
+                Ok(Some(DepthGuard))
+            }
+        })
+    }
+
+    /// Like [`enter`](Self::enter) but fails with a serialization error.
+    pub fn enter_serialize<E: serde::ser::Error>(value: &Value) -> Result<Option<DepthGuard>, E> {
+        DepthGuard::enter(value).map_err(|limit| {
+            E::custom(format_args!("value nested too deeply (limit is {})", limit))
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        VALUE_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
 /// Describes the kind of value.
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum ValueKind {
@@ -282,7 +448,7 @@ pub(crate) enum ValueRepr {
     None,
     U128(Packed<u128>),
     I128(Packed<i128>),
-    String(Arc<String>, StringType),
+    String(Arc<str>, StringType),
     Bytes(Arc<Vec<u8>>),
     Seq(Arc<Vec<Value>>),
     Map(Arc<ValueMap>, MapType),
@@ -316,17 +482,7 @@ pub struct Value(pub(crate) ValueRepr);
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (&self.0, &other.0) {
-            (ValueRepr::None, ValueRepr::None) => true,
-            (ValueRepr::String(a, _), ValueRepr::String(b, _)) => a == b,
-            (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a == b,
-            _ => match ops::coerce(self, other) {
-                Some(ops::CoerceResult::F64(a, b)) => a == b,
-                Some(ops::CoerceResult::I128(a, b)) => a == b,
-                Some(ops::CoerceResult::String(a, b)) => a == b,
-                None => false,
-            },
-        }
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -334,15 +490,29 @@ impl Eq for Value {}
 
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let Some(rv) = ops::cmp_numbers(self, other) {
+            return rv;
+        }
         match (&self.0, &other.0) {
-            (ValueRepr::None, ValueRepr::None) => Some(Ordering::Equal),
-            (ValueRepr::String(a, _), ValueRepr::String(b, _)) => a.partial_cmp(b),
-            (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a.partial_cmp(b),
-            _ => match ops::coerce(self, other) {
-                Some(ops::CoerceResult::F64(a, b)) => a.partial_cmp(&b),
-                Some(ops::CoerceResult::I128(a, b)) => a.partial_cmp(&b),
-                Some(ops::CoerceResult::String(a, b)) => a.partial_cmp(&b),
-                None => None,
+            (ValueRepr::String(a, _), ValueRepr::String(b, _)) => a.cmp(b),
+            (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a.cmp(b),
+            (ValueRepr::Char(a), ValueRepr::Char(b)) => a.cmp(b),
+            (ValueRepr::Dynamic(a), ValueRepr::Dynamic(b)) if Arc::ptr_eq(a, b) => Ordering::Equal,
+            _ => match (self.kind(), other.kind()) {
+                (ValueKind::Seq, ValueKind::Seq) => {
+                    match (self.try_iter_owned(), other.try_iter_owned()) {
+                        (Ok(a), Ok(b)) => a.cmp(b),
+                        _ => Ordering::Equal,
+                    }
+                }
+                (ValueKind::Map, ValueKind::Map) => self.cmp_map(other),
+                (a, b) => a.cmp(&b),
             },
         }
     }
@@ -350,12 +520,20 @@ impl PartialOrd for Value {
 
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let _guard = match DepthGuard::enter(self) {
+            Ok(guard) => guard,
+            Err(_) => return f.write_str("..."),
+        };
         fmt::Debug::fmt(&self.0, f)
     }
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let _guard = match DepthGuard::enter(self) {
+            Ok(guard) => guard,
+            Err(_) => return f.write_str("..."),
+        };
         match &self.0 {
             ValueRepr::Undefined => Ok(()),
             ValueRepr::Bool(val) => write!(f, "{}", val),
@@ -391,7 +569,7 @@ impl fmt::Display for Value {
             }
             ValueRepr::Map(m, _) => {
                 ok!(write!(f, "{{"));
-                for (idx, (key, val)) in m.iter().enumerate() {
+                for (idx, (key, val)) in map_entries(m).enumerate() {
                     if idx > 0 {
                         ok!(write!(f, ", "));
                     }
@@ -446,7 +624,7 @@ impl Value {
     /// let val = Value::from_safe_string("<em>note</em>".into());
     /// ```
     pub fn from_safe_string(value: String) -> Value {
-        ValueRepr::String(Arc::new(value), StringType::Safe).into()
+        ValueRepr::String(Arc::from(value), StringType::Safe).into()
     }
 
     /// Creates a value from a dynamic object.
@@ -521,6 +699,20 @@ impl Value {
     /// # use minijinja::value::Value;
     /// let pow = Value::from_function(|a: u32| a * a);
     /// ```
+    ///
+    /// Like with filters and global functions the closure can declare
+    /// [`&State`](crate::State) as first parameter to get access to the
+    /// engine state.  This for instance allows looking up other variables
+    /// in the context:
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// use minijinja::State;
+    /// let url_for = Value::from_function(|state: &State, path: String| {
+    ///     let base = state.lookup("base_url").unwrap_or_default();
+    ///     format!("{}/{}", base, path)
+    /// });
+    /// ```
     pub fn from_function<F, Rv, Args>(f: F) -> Value
     where
         // the crazy bounds here exist to enable borrowing in closures
@@ -587,6 +779,15 @@ impl Value {
     }
 
     /// Returns `true` if this value is safe.
+    ///
+    /// Safe values are strings that bypass auto escaping.  They are created
+    /// with [`from_safe_string`](Self::from_safe_string) or the `safe` filter.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// assert!(Value::from_safe_string("<em>note</em>".into()).is_safe());
+    /// assert!(!Value::from("<em>note</em>").is_safe());
+    /// ```
     pub fn is_safe(&self) -> bool {
         matches!(&self.0, ValueRepr::String(_, StringType::Safe))
     }
@@ -604,7 +805,28 @@ impl Value {
     /// If the value is a string, return it.
     pub fn as_str(&self) -> Option<&str> {
         match &self.0 {
-            ValueRepr::String(ref s, _) => Some(s.as_str()),
+            ValueRepr::String(ref s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// If the value is a string, return the shared string storage.
+    ///
+    /// Strings are reference counted internally.  This hands out another
+    /// reference to the same storage without copying the string which makes
+    /// it possible to pass large strings through filters and back into new
+    /// values cheaply via [`Value::from`].
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// use std::sync::Arc;
+    /// let value = Value::from(Arc::<str>::from("Hello World!"));
+    /// let shared = value.as_shared_str().unwrap();
+    /// assert_eq!(&*shared, "Hello World!");
+    /// ```
+    pub fn as_shared_str(&self) -> Option<Arc<str>> {
+        match &self.0 {
+            ValueRepr::String(ref s, _) => Some(s.clone()),
             _ => None,
         }
     }
@@ -642,6 +864,15 @@ impl Value {
         None
     }
 
+    /// If the value is a dynamic object, return it.
+    #[cfg(feature = "builtins")]
+    pub(crate) fn as_object(&self) -> Option<&dyn Object> {
+        match self.0 {
+            ValueRepr::Dynamic(ref dy) => Some(&**dy as &dyn Object),
+            _ => None,
+        }
+    }
+
     /// Returns the length of the contained value.
     ///
     /// Values without a length will return `None`.
@@ -667,9 +898,9 @@ impl Value {
 
     /// Looks up an attribute by attribute name.
     ///
-    /// This this returns [`UNDEFINED`](Self::UNDEFINED) when an invalid key is
-    /// resolved.  An error is returned when if the value does not contain an object
-    /// that has attributes.
+    /// This applies the same rules as the `a.b` operator in templates.  It
+    /// returns [`UNDEFINED`](Self::UNDEFINED) when an invalid key is resolved
+    /// and an error if the value itself is undefined.
     ///
     /// ```
     /// # use minijinja::value::Value;
@@ -717,7 +948,8 @@ impl Value {
     ///
     /// This is similar to [`get_attr`](Self::get_attr) but instead of using
     /// a string key this can be any key.  For instance this can be used to
-    /// index into sequences.  Like [`get_attr`](Self::get_attr) this returns
+    /// index into sequences.  This applies the same rules as the `a[b]`
+    /// operator in templates and like [`get_attr`](Self::get_attr) this returns
     /// [`UNDEFINED`](Self::UNDEFINED) when an invalid key is looked up.
     ///
     /// ```
@@ -736,6 +968,56 @@ impl Value {
         }
     }
 
+    /// Looks up a nested item by path.
+    ///
+    /// The path uses the same syntax that templates use for attribute and item
+    /// lookups: names are separated by dots (`a.b.c`) and items are looked up
+    /// with brackets which either hold an integer (`items[0]`, `items[-1]`) or
+    /// a quoted string (`map["weird key"]`).  Keys that contain dots or
+    /// brackets need to use the bracket syntax.  Within quoted strings a
+    /// backslash escapes the following character (`map["say \"hi\""]`).
+    ///
+    /// If the last segment of the path cannot be found,
+    /// [`UNDEFINED`](Self::UNDEFINED) is returned.  Looking up a name on a
+    /// value that is not a map or an index on a value that is neither a
+    /// sequence nor a map, as well as looking through a missing intermediate
+    /// value, is an error.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// # fn test() -> Result<(), minijinja::Error> {
+    /// let ctx = minijinja::context! {
+    ///     items => vec![minijinja::context! { name => "Foo" }],
+    /// };
+    /// let value = ctx.get_item_by_path("items[0].name")?;
+    /// assert_eq!(value.to_string(), "Foo");
+    /// # Ok(()) }
+    /// ```
+    pub fn get_item_by_path(&self, path: &str) -> Result<Value, Error> {
+        let mut rv = self.clone();
+        for segment in ok!(path::parse_path(path)) {
+            let valid = match (&segment, rv.kind()) {
+                (_, ValueKind::Undefined) => {
+                    return Err(Error::new(
+                        ErrorKind::UndefinedError,
+                        format!("cannot look up {:?} on undefined value", segment.to_key()),
+                    ))
+                }
+                (path::PathSegment::Index(_), ValueKind::Seq) => true,
+                (_, ValueKind::Map) => true,
+                _ => false,
+            };
+            if !valid {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot look up {:?} on {}", segment.to_key(), rv.kind()),
+                ));
+            }
+            rv = ok!(rv.get_item(&segment.to_key()));
+        }
+        Ok(rv)
+    }
+
     /// Iterates over the value.
     ///
     /// Depending on the [`kind`](Self::kind) of the value the iterator
@@ -745,6 +1027,9 @@ impl Value {
     /// * [`ValueKind::Seq`]: the iterator yields the items in the sequence.
     /// * [`ValueKind::None`] / [`ValueKind::Undefined`]: the iterator is empty.
     ///
+    /// This is the same iteration a `{% for %}` loop performs.  For all other
+    /// values (for instance numbers or strings) an error is returned.
+    ///
     /// ```
     /// # use minijinja::value::Value;
     /// # fn test() -> Result<(), minijinja::Error> {
@@ -767,6 +1052,79 @@ impl Value {
         })
     }
 
+    /// Converts the value into a vector of values.
+    ///
+    /// This works for sequences including dynamic objects that are of
+    /// sequence shape.  For all other values an error is returned.  If the
+    /// value holds the only reference to a native sequence the items are
+    /// moved out without cloning.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let value = Value::from(vec![1, 2, 3]);
+    /// let items = value.try_into_vec().unwrap();
+    /// assert_eq!(items, vec![Value::from(1), Value::from(2), Value::from(3)]);
+    /// ```
+    pub fn try_into_vec(self) -> Result<Vec<Value>, Error> {
+        match self.0 {
+            ValueRepr::Seq(items) => {
+                Ok(Arc::try_unwrap(items).unwrap_or_else(|items| (*items).clone()))
+            }
+            _ => self.try_to_vec(),
+        }
+    }
+
+    /// Like [`try_into_vec`](Self::try_into_vec) but borrows the value.
+    pub fn try_to_vec(&self) -> Result<Vec<Value>, Error> {
+        match self.0 {
+            ValueRepr::Seq(ref items) => Ok((**items).clone()),
+            _ => match self.as_seq() {
+                Some(seq) => Ok(seq.iter().collect()),
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot convert {} to a sequence", self.kind()),
+                )),
+            },
+        }
+    }
+
+    /// Converts the value into a map with string keys.
+    ///
+    /// This works for maps and dynamic objects that are of struct shape.
+    /// Keys that are not strings are converted into strings.  For all other
+    /// values an error is returned.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let value = minijinja::context! { a => 1, b => 2 };
+    /// let map = value.try_into_map().unwrap();
+    /// assert_eq!(map["a"], Value::from(1));
+    /// ```
+    pub fn try_into_map(self) -> Result<BTreeMap<String, Value>, Error> {
+        self.try_to_map()
+    }
+
+    /// Like [`try_into_map`](Self::try_into_map) but borrows the value.
+    pub fn try_to_map(&self) -> Result<BTreeMap<String, Value>, Error> {
+        match self.0 {
+            ValueRepr::Map(ref items, _) => Ok(items
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect()),
+            _ => match self.as_struct() {
+                Some(s) => Ok(s
+                    .items()
+                    .filter(|(_, v)| !v.is_undefined())
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect()),
+                None => Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot convert {} to a map", self.kind()),
+                )),
+            },
+        }
+    }
+
     /// Returns some reference to the boxed object if it is of type `T`, or None if it isn’t.
     ///
     /// This is basically the "reverse" of [`from_object`](Self::from_object).
@@ -838,7 +1196,13 @@ impl Value {
     }
 
     /// Calls the value directly.
-    pub(crate) fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
+    ///
+    /// If the value holds a function or macro, this invokes it with the given
+    /// arguments.  The `state` is passed through to the called function.
+    /// Macros need to be called with the state of the template that defined
+    /// them, which can be obtained with
+    /// [`Template::eval_to_state`](crate::Template::eval_to_state).
+    pub fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         if let ValueRepr::Dynamic(ref dy) = self.0 {
             dy.call(state, args)
         } else {
@@ -853,7 +1217,7 @@ impl Value {
     #[allow(unused)]
     pub(crate) fn to_cowstr(&self) -> Cow<'_, str> {
         match &self.0 {
-            ValueRepr::String(ref s, _) => Cow::Borrowed(s.as_str()),
+            ValueRepr::String(ref s, _) => Cow::Borrowed(s),
             _ => Cow::Owned(self.to_string()),
         }
     }
@@ -866,7 +1230,12 @@ impl Value {
         args: &[Value],
     ) -> Result<Value, Error> {
         match self.0 {
-            ValueRepr::Dynamic(ref dy) => return dy.call_method(state, name, args),
+            ValueRepr::Dynamic(ref dy) => match dy.call_method(state, name, args) {
+                Err(err)
+                    if err.kind() == ErrorKind::UnknownMethod
+                        && matches!(dy.kind(), ObjectKind::Struct(_) | ObjectKind::Seq(_)) => {}
+                rv => return rv,
+            },
             ValueRepr::Map(ref map, _) => {
                 if let Some(value) = map.get(&Key::Str(name)) {
                     return value.call(state, args);
@@ -874,9 +1243,24 @@ impl Value {
             }
             _ => {}
         }
+        if name == "items" && self.kind() == ValueKind::Map {
+            ok!(from_args::<()>(args));
+            return self.items().map(|items| {
+                items
+                    .into_iter()
+                    .map(|(k, v)| Value::from(vec![k, v]))
+                    .collect()
+            });
+        }
+        #[cfg(feature = "builtins")]
+        {
+            if let Some(rv) = methods::call_builtin_method(state, self, name, args) {
+                return rv;
+            }
+        }
         Err(Error::new(
-            ErrorKind::InvalidOperation,
-            format!("object has no method named {}", name),
+            ErrorKind::UnknownMethod,
+            format!("{} has no method named {}", self.kind(), name),
         ))
     }
 
@@ -899,6 +1283,66 @@ impl Value {
         }
     }
 
+    /// Returns the key/value pairs of a map or struct object.
+    pub(crate) fn items(&self) -> Result<Vec<(Value, Value)>, Error> {
+        let mut rv = match self.0 {
+            ValueRepr::Map(ref map, _) => map
+                .iter()
+                .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                .collect::<Vec<_>>(),
+            _ => match self.as_struct() {
+                Some(obj) => obj
+                    .items()
+                    .map(|(name, value)| (Value::from(name), value))
+                    .collect(),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "cannot convert value into pair list",
+                    ))
+                }
+            },
+        };
+        if sort_map_keys() {
+            rv.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        Ok(rv)
+    }
+
+    /// Compares two values of map kind by their items.
+    ///
+    /// The items are compared in sorted key order so that the insertion
+    /// order of a map (eg: with `preserve_order`) does not matter.
+    fn cmp_map(&self, other: &Value) -> Ordering {
+        match (&self.0, &other.0) {
+            // plain objects have no items, fall back to identity
+            (ValueRepr::Dynamic(a), ValueRepr::Dynamic(b))
+                if matches!(a.kind(), ObjectKind::Plain)
+                    && matches!(b.kind(), ObjectKind::Plain) =>
+            {
+                (Arc::as_ptr(a) as *const () as usize).cmp(&(Arc::as_ptr(b) as *const () as usize))
+            }
+            _ => match (self.try_iter_owned(), other.try_iter_owned()) {
+                (Ok(a), Ok(b)) => {
+                    let mut a = a.collect::<Vec<_>>();
+                    let mut b = b.collect::<Vec<_>>();
+                    a.sort();
+                    b.sort();
+                    a.into_iter()
+                        .map(|k| {
+                            let v = self.get_item_opt(&k).unwrap_or(Value::UNDEFINED);
+                            (k, v)
+                        })
+                        .cmp(b.into_iter().map(|k| {
+                            let v = other.get_item_opt(&k).unwrap_or(Value::UNDEFINED);
+                            (k, v)
+                        }))
+                }
+                _ => Ordering::Equal,
+            },
+        }
+    }
+
     pub(crate) fn iter_as_str_map(&self) -> impl Iterator<Item = (&str, Value)> {
         match self.0 {
             ValueRepr::Map(ref m, _) => Box::new(
@@ -909,10 +1353,8 @@ impl Value {
                 ObjectKind::Plain | ObjectKind::Seq(_) => {
                     Box::new(None.into_iter()) as Box<dyn Iterator<Item = _>>
                 }
-                ObjectKind::Struct(s) => Box::new(
-                    s.fields()
-                        .filter_map(move |attr| Some((attr, some!(s.get_field(attr))))),
-                ) as Box<dyn Iterator<Item = _>>,
+                ObjectKind::Struct(s) => Box::new(s.items().filter(|(_, v)| !v.is_undefined()))
+                    as Box<dyn Iterator<Item = _>>,
             },
             _ => Box::new(None.into_iter()) as Box<dyn Iterator<Item = _>>,
         }
@@ -925,7 +1367,13 @@ impl Value {
             ValueRepr::Seq(ref seq) => (ValueIteratorState::Seq(0, Arc::clone(seq)), seq.len()),
             #[cfg(feature = "preserve_order")]
             ValueRepr::Map(ref items, _) => {
-                (ValueIteratorState::Map(0, Arc::clone(items)), items.len())
+                if sort_map_keys() {
+                    let mut keys = items.keys().cloned().map(Value::from).collect::<Vec<_>>();
+                    keys.sort();
+                    (ValueIteratorState::Seq(0, Arc::new(keys)), items.len())
+                } else {
+                    (ValueIteratorState::Map(0, Arc::clone(items)), items.len())
+                }
             }
             #[cfg(not(feature = "preserve_order"))]
             ValueRepr::Map(ref items, _) => (
@@ -946,7 +1394,10 @@ impl Value {
                         // the assumption is that structs don't have excessive field counts
                         // and that most iterations go over all fields, so creating a
                         // temporary vector here is acceptable.
-                        let attrs = s.fields().map(Value::from).collect::<Vec<_>>();
+                        let mut attrs = s.fields().map(Value::from).collect::<Vec<_>>();
+                        if sort_map_keys() {
+                            attrs.sort();
+                        }
                         let attr_count = s.field_count();
                         (ValueIteratorState::Seq(0, Arc::new(attrs)), attr_count)
                     }
@@ -978,6 +1429,7 @@ impl Serialize for Value {
             return s.end();
         }
 
+        let _guard = ok!(DepthGuard::enter_serialize(self));
         match self.0 {
             ValueRepr::Bool(b) => serializer.serialize_bool(b),
             ValueRepr::U64(u) => serializer.serialize_u64(u),
@@ -994,7 +1446,7 @@ impl Serialize for Value {
             ValueRepr::Map(ref entries, _) => {
                 use serde::ser::SerializeMap;
                 let mut map = ok!(serializer.serialize_map(Some(entries.len())));
-                for (ref k, ref v) in entries.iter() {
+                for (k, v) in map_entries(entries) {
                     ok!(map.serialize_entry(k, v));
                 }
                 map.end()
@@ -1012,8 +1464,7 @@ impl Serialize for Value {
                 ObjectKind::Struct(s) => {
                     use serde::ser::SerializeMap;
                     let mut map = ok!(serializer.serialize_map(None));
-                    for k in s.fields() {
-                        let v = s.get_field(k).unwrap_or(Value::UNDEFINED);
+                    for (k, v) in struct_items(s) {
                         ok!(map.serialize_entry(&k, &v));
                     }
                     map.end()
@@ -1036,6 +1487,11 @@ impl<'a> Iterator for ValueIter<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    #[inline(always)]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
 }
 
 pub(crate) struct OwnedValueIterator {
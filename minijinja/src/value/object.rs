@@ -1,6 +1,8 @@
 use std::any::Any;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::ops::Range;
+use std::sync::Mutex;
 
 use crate::error::{Error, ErrorKind};
 use crate::value::Value;
@@ -73,6 +75,15 @@ pub trait Object: fmt::Display + fmt::Debug + Any + Sync + Send {
             "tried to call non callable object",
         ))
     }
+
+    /// Returns `true` if the object can be invoked directly.
+    ///
+    /// Objects that implement [`call`](Self::call) should also return `true`
+    /// here.  This is used by the `callable` test.  The default
+    /// implementation returns `false`.
+    fn is_callable(&self) -> bool {
+        false
+    }
 }
 
 impl<T: Object> Object for std::sync::Arc<T> {
@@ -90,6 +101,11 @@ impl<T: Object> Object for std::sync::Arc<T> {
     fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         T::call(self, state, args)
     }
+
+    #[inline]
+    fn is_callable(&self) -> bool {
+        T::is_callable(self)
+    }
 }
 
 /// A kind defines the object's behavior.
@@ -401,6 +417,21 @@ pub trait StructObject: Send + Sync {
     fn field_count(&self) -> usize {
         self.fields().count()
     }
+
+    /// Iterates over the fields together with their values.
+    ///
+    /// This is used by the engine whenever both keys and values are needed,
+    /// for instance by the `items` and `dictsort` filters, when serializing or
+    /// when debug printing the object.  The default implementation looks up
+    /// every field returned by [`fields`](Self::fields) with
+    /// [`get_field`](Self::get_field).  Objects that can produce the pairs
+    /// more efficiently can override this.
+    fn items(&self) -> Box<dyn Iterator<Item = (&str, Value)> + '_> {
+        Box::new(
+            self.fields()
+                .map(move |name| (name, self.get_field(name).unwrap_or(Value::UNDEFINED))),
+        )
+    }
 }
 
 impl<T: StructObject> StructObject for std::sync::Arc<T> {
@@ -418,6 +449,89 @@ impl<T: StructObject> StructObject for std::sync::Arc<T> {
     fn field_count(&self) -> usize {
         T::field_count(self)
     }
+
+    #[inline]
+    fn items(&self) -> Box<dyn Iterator<Item = (&str, Value)> + '_> {
+        T::items(self)
+    }
+}
+
+/// Wraps a [`StructObject`] and memoizes the values of its fields.
+///
+/// Every field is looked up with [`get_field`](StructObject::get_field) on
+/// the wrapped object at most once; later accesses return the cached value.
+/// This is useful for objects where computing a field is expensive and the
+/// field is accessed repeatedly from a template.
+///
+/// The cache lives as long as the wrapper itself.  If the same value is
+/// passed to multiple renders, the fields computed in the first render are
+/// reused by later ones.  To get per-render caching, wrap the object anew
+/// for every render.
+///
+/// ```
+/// use minijinja::value::{MemoizedStruct, StructObject, Value};
+///
+/// struct Report;
+///
+/// impl StructObject for Report {
+///     fn get_field(&self, name: &str) -> Option<Value> {
+///         match name {
+///             "total" => Some(Value::from((1..=100).sum::<i64>())),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// let value = Value::from_struct_object(MemoizedStruct::new(Report));
+/// ```
+pub struct MemoizedStruct<T> {
+    inner: T,
+    cache: Mutex<BTreeMap<String, Option<Value>>>,
+}
+
+impl<T: StructObject> MemoizedStruct<T> {
+    /// Wraps a struct object.
+    pub fn new(inner: T) -> MemoizedStruct<T> {
+        MemoizedStruct {
+            inner,
+            cache: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns a reference to the wrapped object.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Unwraps the object, discarding the cache.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: StructObject> StructObject for MemoizedStruct<T> {
+    fn get_field(&self, name: &str) -> Option<Value> {
+        if let Some(rv) = self.cache.lock().unwrap().get(name) {
+            return rv.clone();
+        }
+        // the lock is not held while the field is computed so that the
+        // wrapped object is free to access other fields of itself.
+        let rv = self.inner.get_field(name);
+        self.cache
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert(rv)
+            .clone()
+    }
+
+    fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        self.inner.fields()
+    }
+
+    fn field_count(&self) -> usize {
+        self.inner.field_count()
+    }
 }
 
 #[repr(transparent)]
@@ -456,11 +570,10 @@ pub struct SimpleStructObject<T>(pub T);
 impl<T: StructObject + 'static> fmt::Display for SimpleStructObject<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         ok!(write!(f, "["));
-        for (idx, field) in self.0.fields().enumerate() {
+        for (idx, (field, val)) in self.0.items().enumerate() {
             if idx > 0 {
                 ok!(write!(f, ", "));
             }
-            let val = self.0.get_field(field).unwrap_or(Value::UNDEFINED);
             ok!(write!(f, "{:?}: {:?}", field, val));
         }
         write!(f, "]")
@@ -469,12 +582,7 @@ impl<T: StructObject + 'static> fmt::Display for SimpleStructObject<T> {
 
 impl<T: StructObject + 'static> fmt::Debug for SimpleStructObject<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut m = f.debug_map();
-        for field in self.0.fields() {
-            let value = self.0.get_field(field).unwrap_or(Value::UNDEFINED);
-            m.entry(&field, &value);
-        }
-        m.finish()
+        f.debug_map().entries(self.0.items()).finish()
     }
 }
 
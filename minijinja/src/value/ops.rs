@@ -1,8 +1,9 @@
+use std::cmp::Ordering;
 use std::convert::{TryFrom, TryInto};
 use std::fmt::Write;
 
 use crate::error::{Error, ErrorKind};
-use crate::value::{Arc, ObjectKind, SeqObject, Value, ValueKind, ValueRepr};
+use crate::value::{ObjectKind, SeqObject, Value, ValueKind, ValueRepr};
 
 pub enum CoerceResult {
     I128(i128, i128),
@@ -48,6 +49,119 @@ pub fn coerce(a: &Value, b: &Value) -> Option<CoerceResult> {
     }
 }
 
+enum Number {
+    Int(i128),
+    /// An unsigned integer that does not fit into an `i128`.
+    BigUint(u128),
+    Float(f64),
+}
+
+fn as_number(value: &Value) -> Option<Number> {
+    Some(match value.0 {
+        ValueRepr::Bool(x) => Number::Int(x as i128),
+        ValueRepr::U64(x) => Number::Int(x as i128),
+        ValueRepr::I64(x) => Number::Int(x as i128),
+        ValueRepr::I128(x) => Number::Int(x.0),
+        ValueRepr::U128(x) => match i128::try_from(x.0) {
+            Ok(x) => Number::Int(x),
+            Err(_) => Number::BigUint(x.0),
+        },
+        ValueRepr::F64(x) => Number::Float(x),
+        _ => return None,
+    })
+}
+
+/// Compares two floats with a total order.
+///
+/// NaN sorts after all other numbers (and is equal to itself) and `-0.0`
+/// is equal to `0.0`.
+fn cmp_floats(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// Compares an integer and a float without going through a lossy conversion
+/// of the integer.
+fn cmp_int_float(a: i128, b: f64) -> Ordering {
+    // 2^127 is the first float that is out of the range of i128
+    const LIMIT: f64 = 170141183460469231731687303715884105728.0;
+    if b.is_nan() || b >= LIMIT {
+        return Ordering::Less;
+    } else if b < -LIMIT {
+        return Ordering::Greater;
+    }
+    let truncated = b.trunc();
+    match a.cmp(&(truncated as i128)) {
+        Ordering::Equal => cmp_floats(truncated, b),
+        other => other,
+    }
+}
+
+/// Compares an unsigned integer larger than `i128::MAX` and a float without
+/// going through a lossy conversion of the integer.
+fn cmp_big_uint_float(a: u128, b: f64) -> Ordering {
+    // 2^127 and 2^128 bound the range of integers that end up here
+    const LOWER: f64 = 170141183460469231731687303715884105728.0;
+    const UPPER: f64 = 340282366920938463463374607431768211456.0;
+    if b.is_nan() || b >= UPPER {
+        return Ordering::Less;
+    } else if b < LOWER {
+        return Ordering::Greater;
+    }
+    // floats in this range have no fractional part
+    a.cmp(&(b as u128))
+}
+
+/// Compares two values numerically.
+///
+/// Returns `None` if either of the values is not a number.  Booleans are
+/// considered to be numbers for this purpose.
+pub fn cmp_numbers(a: &Value, b: &Value) -> Option<Ordering> {
+    Some(match (some!(as_number(a)), some!(as_number(b))) {
+        (Number::Int(a), Number::Int(b)) => a.cmp(&b),
+        (Number::Float(a), Number::Float(b)) => cmp_floats(a, b),
+        (Number::Int(a), Number::Float(b)) => cmp_int_float(a, b),
+        (Number::Float(a), Number::Int(b)) => cmp_int_float(b, a).reverse(),
+        (Number::BigUint(a), Number::BigUint(b)) => a.cmp(&b),
+        (Number::BigUint(_), Number::Int(_)) => Ordering::Greater,
+        (Number::Int(_), Number::BigUint(_)) => Ordering::Less,
+        (Number::BigUint(a), Number::Float(b)) => cmp_big_uint_float(a, b),
+        (Number::Float(a), Number::BigUint(b)) => cmp_big_uint_float(b, a).reverse(),
+    })
+}
+
+/// Compares two values for the ordering operators.
+///
+/// Unlike the [`Ord`] implementation on [`Value`] this fails for values that
+/// do not have a meaningful order.  Numbers (including booleans) order
+/// numerically, strings and chars by their contents, bytes by their contents
+/// and sequences item by item.  Undefined values keep their position before
+/// all other values as the strict undefined behavior rejects them earlier.
+/// All other combinations are an error.
+pub fn cmp(lhs: &Value, rhs: &Value, op: &str) -> Result<Ordering, Error> {
+    if let Some(rv) = cmp_numbers(lhs, rhs) {
+        return Ok(rv);
+    }
+    match (&lhs.0, &rhs.0) {
+        (ValueRepr::Char(a), ValueRepr::String(b, _)) => {
+            return Ok((*a.encode_utf8(&mut [0; 4])).cmp(&**b))
+        }
+        (ValueRepr::String(a, _), ValueRepr::Char(b)) => {
+            return Ok((**a).cmp(&*b.encode_utf8(&mut [0; 4])))
+        }
+        _ => {}
+    }
+    match (lhs.kind(), rhs.kind()) {
+        (ValueKind::Undefined, _)
+        | (_, ValueKind::Undefined)
+        | (ValueKind::String, ValueKind::String)
+        | (ValueKind::Char, ValueKind::Char)
+        | (ValueKind::Bytes, ValueKind::Bytes)
+        | (ValueKind::Seq, ValueKind::Seq) => Ok(lhs.cmp(rhs)),
+        _ => Err(impossible_op(op, lhs, rhs)),
+    }
+}
+
 fn get_offset_and_len<F: FnOnce() -> usize>(
     start: i64,
     stop: Option<i64>,
@@ -249,16 +363,24 @@ pub fn neg(val: &Value) -> Result<Value, Error> {
 }
 
 /// Attempts a string concatenation.
-pub fn string_concat(mut left: Value, right: &Value) -> Value {
-    match left.0 {
-        // if we're a string and we have a single reference to it, we can
-        // directly append into ourselves and reconstruct the value
-        ValueRepr::String(ref mut s, _) => {
-            write!(Arc::make_mut(s), "{}", right).ok();
-            left
+pub fn string_concat(left: Value, right: &Value) -> Value {
+    let rv = match left.0 {
+        // strings are stored as `Arc<str>` which cannot grow in place, so if
+        // we're a string we copy ourselves into a buffer that is large enough
+        // and append the other value directly.
+        ValueRepr::String(ref s, _) => {
+            let mut rv = String::with_capacity(s.len() + right.as_str().map_or(0, str::len));
+            rv.push_str(s);
+            write!(rv, "{}", right).ok();
+            rv
         }
         // otherwise we use format! to concat the two values
-        _ => Value::from(format!("{}{}", left, right)),
+        _ => format!("{}{}", left, right),
+    };
+    if left.is_safe() && right.is_safe() {
+        Value::from_safe_string(rv)
+    } else {
+        Value::from(rv)
     }
 }
 
@@ -356,3 +478,44 @@ fn test_concat() {
         Value::from("2342")
     );
 }
+
+#[test]
+fn test_cmp() {
+    assert_eq!(
+        cmp(&Value::from(3), &Value::from(3.0), "==").unwrap(),
+        Ordering::Equal
+    );
+    assert_eq!(
+        cmp(&Value::from(2), &Value::from(10), "<").unwrap(),
+        Ordering::Less
+    );
+    assert_eq!(
+        cmp(&Value::from(u64::MAX), &Value::from(-1i64), ">").unwrap(),
+        Ordering::Greater
+    );
+    assert_eq!(
+        cmp(&Value::from("2"), &Value::from("10"), ">").unwrap(),
+        Ordering::Greater
+    );
+    assert_eq!(
+        cmp(&Value::from('b'), &Value::from("a"), ">").unwrap(),
+        Ordering::Greater
+    );
+
+    let err = cmp(&Value::from(2), &Value::from("10"), "<").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: tried to use < operator on unsupported types number and string"
+    );
+
+    assert_eq!(
+        cmp(&Value::UNDEFINED, &Value::from(1), "<").unwrap(),
+        Ordering::Less
+    );
+
+    let err = cmp(&Value::from(()), &Value::from(1), ">=").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: tried to use >= operator on unsupported types none and number"
+    );
+}
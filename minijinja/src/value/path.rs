@@ -0,0 +1,125 @@
+use crate::error::{Error, ErrorKind};
+use crate::value::Value;
+
+#[cfg(test)]
+use similar_asserts::assert_eq;
+
+/// A single segment of a path as accepted by
+/// [`Value::get_item_by_path`](crate::value::Value::get_item_by_path).
+#[derive(Debug, PartialEq)]
+pub(crate) enum PathSegment<'a> {
+    Name(&'a str),
+    Key(String),
+    Index(i64),
+}
+
+impl<'a> PathSegment<'a> {
+    /// Returns the key that should be used to look up the segment.
+    pub fn to_key(&self) -> Value {
+        match *self {
+            PathSegment::Name(name) => Value::from(name),
+            PathSegment::Key(ref key) => Value::from(key.as_str()),
+            PathSegment::Index(idx) => Value::from(idx),
+        }
+    }
+}
+
+fn invalid_path(path: &str, msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidOperation,
+        format!("invalid path {:?}: {}", path, msg),
+    )
+}
+
+fn parse_quoted(path: &str, rest: &str, quote: char) -> Result<(String, usize), Error> {
+    let mut rv = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, c)) => rv.push(c),
+                None => break,
+            },
+            c if c == quote => return Ok((rv, idx + 1)),
+            c => rv.push(c),
+        }
+    }
+    Err(invalid_path(path, "unterminated string"))
+}
+
+/// Parses a path like `items[0].name` or `map["weird.key"]` into segments.
+pub(crate) fn parse_path(path: &str) -> Result<Vec<PathSegment<'_>>, Error> {
+    let mut rv = Vec::new();
+    let mut rest = path;
+    let mut first = true;
+
+    while !rest.is_empty() {
+        if let Some(after_bracket) = rest.strip_prefix('[') {
+            let after_bracket = after_bracket.trim_start();
+            let (segment, consumed) = match after_bracket.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let (key, len) = ok!(parse_quoted(path, &after_bracket[1..], quote));
+                    (PathSegment::Key(key), len + 1)
+                }
+                _ => {
+                    let end = after_bracket.find(']').unwrap_or(after_bracket.len());
+                    let idx = ok!(after_bracket[..end]
+                        .trim()
+                        .parse::<i64>()
+                        .map_err(|_| invalid_path(path, "expected integer or string in brackets")));
+                    (PathSegment::Index(idx), end)
+                }
+            };
+            let after_segment = after_bracket[consumed..].trim_start();
+            rest = match after_segment.strip_prefix(']') {
+                Some(rest) => rest,
+                None => return Err(invalid_path(path, "expected closing bracket")),
+            };
+            rv.push(segment);
+        } else {
+            let name_start = if first {
+                rest
+            } else {
+                match rest.strip_prefix('.') {
+                    Some(rest) => rest,
+                    None => return Err(invalid_path(path, "expected dot or bracket")),
+                }
+            };
+            let end = name_start.find(['.', '[']).unwrap_or(name_start.len());
+            if end == 0 {
+                return Err(invalid_path(path, "empty attribute name"));
+            }
+            rv.push(PathSegment::Name(&name_start[..end]));
+            rest = &name_start[end..];
+        }
+        first = false;
+    }
+
+    Ok(rv)
+}
+
+#[test]
+fn test_parse_path() {
+    assert_eq!(
+        parse_path("items[0].name").unwrap(),
+        vec![
+            PathSegment::Name("items"),
+            PathSegment::Index(0),
+            PathSegment::Name("name")
+        ]
+    );
+    assert_eq!(
+        parse_path(r#"map["weird.key"]['it\'s'][-1]"#).unwrap(),
+        vec![
+            PathSegment::Name("map"),
+            PathSegment::Key("weird.key".into()),
+            PathSegment::Key("it's".into()),
+            PathSegment::Index(-1),
+        ]
+    );
+    assert_eq!(parse_path("").unwrap(), vec![]);
+    assert!(parse_path("a..b").is_err());
+    assert!(parse_path("a[b]").is_err());
+    assert!(parse_path("a[\"b]").is_err());
+    assert!(parse_path("a[0").is_err());
+}
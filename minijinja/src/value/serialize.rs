@@ -80,7 +80,7 @@ impl Serializer for ValueSerializer {
     }
 
     fn serialize_str(self, value: &str) -> Result<Value, Error> {
-        Ok(ValueRepr::String(Arc::new(value.to_owned()), StringType::Normal).into())
+        Ok(ValueRepr::String(Arc::from(value), StringType::Normal).into())
     }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<Value, Error> {
@@ -112,7 +112,7 @@ impl Serializer for ValueSerializer {
         _variant_index: u32,
         variant: &'static str,
     ) -> Result<Value, Error> {
-        Ok(ValueRepr::String(Arc::new(variant.to_string()), StringType::Normal).into())
+        Ok(ValueRepr::String(Arc::from(variant), StringType::Normal).into())
     }
 
     fn serialize_newtype_struct<T: ?Sized>(
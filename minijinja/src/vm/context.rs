@@ -9,11 +9,11 @@ use crate::vm::loop_object::Loop;
 
 type Locals<'env> = BTreeMap<&'env str, Value>;
 
-/// The maximum recursion in the VM.  Normally each stack frame
+/// The default maximum recursion in the VM.  Normally each stack frame
 /// adds one to this counter (eg: every time a frame is added).
 /// However in some situations more depth is pushed if the cost
 /// of the stack frame is higher.
-const MAX_RECURSION: usize = 500;
+pub(crate) const MAX_RECURSION: usize = 500;
 
 pub(crate) struct LoopState {
     pub(crate) with_loop_var: bool,
@@ -30,6 +30,8 @@ pub(crate) struct Frame<'env> {
     pub(crate) locals: Locals<'env>,
     pub(crate) ctx: Value,
     pub(crate) current_loop: Option<LoopState>,
+    /// If set, lookups do not continue into the outer frames.
+    pub(crate) isolated: bool,
 }
 
 impl<'env> Default for Frame<'env> {
@@ -44,6 +46,7 @@ impl<'env> Frame<'env> {
             locals: Locals::new(),
             ctx,
             current_loop: None,
+            isolated: false,
         }
     }
 }
@@ -71,6 +74,8 @@ impl<'env> fmt::Debug for Frame<'env> {
 #[derive(Default)]
 pub(crate) struct Stack {
     values: Vec<Value>,
+    // the lowest length the stack had since the last call to `take_low_water`
+    low_water: usize,
 }
 
 impl Stack {
@@ -80,7 +85,9 @@ impl Stack {
 
     #[track_caller]
     pub fn pop(&mut self) -> Value {
-        self.values.pop().unwrap()
+        let rv = self.values.pop().unwrap();
+        self.low_water = self.low_water.min(self.values.len());
+        rv
     }
 
     pub fn slice_top(&mut self, n: usize) -> &[Value] {
@@ -89,10 +96,25 @@ impl Stack {
 
     pub fn drop_top(&mut self, n: usize) {
         self.values.truncate(self.values.len() - n);
+        self.low_water = self.low_water.min(self.values.len());
     }
 
     pub fn try_pop(&mut self) -> Option<Value> {
-        self.values.pop()
+        let rv = self.values.pop();
+        self.low_water = self.low_water.min(self.values.len());
+        rv
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns the lowest length of the stack since the last call and
+    /// resets it to the current length.
+    ///
+    /// This is used to find out if a value on the stack was consumed.
+    pub fn take_low_water(&mut self) -> usize {
+        std::mem::replace(&mut self.low_water, self.values.len())
     }
 
     #[track_caller]
@@ -103,14 +125,27 @@ impl Stack {
 
 impl From<Vec<Value>> for Stack {
     fn from(values: Vec<Value>) -> Stack {
-        Stack { values }
+        Stack {
+            low_water: values.len(),
+            values,
+        }
     }
 }
 
-#[derive(Default)]
 pub(crate) struct Context<'env> {
     stack: Vec<Frame<'env>>,
     outer_stack_depth: usize,
+    recursion_limit: usize,
+}
+
+impl<'env> Default for Context<'env> {
+    fn default() -> Context<'env> {
+        Context {
+            stack: Vec::new(),
+            outer_stack_depth: 0,
+            recursion_limit: MAX_RECURSION,
+        }
+    }
 }
 
 impl<'env> fmt::Debug for Context<'env> {
@@ -141,6 +176,10 @@ impl<'env> fmt::Debug for Context<'env> {
                         m.entry(&key, &value);
                     }
                 }
+
+                if frame.isolated {
+                    break;
+                }
             }
             Ok(())
         }
@@ -154,10 +193,11 @@ impl<'env> fmt::Debug for Context<'env> {
 
 impl<'env> Context<'env> {
     /// Creates a context
-    pub fn new(frame: Frame<'env>) -> Context<'env> {
+    pub fn new(frame: Frame<'env>, recursion_limit: usize) -> Context<'env> {
         Context {
             stack: vec![frame],
             outer_stack_depth: 0,
+            recursion_limit,
         }
     }
 
@@ -191,9 +231,13 @@ impl<'env> Context<'env> {
                     }
                 }
             }
+
+            if frame.isolated {
+                break;
+            }
         }
 
-        env.get_global(key)
+        env.global(key)
     }
 
     /// Pushes a new layer.
@@ -209,6 +253,18 @@ impl<'env> Context<'env> {
         self.stack.pop().unwrap()
     }
 
+    /// Returns the number of frames.
+    #[cfg(feature = "builtins")]
+    pub fn frame_count(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Discards all frames above the given count.
+    #[cfg(feature = "builtins")]
+    pub fn discard_frames(&mut self, count: usize) {
+        self.stack.truncate(count);
+    }
+
     /// Returns the current locals.
     #[track_caller]
     #[cfg(feature = "multi-template")]
@@ -233,8 +289,11 @@ impl<'env> Context<'env> {
     /// Increase the stack depth.
     #[allow(unused)]
     pub fn incr_depth(&mut self, delta: usize) -> Result<(), Error> {
-        self.check_depth()?;
         self.outer_stack_depth += delta;
+        if let Err(err) = self.check_depth() {
+            self.outer_stack_depth -= delta;
+            return Err(err);
+        }
         Ok(())
     }
 
@@ -245,11 +304,8 @@ impl<'env> Context<'env> {
     }
 
     fn check_depth(&self) -> Result<(), Error> {
-        if self.depth() > MAX_RECURSION {
-            return Err(Error::new(
-                ErrorKind::InvalidOperation,
-                "recursion limit exceeded",
-            ));
+        if self.depth() > self.recursion_limit {
+            return Err(Error::from(ErrorKind::RecursionLimitExceeded));
         }
         Ok(())
     }
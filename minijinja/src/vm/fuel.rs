@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::{Error, ErrorKind};
+
+/// Tracks the fuel consumed by a single render.
+///
+/// Every executed instruction consumes one unit of fuel.  The tracker is
+/// shared (via an `Arc`) between the state of a render and the states of
+/// the macros it invokes so that the budget covers the entire render.
+#[derive(Debug)]
+pub(crate) struct FuelTracker {
+    initial: u64,
+    remaining: AtomicU64,
+}
+
+impl FuelTracker {
+    /// Creates a new fuel tracker with the given amount of fuel.
+    pub fn new(fuel: u64) -> FuelTracker {
+        FuelTracker {
+            initial: fuel,
+            remaining: AtomicU64::new(fuel),
+        }
+    }
+
+    /// Consumes one unit of fuel, failing if none is left.
+    #[inline(always)]
+    pub fn track(&self) -> Result<(), Error> {
+        // a plain load + store is sufficient here as renders are not
+        // evaluated concurrently.
+        let remaining = self.remaining.load(Ordering::Relaxed);
+        if remaining == 0 {
+            return Err(Error::from(ErrorKind::FuelExhausted));
+        }
+        self.remaining.store(remaining - 1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Consumes the given amount of fuel at once.
+    ///
+    /// This is used by operations which do work proportional to their input
+    /// outside of the instruction loop.  If not enough fuel is left, all of
+    /// it is used up.
+    #[cfg(feature = "builtins")]
+    pub fn consume(&self, amount: u64) -> Result<(), Error> {
+        let remaining = self.remaining.load(Ordering::Relaxed);
+        if remaining < amount {
+            self.remaining.store(0, Ordering::Relaxed);
+            return Err(Error::from(ErrorKind::FuelExhausted));
+        }
+        self.remaining.store(remaining - amount, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the consumed and remaining fuel.
+    pub fn levels(&self) -> (u64, u64) {
+        let remaining = self.remaining.load(Ordering::Relaxed);
+        (self.initial - remaining, remaining)
+    }
+}
@@ -47,11 +47,14 @@ impl Object for Loop {
                 Ok(Value::from(false))
             }
         } else if name == "cycle" {
-            let idx = self.idx.load(Ordering::Relaxed);
-            match args.get(idx % args.len()) {
-                Some(arg) => Ok(arg.clone()),
-                None => Ok(Value::UNDEFINED),
+            if args.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    "loop.cycle requires at least one argument",
+                ));
             }
+            let idx = self.idx.load(Ordering::Relaxed);
+            Ok(args[idx % args.len()].clone())
         } else {
             Err(Error::new(
                 ErrorKind::UnknownMethod,
@@ -6,13 +6,17 @@ use crate::error::{Error, ErrorKind};
 use crate::key::Key;
 use crate::output::Output;
 use crate::utils::AutoEscape;
-use crate::value::{MapType, Object, ObjectKind, StringType, StructObject, Value, ValueRepr};
+use crate::value::{
+    MapType, Object, ObjectKind, StringType, StructObject, Value, ValueMap, ValueRepr,
+};
 use crate::vm::state::State;
 use crate::vm::Vm;
 
 pub(crate) struct MacroData {
-    pub name: Arc<String>,
-    pub arg_spec: Vec<Arc<String>>,
+    pub name: Arc<str>,
+    pub arg_spec: Vec<Arc<str>>,
+    // the default values of the trailing arguments if they are constant.
+    pub defaults: Arc<Vec<Value>>,
     // because values need to be 'static, we can't hold a reference to the
     // instructions that declared the macro.  Instead of that we place the
     // reference to the macro instruction (and the jump offset) in the
@@ -20,6 +24,14 @@ pub(crate) struct MacroData {
     pub macro_ref_id: usize,
     pub closure: Value,
     pub self_reference: bool,
+    // If the macro refers to `caller`, the last entry of `arg_spec` is the
+    // implicit `caller` argument passed by call blocks.
+    pub caller_reference: bool,
+    // If the macro refers to `varargs` or `kwargs` surplus positional and
+    // keyword arguments are passed after all other arguments instead of
+    // failing the call.
+    pub varargs_reference: bool,
+    pub kwargs_reference: bool,
 }
 
 pub(crate) struct Macro {
@@ -40,6 +52,14 @@ impl fmt::Display for Macro {
     }
 }
 
+impl Macro {
+    /// Returns the arguments declared on the macro (without `caller`).
+    fn declared_args(&self) -> &[Arc<str>] {
+        let count = self.data.arg_spec.len() - self.data.caller_reference as usize;
+        &self.data.arg_spec[..count]
+    }
+}
+
 impl Object for Macro {
     fn kind(&self) -> ObjectKind<'_> {
         ObjectKind::Struct(self)
@@ -53,18 +73,24 @@ impl Object for Macro {
             _ => (args, None),
         };
 
-        if args.len() > self.data.arg_spec.len() {
+        let declared_count = self.declared_args().len();
+        if args.len() > declared_count && !self.data.varargs_reference {
             return Err(Error::from(ErrorKind::TooManyArguments));
         }
 
         let mut kwargs_used = BTreeSet::new();
-        let mut arg_values = Vec::with_capacity(self.data.arg_spec.len());
+        let mut arg_values = Vec::with_capacity(self.data.arg_spec.len() + 2);
         for (idx, name) in self.data.arg_spec.iter().enumerate() {
+            let arg = if idx < declared_count {
+                args.get(idx)
+            } else {
+                None
+            };
             let kwarg = match kwargs {
                 Some(kwargs) => kwargs.get(&Key::Str(name)),
                 _ => None,
             };
-            arg_values.push(match (args.get(idx), kwarg) {
+            arg_values.push(match (arg, kwarg) {
                 (Some(_), Some(_)) => {
                     return Err(Error::new(
                         ErrorKind::TooManyArguments,
@@ -80,16 +106,30 @@ impl Object for Macro {
             });
         }
 
+        if self.data.varargs_reference {
+            arg_values.push(Value::from(
+                args.get(declared_count..).unwrap_or_default().to_vec(),
+            ));
+        }
+
+        let mut extra_kwargs = ValueMap::new();
         if let Some(kwargs) = kwargs {
-            for key in kwargs.keys().filter_map(|x| x.as_str()) {
-                if !kwargs_used.contains(key) {
+            for (key, value) in kwargs.iter() {
+                if key.as_str().map_or(false, |key| kwargs_used.contains(key)) {
+                    continue;
+                }
+                if !self.data.kwargs_reference {
                     return Err(Error::new(
                         ErrorKind::TooManyArguments,
                         format!("unknown keyword argument `{}`", key),
                     ));
                 }
+                extra_kwargs.insert(key.clone(), value.clone());
             }
         }
+        if self.data.kwargs_reference {
+            arg_values.push(Value(ValueRepr::Map(extra_kwargs.into(), MapType::Normal)));
+        }
 
         let (instructions, offset) = &state.macros[self.data.macro_ref_id];
         let vm = Vm::new(state.env());
@@ -132,11 +172,25 @@ impl Object for Macro {
             Value::from(rv)
         })
     }
+
+    fn is_callable(&self) -> bool {
+        true
+    }
 }
 
 impl StructObject for Macro {
     fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
-        Box::new(["name", "arguments"].into_iter())
+        Box::new(
+            [
+                "name",
+                "arguments",
+                "defaults",
+                "catch_varargs",
+                "catch_kwargs",
+                "caller",
+            ]
+            .into_iter(),
+        )
     }
 
     fn get_field(&self, name: &str) -> Option<Value> {
@@ -146,12 +200,15 @@ impl StructObject for Macro {
                 StringType::Normal,
             ))),
             "arguments" => Some(Value::from(
-                self.data
-                    .arg_spec
+                self.declared_args()
                     .iter()
                     .map(|x| Value(ValueRepr::String(x.clone(), StringType::Normal)))
                     .collect::<Vec<_>>(),
             )),
+            "defaults" => Some(Value(ValueRepr::Seq(self.data.defaults.clone()))),
+            "catch_varargs" => Some(Value::from(self.data.varargs_reference)),
+            "catch_kwargs" => Some(Value::from(self.data.kwargs_reference)),
+            "caller" => Some(Value::from(self.data.caller_reference)),
             _ => None,
         }
     }
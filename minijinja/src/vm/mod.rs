@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -7,33 +8,58 @@ use crate::compiler::instructions::{
     Instruction, Instructions, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
 };
 use crate::environment::Environment;
-use crate::error::{Error, ErrorKind};
+use crate::error::{Error, ErrorKind, Warning, WarningKind};
 use crate::output::{CaptureMode, Output};
-use crate::utils::AutoEscape;
+use crate::utils::{AutoEscape, UndefinedBehavior, UndefinedInfo};
 use crate::value::{self, ops, MapType, Value, ValueMap, ValueRepr};
 use crate::vm::context::{Context, Frame, LoopState, Stack};
+use crate::vm::fuel::FuelTracker;
 use crate::vm::loop_object::Loop;
+#[cfg(feature = "builtins")]
+use crate::vm::rng::Rng;
 use crate::vm::state::BlockStack;
 
 #[cfg(feature = "macros")]
-use crate::vm::macro_object::{Macro, MacroData};
+use crate::compiler::instructions::{
+    MACRO_FLAG_CALLER, MACRO_FLAG_KWARGS, MACRO_FLAG_SELF_REFERENCE, MACRO_FLAG_VARARGS,
+};
+#[cfg(feature = "macros")]
+use crate::vm::macro_object::MacroData;
 
-pub use crate::vm::state::State;
+#[cfg(feature = "macros")]
+pub(crate) use crate::vm::macro_object::Macro;
+
+pub(crate) use crate::vm::context::MAX_RECURSION;
+pub use crate::vm::state::{Extensions, State};
 
 mod context;
+mod fuel;
 mod loop_object;
 #[cfg(feature = "macros")]
 mod macro_object;
+#[cfg(feature = "builtins")]
+mod rng;
 mod state;
 
+/// The default limit for nested includes and template inheritance.
+pub(crate) const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// The default limit for nested macro calls.
+pub(crate) const MAX_MACRO_DEPTH: usize = 64;
+
 // the cost of a single include against the stack limit.
 #[cfg(feature = "multi-template")]
 const INCLUDE_RECURSION_COST: usize = 10;
 
+// the cost of a single macro call against the stack limit.
+#[cfg(feature = "macros")]
+const MACRO_RECURSION_COST: usize = 5;
+
 /// Helps to evaluate something.
 #[cfg_attr(feature = "internal_debug", derive(Debug))]
 pub struct Vm<'env> {
     env: &'env Environment<'env>,
+    warnings: Option<Arc<Mutex<Vec<Warning>>>>,
 }
 
 fn prepare_blocks<'env, 'vm>(
@@ -45,6 +71,30 @@ fn prepare_blocks<'env, 'vm>(
         .collect()
 }
 
+/// Returns the candidate names for `extends` and `include`.
+///
+/// The name is either a single template name or a sequence of names that
+/// are tried in order.
+#[cfg(feature = "multi-template")]
+fn template_name_choices(name: &Value) -> Result<Vec<Value>, Error> {
+    let choices = match name.as_seq() {
+        Some(seq) => seq.iter().collect(),
+        None => vec![name.clone()],
+    };
+    for choice in &choices {
+        if choice.as_str().is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!(
+                    "template name must be a string or a list of strings, got {}",
+                    choice.kind()
+                ),
+            ));
+        }
+    }
+    Ok(choices)
+}
+
 #[inline(always)]
 fn get_or_lookup_local<T, F>(vec: &mut [Option<T>], local_id: u8, f: F) -> Option<T>
 where
@@ -65,7 +115,16 @@ where
 impl<'env> Vm<'env> {
     /// Creates a new VM.
     pub fn new(env: &'env Environment<'env>) -> Vm<'env> {
-        Vm { env }
+        Vm {
+            env,
+            warnings: None,
+        }
+    }
+
+    /// Makes the VM record warnings into the given collector.
+    pub(crate) fn with_warnings(mut self, warnings: Arc<Mutex<Vec<Warning>>>) -> Vm<'env> {
+        self.warnings = Some(warnings);
+        self
     }
 
     /// Evaluates the given inputs
@@ -76,26 +135,83 @@ impl<'env> Vm<'env> {
         blocks: &BTreeMap<&'env str, Instructions<'env>>,
         out: &mut Output,
         auto_escape: AutoEscape,
+        extensions: &Extensions,
     ) -> Result<Option<Value>, Error> {
         value::with_value_optimization(|| {
-            self.eval_state(
-                &mut State {
-                    env: self.env,
-                    ctx: Context::new(Frame::new(root)),
-                    current_block: None,
-                    current_call: None,
-                    auto_escape,
-                    instructions,
-                    blocks: prepare_blocks(blocks),
-                    loaded_templates: BTreeSet::new(),
-                    #[cfg(feature = "macros")]
-                    macros: Arc::new(Vec::new()),
-                },
-                out,
-            )
+            value::with_max_value_depth(self.env.max_value_depth(), || {
+                value::with_sorted_map_keys(self.env.sort_map_keys(), || {
+                    let mut state = self.new_state(
+                        instructions,
+                        root,
+                        blocks,
+                        auto_escape,
+                        Cow::Borrowed(extensions),
+                    );
+                    self.eval_state(&mut state, out)
+                })
+            })
         })
     }
 
+    /// Evaluates the given inputs and returns the state after evaluation.
+    pub fn eval_to_state<'vm>(
+        &self,
+        instructions: &'vm Instructions<'env>,
+        root: Value,
+        blocks: &'vm BTreeMap<&'env str, Instructions<'env>>,
+        out: &mut Output,
+        auto_escape: AutoEscape,
+    ) -> Result<State<'vm, 'env>, Error> {
+        value::with_value_optimization(|| {
+            value::with_max_value_depth(self.env.max_value_depth(), || {
+                value::with_sorted_map_keys(self.env.sort_map_keys(), || {
+                    let mut state = self.new_state(
+                        instructions,
+                        root,
+                        blocks,
+                        auto_escape,
+                        Cow::Owned(Extensions::new()),
+                    );
+                    ok!(self.eval_state(&mut state, out));
+                    Ok(state)
+                })
+            })
+        })
+    }
+
+    fn new_state<'vm>(
+        &self,
+        instructions: &'vm Instructions<'env>,
+        root: Value,
+        blocks: &'vm BTreeMap<&'env str, Instructions<'env>>,
+        auto_escape: AutoEscape,
+        extensions: Cow<'vm, Extensions>,
+    ) -> State<'vm, 'env> {
+        State {
+            env: self.env,
+            ctx: Context::new(Frame::new(root), self.env.recursion_limit()),
+            current_block: None,
+            current_call: None,
+            auto_escape,
+            instructions,
+            blocks: prepare_blocks(blocks),
+            loaded_templates: BTreeSet::new(),
+            extensions,
+            fuel_tracker: self.env.fuel().map(|fuel| Arc::new(FuelTracker::new(fuel))),
+            warnings: self.warnings.clone(),
+            #[cfg(feature = "multi-template")]
+            include_depth: 0,
+            #[cfg(feature = "macros")]
+            macro_depth: 0,
+            #[cfg(feature = "builtins")]
+            rng: Arc::new(Rng::new(self.env.random_seed())),
+            #[cfg(feature = "macros")]
+            macros: Arc::new(Vec::new()),
+            #[cfg(feature = "source")]
+            retained_templates: Vec::new(),
+        }
+    }
+
     /// Evaluate a macro in a state.
     #[inline(always)]
     #[cfg(feature = "macros")]
@@ -108,9 +224,18 @@ impl<'env> Vm<'env> {
         state: &State,
         args: Vec<Value>,
     ) -> Result<Option<Value>, Error> {
+        if state.macro_depth >= self.env.max_macro_depth() {
+            return Err(Error::new(
+                ErrorKind::RecursionLimitExceeded,
+                format!(
+                    "too many nested macro calls (limit is {})",
+                    self.env.max_macro_depth()
+                ),
+            ));
+        }
         value::with_value_optimization(|| {
-            let mut ctx = Context::new(Frame::new(root));
-            ok!(ctx.incr_depth(state.ctx.depth()));
+            let mut ctx = Context::new(Frame::new(root), self.env.recursion_limit());
+            ok!(ctx.incr_depth(state.ctx.depth() + MACRO_RECURSION_COST));
             self.eval_impl(
                 &mut State {
                     env: self.env,
@@ -121,8 +246,19 @@ impl<'env> Vm<'env> {
                     instructions,
                     blocks: BTreeMap::default(),
                     loaded_templates: BTreeSet::new(),
+                    extensions: Cow::Borrowed(&state.extensions),
+                    fuel_tracker: state.fuel_tracker.clone(),
+                    warnings: state.warnings.clone(),
+                    #[cfg(feature = "multi-template")]
+                    include_depth: state.include_depth,
+                    #[cfg(feature = "macros")]
+                    macro_depth: state.macro_depth + 1,
+                    #[cfg(feature = "builtins")]
+                    rng: state.rng.clone(),
                     #[cfg(feature = "macros")]
                     macros: state.macros.clone(),
+                    #[cfg(feature = "source")]
+                    retained_templates: Vec::new(),
                 },
                 out,
                 Stack::from(args),
@@ -153,6 +289,11 @@ impl<'env> Vm<'env> {
         let mut next_loop_recursion_jump = None;
         let mut loaded_filters = [None; MAX_LOCALS];
         let mut loaded_tests = [None; MAX_LOCALS];
+        let undefined_behavior = self.env.undefined_behavior();
+        // the name of the variable or attribute that most recently produced an
+        // undefined value and its position on the stack.  This is used to name
+        // it in strict mode errors and forgotten once the value is consumed.
+        let mut undefined_name: Option<(&str, usize)> = None;
 
         // If we are extending we are holding the instructions of the target parent
         // template here.  This is used to detect multiple extends and the evaluation
@@ -197,10 +338,21 @@ impl<'env> Vm<'env> {
                 ($op:tt) => {{
                     b = stack.pop();
                     a = stack.pop();
+                    assert_defined!(a, b);
                     stack.push(Value::from(a $op b));
                 }};
             }
 
+            macro_rules! cmp_binop {
+                ($op:tt) => {{
+                    b = stack.pop();
+                    a = stack.pop();
+                    assert_defined!(a, b);
+                    let ordering = ctx_ok!(ops::cmp(&a, &b, stringify!($op)));
+                    stack.push(Value::from(ordering $op std::cmp::Ordering::Equal));
+                }};
+            }
+
             macro_rules! bail {
                 ($err:expr) => {{
                     err = $err;
@@ -218,6 +370,35 @@ impl<'env> Vm<'env> {
                 };
             }
 
+            macro_rules! assert_defined {
+                ($($value:expr),+) => {
+                    if matches!(undefined_behavior, UndefinedBehavior::Strict)
+                        && ($($value.is_undefined())||+)
+                    {
+                        bail!(undefined_error(undefined_name.map(|x| x.0)));
+                    }
+                };
+            }
+
+            macro_rules! push_undefined_named {
+                ($name:expr, $value:expr) => {{
+                    stack.push($value);
+                    undefined_name = Some(($name, stack.len() - 1));
+                    stack.take_low_water();
+                }};
+            }
+
+            if let Some(ref tracker) = state.fuel_tracker {
+                ctx_ok!(tracker.track());
+            }
+
+            // forget the name of an undefined value once it was consumed
+            if let Some((_, idx)) = undefined_name {
+                if stack.take_low_water() <= idx {
+                    undefined_name = None;
+                }
+            }
+
             match instr {
                 Instruction::EmitRaw(val) => {
                     // this only produces a format error, no need to attach
@@ -225,22 +406,78 @@ impl<'env> Vm<'env> {
                     ok!(out.write_str(val).map_err(Error::from));
                 }
                 Instruction::Emit => {
-                    ctx_ok!(self.env.format(&stack.pop(), state, out));
+                    a = stack.pop();
+                    assert_defined!(a);
+                    ctx_ok!(self.env.format(&a, state, out));
                 }
                 Instruction::StoreLocal(name) => {
                     state.ctx.store(name, stack.pop());
                 }
                 Instruction::Lookup(name) => {
-                    stack.push(state.ctx.load(self.env, name).unwrap_or(Value::UNDEFINED));
+                    let mut rv = state.ctx.load(self.env, name).unwrap_or(Value::UNDEFINED);
+                    if rv.is_undefined() {
+                        if state.warnings.is_some() {
+                            self.warn_undefined(state, pc, format!("`{}` is undefined", name));
+                        }
+                        if self.env.has_undefined_factory() {
+                            rv = self.make_undefined(state, pc, Cow::Borrowed(*name), None);
+                        }
+                        push_undefined_named!(*name, rv);
+                    } else {
+                        stack.push(rv);
+                    }
                 }
                 Instruction::GetAttr(name) => {
                     a = stack.pop();
-                    stack.push(ctx_ok!(a.get_attr(name)));
+                    let mut rv = if a.is_undefined()
+                        && matches!(undefined_behavior, UndefinedBehavior::Chainable)
+                    {
+                        Value::UNDEFINED
+                    } else {
+                        assert_defined!(a);
+                        ctx_ok!(a.get_attr(name))
+                    };
+                    if rv.is_undefined() {
+                        if state.warnings.is_some() && !a.is_undefined() {
+                            self.warn_undefined(
+                                state,
+                                pc,
+                                format!("attribute `{}` is undefined", name),
+                            );
+                        }
+                        if self.env.has_undefined_factory() {
+                            rv = self.make_undefined(state, pc, Cow::Borrowed(*name), Some(&a));
+                        }
+                        push_undefined_named!(*name, rv);
+                    } else {
+                        stack.push(rv);
+                    }
                 }
                 Instruction::GetItem => {
                     a = stack.pop();
                     b = stack.pop();
-                    stack.push(ctx_ok!(b.get_item(&a)));
+                    let mut rv = if b.is_undefined()
+                        && matches!(undefined_behavior, UndefinedBehavior::Chainable)
+                    {
+                        Value::UNDEFINED
+                    } else {
+                        assert_defined!(b);
+                        ctx_ok!(b.get_item(&a))
+                    };
+                    if rv.is_undefined() {
+                        undefined_name = None;
+                        if state.warnings.is_some() && !b.is_undefined() {
+                            self.warn_undefined(state, pc, format!("item {:?} is undefined", a));
+                        }
+                        if self.env.has_undefined_factory() {
+                            let key = match a.as_str() {
+                                Some(key) => Cow::Borrowed(key),
+                                None => Cow::Owned(a.to_string()),
+                            };
+                            rv = self.make_undefined(state, pc, key, Some(&b));
+                        }
+                    }
+                    stack.push(rv);
                 }
                 Instruction::Slice => {
                     let step = stack.pop();
@@ -303,17 +540,19 @@ impl<'env> Vm<'env> {
                 Instruction::Pow => func_binop!(pow),
                 Instruction::Eq => op_binop!(==),
                 Instruction::Ne => op_binop!(!=),
-                Instruction::Gt => op_binop!(>),
-                Instruction::Gte => op_binop!(>=),
-                Instruction::Lt => op_binop!(<),
-                Instruction::Lte => op_binop!(<=),
+                Instruction::Gt => cmp_binop!(>),
+                Instruction::Gte => cmp_binop!(>=),
+                Instruction::Lt => cmp_binop!(<),
+                Instruction::Lte => cmp_binop!(<=),
                 Instruction::Not => {
                     a = stack.pop();
+                    assert_defined!(a);
                     stack.push(Value::from(!a.is_true()));
                 }
                 Instruction::StringConcat => {
                     a = stack.pop();
                     b = stack.pop();
+                    assert_defined!(a, b);
                     stack.push(ops::string_concat(b, &a));
                 }
                 Instruction::In => {
@@ -328,6 +567,13 @@ impl<'env> Vm<'env> {
                 Instruction::PushWith => {
                     ctx_ok!(state.ctx.push_frame(Frame::default()));
                 }
+                #[cfg(feature = "multi-template")]
+                Instruction::PushIsolatedWith => {
+                    ctx_ok!(state.ctx.push_frame(Frame {
+                        isolated: true,
+                        ..Frame::default()
+                    }));
+                }
                 Instruction::PopFrame => {
                     if let Some(mut loop_ctx) = state.ctx.pop_frame().current_loop {
                         if let Some((target, end_capture)) = loop_ctx.current_recursion_jump.take()
@@ -347,6 +593,7 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::PushLoop(flags) => {
                     a = stack.pop();
+                    assert_defined!(a);
                     ctx_ok!(self.push_loop(state, a, *flags, pc, next_loop_recursion_jump.take()));
                 }
                 Instruction::Iterate(jump_target) => {
@@ -370,12 +617,14 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::JumpIfFalse(jump_target) => {
                     a = stack.pop();
+                    assert_defined!(a);
                     if !a.is_true() {
                         pc = *jump_target;
                         continue;
                     }
                 }
                 Instruction::JumpIfFalseOrPop(jump_target) => {
+                    assert_defined!(stack.peek());
                     if !stack.peek().is_true() {
                         pc = *jump_target;
                         continue;
@@ -384,6 +633,7 @@ impl<'env> Vm<'env> {
                     }
                 }
                 Instruction::JumpIfTrueOrPop(jump_target) => {
+                    assert_defined!(stack.peek());
                     if stack.peek().is_true() {
                         pc = *jump_target;
                         continue;
@@ -391,16 +641,59 @@ impl<'env> Vm<'env> {
                         stack.pop();
                     }
                 }
+                #[cfg(feature = "builtins")]
+                Instruction::JumpIfDefinedOrPop(jump_target) => {
+                    let top = stack.peek();
+                    if !top.is_undefined() && !top.is_none() {
+                        pc = *jump_target;
+                        continue;
+                    } else {
+                        stack.pop();
+                    }
+                }
+                #[cfg(feature = "builtins")]
+                Instruction::JumpIfBuiltinFilter(name, jump_target) => {
+                    if state
+                        .env
+                        .get_filter(name)
+                        .map_or(false, |filter| filter.is_builtin())
+                    {
+                        pc = *jump_target;
+                        continue;
+                    }
+                }
                 #[cfg(feature = "multi-template")]
                 Instruction::CallBlock(name) => {
-                    if parent_instructions.is_none() {
+                    // if only some blocks are requested, blocks which neither
+                    // are requested nor contain a requested one are skipped
+                    let skip = out.is_filtering_blocks()
+                        && !out.is_block_requested(name)
+                        && !self.calls_requested_block(state, name, out);
+                    if parent_instructions.is_none() && !skip {
                         let old_block = state.current_block;
                         state.current_block = Some(name);
                         if let Some(block_stack) = state.blocks.get(name) {
                             let old_instructions =
                                 mem::replace(&mut state.instructions, block_stack.instructions());
                             ctx_ok!(state.ctx.push_frame(Frame::default()));
+                            // top-level blocks are recorded separately if requested,
+                            // when filtering blocks all requested blocks are.
+                            let record = out.is_capturing_blocks()
+                                && if out.is_filtering_blocks() {
+                                    out.is_block_requested(name)
+                                } else {
+                                    old_block.is_none()
+                                };
+                            if record {
+                                out.begin_capture(CaptureMode::Capture);
+                            }
                             let rv = self.eval_state(state, out);
+                            if record {
+                                let captured = out.end_capture(AutoEscape::None);
+                                let captured = captured.as_str().unwrap_or_default();
+                                ctx_ok!(out.write_str(captured).map_err(Error::from));
+                                out.record_block(name, captured);
+                            }
                             state.ctx.pop_frame();
                             state.instructions = old_instructions;
                             ctx_ok!(rv);
@@ -440,7 +733,18 @@ impl<'env> Vm<'env> {
                             )
                         }));
                     let args = stack.slice_top(*arg_count);
-                    a = ctx_ok!(filter.apply_to(state, args));
+                    a = match filter.apply_to(state, args) {
+                        Ok(rv) => rv,
+                        // name the undefined argument the filter rejected
+                        Err(err) if err.kind() == ErrorKind::UndefinedError => match undefined_name
+                        {
+                            Some((name, idx)) if idx + *arg_count >= stack.len() => {
+                                bail!(undefined_error(Some(name)))
+                            }
+                            _ => bail!(err),
+                        },
+                        Err(err) => bail!(err),
+                    };
                     stack.drop_top(*arg_count);
                     stack.push(a);
                     state.current_call = Some(name);
@@ -454,11 +758,27 @@ impl<'env> Vm<'env> {
                         Error::new(ErrorKind::UnknownTest, format!("test {} is unknown", name))
                     }));
                     let args = stack.slice_top(*arg_count);
-                    let rv = ctx_ok!(test.perform(state, args));
+                    let rv = ctx_ok!(test
+                        .perform(state, args)
+                        .map_err(|err| err.with_test_name(name)));
                     stack.drop_top(*arg_count);
                     stack.push(Value::from(rv));
                     state.current_call = None;
                 }
+                Instruction::CallTag(name, arg_count) => {
+                    state.current_call = Some(name);
+                    let tag = ctx_ok!(state.env.get_tag(name).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("tag {} is unknown", name),
+                        )
+                    }));
+                    let args = stack.slice_top(*arg_count);
+                    a = ctx_ok!(tag.apply_to(state, args));
+                    stack.drop_top(*arg_count);
+                    stack.push(a);
+                    state.current_call = None;
+                }
                 Instruction::CallFunction(name, arg_count) => {
                     state.current_call = Some(name);
 
@@ -486,6 +806,12 @@ impl<'env> Vm<'env> {
                         a = ctx_ok!(func.call(state, args));
                         stack.drop_top(*arg_count);
                         stack.push(a);
+                    // caller is only bound in macros invoked by a call block
+                    } else if cfg!(feature = "macros") && *name == "caller" {
+                        bail!(Error::new(
+                            ErrorKind::UnknownFunction,
+                            "caller() can only be used in a macro invoked by a call block",
+                        ));
                     } else {
                         bail!(Error::new(
                             ErrorKind::UnknownFunction,
@@ -525,6 +851,13 @@ impl<'env> Vm<'env> {
                     // would only be visible (and unused) internally.
                     recurse_loop!(false);
                 }
+                #[cfg(feature = "multi-template")]
+                Instruction::RequiredBlock(name) => {
+                    bail!(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("required block '{}' was not overridden", name),
+                    ));
+                }
                 // Explanation on the behavior of `LoadBlocks` and `RenderParent`.
                 // MiniJinja inherits the behavior from Jinja2 where extending
                 // loads the blocks (`LoadBlocks`) and the rest of the template
@@ -584,11 +917,50 @@ impl<'env> Vm<'env> {
                     }
                     stack.push(Value(ValueRepr::Map(module.into(), MapType::Normal)));
                 }
-                #[cfg(feature = "macros")]
-                Instruction::BuildMacro(name, offset, self_reference) => {
-                    self.build_macro(&mut stack, state, *offset, name, *self_reference);
+                #[cfg(feature = "multi-template")]
+                Instruction::ImportName(name) => {
+                    let template_name = stack.pop();
+                    match state.ctx.current_locals().get(name) {
+                        Some(value) => stack.push(value.clone()),
+                        None => bail!(Error::new(
+                            ErrorKind::InvalidOperation,
+                            format!("template {:?} does not export `{}`", template_name, name),
+                        )),
+                    }
+                    stack.push(template_name);
                 }
                 #[cfg(feature = "macros")]
+                Instruction::BuildMacro(name, offset, flags) => {
+                    self.build_macro(&mut stack, state, *offset, name, *flags);
+                }
+                #[cfg(feature = "builtins")]
+                Instruction::CatchErrors(jump_target) => {
+                    let frame_count = state.ctx.frame_count();
+                    let capture_depth = out.capture_depth();
+                    let old_auto_escape = state.auto_escape;
+                    let rv = match self.eval_impl(state, out, Stack::from(Vec::new()), pc + 1) {
+                        Ok(rv) => rv.unwrap_or(Value::UNDEFINED),
+                        // running out of resources is never recovered from
+                        Err(err)
+                            if matches!(
+                                err.kind(),
+                                ErrorKind::FuelExhausted | ErrorKind::RecursionLimitExceeded
+                            ) =>
+                        {
+                            bail!(err)
+                        }
+                        Err(_) => {
+                            state.ctx.discard_frames(frame_count);
+                            out.discard_captures(capture_depth);
+                            state.auto_escape = old_auto_escape;
+                            Value::UNDEFINED
+                        }
+                    };
+                    stack.push(rv);
+                    pc = *jump_target;
+                    continue;
+                }
+                #[cfg(any(feature = "macros", feature = "builtins"))]
                 Instruction::Return => break,
             }
             pc += 1;
@@ -605,22 +977,9 @@ impl<'env> Vm<'env> {
         out: &mut Output,
         ignore_missing: bool,
     ) -> Result<(), Error> {
-        use crate::value::SeqObject;
-
-        let single_name_slice = std::slice::from_ref(&name);
-        let choices = name
-            .as_seq()
-            .unwrap_or(&single_name_slice as &dyn SeqObject);
-
         let mut templates_tried = vec![];
-        for choice in choices.iter() {
-            let name = ok!(choice.as_str().ok_or_else(|| {
-                Error::new(
-                    ErrorKind::InvalidOperation,
-                    "template name was not a string",
-                )
-            }));
-            let tmpl = match self.env.get_template(name) {
+        for choice in ok!(template_name_choices(&name)) {
+            let tmpl = match self.env.get_template(choice.as_str().unwrap_or_default()) {
                 Ok(tmpl) => tmpl,
                 Err(err) => {
                     if err.kind() == ErrorKind::TemplateNotFound {
@@ -631,21 +990,40 @@ impl<'env> Vm<'env> {
                     continue;
                 }
             };
+            if state.include_depth >= self.env.max_include_depth() {
+                return Err(Error::new(
+                    ErrorKind::RecursionLimitExceeded,
+                    format!(
+                        "too many nested includes (limit is {})",
+                        self.env.max_include_depth()
+                    ),
+                ));
+            }
+            ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST));
+            state.retain_template(&tmpl);
+            // the included template might extend others which also counts
+            // towards the depth, so the old depth is restored afterwards.
+            let old_include_depth = state.include_depth;
+            state.include_depth += 1;
             let old_escape = mem::replace(&mut state.auto_escape, tmpl.initial_auto_escape());
             let old_instructions = mem::replace(&mut state.instructions, tmpl.instructions());
             let old_blocks = mem::replace(&mut state.blocks, prepare_blocks(tmpl.blocks()));
-            ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST));
+            let old_loaded_templates = mem::take(&mut state.loaded_templates);
             let rv = self.eval_state(state, out);
             state.ctx.decr_depth(INCLUDE_RECURSION_COST);
+            state.include_depth = old_include_depth;
             state.auto_escape = old_escape;
             state.instructions = old_instructions;
             state.blocks = old_blocks;
-            ok!(rv.map_err(|err| {
-                Error::new(
+            state.loaded_templates = old_loaded_templates;
+            ok!(rv.map_err(|err| match err.kind() {
+                // running out of resources is reported as such
+                ErrorKind::FuelExhausted | ErrorKind::RecursionLimitExceeded => err,
+                _ => Error::new(
                     ErrorKind::BadInclude,
                     format!("error in \"{}\"", tmpl.name()),
                 )
-                .with_source(err)
+                .with_source(err),
             }));
             return Ok(());
         }
@@ -683,7 +1061,7 @@ impl<'env> Vm<'env> {
         if !block_stack.push() {
             return Err(Error::new(
                 ErrorKind::InvalidOperation,
-                "no parent block exists",
+                format!("no parent block exists for block '{}'", name),
             ));
         }
 
@@ -726,32 +1104,83 @@ impl<'env> Vm<'env> {
         }
     }
 
+    /// Checks if a block directly or indirectly calls a requested block.
+    #[cfg(feature = "multi-template")]
+    fn calls_requested_block(&self, state: &State<'_, 'env>, name: &str, out: &Output) -> bool {
+        let mut pending = vec![name];
+        let mut seen = BTreeSet::new();
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            let instructions = match state.blocks.get(name) {
+                Some(block_stack) => block_stack.instructions(),
+                None => continue,
+            };
+            for idx in 0..instructions.len() {
+                if let Some(Instruction::CallBlock(name)) = instructions.get(idx) {
+                    if out.is_block_requested(name) {
+                        return true;
+                    }
+                    pending.push(name);
+                }
+            }
+        }
+        false
+    }
+
     #[cfg(feature = "multi-template")]
     fn load_blocks(
         &self,
         name: Value,
         state: &mut State<'_, 'env>,
     ) -> Result<&'env Instructions<'env>, Error> {
-        let name = match name.as_str() {
-            Some(name) => name,
+        // the first existing template of the candidates is extended
+        let choices = ok!(template_name_choices(&name));
+        let mut tmpl = None;
+        for choice in &choices {
+            match self.env.get_template(choice.as_str().unwrap_or_default()) {
+                Ok(rv) => {
+                    tmpl = Some(rv);
+                    break;
+                }
+                Err(err) if err.kind() == ErrorKind::TemplateNotFound && choices.len() > 1 => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let tmpl = match tmpl {
+            Some(tmpl) => tmpl,
             None => {
                 return Err(Error::new(
-                    ErrorKind::InvalidOperation,
-                    "template name was not a string",
+                    ErrorKind::TemplateNotFound,
+                    format!(
+                        "tried to extend one of multiple templates, none of which existed {}",
+                        Value::from(choices)
+                    ),
                 ))
             }
         };
-        if state.loaded_templates.contains(&name) {
+        if state.loaded_templates.contains(&tmpl.name()) {
             return Err(Error::new(
                 ErrorKind::InvalidOperation,
                 format!(
                     "cycle in template inheritance. {:?} was referenced more than once",
-                    name
+                    tmpl.name()
                 ),
             ));
         }
-        let tmpl = ok!(self.env.get_template(name));
+        if state.include_depth >= self.env.max_include_depth() {
+            return Err(Error::new(
+                ErrorKind::RecursionLimitExceeded,
+                format!(
+                    "too many levels of template inheritance (limit is {})",
+                    self.env.max_include_depth()
+                ),
+            ));
+        }
+        state.retain_template(&tmpl);
         state.loaded_templates.insert(tmpl.instructions().name());
+        state.include_depth += 1;
         for (name, instr) in tmpl.blocks().iter() {
             state
                 .blocks
@@ -769,6 +1198,7 @@ impl<'env> Vm<'env> {
     ) -> Result<AutoEscape, Error> {
         match (value.as_str(), value == Value::from(true)) {
             (Some("html"), _) => Ok(AutoEscape::Html),
+            (Some("xml"), _) => Ok(AutoEscape::Xml),
             #[cfg(feature = "json")]
             (Some("json"), _) => Ok(AutoEscape::Json),
             (Some("none"), _) | (None, false) => Ok(AutoEscape::None),
@@ -847,8 +1277,12 @@ impl<'env> Vm<'env> {
         state: &mut State,
         offset: usize,
         name: &str,
-        self_reference: bool,
+        flags: u8,
     ) {
+        let defaults = match stack.pop().0 {
+            ValueRepr::Seq(defaults) => defaults,
+            _ => unreachable!(),
+        };
         let arg_spec = match stack.pop().0 {
             ValueRepr::Seq(args) => args
                 .iter()
@@ -864,14 +1298,69 @@ impl<'env> Vm<'env> {
         Arc::make_mut(&mut state.macros).push((state.instructions, offset));
         stack.push(Value::from_object(Macro {
             data: Arc::new(MacroData {
-                name: Arc::new(name.to_string()),
+                name: Arc::from(name),
                 arg_spec,
+                defaults,
                 macro_ref_id,
                 closure,
-                self_reference,
+                self_reference: flags & MACRO_FLAG_SELF_REFERENCE != 0,
+                caller_reference: flags & MACRO_FLAG_CALLER != 0,
+                varargs_reference: flags & MACRO_FLAG_VARARGS != 0,
+                kwargs_reference: flags & MACRO_FLAG_KWARGS != 0,
             }),
         }));
     }
+
+    /// Records a warning for a failed lookup.
+    #[inline(never)]
+    #[cold]
+    fn warn_undefined(&self, state: &State, pc: usize, detail: String) {
+        // lookups that are immediately checked with `is defined` are not
+        // worth a warning.
+        if matches!(
+            state.instructions.get(pc + 1),
+            Some(Instruction::PerformTest("defined" | "undefined", 1, _))
+        ) {
+            return;
+        }
+        if let Some(ref warnings) = state.warnings {
+            warnings.lock().unwrap().push(Warning {
+                kind: WarningKind::UndefinedAccess,
+                detail,
+                name: state.name().to_string(),
+                line: state.instructions.get_line(pc),
+            });
+        }
+    }
+
+    /// Produces the value for a failed lookup via the undefined factory.
+    #[inline(never)]
+    fn make_undefined(
+        &self,
+        state: &State,
+        pc: usize,
+        name: Cow<'_, str>,
+        parent: Option<&Value>,
+    ) -> Value {
+        self.env.make_undefined(&UndefinedInfo {
+            name,
+            parent,
+            template_name: state.name(),
+            line: state.instructions.get_line(pc),
+        })
+    }
+}
+
+#[inline(never)]
+#[cold]
+fn undefined_error(name: Option<&str>) -> Error {
+    match name {
+        Some(name) => Error::new(
+            ErrorKind::UndefinedError,
+            format!("`{}` is undefined", name),
+        ),
+        None => Error::from(ErrorKind::UndefinedError),
+    }
 }
 
 #[inline(never)]
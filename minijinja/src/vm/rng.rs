@@ -0,0 +1,52 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// A small pseudo random number generator for a single render.
+///
+/// This is a splitmix64 generator which is good enough for things like
+/// placeholder texts but must not be used for anything security relevant.
+/// Like the fuel tracker it's shared (via an `Arc`) between the state of a
+/// render and the states of the macros it invokes.
+#[derive(Debug)]
+pub(crate) struct Rng {
+    state: AtomicU64,
+}
+
+impl Rng {
+    /// Creates a new generator from a seed or from some entropy.
+    pub fn new(seed: Option<u64>) -> Rng {
+        Rng {
+            state: AtomicU64::new(seed.unwrap_or_else(|| {
+                let mut hasher = RandomState::new().build_hasher();
+                SystemTime::now().hash(&mut hasher);
+                hasher.finish()
+            })),
+        }
+    }
+
+    /// Returns the next random number.
+    pub fn next_u64(&self) -> u64 {
+        // a plain load + store is sufficient here as renders are not
+        // evaluated concurrently.
+        let state = self
+            .state
+            .load(Ordering::Relaxed)
+            .wrapping_add(0x9e3779b97f4a7c15);
+        self.state.store(state, Ordering::Relaxed);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number in the range `lower..upper`.
+    pub fn gen_range(&self, lower: usize, upper: usize) -> usize {
+        if upper <= lower {
+            lower
+        } else {
+            lower + (self.next_u64() % (upper - lower) as u64) as usize
+        }
+    }
+}
@@ -1,11 +1,20 @@
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::sync::{Arc, Mutex};
 
 use crate::compiler::instructions::Instructions;
 use crate::environment::Environment;
-use crate::error::{Error, ErrorKind};
+use crate::error::{Error, ErrorKind, Warning};
+#[cfg(feature = "source")]
+use crate::source::LoadedTemplate;
+use crate::template::Template;
 use crate::value::{ArgType, Value};
 use crate::vm::context::Context;
+use crate::vm::fuel::FuelTracker;
+#[cfg(feature = "builtins")]
+use crate::vm::rng::Rng;
 use crate::AutoEscape;
 
 /// Provides access to the current execution state of the engine.
@@ -30,8 +39,20 @@ pub struct State<'vm, 'env> {
     pub(crate) blocks: BTreeMap<&'env str, BlockStack<'vm, 'env>>,
     #[allow(unused)]
     pub(crate) loaded_templates: BTreeSet<&'env str>,
+    pub(crate) extensions: Cow<'vm, Extensions>,
+    pub(crate) fuel_tracker: Option<Arc<FuelTracker>>,
+    pub(crate) warnings: Option<Arc<Mutex<Vec<Warning>>>>,
+    #[cfg(feature = "multi-template")]
+    pub(crate) include_depth: usize,
+    #[cfg(feature = "macros")]
+    pub(crate) macro_depth: usize,
+    #[cfg(feature = "builtins")]
+    pub(crate) rng: Arc<Rng>,
     #[cfg(feature = "macros")]
     pub(crate) macros: std::sync::Arc<Vec<(&'vm Instructions<'env>, usize)>>,
+    // keeps reloaded templates alive that are referenced by the state
+    #[cfg(feature = "source")]
+    pub(crate) retained_templates: Vec<Arc<LoadedTemplate>>,
 }
 
 impl<'vm, 'env> fmt::Debug for State<'vm, 'env> {
@@ -74,11 +95,82 @@ impl<'vm, 'env> State<'vm, 'env> {
         self.current_call
     }
 
+    /// Returns the fuel levels.
+    ///
+    /// When fuel tracking is enabled (see
+    /// [`Environment::set_fuel`](crate::Environment::set_fuel)) this returns
+    /// the fuel consumed so far and the fuel that is remaining in the current
+    /// render as a tuple.
+    pub fn fuel_levels(&self) -> Option<(u64, u64)> {
+        self.fuel_tracker.as_ref().map(|x| x.levels())
+    }
+
     /// Looks up a variable by name in the context.
+    ///
+    /// The lookup is performed against the live scope at the time of the
+    /// call.  This means that variables set in loops, `with` blocks or via
+    /// `set` are visible just like the variables passed to the render call.
+    /// If no variable is found in the context the globals of the environment
+    /// are consulted.
     pub fn lookup(&self, name: &str) -> Option<Value> {
         self.ctx.load(self.env(), name)
     }
 
+    /// Returns the extension of the given type if it was provided.
+    ///
+    /// Extensions are passed to the render call via [`Extensions`] and give
+    /// filters, functions, tests and [object methods](crate::value::Object::call_method)
+    /// access to data that is not part of the template context such as a
+    /// database handle.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Extensions, State, context};
+    /// struct Config {
+    ///     prefix: String,
+    /// }
+    ///
+    /// fn prefixed(state: &State, value: String) -> String {
+    ///     let config = state.extension::<Config>().expect("config missing");
+    ///     format!("{}{}", config.prefix, value)
+    /// }
+    ///
+    /// let mut env = Environment::new();
+    /// env.add_filter("prefixed", prefixed);
+    /// env.add_template("hello", "{{ name|prefixed }}").unwrap();
+    ///
+    /// let mut extensions = Extensions::new();
+    /// extensions.insert(Config { prefix: "Mr. ".into() });
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// let rv = tmpl.render_with_extensions(context!(name => "Doe"), &extensions);
+    /// assert_eq!(rv.unwrap(), "Mr. Doe");
+    /// ```
+    pub fn extension<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+
+    /// Keeps the template alive for as long as the state exists.
+    ///
+    /// This has to be called before the state references any of the template's
+    /// instructions.
+    pub(crate) fn retain_template(&mut self, tmpl: &Template<'env>) {
+        #[cfg(feature = "source")]
+        {
+            if let Some(owner) = tmpl.owner() {
+                if !self
+                    .retained_templates
+                    .iter()
+                    .any(|x| Arc::ptr_eq(x, owner))
+                {
+                    self.retained_templates.push(owner.clone());
+                }
+            }
+        }
+        #[cfg(not(feature = "source"))]
+        {
+            let _ = tmpl;
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn with_dummy<R, F: FnOnce(&State) -> R>(env: &'env Environment<'env>, f: F) -> R {
         f(&State {
@@ -89,8 +181,19 @@ impl<'vm, 'env> State<'vm, 'env> {
             instructions: &Instructions::new("<unknown>", ""),
             blocks: BTreeMap::new(),
             loaded_templates: BTreeSet::new(),
+            extensions: Cow::Owned(Extensions::new()),
+            fuel_tracker: None,
+            warnings: None,
+            #[cfg(feature = "multi-template")]
+            include_depth: 0,
+            #[cfg(feature = "macros")]
+            macro_depth: 0,
+            #[cfg(feature = "builtins")]
+            rng: Arc::new(Rng::new(None)),
             macros: Default::default(),
             current_call: None,
+            #[cfg(feature = "source")]
+            retained_templates: Vec::new(),
         })
     }
 
@@ -132,6 +235,64 @@ impl<'a> ArgType<'a> for &State<'_, '_> {
     }
 }
 
+/// A typed store of extra data made available to a render.
+///
+/// Extensions hold at most one value per type and can be retrieved from the
+/// engine via [`State::extension`].  The extensions are only borrowed for the
+/// duration of the render, so the same store can be reused for many renders.
+/// Because they are held in an [`Arc`] they need to be `Send` and `Sync` which
+/// allows the store to be shared between threads rendering concurrently.  To
+/// hand out request scoped services wrap them in a new extensions store per
+/// request.
+#[derive(Default, Clone)]
+pub struct Extensions {
+    map: BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+impl Extensions {
+    /// Creates an empty extension store.
+    pub fn new() -> Extensions {
+        Extensions::default()
+    }
+
+    /// Inserts a value into the store.
+    ///
+    /// If a value of the same type was already stored it's replaced and
+    /// the old value is returned.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<Arc<T>> {
+        self.map
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|old| old.downcast::<T>().ok())
+    }
+
+    /// Returns a reference to the value of the given type.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    /// Removes the value of the given type from the store.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<Arc<T>> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast::<T>().ok())
+    }
+
+    /// Returns `true` if no extensions are stored.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
 /// Tracks a block and it's parents for super.
 #[derive(Default)]
 pub(crate) struct BlockStack<'vm, 'env> {
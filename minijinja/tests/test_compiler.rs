@@ -1,5 +1,10 @@
 #![cfg(feature = "unstable_machinery")]
-use minijinja::machinery::{CodeGenerator, Instruction};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use minijinja::machinery::{
+    CodeGenerator, CompiledTemplate, Instruction, Instructions, TemplateConfig,
+};
 use minijinja::value::Value;
 
 #[test]
@@ -9,7 +14,7 @@ fn test_for_loop() {
     c.start_for_loop(true, false);
     c.add(Instruction::Emit);
     c.end_for_loop(false);
-    c.add(Instruction::EmitRaw("!"));
+    c.add(Instruction::EmitRaw("!".into()));
 
     insta::assert_debug_snapshot!(&c.finish());
 }
@@ -19,13 +24,13 @@ fn test_if_branches() {
     let mut c = CodeGenerator::new("<unknown>", "");
     c.add(Instruction::Lookup("false"));
     c.start_if();
-    c.add(Instruction::EmitRaw("nope1"));
+    c.add(Instruction::EmitRaw("nope1".into()));
     c.start_else();
     c.add(Instruction::Lookup("nil"));
     c.start_if();
-    c.add(Instruction::EmitRaw("nope1"));
+    c.add(Instruction::EmitRaw("nope1".into()));
     c.start_else();
-    c.add(Instruction::EmitRaw("yes"));
+    c.add(Instruction::EmitRaw("yes".into()));
     c.end_if();
     c.end_if();
 
@@ -57,3 +62,66 @@ fn test_const() {
 
     insta::assert_debug_snapshot!(&c.finish());
 }
+
+#[test]
+fn test_feature_flag_folding() {
+    fn raw_output<'a>(instructions: &'a Instructions<'_>) -> Vec<&'a str> {
+        (0..instructions.len())
+            .filter_map(|idx| match instructions.get(idx) {
+                Some(Instruction::EmitRaw(raw)) => Some(raw.as_ref()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn feature_calls(instructions: &Instructions<'_>) -> usize {
+        (0..instructions.len())
+            .filter(|&idx| {
+                matches!(
+                    instructions.get(idx),
+                    Some(Instruction::CallFunction("feature", _))
+                )
+            })
+            .count()
+    }
+
+    let source = "{% if feature('new_ui') %}new{% else %}old{% endif %}\
+                  {% if not feature('beta') %}stable{% else %}beta{% endif %}\
+                  {% if feature('unknown') %}unknown{% endif %}";
+    let mut flags = BTreeMap::new();
+    flags.insert("new_ui".to_string(), true);
+    flags.insert("beta".to_string(), false);
+
+    let config = TemplateConfig {
+        feature_flags: Arc::new(flags),
+        fold_feature_calls: true,
+        ..TemplateConfig::default()
+    };
+    let folded = CompiledTemplate::from_name_and_source("t", source, &config).unwrap();
+    assert_eq!(
+        raw_output(&folded.instructions),
+        vec!["new", "stable", "unknown"]
+    );
+    assert_eq!(feature_calls(&folded.instructions), 1);
+
+    let unfolded =
+        CompiledTemplate::from_name_and_source("t", source, &TemplateConfig::default()).unwrap();
+    assert_eq!(
+        raw_output(&unfolded.instructions),
+        vec!["new", "old", "stable", "beta", "unknown"]
+    );
+    assert_eq!(feature_calls(&unfolded.instructions), 3);
+
+    // a local variable named `feature` disables folding for the template
+    let shadowed_source = format!("{{% set feature = other %}}{}", source);
+    let shadowed = CompiledTemplate::from_name_and_source("t", &shadowed_source, &config).unwrap();
+    assert_eq!(feature_calls(&shadowed.instructions), 3);
+
+    // as does replacing the built-in function
+    let config = TemplateConfig {
+        fold_feature_calls: false,
+        ..config
+    };
+    let replaced = CompiledTemplate::from_name_and_source("t", source, &config).unwrap();
+    assert_eq!(feature_calls(&replaced.instructions), 3);
+}
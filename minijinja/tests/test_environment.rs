@@ -1,9 +1,22 @@
 use std::collections::BTreeMap;
+use std::fmt::Write;
 
 use similar_asserts::assert_eq;
 
 use minijinja::value::Value;
-use minijinja::Environment;
+use minijinja::{
+    context, Environment, Error, ErrorKind, Expression, NewlineMode, State, Syntax,
+    UndefinedBehavior, UndefinedInfo,
+};
+
+#[cfg(feature = "json")]
+fn root_cause(err: &Error) -> &(dyn std::error::Error + 'static) {
+    let mut err = err as &(dyn std::error::Error + 'static);
+    while let Some(next) = err.source() {
+        err = next;
+    }
+    err
+}
 
 #[test]
 fn test_basic() {
@@ -39,6 +52,102 @@ fn test_expression_lifetimes() {
     }
 }
 
+#[test]
+fn test_expression_undeclared_variables() {
+    let mut env = Environment::new();
+    env.add_filter("shout", |value: String| value.to_uppercase());
+    let undeclared = |expr: &str, nested: bool| {
+        let mut rv: Vec<_> = env
+            .compile_expression(expr)
+            .unwrap()
+            .undeclared_variables(nested)
+            .unwrap()
+            .into_iter()
+            .collect();
+        rv.sort();
+        rv
+    };
+
+    assert_eq!(undeclared("items|length > 3", false), ["items"]);
+    assert_eq!(
+        undeclared("user.profile.age >= 18 and user['name'] != banned", false),
+        ["banned", "user"]
+    );
+    assert_eq!(
+        undeclared("user.profile.age >= 18 and user['name'] != banned", true),
+        ["banned", "user.name", "user.profile.age"]
+    );
+    assert_eq!(
+        undeclared("[x, y]|select('odd')|join(sep)", false),
+        ["sep", "x", "y"]
+    );
+    assert!(undeclared("1 + 2", true).is_empty());
+
+    let is_constant = |expr: &str| env.compile_expression(expr).unwrap().is_constant();
+    assert!(is_constant("42"));
+    assert!(is_constant("[1, 2, 3]|length > 2 and 'x' is string"));
+    assert!(is_constant("{'a': [1, 2]}['a'][1:]"));
+    assert!(!is_constant("items|length > 3"));
+    assert!(!is_constant("user.name"));
+    assert!(!is_constant("range(10)|length"));
+    assert!(!is_constant("'x'.upper()"));
+    assert!(!is_constant("'x'|shout"));
+
+    let expr = env.compile_expression("[1, 2]|length").unwrap();
+    assert!(expr.is_constant());
+    assert!(expr.is_constant());
+
+    let mut env = Environment::new();
+    env.add_filter("length", |_: Value| 42);
+    env.add_test("string", |_: Value| true);
+    assert!(!env
+        .compile_expression("[1, 2]|length")
+        .unwrap()
+        .is_constant());
+    assert!(!env.compile_expression("1 is string").unwrap().is_constant());
+    assert!(env.compile_expression("1 is number").unwrap().is_constant());
+}
+
+#[test]
+fn test_owned_expression() {
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let env: &'static Environment<'static> = Box::leak(Box::new(Environment::new()));
+    let expr: Expression<'static, 'static> = {
+        let source = String::from("x > limit and name|lower == 'alice'");
+        env.compile_expression_owned(&source).unwrap()
+    };
+    assert_send_sync(&expr);
+    let expr = Arc::new(expr);
+
+    let handles = (0..2)
+        .map(|idx| {
+            let expr = expr.clone();
+            thread::spawn(move || {
+                (0..100)
+                    .map(|x| {
+                        expr.eval(context!(x => x, limit => 50 + idx, name => "Alice"))
+                            .unwrap()
+                            .is_true()
+                    })
+                    .filter(|x| *x)
+                    .count()
+            })
+        })
+        .collect::<Vec<_>>();
+    let counts = handles
+        .into_iter()
+        .map(|x| x.join().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![49, 48]);
+
+    let err = env.compile_expression_owned("x +").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+}
+
 #[test]
 fn test_clone() {
     let mut env = Environment::new();
@@ -66,3 +175,1412 @@ fn test_template_removal() {
     env.remove_template("test");
     assert!(env.get_template("test").is_err());
 }
+
+#[test]
+fn test_templates_iteration() {
+    let mut env = Environment::new();
+    env.add_template("c.txt", "C {{ x }}").unwrap();
+    env.add_template("a.txt", "A").unwrap();
+    env.add_template("b.txt", "B").unwrap();
+    let templates: Vec<_> = env.templates().collect();
+    let rv: Vec<_> = templates
+        .iter()
+        .map(|(name, tmpl)| (*name, tmpl.name(), tmpl.source()))
+        .collect();
+    assert_eq!(
+        rv,
+        [
+            ("a.txt", "a.txt", "A"),
+            ("b.txt", "b.txt", "B"),
+            ("c.txt", "c.txt", "C {{ x }}"),
+        ]
+    );
+    let (_, tmpl) = env.templates().last().unwrap();
+    assert_eq!(tmpl.render(minijinja::context!(x => 42)).unwrap(), "C 42");
+    assert_eq!(
+        env.template_names().collect::<Vec<_>>(),
+        ["a.txt", "b.txt", "c.txt"]
+    );
+    env.remove_template("b.txt");
+    assert_eq!(env.template_names().collect::<Vec<_>>(), ["a.txt", "c.txt"]);
+}
+
+#[test]
+fn test_closures_capturing_config() {
+    use std::sync::Arc;
+
+    struct Config {
+        static_url: String,
+    }
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let config = Arc::new(Config {
+        static_url: "/static/".into(),
+    });
+    let mut env = Environment::new();
+    let cfg = config.clone();
+    env.add_filter("static", move |path: String| {
+        format!("{}{}", cfg.static_url, path)
+    });
+    let cfg = config.clone();
+    env.add_function("static_url", move || cfg.static_url.clone());
+    let cfg = config;
+    env.add_test("static", move |path: String| {
+        path.starts_with(&cfg.static_url)
+    });
+    assert_send_sync(&env);
+
+    let rv = env
+        .render_str(
+            "{{ 'a.css'|static }}|{{ static_url() }}|{{ 'a.css'|static is static }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "/static/a.css|/static/|true");
+}
+
+#[test]
+fn test_strict_kwargs() {
+    use minijinja::value::Kwargs;
+    use minijinja::{Error, ErrorKind};
+
+    fn pad(value: String, kwargs: Kwargs) -> Result<String, Error> {
+        let width = kwargs.get::<Option<usize>>("width")?.unwrap_or(0);
+        kwargs.assert_all_used()?;
+        Ok(format!("{:>width$}", value, width = width))
+    }
+
+    let mut env = Environment::new();
+    env.add_filter("pad", pad);
+    env.add_function("answer", || 42);
+    env.add_test("short", |value: String| value.len() < 3);
+
+    // unknown keyword arguments are ignored by default
+    assert!(!env.strict_kwargs());
+    let rv = env
+        .render_str(
+            "{{ 'x'|pad(widht=3) }}|{{ [2, 1]|sort(revrse=true) }}|{{ 'x'|pad(width=3) }}|{{ dict(a=1) }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "x|[1, 2]|  x|{\"a\": 1}");
+
+    env.set_strict_kwargs(true);
+    for (source, expected) in [
+        ("{{ 'x'|pad(widht=3) }}", "unknown keyword argument 'widht'"),
+        (
+            "{{ [2, 1]|sort(revrse=true) }}",
+            "unknown keyword argument 'revrse'",
+        ),
+        (
+            "{{ 'x'|upper(foo=1) }}",
+            "unexpected keyword arguments: foo",
+        ),
+        ("{{ answer(foo=1) }}", "unexpected keyword arguments: foo"),
+        (
+            "{{ 'x' is short(foo=1) }}",
+            "unexpected keyword arguments: foo",
+        ),
+    ] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TooManyArguments);
+        assert!(err.to_string().contains(expected), "{}", err);
+    }
+
+    // known keyword arguments still work
+    let rv = env
+        .render_str(
+            "{{ 'x'|pad(width=3) }}|{{ [1, 2]|sort(reverse=true) }}|{{ dict(a=1) }}|{{ dict({'a': 1}, b=2) }}",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "  x|[2, 1]|{\"a\": 1}|{\"a\": 1, \"b\": 2}");
+}
+
+#[test]
+fn test_recursion_limit() {
+    let mut env = Environment::new();
+    assert_eq!(env.recursion_limit(), 500);
+    env.add_template("self.txt", "{% include 'self.txt' %}")
+        .unwrap();
+    env.add_template(
+        "macro.txt",
+        "{% macro x(n) %}{{ n }}{% if n > 0 %}.{{ x(n - 1) }}{% endif %}{% endmacro %}{{ x(depth) }}",
+    )
+    .unwrap();
+
+    // a deeply recursive macro and a self including template fail gracefully
+    // with the default limits
+    let err = env
+        .get_template("macro.txt")
+        .unwrap()
+        .render(context!(depth => 100_000))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many nested macro calls (limit is 64) (in macro.txt:1)"
+    );
+    let err = env
+        .get_template("self.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many nested includes (limit is 32) (in self.txt:1)"
+    );
+
+    // shallow recursion is fine
+    let rv = env
+        .get_template("macro.txt")
+        .unwrap()
+        .render(context!(depth => 3))
+        .unwrap();
+    assert_eq!(rv, "3.2.1.0");
+
+    // the limit is configurable
+    env.set_recursion_limit(5);
+    assert_eq!(env.recursion_limit(), 5);
+    let err = env
+        .get_template("macro.txt")
+        .unwrap()
+        .render(context!(depth => 10))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(err.to_string(), "recursion limit exceeded (in macro.txt:1)");
+    let err = env
+        .render_str(
+            "{% for item in [[[[[[[1]]]]]]] recursive %}\
+             {% if item is sequence %}{{ loop(item) }}{% endif %}{% endfor %}",
+            (),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+}
+
+#[test]
+fn test_nesting_limits() {
+    let mut env = Environment::new();
+    assert_eq!(env.max_include_depth(), 32);
+    assert_eq!(env.max_macro_depth(), 64);
+    assert_eq!(env.max_value_depth(), 100);
+
+    // the dedicated limits also apply if the general limit is out of the way
+    env.set_recursion_limit(usize::MAX);
+    env.set_max_include_depth(5);
+    env.set_max_macro_depth(10);
+    env.set_max_value_depth(3);
+
+    env.add_template("self.txt", "{% include 'self.txt' %}")
+        .unwrap();
+    let err = env
+        .get_template("self.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many nested includes (limit is 5) (in self.txt:1)"
+    );
+
+    env.add_template(
+        "macro.txt",
+        "{% macro x(n) %}{{ n }}{% if n > 0 %}.{{ x(n - 1) }}{% endif %}{% endmacro %}{{ x(depth) }}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("macro.txt").unwrap();
+    assert_eq!(
+        tmpl.render(context!(depth => 9)).unwrap(),
+        "9.8.7.6.5.4.3.2.1.0"
+    );
+    let err = tmpl.render(context!(depth => 1_000_000)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many nested macro calls (limit is 10) (in macro.txt:1)"
+    );
+
+    for (name, source) in [
+        ("layout0.txt", "{% extends 'layout1.txt' %}"),
+        ("layout1.txt", "{% extends 'layout2.txt' %}"),
+        ("layout2.txt", "{% extends 'layout3.txt' %}"),
+        ("layout3.txt", "{% extends 'layout4.txt' %}"),
+        ("layout4.txt", "{% extends 'layout5.txt' %}"),
+        ("layout5.txt", "{% extends 'layout6.txt' %}"),
+        ("layout6.txt", "done"),
+    ] {
+        env.add_template(name, source).unwrap();
+    }
+    let err = env
+        .get_template("layout0.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many levels of template inheritance \
+         (limit is 5) (in layout5.txt:1)"
+    );
+    assert_eq!(
+        env.get_template("layout2.txt").unwrap().render(()).unwrap(),
+        "done"
+    );
+
+    // the depth is tracked per include, sibling includes do not add up
+    env.add_template("child.txt", "{% extends 'layout3.txt' %}")
+        .unwrap();
+    env.add_template(
+        "siblings.txt",
+        "{% for x in range(3) %}{% include 'child.txt' %}{% endfor %}",
+    )
+    .unwrap();
+    assert_eq!(
+        env.get_template("siblings.txt")
+            .unwrap()
+            .render(())
+            .unwrap(),
+        "donedonedone"
+    );
+
+    // includes and inheritance count towards the same depth
+    env.add_template("nested.txt", "{% include 'child.txt' %}")
+        .unwrap();
+    env.add_template("outer.txt", "{% include 'nested.txt' %}")
+        .unwrap();
+    let err = env
+        .get_template("outer.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::RecursionLimitExceeded);
+    assert_eq!(
+        err.to_string(),
+        "recursion limit exceeded: too many levels of template inheritance \
+         (limit is 5) (in layout5.txt:1)"
+    );
+
+    // deeply nested values are cut off when printed and fail to serialize
+    let mut value = Value::from(vec![1]);
+    for _ in 0..1000 {
+        value = Value::from(vec![value]);
+    }
+    let rv = env
+        .render_str(
+            "{{ shallow }}|{{ deep }}",
+            context!(shallow => [[1]], deep => value),
+        )
+        .unwrap();
+    assert_eq!(rv, "[[1]]|[[[...]]]");
+    assert!(!value.to_string().contains("..."));
+    #[cfg(feature = "json")]
+    {
+        let err = env
+            .render_str("{{ deep|tojson }}", context!(deep => value))
+            .unwrap_err();
+        assert_eq!(
+            root_cause(&err).to_string(),
+            "value nested too deeply (limit is 3)"
+        );
+    }
+}
+
+#[test]
+fn test_globals_state_lookup() {
+    let mut env = Environment::new();
+    env.add_global("version", Value::from("1.0"));
+    env.add_filter("versioned", |state: &State, path: String| {
+        let version = state.lookup("version").unwrap_or_default();
+        format!("{}?v={}", path, version)
+    });
+    env.add_template("test.txt", "{{ version }} {{ 'app.js'|versioned }}")
+        .unwrap();
+
+    // the global is not part of the render context but still resolves
+    let tmpl = env.get_template("test.txt").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "1.0 app.js?v=1.0");
+    assert_eq!(
+        tmpl.render(context!(version => "2.0")).unwrap(),
+        "2.0 app.js?v=2.0"
+    );
+
+    env.remove_global("version");
+    let tmpl = env.get_template("test.txt").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), " app.js?v=none");
+}
+
+#[test]
+fn test_globals_visibility() {
+    let mut env = Environment::new();
+    env.add_global("site_name", Value::from("My Site"));
+    env.add_global("build", Value::from(42));
+    assert_eq!(env.global("site_name"), Some(Value::from("My Site")));
+    assert_eq!(env.global("missing"), None);
+
+    env.add_template("header.txt", "[{{ site_name }}:{{ build }}]")
+        .unwrap();
+    env.add_template(
+        "index.txt",
+        "{% macro footer() %}({{ site_name }}){% endmacro %}\
+         {% include 'header.txt' %}\
+         {{ site_name }}\
+         {{ footer() }}\
+         {% set site_name = 'Changed' %}{{ site_name }}\
+         {% include 'header.txt' %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("index.txt").unwrap();
+
+    // globals are visible in includes and macros, `set` only shadows
+    let rv = tmpl.render(()).unwrap();
+    assert_eq!(rv, "[My Site:42]My Site(My Site)Changed[Changed:42]");
+    assert_eq!(env.global("site_name"), Some(Value::from("My Site")));
+    assert_eq!(tmpl.render(()).unwrap(), rv);
+
+    // render context variables shadow globals
+    let rv = tmpl.render(context!(build => 23)).unwrap();
+    assert_eq!(rv, "[My Site:23]My Site(My Site)Changed[Changed:23]");
+
+    // expressions see globals as well
+    let expr = env.compile_expression("build * 2").unwrap();
+    assert_eq!(expr.eval(()).unwrap(), Value::from(84));
+    assert_eq!(expr.eval(context!(build => 1)).unwrap(), Value::from(2));
+
+    env.remove_global("build");
+    assert_eq!(env.global("build"), None);
+    let expr = env.compile_expression("build is defined").unwrap();
+    assert_eq!(expr.eval(()).unwrap(), Value::from(false));
+}
+
+#[test]
+fn test_fuel() {
+    let mut env = Environment::new();
+    assert_eq!(env.fuel(), None);
+    env.add_template("loop.txt", "{% for _ in range(10**9) %}.{% endfor %}")
+        .unwrap();
+    env.add_template(
+        "macro.txt",
+        "{% macro spin(n) %}{% for _ in range(n) %}.{% endfor %}{% endmacro %}\
+         {% for _ in range(100) %}{% for _ in range(100) %}{{ spin(10) }}{% endfor %}{% endfor %}",
+    )
+    .unwrap();
+
+    env.add_template(
+        "nested.txt",
+        "{% for a in range(100) %}\n{% for b in range(100) %}{{ b }}{% endfor %}\n{% endfor %}",
+    )
+    .unwrap();
+
+    env.set_fuel(Some(1000));
+    assert_eq!(env.fuel(), Some(1000));
+    for name in ["loop.txt", "macro.txt"] {
+        let err = env.get_template(name).unwrap().render(()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::FuelExhausted);
+        assert_eq!(err.to_string(), format!("fuel exhausted (in {}:1)", name));
+    }
+
+    // runaway templates fail at the same point on every render
+    let tmpl = env.get_template("nested.txt").unwrap();
+    let err = tmpl.render(()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FuelExhausted);
+    assert_eq!(err.name(), Some("nested.txt"));
+    assert_eq!(err.line(), Some(2));
+    let mut partial = Vec::new();
+    tmpl.render_to_write((), &mut partial).unwrap_err();
+    let mut partial2 = Vec::new();
+    tmpl.render_to_write((), &mut partial2).unwrap_err();
+    assert!(!partial.is_empty());
+    assert_eq!(partial, partial2);
+
+    // a generous budget does not change the output of normal templates
+    let source = "{% for x in range(10) %}{{ x }}{% if not loop.last %},{% endif %}{% endfor %}";
+    assert_eq!(env.render_str(source, ()).unwrap(), "0,1,2,3,4,5,6,7,8,9");
+
+    // every render starts out with the full budget
+    env.add_function("fuel_levels", |state: &State| {
+        let (consumed, remaining) = state.fuel_levels().unwrap();
+        format!("{}/{}", consumed, remaining)
+    });
+    env.add_template("small.txt", "{{ fuel_levels() }}")
+        .unwrap();
+    let tmpl = env.get_template("small.txt").unwrap();
+    let first = tmpl.render(()).unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), first);
+    let (consumed, remaining) = first.split_once('/').unwrap();
+    assert_eq!(
+        consumed.parse::<u64>().unwrap() + remaining.parse::<u64>().unwrap(),
+        1000
+    );
+
+    env.set_fuel(None);
+    let rv = env
+        .render_str("{% for _ in range(10000) %}.{% endfor %}", ())
+        .unwrap();
+    assert_eq!(rv.len(), 10000);
+}
+
+#[test]
+fn test_introspection_and_removal() {
+    let mut env = Environment::empty();
+    assert_eq!(env.filters().count(), 0);
+    env.add_filter("shout", |v: String| v.to_uppercase());
+    env.add_filter("double", |v: i64| v * 2);
+    env.add_test("big", |v: i64| v > 100);
+    env.add_function("greet", |name: String| format!("Hello {}!", name));
+    env.add_function("answer", || 42);
+    env.add_global("site_name", Value::from("My Site"));
+
+    assert!(env.has_filter("shout"));
+    assert!(!env.has_filter("missing"));
+    assert!(env.has_test("big"));
+    assert!(!env.has_test("shout"));
+
+    // names are reported in sorted order
+    assert_eq!(env.filters().collect::<Vec<_>>(), vec!["double", "shout"]);
+    assert_eq!(env.tests().collect::<Vec<_>>(), vec!["big"]);
+    assert_eq!(env.functions().collect::<Vec<_>>(), vec!["answer", "greet"]);
+
+    env.add_template(
+        "test.txt",
+        "{{ 'hi'|shout }} {{ 200 is big }} {{ greet('World') }}",
+    )
+    .unwrap();
+    let rv = env.get_template("test.txt").unwrap().render(()).unwrap();
+    assert_eq!(rv, "HI true Hello World!");
+
+    env.remove_filter("shout");
+    assert!(!env.has_filter("shout"));
+    assert_eq!(env.filters().collect::<Vec<_>>(), vec!["double"]);
+    let err = env
+        .get_template("test.txt")
+        .unwrap()
+        .render(())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFilter);
+    assert_eq!(
+        err.to_string(),
+        "unknown filter: filter shout is unknown (in test.txt:1)"
+    );
+
+    env.remove_test("big");
+    assert!(!env.has_test("big"));
+    let err = env.render_str("{{ 200 is big }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownTest);
+
+    // remove_function leaves non function globals alone
+    env.remove_function("site_name");
+    assert_eq!(env.global("site_name"), Some(Value::from("My Site")));
+    env.remove_function("greet");
+    assert_eq!(env.global("greet"), None);
+    assert_eq!(env.functions().collect::<Vec<_>>(), vec!["answer"]);
+    let err = env.render_str("{{ greet('World') }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownFunction);
+}
+
+#[test]
+fn test_fuel_shared_across_render() {
+    let mut env = Environment::new();
+    env.add_template(
+        "macros.txt",
+        "{% macro count(n) %}{% for x in range(n) %}{{ x }}{% endfor %}{% endmacro %}",
+    )
+    .unwrap();
+    env.add_template("part.txt", "{% for x in range(n) %}.{% endfor %}")
+        .unwrap();
+    env.add_template(
+        "index.txt",
+        "{% from 'macros.txt' import count %}\
+         {% for _ in range(3) %}{{ count(n) }}|{% include 'part.txt' %}|{% endfor %}",
+    )
+    .unwrap();
+
+    // measure the fuel a full render needs
+    env.set_fuel(Some(u64::MAX));
+    env.add_function("levels", |state: &State| {
+        let (consumed, _) = state.fuel_levels().unwrap();
+        consumed
+    });
+    env.add_template("measure.txt", "{% include 'index.txt' %}{{ levels() }}")
+        .unwrap();
+    let rv = env
+        .get_template("measure.txt")
+        .unwrap()
+        .render(context!(n => 10))
+        .unwrap();
+    let needed: u64 = rv.rsplit('|').next().unwrap().parse().unwrap();
+
+    // the budget covers the macro calls and includes: a budget sufficient
+    // for the whole tree completes, one that is too small aborts.
+    env.set_fuel(Some(needed));
+    let tmpl = env.get_template("index.txt").unwrap();
+    let rv = tmpl.render(context!(n => 10)).unwrap();
+    assert_eq!(rv.matches('|').count(), 6);
+
+    env.set_fuel(Some(needed / 2));
+    let tmpl = env.get_template("index.txt").unwrap();
+    let err = tmpl.render(context!(n => 10)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::FuelExhausted);
+}
+
+#[test]
+fn test_render_named_str() {
+    #[allow(unused_mut)]
+    let mut env = Environment::new();
+    #[cfg(feature = "debug")]
+    env.set_debug(true);
+
+    let err = env
+        .render_named_str(
+            "snippet.txt",
+            "Hello\n{{ user.name.first }}!",
+            context!(user => context!()),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UndefinedError);
+    assert_eq!(err.name(), Some("snippet.txt"));
+    assert_eq!(err.line(), Some(2));
+    assert_eq!(err.to_string(), "undefined value (in snippet.txt:2)");
+    #[cfg(feature = "debug")]
+    {
+        let debug = format!("{:#}", err);
+        assert!(debug.contains("2 > {{ user.name.first }}!"), "{}", debug);
+    }
+
+    // syntax errors carry the name as well
+    let err = env
+        .render_named_str("broken.txt", "\n\n{{ foo(", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(err.name(), Some("broken.txt"));
+    assert_eq!(err.line(), Some(3));
+
+    // auto escaping follows the name
+    let ctx = context!(x => "<b>");
+    assert_eq!(
+        env.render_named_str("a.html", "{{ x }}", ctx.clone())
+            .unwrap(),
+        "&lt;b&gt;"
+    );
+    assert_eq!(
+        env.render_named_str("a.txt", "{{ x }}", ctx).unwrap(),
+        "<b>"
+    );
+
+    // the template is not registered
+    assert!(env.get_template("a.html").is_err());
+}
+
+#[test]
+fn test_feature_flags() {
+    let mut env = Environment::new();
+    env.set_feature_flag("new_ui", true);
+    env.add_template(
+        "index",
+        "{% if feature('new_ui') %}new{% else %}old{% endif %}|\
+         {{ feature('new_ui') }}|{{ feature('late') }}|{{ feature('missing') }}",
+    )
+    .unwrap();
+    assert_eq!(env.feature_flag("new_ui"), Some(true));
+    assert_eq!(env.feature_flag("missing"), None);
+
+    // flags unknown at compile time are resolved when rendering
+    env.set_feature_flag("late", true);
+    let tmpl = env.get_template("index").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "new|true|true|false");
+
+    // the render context cannot shadow flags resolved at compile time
+    let ctx = context!(feature => Value::from_function(|_: String| "context"));
+    assert_eq!(tmpl.render(ctx).unwrap(), "new|true|context|context");
+
+    // shadowed or replaced `feature` functions are not resolved at compile time
+    env.add_template(
+        "shadowed",
+        "{% macro feature(name) %}{{ name }}{% endmacro %}{{ feature('new_ui') }}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("shadowed").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "new_ui");
+    env.set_feature_flag("off", false);
+    env.add_function("feature", |name: String| format!("custom {}", name));
+    env.add_template(
+        "replaced",
+        "{% if feature('off') %}{{ feature('off') }}{% endif %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("replaced").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "custom off");
+}
+
+#[test]
+fn test_undefined_behavior() {
+    fn render(behavior: UndefinedBehavior, source: &str) -> Result<String, String> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(behavior);
+        env.render_str(source, context!(settings => context!(theme => context!())))
+            .map_err(|err| err.to_string())
+    }
+
+    assert_eq!(
+        Environment::new().undefined_behavior(),
+        UndefinedBehavior::Lenient
+    );
+
+    // lenient
+    let mode = UndefinedBehavior::Lenient;
+    assert_eq!(render(mode, "[{{ missing }}]").unwrap(), "[]");
+    assert_eq!(
+        render(mode, "{{ missing.attr }}").unwrap_err(),
+        "undefined value (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{% for x in missing %}{{ x }}{% endfor %}!").unwrap(),
+        "!"
+    );
+    assert_eq!(render(mode, "{{ missing == 1 }}").unwrap(), "false");
+    assert_eq!(render(mode, "{{ missing < 1 }}").unwrap(), "true");
+
+    // chainable
+    let mode = UndefinedBehavior::Chainable;
+    assert_eq!(render(mode, "[{{ missing }}]").unwrap(), "[]");
+    assert_eq!(render(mode, "[{{ missing.attr }}]").unwrap(), "[]");
+    assert_eq!(
+        render(mode, "[{{ settings.theme.color.name }}]").unwrap(),
+        "[]"
+    );
+    assert_eq!(render(mode, "[{{ missing['a'][0] }}]").unwrap(), "[]");
+    assert_eq!(
+        render(mode, "{% for x in missing.items %}{{ x }}{% endfor %}!").unwrap(),
+        "!"
+    );
+    assert_eq!(render(mode, "{{ missing == 1 }}").unwrap(), "false");
+
+    // strict
+    let mode = UndefinedBehavior::Strict;
+    assert_eq!(
+        render(mode, "[{{ missing }}]").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ missing.attr }}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ settings.theme.color }}").unwrap_err(),
+        "undefined value: `color` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{% for x in missing %}{{ x }}{% endfor %}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ missing == 1 }}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(
+            mode,
+            "{{ missing is defined }}|{{ missing|default('fallback') }}|{{ settings.theme }}"
+        )
+        .unwrap(),
+        "false|fallback|{}"
+    );
+    assert_eq!(
+        render(mode, "{% set a = missing is defined %}{{ foo() }}").unwrap_err(),
+        "unknown function: foo is unknown (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ missing|upper }}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{% if missing %}yes{% endif %}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ missing and 1 }}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+    assert_eq!(
+        render(mode, "{{ not missing }}").unwrap_err(),
+        "undefined value: `missing` is undefined (in <string>:1)"
+    );
+}
+
+#[test]
+fn test_undefined_factory() {
+    use std::sync::{Arc, Mutex};
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Chainable);
+    env.set_undefined_factory({
+        let calls = calls.clone();
+        move |info: &UndefinedInfo| {
+            calls.lock().unwrap().push(format!(
+                "{}:{:?} {} on {:?}",
+                info.template_name(),
+                info.line(),
+                info.name(),
+                info.parent()
+            ));
+            Value::UNDEFINED
+        }
+    });
+    env.add_template(
+        "nested.txt",
+        "{{ user.profile.name }}\n{% for item in missing %}{{ item }}{% endfor %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("nested.txt").unwrap();
+    let rv = tmpl.render(context!(user => context!(id => 1))).unwrap();
+    assert_eq!(rv, "\n");
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![
+            "nested.txt:Some(1) profile on Some({\"id\": 1})".to_string(),
+            "nested.txt:Some(1) name on Some(Undefined)".to_string(),
+            "nested.txt:Some(2) missing on None".to_string(),
+        ]
+    );
+
+    // returning a defined value substitutes it for the undefined one
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_undefined_factory(|info| Value::from(format!("⟦missing:{}⟧", info.name())));
+    let rv = env
+        .render_str(
+            "{{ user.name }}|{{ items[0] }}|{{ missing }}",
+            context!(user => context!(), items => Vec::<i32>::new()),
+        )
+        .unwrap();
+    assert_eq!(rv, "⟦missing:name⟧|⟦missing:0⟧|⟦missing:missing⟧");
+}
+
+#[test]
+fn test_lstrip_blocks() {
+    let mut env = Environment::new();
+    env.set_lstrip_blocks(true);
+    assert!(env.lstrip_blocks());
+    assert!(!env.trim_blocks());
+
+    // start of file
+    assert_eq!(
+        env.render_str("  {% if true %}yes{% endif %}", ()).unwrap(),
+        "yes"
+    );
+    assert_eq!(env.render_str("\t {# comment #}x", ()).unwrap(), "x");
+    // mid-line tags keep their whitespace
+    assert_eq!(
+        env.render_str("text {% if true %}yes{% endif %}", ())
+            .unwrap(),
+        "text yes"
+    );
+    assert_eq!(
+        env.render_str("a\n  b {% if true %}c{% endif %}", ())
+            .unwrap(),
+        "a\n  b c"
+    );
+    assert_eq!(
+        env.render_str("{% if true %}  {% endif %}|", ()).unwrap(),
+        "  |"
+    );
+    // indented tags
+    assert_eq!(
+        env.render_str("a\n    {% if true %}b\n  \t{% endif %}c", ())
+            .unwrap(),
+        "a\nb\nc"
+    );
+    // variables are never stripped
+    assert_eq!(env.render_str("a\n  {{ 1 }}", ()).unwrap(), "a\n  1");
+    // a plus sign disables stripping, a minus sign strips everything
+    assert_eq!(
+        env.render_str("a\n  {%+ if true %}b{% endif %}", ())
+            .unwrap(),
+        "a\n  b"
+    );
+    assert_eq!(
+        env.render_str("a\n  {%- if true %}b{% endif %}", ())
+            .unwrap(),
+        "ab"
+    );
+}
+
+#[test]
+fn test_trim_blocks() {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    assert_eq!(
+        env.render_str("{% if true %}\nyes\n{% endif %}\nend", ())
+            .unwrap(),
+        "yes\nend"
+    );
+    assert_eq!(env.render_str("{# comment #}\r\nx", ()).unwrap(), "x");
+    assert_eq!(env.render_str("{{ 1 }}\nx", ()).unwrap(), "1\nx");
+    assert_eq!(
+        env.render_str("{% if true +%}\nyes{% endif %}", ())
+            .unwrap(),
+        "\nyes"
+    );
+
+    env.set_lstrip_blocks(true);
+    assert_eq!(
+        env.render_str(
+            "<ul>\n  {% for x in [1, 2] %}\n  <li>{{ x }}</li>\n  {% endfor %}\n</ul>",
+            ()
+        )
+        .unwrap(),
+        "<ul>\n  <li>1</li>\n  <li>2</li>\n</ul>"
+    );
+}
+
+#[test]
+fn test_custom_syntax() {
+    let mut env = Environment::new();
+    env.set_syntax(Syntax {
+        block_start: "<%".into(),
+        block_end: "%>".into(),
+        variable_start: "((".into(),
+        variable_end: "))".into(),
+        comment_start: "<#".into(),
+        comment_end: "#>".into(),
+    })
+    .unwrap();
+    assert_eq!(env.syntax().block_start, "<%");
+    assert!(format!("{:?}", env).contains(r#"block_start: "<%""#));
+
+    env.add_template(
+        "main.tf",
+        "<# comment #><% for name in names -%>\n\
+         resource \"{{ type }}\" \"(( name ))\" { count = (( loop.index * 2 )) }\n\
+         <%- endfor %>",
+    )
+    .unwrap();
+    let tmpl = env.get_template("main.tf").unwrap();
+    assert_eq!(
+        tmpl.render(context!(names => ["a", "b"])).unwrap(),
+        "resource \"{{ type }}\" \"a\" { count = 2 }resource \"{{ type }}\" \"b\" { count = 4 }"
+    );
+
+    // whitespace control and plain delimiters from the default syntax
+    assert_eq!(
+        env.render_str("{% if %}|{# x #}|(( 1 -))  <%- if true %>!<% endif %>", ())
+            .unwrap(),
+        "{% if %}|{# x #}|1!"
+    );
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    assert_eq!(
+        env.render_str("a\n  <% if true %>\nb\n  <# c #>\n<% endif %>\n", ())
+            .unwrap(),
+        "a\nb\n"
+    );
+
+    // errors mention the configured delimiters
+    let err = env.render_str("<# unclosed", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert!(err.to_string().contains("expected `#>`"));
+    let err = env.render_str("<% raw %>unclosed", ()).unwrap_err();
+    assert!(err.to_string().contains("expected `<% endraw %>`"));
+
+    // conflicting delimiters are rejected and leave the syntax unchanged
+    let err = env
+        .set_syntax(Syntax {
+            block_start: "((".into(),
+            variable_start: "((".into(),
+            ..Syntax::default()
+        })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: block_start delimiter `((` conflicts with variable_start delimiter `((`"
+    );
+    let err = env
+        .set_syntax(Syntax {
+            comment_start: "{".into(),
+            ..Syntax::default()
+        })
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    let err = env
+        .set_syntax(Syntax {
+            block_end: "".into(),
+            ..Syntax::default()
+        })
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: block_end delimiter must not be empty"
+    );
+    assert_eq!(env.syntax().variable_start, "((");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_sort_map_keys() {
+    use minijinja::value::StructObject;
+    use std::collections::HashMap;
+
+    struct Point;
+
+    impl StructObject for Point {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "z" => Some(Value::from(3)),
+                "y" => Some(Value::from(2)),
+                "x" => Some(Value::from(1)),
+                _ => None,
+            }
+        }
+
+        fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["z", "y", "x"].into_iter())
+        }
+    }
+
+    let template = "{% for key in map %}{{ key }}{% endfor %}|\
+                    {% for key, value in map|items %}{{ key }}={{ value }} {% endfor %}|\
+                    {{ map|dictsort(by='value') }}|{{ map|tojson }}";
+
+    let mut env = Environment::new();
+    assert!(!env.sort_map_keys());
+    let ctx = context!(map => Value::from_struct_object(Point));
+    assert_eq!(
+        env.render_str(template, ctx.clone()).unwrap(),
+        r#"zyx|z=3 y=2 x=1 |[["x", 1], ["y", 2], ["z", 3]]|{"z":3,"y":2,"x":1}"#
+    );
+
+    env.set_sort_map_keys(true);
+    assert!(env.sort_map_keys());
+    assert_eq!(
+        env.render_str(template, ctx).unwrap(),
+        r#"xyz|x=1 y=2 z=3 |[["x", 1], ["y", 2], ["z", 3]]|{"x":1,"y":2,"z":3}"#
+    );
+
+    // maps created from hash maps render the same way every time
+    let outputs = (0..10)
+        .map(|_| {
+            let map = (0..20)
+                .map(|idx| (format!("key{:02}", idx), idx))
+                .collect::<HashMap<_, _>>();
+            env.render_str(template, context!(map)).unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert!(outputs.iter().all(|x| x == &outputs[0]));
+    assert!(outputs[0].starts_with("key00key01key02"));
+}
+
+#[test]
+fn test_custom_formatter() {
+    use minijinja::escape_formatter;
+    use minijinja::value::ValueKind;
+
+    let mut env = Environment::new();
+    env.set_formatter(|out, state, value| match value.kind() {
+        ValueKind::Map => Err(Error::new(
+            ErrorKind::InvalidOperation,
+            "refusing to print a map",
+        )),
+        ValueKind::Number => match f64::try_from(value.clone()) {
+            Ok(f) if f.fract() != 0.0 => write!(out, "{:.2}", f).map_err(Error::from),
+            _ => escape_formatter(out, state, value),
+        },
+        ValueKind::None if state.name().ends_with(".json") => {
+            out.write_str("null").map_err(Error::from)
+        }
+        ValueKind::None => Ok(()),
+        _ => escape_formatter(out, state, value),
+    });
+    env.add_template(
+        "page.html",
+        "{{ ratio }}|{{ value }}|{{ value|safe }}|{{ nothing }}|{{ 2 }}",
+    )
+    .unwrap();
+    env.add_template("data.json", "{{ nothing }}").unwrap();
+    env.add_template("map.txt", "before {{ map }} after")
+        .unwrap();
+
+    let ctx = context!(ratio => 1.23456, value => "<b>", nothing => ());
+    assert_eq!(
+        env.get_template("page.html").unwrap().render(&ctx).unwrap(),
+        "1.23|&lt;b&gt;|<b>||2"
+    );
+    // filters are applied before the formatter sees the value
+    assert_eq!(
+        env.render_named_str(
+            "t.html",
+            "{{ ratio|round(1) }}|{{ [1, 2]|join('<') }}",
+            &ctx
+        )
+        .unwrap(),
+        "1.20|1&lt;2"
+    );
+    assert_eq!(
+        env.get_template("data.json").unwrap().render(&ctx).unwrap(),
+        "null"
+    );
+
+    let err = env
+        .get_template("map.txt")
+        .unwrap()
+        .render(context!(map => context!(a => 1)))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: refusing to print a map (in map.txt:1)"
+    );
+}
+
+#[test]
+fn test_lipsum() {
+    let mut env = Environment::new();
+    env.set_random_seed(Some(42));
+
+    let rv = env.render_str("{{ lipsum() }}", ()).unwrap();
+    assert_eq!(rv.lines().count(), 5);
+    for line in rv.lines() {
+        assert!(line.starts_with("<p>") && line.ends_with(".</p>"));
+        let words = line.split_whitespace().count();
+        assert!((20..=100).contains(&words));
+    }
+
+    let rv = env
+        .render_str("{{ lipsum(3, html=false, min=5, max=10) }}", ())
+        .unwrap();
+    let paragraphs = rv.split("\n\n").collect::<Vec<_>>();
+    assert_eq!(paragraphs.len(), 3);
+    for paragraph in paragraphs {
+        assert!(!paragraph.contains('<'));
+        assert!(paragraph.starts_with(char::is_uppercase));
+        assert!((5..=10).contains(&paragraph.split_whitespace().count()));
+    }
+
+    // a fixed seed makes the output deterministic, multiple calls in one
+    // render still produce different text
+    let template = "{{ lipsum(1) }}|{{ lipsum(1) }}";
+    let rv = env.render_str(template, ()).unwrap();
+    assert_eq!(env.render_str(template, ()).unwrap(), rv);
+    let (a, b) = rv.split_once('|').unwrap();
+    assert_ne!(a, b);
+    env.set_random_seed(Some(23));
+    assert_ne!(env.render_str(template, ()).unwrap(), rv);
+
+    let err = env
+        .render_str("{{ lipsum(min=10, max=5) }}", ())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid operation: min must not be larger than max (in <string>:1)"
+    );
+}
+
+#[test]
+fn test_clone_overlay() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let mut base = Environment::new();
+    base.add_template("hello.txt", "Hello {{ user }} on {{ site }}!")
+        .unwrap();
+    base.add_global("site", Value::from("example.com"));
+    let base = Arc::new(base);
+
+    let handles = (0..8)
+        .map(|idx| {
+            let base = base.clone();
+            thread::spawn(move || {
+                let mut env = (*base).clone();
+                env.add_global("user", Value::from(format!("user-{idx}")));
+                env.add_filter("shout", |value: String| value.to_uppercase());
+                env.add_template("extra.txt", "{{ user|shout }}").unwrap();
+                let hello = env.get_template("hello.txt").unwrap();
+                let extra = env.get_template("extra.txt").unwrap();
+                (
+                    hello.render(context!()).unwrap(),
+                    extra.render(context!()).unwrap(),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (idx, handle) in handles.into_iter().enumerate() {
+        let (hello, extra) = handle.join().unwrap();
+        assert_eq!(hello, format!("Hello user-{idx} on example.com!"));
+        assert_eq!(extra, format!("USER-{idx}"));
+    }
+
+    // none of the modifications leaked into the base environment
+    assert_eq!(base.global("user"), None);
+    assert!(!base.has_filter("shout"));
+    assert_eq!(
+        base.get_template("extra.txt").unwrap_err().kind(),
+        ErrorKind::TemplateNotFound
+    );
+    assert_eq!(
+        base.get_template("hello.txt")
+            .unwrap()
+            .render(context!(user => "Peter"))
+            .unwrap(),
+        "Hello Peter on example.com!"
+    );
+}
+
+#[test]
+fn test_custom_tag() {
+    fn upper(body: Value) -> Value {
+        let rv = body.as_str().unwrap_or_default().to_uppercase();
+        if body.is_safe() {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        }
+    }
+
+    let mut env = Environment::new();
+    env.add_tag("upper", upper);
+    env.add_tag("wrap", |state: &State, body: String, tag: String| {
+        format!("<{}>{}</{}>|{}", tag, body, tag, state.name())
+    });
+    env.add_template(
+        "hello.html",
+        "{% upper %}Hello {{ name }}!{% endupper %}{% for x in [1] %}{% upper %}{{ x }}{{ name }}{% endupper %}{% endfor %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("hello.html").unwrap();
+    assert_eq!(
+        tmpl.render(context!(name => "<b>")).unwrap(),
+        "HELLO &LT;B&GT;!1&LT;B&GT;"
+    );
+
+    assert_eq!(
+        env.render_str(
+            "{% wrap 'p' %}{% upper %}{{ x }}{% endupper %}{% endwrap %}",
+            context!(x => "a")
+        )
+        .unwrap(),
+        "<p>A</p>|<string>"
+    );
+    assert_eq!(
+        env.render_str(
+            "{% set x = 1 %}{% upper %}{% set x = 2 %}{{ x }}{% endupper %}{{ x }}",
+            ()
+        )
+        .unwrap(),
+        "21"
+    );
+    assert_eq!(
+        env.render_str("{% wrap 'i', 'x' %}{% endwrap %}", ())
+            .unwrap_err()
+            .kind(),
+        ErrorKind::TooManyArguments
+    );
+
+    let err = env
+        .render_str("{% shout %}x{% endshout %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    let err = env.render_str("{% upper %}x", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+}
+
+#[test]
+#[should_panic(expected = "cannot register custom tag \"for\"")]
+fn test_custom_tag_rejects_builtin_statement() {
+    let mut env = Environment::new();
+    env.add_tag("for", |body: String| body);
+}
+
+#[test]
+fn test_trailing_newline_and_newline_mode() {
+    let mut env = Environment::new();
+    assert!(!env.keep_trailing_newline());
+    assert_eq!(env.render_str("a\n", ()).unwrap(), "a");
+    assert_eq!(env.render_str("a\r\n", ()).unwrap(), "a");
+    assert_eq!(env.render_str("a\n\n", ()).unwrap(), "a\n");
+    assert_eq!(env.render_str("a", ()).unwrap(), "a");
+
+    env.set_keep_trailing_newline(true);
+    assert_eq!(env.render_str("a\n", ()).unwrap(), "a\n");
+    assert_eq!(env.render_str("a\r\n", ()).unwrap(), "a\r\n");
+    assert_eq!(env.render_str("a\n\n", ()).unwrap(), "a\n\n");
+    assert_eq!(env.render_str("a", ()).unwrap(), "a");
+
+    // crlf sources are preserved by default
+    let source = "{% for x in seq %}{{ x }}\r\n{% endfor %}{% raw %}\r{% endraw %}\r\n";
+    let ctx = context!(seq => ["1", "2\r\n"]);
+    assert_eq!(env.newline_mode(), NewlineMode::Preserve);
+    assert_eq!(
+        env.render_str(source, ctx.clone()).unwrap(),
+        "1\r\n2\r\n\r\n{% raw %}\r{% endraw %}\r\n"
+    );
+
+    // only template data is rewritten, never the output of expressions
+    env.set_newline_mode(NewlineMode::Lf);
+    assert_eq!(
+        env.render_str(source, ctx.clone()).unwrap(),
+        "1\n2\r\n\n{% raw %}\n{% endraw %}\n"
+    );
+    env.set_newline_mode(NewlineMode::CrLf);
+    assert_eq!(
+        env.render_str("a\nb\r\nc\rd\n", ()).unwrap(),
+        "a\r\nb\r\nc\r\nd\r\n"
+    );
+    env.set_newline_mode(NewlineMode::Cr);
+    assert_eq!(env.render_str("a\nb\r\n", ()).unwrap(), "a\rb\r");
+
+    // trim_blocks still removes the newline after a block in crlf sources
+    env.set_newline_mode(NewlineMode::Lf);
+    env.set_keep_trailing_newline(false);
+    env.set_trim_blocks(true);
+    assert_eq!(
+        env.render_str("{% if true %}\r\nyes\r\n{% endif %}\r\nend\r\n", ())
+            .unwrap(),
+        "yes\nend"
+    );
+}
+
+#[test]
+fn test_function_typed_args() {
+    fn pad(value: String, width: usize, fill: Option<&str>) -> String {
+        let fill = fill.unwrap_or(" ");
+        let mut rv = value;
+        while rv.chars().count() < width {
+            rv.push_str(fill);
+        }
+        rv
+    }
+
+    let mut env = Environment::new();
+    env.add_function("pad", pad);
+    env.add_function("pad_args", |args: minijinja::value::Rest<Value>| {
+        let (value, width, fill): (String, usize, Option<&str>) =
+            minijinja::value::from_args(&args)?;
+        Ok::<_, Error>(pad(value, width, fill))
+    });
+    env.add_function(
+        "pad_kwargs",
+        |value: String, kwargs: minijinja::value::Kwargs| -> Result<String, Error> {
+            let rv = pad(value, kwargs.get("width")?, kwargs.get("fill")?);
+            kwargs.assert_all_used()?;
+            Ok(rv)
+        },
+    );
+
+    for func in ["pad", "pad_args"] {
+        let render = |args: &str| env.render_str(&format!("[{{{{ {}({}) }}}}]", func, args), ());
+        assert_eq!(render("'ab', 4").unwrap(), "[ab  ]");
+        assert_eq!(render("'ab', 4, '-'").unwrap(), "[ab--]");
+        assert_eq!(render("'ab', 4, none").unwrap(), "[ab  ]");
+        assert_eq!(
+            render("'ab'").unwrap_err().kind(),
+            ErrorKind::MissingArgument
+        );
+        assert_eq!(
+            render("'ab', 'x'").unwrap_err().kind(),
+            ErrorKind::InvalidOperation
+        );
+        assert_eq!(
+            render("'ab', 4, '-', 1").unwrap_err().kind(),
+            ErrorKind::TooManyArguments
+        );
+    }
+
+    assert_eq!(
+        env.render_str("[{{ pad_kwargs('ab', width=3, fill='.') }}]", ())
+            .unwrap(),
+        "[ab.]"
+    );
+    assert_eq!(
+        env.render_str("[{{ pad_kwargs('ab', width=3) }}]", ())
+            .unwrap(),
+        "[ab ]"
+    );
+    assert_eq!(
+        env.render_str("{{ pad_kwargs('ab', fill='.') }}", ())
+            .unwrap_err()
+            .kind(),
+        ErrorKind::MissingArgument
+    );
+}
+
+#[test]
+fn test_whitespace_combinations() {
+    let source = "\
+<ul>
+  {% for item in items %}
+    {% if item.visible %}
+  <li>{{ item.name }}</li>
+    {% endif %}
+  {% endfor %}
+  {%+ if true +%}
+  <li>kept</li>
+  {% endif %}
+</ul>
+";
+    let ctx = context! {
+        items => vec![
+            context!(name => "a", visible => true),
+            context!(name => "b", visible => false),
+        ]
+    };
+    let mut rv = String::new();
+    for (trim_blocks, lstrip_blocks) in [(false, false), (true, false), (false, true), (true, true)]
+    {
+        let mut env = Environment::new();
+        env.set_trim_blocks(trim_blocks);
+        env.set_lstrip_blocks(lstrip_blocks);
+        writeln!(
+            rv,
+            "--- trim_blocks={} lstrip_blocks={} ---\n{}",
+            trim_blocks,
+            lstrip_blocks,
+            env.render_str(source, ctx.clone()).unwrap()
+        )
+        .unwrap();
+    }
+    insta::assert_snapshot!(rv);
+}
+
+#[test]
+#[cfg(all(feature = "debug", feature = "multi-template"))]
+fn test_debug_info_in_include() {
+    let mut env = Environment::new();
+    env.add_template(
+        "page.html",
+        "<h1>{{ title }}</h1>\n{% for user in users %}\n  {% include 'partials/user.html' %}\n{% endfor %}",
+    )
+    .unwrap();
+    env.add_template(
+        "partials/user.html",
+        "<div>\n  <b>{{ user.name }}</b>\n  <i>{{ user.address.street }}</i>\n</div>",
+    )
+    .unwrap();
+    let ctx = context!(title => "Users", users => vec![context!(name => "Peter")]);
+
+    env.set_debug(true);
+    let err = env
+        .get_template("page.html")
+        .unwrap()
+        .render(ctx.clone())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadInclude);
+    insta::assert_snapshot!(err.display_debug_info().to_string());
+
+    // without debug mode no source information is retained
+    env.set_debug(false);
+    let err = env
+        .get_template("page.html")
+        .unwrap()
+        .render(ctx)
+        .unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "could not render include: error in \"partials/user.html\" (in page.html:3)"
+    );
+    assert_eq!(err.display_debug_info().to_string(), "");
+}
@@ -7,7 +7,8 @@ use std::fmt::Write;
 fn test_lexer() {
     insta::glob!("lexer-inputs/*.txt", |path| {
         let contents = std::fs::read_to_string(path).unwrap();
-        let tokens: Result<Vec<_>, _> = tokenize(&contents, false).collect();
+        let tokens: Result<Vec<_>, _> =
+            tokenize(&contents, false, Default::default(), Default::default()).collect();
         let tokens = tokens.unwrap().into_iter().map(|x| x.0).collect::<Vec<_>>();
         insta::with_settings!({
             description => contents.trim_end(),
@@ -15,3 +15,95 @@ fn test_parser() {
         });
     });
 }
+
+#[test]
+fn test_parse_structure() {
+    use minijinja::machinery::ast::{Expr, Stmt};
+
+    let ast = parse(
+        "Hello {{ user.name|upper }}!\n{% for item in items %}{{ item }}{% endfor %}",
+        "hello.txt",
+    )
+    .unwrap();
+    let children = match ast {
+        Stmt::Template(ref tmpl) => &tmpl.children,
+        _ => panic!("expected template"),
+    };
+    assert_eq!(children.len(), 4);
+
+    match &children[0] {
+        Stmt::EmitRaw(raw) => assert_eq!(raw.raw, "Hello "),
+        _ => panic!("expected raw output"),
+    }
+    match &children[1] {
+        Stmt::EmitExpr(emit) => {
+            let filter = match &emit.expr {
+                Expr::Filter(filter) => filter,
+                _ => panic!("expected filter"),
+            };
+            assert_eq!(filter.name, "upper");
+            let attr = filter.expr.as_ref().unwrap();
+            match attr {
+                Expr::GetAttr(attr) => assert_eq!(attr.name, "name"),
+                _ => panic!("expected attribute lookup"),
+            }
+            let span = attr.span();
+            assert_eq!(
+                (span.start_line, span.start_col, span.end_line, span.end_col),
+                (1, 9, 1, 18)
+            );
+        }
+        _ => panic!("expected expression output"),
+    }
+    match &children[3] {
+        Stmt::ForLoop(for_loop) => {
+            assert!(matches!(&for_loop.target, Expr::Var(var) if var.id == "item"));
+            assert!(matches!(&for_loop.iter, Expr::Var(var) if var.id == "items"));
+            assert_eq!(for_loop.body.len(), 1);
+            assert_eq!(children[3].span().start_line, 2);
+        }
+        _ => panic!("expected for loop"),
+    }
+
+    let err = parse("{% for %}", "broken.txt").unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+    assert_eq!(err.name(), Some("broken.txt"));
+}
+
+#[test]
+fn test_format() {
+    use minijinja::machinery::format;
+
+    let source = "Hello {{user.name|upper}}!\r\n{%-for  item in items   -%}\n  <li>{{ loop.index~':'~item  }}</li>\n{%endfor%}";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        formatted,
+        "Hello {{ user.name|upper }}!\r\n{%- for item in items -%}\n  <li>{{ loop.index ~ ':' ~ item }}</li>\n{% endfor %}"
+    );
+    assert_eq!(format(&formatted).unwrap(), formatted);
+
+    let messy = [
+        "{{x if y else -1}}{{ a - -b }}{{(a+b)*c}}",
+        "{% set x=foo(a , b=2)%}{{x[1:2]}}{{ {'a':1,\"b\" : [1,2]} }}",
+        "{% macro   m(a, b=1)%}{{a}}{%endmacro%}{%call(x)m(1)%}{{x}}{%endcall%}",
+        "{%+ if not(x) and x is divisibleby (3) +%}ä{% endif %}\n",
+    ];
+    let expected = [
+        "{{ x if y else -1 }}{{ a - -b }}{{ (a + b) * c }}",
+        "{% set x = foo(a, b=2) %}{{ x[1:2] }}{{ {'a': 1, \"b\": [1, 2]} }}",
+        "{% macro m(a, b=1) %}{{ a }}{% endmacro %}{% call(x) m(1) %}{{ x }}{% endcall %}",
+        "{%+ if not (x) and x is divisibleby(3) +%}ä{% endif %}\n",
+    ];
+    for (source, expected) in messy.iter().zip(expected.iter()) {
+        let formatted = format(source).unwrap();
+        assert_eq!(&formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    // comments and raw blocks are retained verbatim
+    let verbatim = "{#  a  {{ comment }} #}{% raw %}{{  x  }}{% endraw %}";
+    assert_eq!(format(verbatim).unwrap(), verbatim);
+
+    let err = format("{{ foo").unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+}
@@ -1,6 +1,6 @@
 #![cfg(feature = "source")]
 
-use minijinja::{Environment, Source};
+use minijinja::{context, Environment, Error, ErrorKind, Source, Syntax};
 
 use similar_asserts::assert_eq;
 
@@ -40,3 +40,333 @@ fn test_dynamic() {
         "template not found: template \"missing\" does not exist"
     );
 }
+
+#[test]
+fn test_loader() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let loads = Arc::new(AtomicUsize::new(0));
+    let mut env = Environment::new();
+    env.add_template(
+        "index.html",
+        "{% extends 'layout.html' %}{% block body %}{% include 'item.html' %}{% endblock %}",
+    )
+    .unwrap();
+    env.set_loader({
+        let loads = loads.clone();
+        move |name| {
+            loads.fetch_add(1, Ordering::Relaxed);
+            match name {
+                "layout.html" => Ok(Some(
+                    "{% import 'macros.html' as m %}<body>{{ m.bold('x') }}{% block body %}{% endblock %}</body>".into(),
+                )),
+                "macros.html" => Ok(Some("{% macro bold(x) %}<b>{{ x }}</b>{% endmacro %}".into())),
+                "item.html" => Ok(Some(format!("<i>{}</i>", name))),
+                "broken.html" => Err(Error::new(ErrorKind::InvalidOperation, "database is down")),
+                _ => Ok(None),
+            }
+        }
+    })
+    .unwrap();
+
+    let render = |env: &Environment| env.get_template("index.html").unwrap().render(()).unwrap();
+    assert_eq!(render(&env), "<body><b>x</b><i>item.html</i></body>");
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+
+    // loaded templates are cached
+    assert_eq!(render(&env), "<body><b>x</b><i>item.html</i></body>");
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+
+    // missing templates and loader errors are reported differently
+    let err = env.get_template("missing.html").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TemplateNotFound);
+    let err = env.get_template("broken.html").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(err.to_string(), "invalid operation: database is down");
+    let rv = env
+        .render_str("{% include 'missing.html' ignore missing %}ok", ())
+        .unwrap();
+    assert_eq!(rv, "ok");
+    let err = env
+        .render_str("{% include 'broken.html' ignore missing %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+
+    // only templates which were added or loaded are listed
+    let names: Vec<_> = env.templates().map(|(name, _)| name).collect();
+    assert_eq!(
+        names,
+        ["index.html", "item.html", "layout.html", "macros.html"]
+    );
+
+    // removing or clearing templates loads them again on next use
+    let before = loads.load(Ordering::Relaxed);
+    env.remove_template("item.html");
+    render(&env);
+    assert_eq!(loads.load(Ordering::Relaxed), before + 1);
+    env.clear_templates();
+    assert_eq!(
+        env.get_template("index.html").unwrap_err().kind(),
+        ErrorKind::TemplateNotFound
+    );
+}
+
+#[test]
+fn test_loader_recompile_error() {
+    let mut env = Environment::new();
+    env.add_template("index.html", "{{ x }}<% if %>").unwrap();
+    env.set_syntax(Syntax {
+        block_start: "<%".into(),
+        block_end: "%>".into(),
+        ..Syntax::default()
+    })
+    .unwrap();
+    let err = env.set_loader(|_| Ok(None)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+
+    // the environment still holds the template compiled before
+    let tmpl = env.get_template("index.html").unwrap();
+    assert_eq!(tmpl.render(context!(x => 1)).unwrap(), "1<% if %>");
+}
+
+#[test]
+fn test_loader_shared_across_threads() {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let db: BTreeMap<&str, &str> = [
+        ("a.html", "A{% include 'b.html' %}"),
+        ("b.html", "B{% include 'c.html' %}"),
+        ("c.html", "C{{ n }}"),
+    ]
+    .into_iter()
+    .collect();
+    let loads = Arc::new(AtomicUsize::new(0));
+    let mut env = Environment::new();
+    env.set_loader({
+        let loads = loads.clone();
+        move |name| {
+            loads.fetch_add(1, Ordering::Relaxed);
+            Ok(db.get(name).map(|x| x.to_string()))
+        }
+    })
+    .unwrap();
+
+    let env = Arc::new(env);
+    let handles: Vec<_> = (0..8)
+        .map(|n| {
+            let env = env.clone();
+            std::thread::spawn(move || {
+                let tmpl = env.get_template("a.html").unwrap();
+                tmpl.render(minijinja::context!(n)).unwrap()
+            })
+        })
+        .collect();
+    for (n, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join().unwrap(), format!("ABC{}", n));
+    }
+    assert_eq!(loads.load(Ordering::Relaxed), 3);
+    assert_eq!(
+        env.template_names().collect::<Vec<_>>(),
+        ["a.html", "b.html", "c.html"]
+    );
+}
+
+#[test]
+#[cfg(feature = "loader")]
+fn test_path_loader() {
+    use std::fs;
+
+    use minijinja::{path_loader, path_loader_with_extensions};
+
+    let base = std::env::temp_dir().join(format!("minijinja-path-loader-{}", std::process::id()));
+    let root = base.join("templates");
+    fs::remove_dir_all(&base).ok();
+    fs::create_dir_all(root.join("emails")).unwrap();
+    fs::write(base.join("secret"), "secret").unwrap();
+    fs::write(
+        root.join("index.html"),
+        "{% include 'emails/welcome.html' %}",
+    )
+    .unwrap();
+    fs::write(root.join("emails/welcome.html"), "Welcome {{ name }}!").unwrap();
+    fs::write(root.join("notes.md"), "notes").unwrap();
+    fs::write(root.join(".hidden.html"), "hidden").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(base.join("secret"), root.join("link.html")).unwrap();
+
+    let mut env = Environment::new();
+    env.set_loader(path_loader(&root)).unwrap();
+    let rv = env
+        .get_template("index.html")
+        .unwrap()
+        .render(context!(name => "Peter"))
+        .unwrap();
+    assert_eq!(rv, "Welcome Peter!");
+    assert_eq!(
+        env.get_template("emails\\welcome.html").unwrap().source(),
+        "Welcome {{ name }}!"
+    );
+    assert_eq!(env.get_template("notes.md").unwrap().source(), "notes");
+
+    for name in [
+        "../secret",
+        "a/../../secret",
+        "emails/../../secret",
+        "..\\secret",
+        "/etc/passwd",
+        ".hidden.html",
+        "missing.html",
+        "emails",
+        #[cfg(unix)]
+        "link.html",
+    ] {
+        let err = env.get_template(name).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TemplateNotFound, "{}", name);
+    }
+
+    let mut env = Environment::new();
+    env.set_loader(path_loader_with_extensions(&root, ["html"]))
+        .unwrap();
+    assert!(env.get_template("emails/welcome.html").is_ok());
+    let err = env.get_template("notes.md").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TemplateNotFound);
+
+    // read failures are not reported as missing templates
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = root.join("locked.html");
+        fs::write(&path, "locked").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read_to_string(&path).is_err() {
+            let err = env.get_template("locked.html").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+        }
+    }
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+#[cfg(feature = "loader")]
+fn test_auto_reload() {
+    use std::fs;
+
+    use minijinja::path_loader;
+
+    let root = std::env::temp_dir().join(format!("minijinja-auto-reload-{}", std::process::id()));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), "[{% include 'item.html' %}]").unwrap();
+    fs::write(root.join("item.html"), "Hello {{ name }}!").unwrap();
+
+    let mut env = Environment::new();
+    env.add_template("static.html", "static").unwrap();
+    env.set_loader(path_loader(&root)).unwrap();
+    let render = |env: &Environment| {
+        env.get_template("index.html")
+            .unwrap()
+            .render(context!(name => "Peter"))
+            .unwrap()
+    };
+    assert_eq!(render(&env), "[Hello Peter!]");
+
+    // without auto reloading the cached template is used
+    fs::write(root.join("item.html"), "Bye {{ name }}!").unwrap();
+    assert_eq!(render(&env), "[Hello Peter!]");
+
+    // with auto reloading changes are picked up, also in included templates
+    env.set_auto_reload(true);
+    assert!(env.auto_reload());
+    assert_eq!(render(&env), "[Bye Peter!]");
+    fs::write(root.join("index.html"), "<{% include 'item.html' %}>").unwrap();
+    assert_eq!(render(&env), "<Bye Peter!>");
+    let templates: Vec<_> = env.templates().collect();
+    assert_eq!(
+        templates
+            .iter()
+            .map(|(name, tmpl)| (*name, tmpl.source()))
+            .collect::<Vec<_>>(),
+        [
+            ("index.html", "<{% include 'item.html' %}>"),
+            ("item.html", "Bye {{ name }}!"),
+            ("static.html", "static"),
+        ]
+    );
+
+    // templates the loader does not know and deleted files stay available
+    assert_eq!(env.get_template("static.html").unwrap().source(), "static");
+    fs::remove_file(root.join("item.html")).unwrap();
+    assert_eq!(render(&env), "<Bye Peter!>");
+
+    // syntax errors are reported and fixing them recovers
+    fs::write(root.join("index.html"), "{% if %}").unwrap();
+    assert_eq!(
+        env.get_template("index.html").unwrap_err().kind(),
+        ErrorKind::SyntaxError
+    );
+    fs::write(root.join("index.html"), "fixed").unwrap();
+    assert_eq!(env.get_template("index.html").unwrap().source(), "fixed");
+
+    fs::remove_dir_all(&root).ok();
+}
+
+#[test]
+#[cfg(feature = "loader")]
+fn test_auto_reload_from_path() {
+    use std::fs;
+
+    let root = std::env::temp_dir().join(format!(
+        "minijinja-auto-reload-files-{}",
+        std::process::id()
+    ));
+    fs::remove_dir_all(&root).ok();
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("index.html"), "first").unwrap();
+
+    let mut env = Environment::new();
+    env.set_source(Source::from_path(&root));
+    env.set_auto_reload(true);
+    assert_eq!(env.get_template("index.html").unwrap().source(), "first");
+    assert_eq!(env.get_template("index.html").unwrap().source(), "first");
+    fs::write(root.join("index.html"), "second!").unwrap();
+    assert_eq!(env.get_template("index.html").unwrap().source(), "second!");
+
+    fs::remove_dir_all(&root).ok();
+}
+
+#[test]
+#[cfg(feature = "loader")]
+fn test_auto_reload_keeps_states_alive() {
+    use std::sync::{Arc, Mutex};
+
+    let current = Arc::new(Mutex::new("{% macro m() %}first{% endmacro %}".to_string()));
+    let mut env = Environment::new();
+    env.set_loader({
+        let current = current.clone();
+        move |_| Ok(Some(current.lock().unwrap().clone()))
+    })
+    .unwrap();
+    env.set_auto_reload(true);
+
+    *current.lock().unwrap() = "{% macro m() %}second{% endmacro %}".into();
+    let state = env
+        .get_template("macros")
+        .unwrap()
+        .eval_to_state(())
+        .unwrap();
+    let tmpl = env.get_template("macros").unwrap();
+
+    // the states and templates still refer to the version they were created with
+    *current.lock().unwrap() = "{% macro m() %}third{% endmacro %}".into();
+    assert_eq!(
+        env.get_template("macros").unwrap().source(),
+        "{% macro m() %}third{% endmacro %}"
+    );
+    let m = state.lookup("m").unwrap();
+    assert_eq!(m.call(&state, &[]).unwrap().as_str(), Some("second"));
+    assert_eq!(tmpl.source(), "{% macro m() %}second{% endmacro %}");
+}
@@ -2,7 +2,7 @@ use std::cmp::Ordering;
 use std::fmt;
 
 use insta::assert_snapshot;
-use minijinja::value::{Object, ObjectKind, SeqObject, StructObject, Value};
+use minijinja::value::{Object, ObjectKind, SeqObject, StructObject, Value, ValueChange};
 
 #[test]
 fn test_sort() {
@@ -29,6 +29,72 @@ fn test_sort() {
     "###);
 }
 
+#[test]
+fn test_sort_mixed_numbers() {
+    let mut v = vec![
+        Value::from(3),
+        Value::from(f64::NAN),
+        Value::from(1.5),
+        Value::from(f64::INFINITY),
+        Value::from(2),
+        Value::from(f64::NEG_INFINITY),
+        Value::from(-0.0),
+    ];
+    v.sort();
+    insta::assert_debug_snapshot!(&v, @r###"
+    [
+        -inf,
+        -0.0,
+        1.5,
+        2,
+        3,
+        inf,
+        NaN,
+    ]
+    "###);
+    assert_eq!(v.iter().max(), Some(&Value::from(f64::NAN)));
+    assert_eq!(v.iter().min(), Some(&Value::from(f64::NEG_INFINITY)));
+}
+
+#[test]
+fn test_float_total_order() {
+    let nan = Value::from(f64::NAN);
+    assert_eq!(nan, nan);
+    assert!(nan > Value::from(f64::INFINITY));
+    assert!(nan > Value::from(i64::MAX));
+    assert!(nan > Value::from(u128::MAX));
+    assert_eq!(Value::from(-0.0), Value::from(0.0));
+    assert_eq!(Value::from(-0.0), Value::from(0));
+    assert_eq!(Value::from(1), Value::from(1.0));
+    assert_eq!(Value::from(true), Value::from(1));
+    assert!(Value::from(f64::INFINITY) > Value::from(u128::MAX));
+    assert!(Value::from(f64::NEG_INFINITY) < Value::from(i128::MIN));
+}
+
+#[test]
+fn test_int_float_precision() {
+    // 2^53 + 1 cannot be represented as f64 and would round to 2^53
+    let int = Value::from(9007199254740993i64);
+    let float = Value::from(9007199254740992.0f64);
+    assert_ne!(int, float);
+    assert!(int > float);
+    assert!(float < int);
+    assert_eq!(Value::from(9007199254740992i64), float);
+    assert!(Value::from(i64::MAX) < Value::from(9223372036854775808.0f64));
+    assert!(Value::from(u64::MAX) < Value::from(18446744073709551616.0f64));
+    assert!(Value::from(2) < Value::from(2.5));
+    assert!(Value::from(-2) > Value::from(-2.5));
+    // both round to 2^128 as f64 but are distinct integers
+    assert!(Value::from(u128::MAX - 1) < Value::from(u128::MAX));
+    assert_ne!(Value::from(u128::MAX - 1), Value::from(u128::MAX));
+    assert!(Value::from(u128::MAX) > Value::from(i128::MAX));
+    assert!(Value::from(u128::MAX) < Value::from(340282366920938463463374607431768211456.0f64));
+    assert_eq!(
+        Value::from(1u128 << 127),
+        Value::from(170141183460469231731687303715884105728.0f64)
+    );
+}
+
 #[test]
 fn test_safe_string_roundtrip() {
     let v = Value::from_safe_string("<b>HTML</b>".into());
@@ -122,6 +188,12 @@ fn test_map_object_iteration_and_indexing() {
         point => Value::from_object(Point(1, 2, 3))
     );
     assert_snapshot!(rv, @r###"[1, 3, Undefined]"###);
+
+    let rv = minijinja::render!(
+        "{{ point.items() }}|{{ point|dictsort(by='value', reverse=true) }}",
+        point => Value::from_object(Point(1, 3, 2))
+    );
+    assert_snapshot!(rv, @r###"[["x", 1], ["y", 3], ["z", 2]]|[["y", 3], ["z", 2], ["x", 1]]"###);
 }
 
 #[test]
@@ -172,3 +244,587 @@ fn test_seq_object_iteration_and_indexing() {
     );
     assert_snapshot!(rv, @r###"[1, 3, Undefined]"###);
 }
+
+#[test]
+fn test_shared_str() {
+    use std::sync::Arc;
+
+    let big: Arc<str> = Arc::from("x".repeat(4 * 1024 * 1024));
+    let mut env = minijinja::Environment::new();
+    env.add_filter("a", |v: Arc<str>| v);
+    env.add_filter("b", |v: Arc<str>| v);
+    env.add_filter("c", |v: Value| v.as_shared_str().unwrap());
+    let expr = env.compile_expression("big|a|b|c").unwrap();
+    let rv = expr
+        .eval(minijinja::context!(big => Value::from(big.clone())))
+        .unwrap();
+    let shared = rv.as_shared_str().unwrap();
+    assert!(Arc::ptr_eq(&shared, &big));
+    assert_eq!(rv, Value::from("x".repeat(4 * 1024 * 1024)));
+    assert_eq!(Value::from(7).as_shared_str(), None);
+}
+
+#[test]
+fn test_try_into_vec_and_map() {
+    struct Point(i32, i32);
+
+    impl StructObject for Point {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(self.0)),
+                "y" => Some(Value::from(self.1)),
+                _ => None,
+            }
+        }
+
+        fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["x", "y"].into_iter())
+        }
+    }
+
+    let v = Value::from(vec![1, 2, 3]);
+    assert_eq!(
+        v.try_to_vec().unwrap(),
+        vec![Value::from(1), Value::from(2), Value::from(3)]
+    );
+    assert_eq!(v.try_into_vec().unwrap().len(), 3);
+
+    let v = Value::from_seq_object(vec![Value::from(true), Value::from("x")]);
+    assert_eq!(
+        v.try_into_vec().unwrap(),
+        vec![Value::from(true), Value::from("x")]
+    );
+
+    let v = minijinja::context! { a => 1, b => "two" };
+    let map = v.try_to_map().unwrap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["a"], Value::from(1));
+    assert_eq!(v.try_into_map().unwrap()["b"], Value::from("two"));
+
+    let v = Value::from_struct_object(Point(1, 2));
+    let map = v.try_into_map().unwrap();
+    assert_eq!(map.keys().collect::<Vec<_>>(), vec!["x", "y"]);
+    assert_eq!(map["y"], Value::from(2));
+
+    assert!(Value::from(42).try_into_vec().is_err());
+    assert!(Value::from(vec![1]).try_into_map().is_err());
+    assert!(Value::from_struct_object(Point(1, 2)).try_to_vec().is_err());
+}
+
+#[test]
+fn test_function_value_with_state() {
+    use minijinja::State;
+
+    let url_for = Value::from_function(|state: &State, path: String| {
+        let base = state.lookup("base_url").unwrap_or_default();
+        format!("{}/{}", base, path)
+    });
+    let rv = minijinja::render!(
+        "{{ url_for('index.html') }}",
+        url_for,
+        base_url => "https://example.com",
+    );
+    assert_eq!(rv, "https://example.com/index.html");
+}
+
+#[test]
+fn test_get_item_by_path() {
+    struct Point(i32, i32);
+
+    impl StructObject for Point {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(self.0)),
+                "y" => Some(Value::from(self.1)),
+                _ => None,
+            }
+        }
+
+        fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["x", "y"].into_iter())
+        }
+    }
+
+    let mut weird = std::collections::BTreeMap::new();
+    weird.insert("a.b", Value::from(1));
+    weird.insert("it's \"quoted\"", Value::from(2));
+
+    let ctx = minijinja::context! {
+        items => vec![
+            minijinja::context! { name => "first" },
+            minijinja::context! { name => "last" },
+        ],
+        points => Value::from_seq_object(vec![Value::from_struct_object(Point(1, 2))]),
+        weird,
+        title => "Hello",
+    };
+
+    assert_eq!(
+        ctx.get_item_by_path("items[0].name").unwrap(),
+        Value::from("first")
+    );
+    assert_eq!(
+        ctx.get_item_by_path("items[-1].name").unwrap(),
+        Value::from("last")
+    );
+    assert_eq!(ctx.get_item_by_path("points[0].y").unwrap(), Value::from(2));
+    assert_eq!(
+        ctx.get_item_by_path(r#"weird["a.b"]"#).unwrap(),
+        Value::from(1)
+    );
+    assert_eq!(
+        ctx.get_item_by_path(r#"weird['it\'s "quoted"']"#).unwrap(),
+        Value::from(2)
+    );
+    assert_eq!(ctx.get_item_by_path("").unwrap().len(), Some(4));
+
+    // missing leaves are undefined
+    assert!(ctx
+        .get_item_by_path("items[0].missing")
+        .unwrap()
+        .is_undefined());
+    assert!(ctx.get_item_by_path("items[42]").unwrap().is_undefined());
+    assert!(ctx.get_item_by_path("weird.a").unwrap().is_undefined());
+
+    // type mismatches and missing intermediates are errors
+    assert!(ctx.get_item_by_path("title.length").is_err());
+    assert!(ctx.get_item_by_path("title[0]").is_err());
+    assert!(ctx.get_item_by_path("items.name").is_err());
+    assert!(ctx.get_item_by_path("missing.name").is_err());
+    assert!(ctx.get_item_by_path("items[").is_err());
+}
+
+#[test]
+fn test_struct_object_items() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Headers {
+        lookups: AtomicUsize,
+    }
+
+    impl StructObject for Headers {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            match name {
+                "content-type" => Some(Value::from("text/html")),
+                "x-count" => Some(Value::from(2)),
+                _ => None,
+            }
+        }
+
+        fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["x-count", "content-type"].into_iter())
+        }
+
+        fn items(&self) -> Box<dyn Iterator<Item = (&str, Value)> + '_> {
+            Box::new(
+                [
+                    ("x-count", Value::from(2)),
+                    ("content-type", Value::from("text/html")),
+                ]
+                .into_iter(),
+            )
+        }
+    }
+
+    let headers = Arc::new(Headers {
+        lookups: AtomicUsize::new(0),
+    });
+    let env = minijinja::Environment::new();
+    let ctx = minijinja::context!(headers => Value::from_struct_object(headers.clone()));
+    let rv = env
+        .render_str(
+            "{% for k, v in headers|items %}{{ k }}={{ v }};{% endfor %}|\
+             {% for k, v in headers|dictsort %}{{ k }}={{ v }};{% endfor %}|\
+             {{ headers }}",
+            ctx,
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        r#"x-count=2;content-type=text/html;|content-type=text/html;x-count=2;|["x-count": 2, "content-type": "text/html"]"#
+    );
+    assert_eq!(headers.lookups.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_memoized_struct() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use minijinja::value::MemoizedStruct;
+
+    struct Expensive {
+        calls: AtomicUsize,
+    }
+
+    impl StructObject for Expensive {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            match name {
+                "value" => Some(Value::from(42)),
+                _ => None,
+            }
+        }
+
+        fn fields(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+            Box::new(["value"].into_iter())
+        }
+    }
+
+    let obj = Arc::new(MemoizedStruct::new(Expensive {
+        calls: AtomicUsize::new(0),
+    }));
+    let env = minijinja::Environment::new();
+    let ctx = minijinja::context!(obj => Value::from_struct_object(obj.clone()));
+    let rv = env
+        .render_str(
+            "{{ obj.value }}|{{ obj.value }}|{{ obj.missing is defined }}|\
+             {{ obj.missing is defined }}|{{ obj }}",
+            ctx.clone(),
+        )
+        .unwrap();
+    assert_eq!(rv, r#"42|42|false|false|["value": 42]"#);
+    assert_eq!(obj.inner().calls.load(Ordering::Relaxed), 2);
+
+    // the cache lives as long as the wrapper
+    env.render_str("{{ obj.value }}", ctx).unwrap();
+    assert_eq!(obj.inner().calls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn test_value_from_collections() {
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+    let items = |value: &Value| value.try_iter().unwrap().collect::<Vec<_>>();
+
+    let value = Value::from(HashMap::from([("b", 2), ("a", 1)]));
+    assert_eq!(value.get_attr("a").unwrap(), Value::from(1));
+    assert_eq!(value.get_item(&Value::from("b")).unwrap(), Value::from(2));
+    assert_eq!(items(&value), vec![Value::from("a"), Value::from("b")]);
+
+    let value = Value::from(BTreeMap::from([(2, "two"), (1, "one")]));
+    assert_eq!(value.get_item(&Value::from(1)).unwrap(), Value::from("one"));
+    assert_eq!(items(&value), vec![Value::from(1), Value::from(2)]);
+
+    let value = Value::from(vec![("x", 1), ("y", 2)]);
+    assert_eq!(value.get_attr("y").unwrap(), Value::from(2));
+    assert_eq!(value.len(), Some(2));
+    assert_eq!(items(&value), vec![Value::from("x"), Value::from("y")]);
+
+    let value = Value::from(VecDeque::from([3, 1, 2]));
+    assert_eq!(value.get_item(&Value::from(0)).unwrap(), Value::from(3));
+    assert_eq!(value.to_string(), "[3, 1, 2]");
+    let value = Value::from(BTreeSet::from([3, 1, 2]));
+    assert_eq!(value.to_string(), "[1, 2, 3]");
+    let value = Value::from(HashSet::from(["a"]));
+    assert_eq!(value.to_string(), r#"["a"]"#);
+
+    let value = Value::from((1, "two", 3.5, true));
+    assert_eq!(value.len(), Some(4));
+    assert_eq!(value.get_item(&Value::from(1)).unwrap(), Value::from("two"));
+    assert_eq!(value.to_string(), r#"[1, "two", 3.5, true]"#);
+    assert_eq!(Value::from((42,)).to_string(), "[42]");
+}
+
+#[test]
+#[cfg(feature = "preserve_order")]
+fn test_value_from_pairs_preserves_order() {
+    let value = Value::from(vec![("z", 1), ("a", 2)]);
+    assert_eq!(
+        value.try_iter().unwrap().collect::<Vec<_>>(),
+        vec![Value::from("z"), Value::from("a")]
+    );
+}
+
+#[test]
+fn test_map_eq_ignores_order() {
+    let a = Value::from(vec![("z", 1), ("a", 2)]);
+    let b = Value::from(vec![("a", 2), ("z", 1)]);
+    assert_eq!(a, b);
+    assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+    assert_ne!(a, Value::from(vec![("a", 1), ("z", 2)]));
+}
+
+#[test]
+fn test_get_attr_and_item() -> Result<(), minijinja::Error> {
+    struct User;
+
+    impl StructObject for User {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "name" => Some(Value::from("Peter")),
+                _ => None,
+            }
+        }
+    }
+
+    let value = minijinja::context! {
+        user => minijinja::context! { name => "Peter", tags => ["a", "b"] },
+        obj => Value::from_struct_object(User),
+    };
+
+    assert_eq!(
+        value.get_attr("user")?.get_item(&Value::from("name"))?,
+        Value::from("Peter")
+    );
+    assert_eq!(
+        value
+            .get_attr("user")?
+            .get_attr("tags")?
+            .get_item_by_index(1)?,
+        Value::from("b")
+    );
+    assert_eq!(
+        value.get_item(&Value::from("obj"))?.get_attr("name")?,
+        Value::from("Peter")
+    );
+
+    // missing keys are undefined, looking into undefined is an error
+    let missing = value.get_attr("user")?.get_attr("missing")?;
+    assert!(missing.is_undefined());
+    assert!(value.get_attr("obj")?.get_attr("missing")?.is_undefined());
+    assert!(Value::from(42).get_attr("name")?.is_undefined());
+    assert_eq!(
+        missing.get_attr("name").unwrap_err().kind(),
+        minijinja::ErrorKind::UndefinedError
+    );
+    Ok(())
+}
+
+#[test]
+fn test_try_iter() {
+    let value = Value::from(vec![1, 2, 3]);
+    let iter = value.try_iter().unwrap();
+    assert_eq!(iter.size_hint(), (3, Some(3)));
+    assert_eq!(
+        iter.collect::<Vec<_>>(),
+        vec![Value::from(1), Value::from(2), Value::from(3)]
+    );
+
+    let value = Value::from(std::collections::BTreeMap::from([("b", 2), ("a", 1)]));
+    assert_eq!(
+        value.try_iter().unwrap().collect::<Vec<_>>(),
+        vec![Value::from("a"), Value::from("b")]
+    );
+
+    assert_eq!(Value::UNDEFINED.try_iter().unwrap().count(), 0);
+
+    let err = Value::from(42).try_iter().err().unwrap();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.to_string(), "invalid operation: number is not iterable");
+}
+
+#[test]
+fn test_deep_eq_and_diff() {
+    let value = Value::from_serializable(&serde_json::json!({
+        "user": {"name": "Peter", "tags": ["a", "b"]},
+        "count": 1,
+    }));
+
+    let same = Value::from_serializable(&serde_json::json!({
+        "count": 1.0,
+        "user": {"tags": ["a", "b"], "name": "Peter"},
+    }));
+    assert!(value.deep_eq(&same));
+    assert_eq!(value.diff(&same), None);
+    assert!(!Value::from(true).deep_eq(&Value::from(1)));
+    assert!(!Value::from('a').deep_eq(&Value::from("a")));
+
+    let changed = Value::from_serializable(&serde_json::json!({
+        "user": {"name": "Paul", "tags": ["a"], "weird key": null},
+        "count": 1,
+    }));
+    assert!(!value.deep_eq(&changed));
+    let diff = value.diff(&changed).unwrap();
+    assert_eq!(
+        diff.get("user.name"),
+        Some(&ValueChange::Changed {
+            old: Value::from("Peter"),
+            new: Value::from("Paul"),
+        })
+    );
+    assert_eq!(
+        diff.get("user.tags[1]"),
+        Some(&ValueChange::Removed(Value::from("b")))
+    );
+    assert_snapshot!(diff.to_string(), @r###"
+    ~ user.name: "Peter" -> "Paul"
+    - user.tags[1]: "b"
+    + user["weird key"]: None
+    "###);
+
+    // the reported paths can be resolved against the values
+    for (path, change) in diff.changes() {
+        match change {
+            ValueChange::Added(new) => assert_eq!(&changed.get_item_by_path(path).unwrap(), new),
+            ValueChange::Removed(old) => assert_eq!(&value.get_item_by_path(path).unwrap(), old),
+            ValueChange::Changed { old, new } => {
+                assert_eq!(&value.get_item_by_path(path).unwrap(), old);
+                assert_eq!(&changed.get_item_by_path(path).unwrap(), new);
+            }
+        }
+    }
+
+    // the value itself is reported with an empty path
+    let diff = Value::from(1).diff(&Value::from("1")).unwrap();
+    assert_eq!(diff.to_string(), r#"~ : 1 -> "1""#);
+
+    // values nested too deeply are reported as changed instead of compared
+    let nested = |depth| {
+        let mut value = Value::from(vec![1]);
+        for _ in 0..depth {
+            value = Value::from(vec![value]);
+        }
+        value
+    };
+    assert!(nested(50).deep_eq(&nested(50)));
+    assert!(!nested(1000).deep_eq(&nested(1000)));
+    let diff = nested(1000).diff(&nested(1000)).unwrap();
+    assert_eq!(diff.changes().len(), 1);
+    assert_eq!(diff.changes()[0].0, "[0]".repeat(100));
+}
+
+#[test]
+#[cfg(feature = "deserialization")]
+fn test_via_deserialize() {
+    use minijinja::value::ViaDeserialize;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Align {
+        Left,
+        Right,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Column {
+        title: String,
+        width: usize,
+        align: Option<Align>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct TableConfig {
+        columns: Vec<Column>,
+        border: bool,
+    }
+
+    let mut env = minijinja::Environment::new();
+    env.add_filter(
+        "table_header",
+        |config: ViaDeserialize<TableConfig>| -> String {
+            let sep = if config.border { "|" } else { " " };
+            config
+                .columns
+                .iter()
+                .map(|col| match col.align {
+                    Some(Align::Right) => format!("{:>1$}", col.title, col.width),
+                    _ => format!("{:<1$}", col.title, col.width),
+                })
+                .collect::<Vec<_>>()
+                .join(sep)
+        },
+    );
+
+    let rv = env
+        .render_str(
+            "[{{ {'columns': [{'title': 'a', 'width': 3}, {'title': 'b', 'width': 4, 'align': 'right'}], 'border': true}|table_header }}]",
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "[a  |   b]");
+
+    let err = env
+        .render_str(
+            "{{ {'columns': [{'title': 'a'}], 'border': true}|table_header }}",
+            (),
+        )
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::CannotDeserialize);
+    assert_eq!(
+        err.to_string(),
+        "cannot deserialize: missing field `width` (in <string>:1)"
+    );
+
+    // values can also be deserialized directly
+    let value = Value::from_serializable(&serde_json::json!({
+        "columns": [{"title": "x", "width": 1, "align": "left"}],
+        "border": false,
+    }));
+    assert_eq!(
+        TableConfig::deserialize(&value).unwrap(),
+        TableConfig {
+            columns: vec![Column {
+                title: "x".into(),
+                width: 1,
+                align: Some(Align::Left),
+            }],
+            border: false,
+        }
+    );
+    assert_eq!(
+        <(i32, Option<String>)>::deserialize(Value::from(vec![Value::from(1), Value::from(())]))
+            .unwrap(),
+        (1, None)
+    );
+}
+
+#[test]
+#[cfg(feature = "deserialization")]
+fn test_value_deserializer() {
+    use minijinja::value::ValueDeserializer;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Config {
+        name: String,
+        tags: Vec<String>,
+        limit: Option<u32>,
+        shapes: Vec<Shape>,
+    }
+
+    let value = Value::from_serializable(&serde_json::json!({
+        "name": "demo",
+        "tags": ["a", "b"],
+        "limit": null,
+        "shapes": ["Point", {"Circle": 1.5}, {"Rect": {"w": 2, "h": 3}}],
+    }));
+    assert_eq!(
+        Config::deserialize(ValueDeserializer::new(value)).unwrap(),
+        Config {
+            name: "demo".into(),
+            tags: vec!["a".into(), "b".into()],
+            limit: None,
+            shapes: vec![Shape::Point, Shape::Circle(1.5), Shape::Rect { w: 2, h: 3 }],
+        }
+    );
+
+    let value = Value::from(vec![Value::from(1), Value::from(2)]);
+    assert_eq!(
+        Vec::<u8>::deserialize(ValueDeserializer::new(value)).unwrap(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        Option::<i32>::deserialize(ValueDeserializer::new(Value::from(()))).unwrap(),
+        None
+    );
+    assert_eq!(
+        Option::<i32>::deserialize(ValueDeserializer::new(Value::from(42))).unwrap(),
+        Some(42)
+    );
+
+    let err = Shape::deserialize(ValueDeserializer::new(Value::from("Hexagon"))).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::CannotDeserialize);
+    let err = String::deserialize(ValueDeserializer::new(Value::from(true))).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::CannotDeserialize);
+}
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 
 use minijinja::machinery::{make_string_output, CodeGenerator, Instruction, Instructions, Vm};
 use minijinja::value::Value;
-use minijinja::{AutoEscape, Environment, Error};
+use minijinja::{AutoEscape, Environment, Error, Extensions};
 
 use similar_asserts::assert_eq;
 
@@ -23,6 +23,7 @@ pub fn simple_eval<S: serde::Serialize>(
         &empty_blocks,
         &mut output,
         AutoEscape::None,
+        &Extensions::new(),
     )?;
     Ok(rv)
 }
@@ -40,7 +41,7 @@ fn test_loop() {
     c.start_for_loop(false, false);
     c.add(Instruction::Emit);
     c.end_for_loop(false);
-    c.add(Instruction::EmitRaw("!"));
+    c.add(Instruction::EmitRaw("!".into()));
 
     let output = simple_eval(&c.finish().0, ctx).unwrap();
 
@@ -56,9 +57,9 @@ fn test_if() {
         let mut c = CodeGenerator::new("<unknown>", "");
         c.add(Instruction::Lookup("cond"));
         c.start_if();
-        c.add(Instruction::EmitRaw("true"));
+        c.add(Instruction::EmitRaw("true".into()));
         c.start_else();
-        c.add(Instruction::EmitRaw("false"));
+        c.add(Instruction::EmitRaw("false".into()));
         c.end_if();
 
         let output = simple_eval(&c.finish().0, ctx).unwrap();
@@ -76,13 +77,13 @@ fn test_if_branches() {
     let mut c = CodeGenerator::new("<unknown>", "");
     c.add(Instruction::Lookup("false"));
     c.start_if();
-    c.add(Instruction::EmitRaw("nope1"));
+    c.add(Instruction::EmitRaw("nope1".into()));
     c.start_else();
     c.add(Instruction::Lookup("nil"));
     c.start_if();
-    c.add(Instruction::EmitRaw("nope1"));
+    c.add(Instruction::EmitRaw("nope1".into()));
     c.start_else();
-    c.add(Instruction::EmitRaw("yes"));
+    c.add(Instruction::EmitRaw("yes".into()));
     c.end_if();
     c.end_if();
 
@@ -101,7 +102,7 @@ fn test_basic() {
     ctx.insert("b", Value::from(23));
 
     let mut i = Instructions::new("", "");
-    i.add(Instruction::EmitRaw("Hello "));
+    i.add(Instruction::EmitRaw("Hello ".into()));
     i.add(Instruction::Lookup("user"));
     i.add(Instruction::GetAttr("name"));
     i.add(Instruction::Emit);
@@ -119,7 +120,7 @@ fn test_basic() {
 fn test_error_info() {
     let mut c = CodeGenerator::new("hello.html", "");
     c.set_line(1);
-    c.add(Instruction::EmitRaw("<h1>Hello</h1>\n"));
+    c.add(Instruction::EmitRaw("<h1>Hello</h1>\n".into()));
     c.set_line(2);
     c.add(Instruction::Lookup("a_string"));
     c.add(Instruction::Lookup("an_int"));
@@ -4,7 +4,7 @@ use std::io::{self, Read};
 use std::path::PathBuf;
 
 use argh::FromArgs;
-use minijinja::machinery::{parse, CompiledTemplate, Instructions};
+use minijinja::machinery::{parse, CompiledTemplate, Instructions, TemplateConfig};
 
 fn print_instructions(instructions: &Instructions, block_name: &str) {
     println!("Block: {:?}", block_name);
@@ -46,7 +46,8 @@ fn execute() -> Result<(), Box<dyn Error>> {
     };
 
     if cli.disassemble || !cli.dump_ast {
-        let tmpl = CompiledTemplate::from_name_and_source(&filename, &source)?;
+        let tmpl =
+            CompiledTemplate::from_name_and_source(&filename, &source, &TemplateConfig::default())?;
         for (block_name, instructions) in tmpl.blocks.iter() {
             print_instructions(instructions, block_name);
         }